@@ -60,6 +60,8 @@ pub struct TransferStateEventData {
     pub protocol_version: i32,
     pub transfer_id: String,
     pub result: i32,
+    /// The connection attempts made after the first one
+    pub reconnects: i32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -71,6 +73,19 @@ pub struct TransferFileEventData {
     pub direction: TransferDirection,
     pub transferred: i32,
     pub result: i32,
+    /// In kB, the offset the file was started or resumed from
+    pub resume_offset: i32,
+    /// In kB
+    pub file_size: i32,
+    /// In milliseconds, the time the upload waited for the other uploads.
+    /// Always 0 on downloads
+    pub throttle_time: i32,
+    /// In milliseconds, the time spent checking the partially downloaded data
+    /// before resuming. Always 0 on uploads
+    pub verify_checksum_time: i32,
+    /// In milliseconds, the time spent checking the downloaded file. Always 0
+    /// on uploads
+    pub finalize_checksum_time: i32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
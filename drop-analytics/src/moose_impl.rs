@@ -163,6 +163,7 @@ impl super::Moose for MooseImpl {
             data.transfer_id,
             data.result,
             data.protocol_version,
+            data.reconnects,
             None
         );
     }
@@ -178,6 +179,11 @@ impl super::Moose for MooseImpl {
             data.path_id,
             data.phase.into(),
             data.transferred,
+            data.resume_offset,
+            data.file_size,
+            data.throttle_time,
+            data.verify_checksum_time,
+            data.finalize_checksum_time,
             None
         );
     }
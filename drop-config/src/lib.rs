@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use drop_core::ChecksumAlgorithm;
+
 #[derive(Debug, Clone, Default)]
 pub struct Config {
     pub drop: DropConfig,
@@ -11,6 +13,9 @@ pub struct DropConfig {
     pub dir_depth_limit: usize,
     pub transfer_file_limit: usize,
     pub storage_path: String,
+    // If unset the transfers are kept in memory only, so there is no history
+    // and the transfers can be resumed only while the process lives
+    pub history_enabled: bool,
     // If set the checksum events will be emited for every file of this or bigger size
     pub checksum_events_size_threshold: Option<usize>,
     // If set the checksum events will be emited for every checksum_events_granularity bytes
@@ -18,6 +23,22 @@ pub struct DropConfig {
     pub checksum_events_granularity: u64,
     pub connection_retries: u32,
     pub auto_retry_interval: Option<Duration>,
+    // Algorithm requested from the sender for the file integrity checks. Peers
+    // older than protocol V7 always use SHA-256
+    pub checksum_algorithm: ChecksumAlgorithm,
+    // If set the space for the whole file is reserved when the download starts
+    pub preallocate_downloads: bool,
+    // If set the directories are sent as a single tar archive each, unpacked
+    // by the receiver. Requires protocol V7 on the receiving side
+    pub archive_directories: bool,
+    // The progress events are emitted once the file advances by at least this
+    // many bytes
+    pub progress_events_granularity: u64,
+    // And at least this much time has passed since the previous progress event.
+    // Applies to the checksum progress events too
+    pub progress_events_interval: Duration,
+    // Bounds the transfer history kept in the storage
+    pub retention: RetentionPolicy,
 }
 
 impl Default for DropConfig {
@@ -26,18 +47,44 @@ impl Default for DropConfig {
             dir_depth_limit: 5,
             transfer_file_limit: 1000,
             storage_path: "libdrop.sqlite".to_string(),
+            history_enabled: true,
             checksum_events_size_threshold: None,
             checksum_events_granularity: 256 * 1024,
             connection_retries: 5,
             auto_retry_interval: None,
+            checksum_algorithm: ChecksumAlgorithm::Sha256,
+            preallocate_downloads: false,
+            archive_directories: false,
+            progress_events_granularity: 64 * 1024,
+            progress_events_interval: Duration::ZERO,
+            retention: RetentionPolicy::default(),
         }
     }
 }
 
+// Transfers that can still be resumed are never removed
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    // Transfers older than this are removed
+    pub max_age: Option<Duration>,
+    // Only this many of the newest transfers are kept
+    pub max_transfers: Option<usize>,
+    // If set only the finished transfers are removed
+    pub terminal_only: bool,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_transfers.is_some()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct MooseConfig {
     pub event_path: String,
     pub prod: bool,
+    // No analytics events are reported when set
+    pub disabled: bool,
 }
 
 pub const PORT: u16 = 49111;
@@ -46,4 +93,8 @@ pub const PING_INTERVAL: Duration = Duration::new(30, 0);
 pub const MAX_UPLOADS_IN_FLIGHT: usize = 4;
 pub const MAX_REQUESTS_PER_SEC: u32 = 50;
 pub const WS_SEND_TIMEOUT: Duration = Duration::new(20, 0);
+pub const RETENTION_INTERVAL: Duration = Duration::new(60 * 60, 0);
+// Files and directories of a single archive, the archives are exempt from the
+// `transfer_file_limit`
+pub const MAX_ARCHIVE_MEMBERS: usize = 100_000;
 pub const FIRST_RETRY_AFTER: Duration = Duration::new(1, 0);
@@ -0,0 +1,20 @@
+/// Hashing algorithm used for the file integrity checks
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    strum::Display,
+    strum::EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
@@ -1,3 +1,5 @@
+mod checksum;
 mod status;
 
+pub use checksum::ChecksumAlgorithm;
 pub use status::Status;
@@ -0,0 +1,6 @@
+-- Add migration script here
+
+ALTER TABLE incoming_paths ADD COLUMN checksum_algorithm TEXT;
+
+-- All of the checksums stored so far were calculated with SHA-256
+UPDATE incoming_paths SET checksum_algorithm = 'sha256' WHERE checksum IS NOT NULL;
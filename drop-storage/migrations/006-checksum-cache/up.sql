@@ -0,0 +1,13 @@
+-- Add migration script here
+
+-- checksums of the outgoing files, valid as long as the file is not modified.
+-- The resumed uploads request the checksum of the file prefix, hence the limit
+CREATE TABLE IF NOT EXISTS checksum_cache (
+  uri TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  modified_at_ns INTEGER NOT NULL,
+  limit_bytes INTEGER NOT NULL,
+  algorithm TEXT NOT NULL,
+  checksum BLOB NOT NULL,
+  PRIMARY KEY(uri, limit_bytes, algorithm)
+);
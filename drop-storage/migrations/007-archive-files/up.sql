@@ -0,0 +1,7 @@
+-- Add migration script here
+
+-- Files carrying a tar archive of the whole directory tree, unpacked on arrival
+ALTER TABLE incoming_paths ADD COLUMN is_archive BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Outgoing files carrying a tar archive of the whole directory tree
+ALTER TABLE outgoing_paths ADD COLUMN is_archive BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,394 @@
+//! The part of the storage the transfers depend on while running: the
+//! transfer and file state changes and the sync state needed to resume the
+//! transfers after a restart
+
+use std::time::Duration;
+
+use drop_core::ChecksumAlgorithm;
+use uuid::Uuid;
+
+use crate::{
+    sync,
+    types::{FileSyncState, IncomingTransferToRetry, TempFileLocation},
+    ChecksumCacheKey, FileChecksum, FinishedIncomingFile, OutgoingTransferToRetry, Storage,
+    TransferInfo,
+};
+
+/// Persistence of the transfers. [`Storage`] keeps everything in SQLite,
+/// [`crate::MemoryStorage`] keeps only what's needed to resume the transfers
+/// and forgets it with the process.
+#[async_trait::async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Returns `None` if the transfer with the same ID already exists
+    async fn insert_transfer(&self, transfer: &TransferInfo) -> Option<()>;
+
+    async fn update_transfer_sync_states(&self, transfer_id: Uuid, local: sync::TransferState);
+
+    async fn transfer_sync_state(&self, transfer_id: Uuid) -> Option<sync::Transfer>;
+
+    /// Marks the transfer as not resumable anymore
+    async fn transfer_sync_clear(&self, transfer_id: Uuid) -> Option<()>;
+
+    async fn outgoing_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState>;
+
+    async fn update_outgoing_file_sync_states(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        local: sync::FileState,
+    );
+
+    async fn incoming_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState>;
+
+    /// Records the download of the file into `base_dir` as in flight
+    async fn start_incoming_file(&self, transfer_id: Uuid, file_id: &str, base_dir: &str);
+
+    /// Terminates the file, returns `None` if it was not in flight
+    async fn stop_incoming_file(&self, transfer_id: Uuid, file_id: &str) -> Option<()>;
+
+    async fn insert_transfer_failed_state(&self, transfer_id: Uuid, error: u32);
+
+    async fn insert_transfer_cancel_state(&self, transfer_id: Uuid, by_peer: bool);
+
+    async fn insert_outgoing_path_started_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_sent: i64,
+    );
+
+    async fn insert_incoming_path_started_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_received: i64,
+    );
+
+    async fn insert_outgoing_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        error: u32,
+        bytes_sent: i64,
+    );
+
+    async fn insert_incoming_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        error: u32,
+        bytes_received: i64,
+    );
+
+    async fn insert_outgoing_path_completed_state(&self, transfer_id: Uuid, path_id: &str);
+
+    async fn insert_incoming_path_completed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        final_path: &str,
+    );
+
+    async fn insert_outgoing_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        by_peer: bool,
+        bytes_sent: i64,
+    );
+
+    async fn insert_incoming_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        by_peer: bool,
+        bytes_received: i64,
+    );
+
+    async fn insert_outgoing_path_paused_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_sent: i64,
+    );
+
+    async fn insert_incoming_path_paused_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_received: i64,
+    );
+
+    async fn outgoing_transfers_to_resume(&self) -> Vec<OutgoingTransferToRetry>;
+
+    async fn incoming_transfers_to_resume(&self) -> Vec<IncomingTransferToRetry>;
+
+    /// The files of the transfer which were being downloaded
+    async fn incoming_files_to_resume(&self, transfer_id: Uuid) -> Vec<sync::FileInFlight>;
+
+    async fn finished_incoming_files(&self, transfer_id: Uuid) -> Vec<FinishedIncomingFile>;
+
+    async fn save_checksum(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        algorithm: ChecksumAlgorithm,
+        checksum: &[u8],
+    );
+
+    async fn fetch_checksums(&self, transfer_id: Uuid) -> Vec<FileChecksum>;
+
+    async fn fetch_cached_checksum(&self, key: &ChecksumCacheKey) -> Option<Vec<u8>>;
+
+    async fn save_cached_checksum(&self, key: &ChecksumCacheKey, checksum: &[u8]);
+
+    /// The directories the files of the transfer were downloaded into
+    async fn fetch_temp_locations(&self, transfer_id: Uuid) -> Vec<TempFileLocation>;
+
+    async fn fetch_base_dirs_for_file(&self, transfer_id: Uuid, file_id: &str) -> Vec<String>;
+
+    /// Removes the leftovers of the previous runs, returns the number of
+    /// removed transfers
+    async fn cleanup_garbage_transfers(&self) -> usize;
+
+    /// Returns the number of removed transfers
+    async fn apply_retention(
+        &self,
+        max_age: Option<Duration>,
+        max_transfers: Option<usize>,
+        terminal_only: bool,
+    ) -> usize;
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for Storage {
+    async fn insert_transfer(&self, transfer: &TransferInfo) -> Option<()> {
+        Storage::insert_transfer(self, transfer).await
+    }
+
+    async fn update_transfer_sync_states(&self, transfer_id: Uuid, local: sync::TransferState) {
+        Storage::update_transfer_sync_states(self, transfer_id, local).await
+    }
+
+    async fn transfer_sync_state(&self, transfer_id: Uuid) -> Option<sync::Transfer> {
+        Storage::transfer_sync_state(self, transfer_id).await
+    }
+
+    async fn transfer_sync_clear(&self, transfer_id: Uuid) -> Option<()> {
+        Storage::transfer_sync_clear(self, transfer_id).await
+    }
+
+    async fn outgoing_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState> {
+        Storage::outgoing_file_sync_state(self, transfer_id, file_id).await
+    }
+
+    async fn update_outgoing_file_sync_states(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        local: sync::FileState,
+    ) {
+        Storage::update_outgoing_file_sync_states(self, transfer_id, file_id, local).await
+    }
+
+    async fn incoming_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState> {
+        Storage::incoming_file_sync_state(self, transfer_id, file_id).await
+    }
+
+    async fn start_incoming_file(&self, transfer_id: Uuid, file_id: &str, base_dir: &str) {
+        Storage::start_incoming_file(self, transfer_id, file_id, base_dir).await
+    }
+
+    async fn stop_incoming_file(&self, transfer_id: Uuid, file_id: &str) -> Option<()> {
+        Storage::stop_incoming_file(self, transfer_id, file_id).await
+    }
+
+    async fn insert_transfer_failed_state(&self, transfer_id: Uuid, error: u32) {
+        Storage::insert_transfer_failed_state(self, transfer_id, error).await
+    }
+
+    async fn insert_transfer_cancel_state(&self, transfer_id: Uuid, by_peer: bool) {
+        Storage::insert_transfer_cancel_state(self, transfer_id, by_peer).await
+    }
+
+    async fn insert_outgoing_path_started_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_sent: i64,
+    ) {
+        Storage::insert_outgoing_path_started_state(self, transfer_id, path_id, bytes_sent).await
+    }
+
+    async fn insert_incoming_path_started_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_received: i64,
+    ) {
+        Storage::insert_incoming_path_started_state(self, transfer_id, path_id, bytes_received)
+            .await
+    }
+
+    async fn insert_outgoing_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        error: u32,
+        bytes_sent: i64,
+    ) {
+        Storage::insert_outgoing_path_failed_state(self, transfer_id, path_id, error, bytes_sent)
+            .await
+    }
+
+    async fn insert_incoming_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        error: u32,
+        bytes_received: i64,
+    ) {
+        Storage::insert_incoming_path_failed_state(
+            self,
+            transfer_id,
+            path_id,
+            error,
+            bytes_received,
+        )
+        .await
+    }
+
+    async fn insert_outgoing_path_completed_state(&self, transfer_id: Uuid, path_id: &str) {
+        Storage::insert_outgoing_path_completed_state(self, transfer_id, path_id).await
+    }
+
+    async fn insert_incoming_path_completed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        final_path: &str,
+    ) {
+        Storage::insert_incoming_path_completed_state(self, transfer_id, path_id, final_path).await
+    }
+
+    async fn insert_outgoing_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        by_peer: bool,
+        bytes_sent: i64,
+    ) {
+        Storage::insert_outgoing_path_reject_state(self, transfer_id, path_id, by_peer, bytes_sent)
+            .await
+    }
+
+    async fn insert_incoming_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        by_peer: bool,
+        bytes_received: i64,
+    ) {
+        Storage::insert_incoming_path_reject_state(
+            self,
+            transfer_id,
+            path_id,
+            by_peer,
+            bytes_received,
+        )
+        .await
+    }
+
+    async fn insert_outgoing_path_paused_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_sent: i64,
+    ) {
+        Storage::insert_outgoing_path_paused_state(self, transfer_id, path_id, bytes_sent).await
+    }
+
+    async fn insert_incoming_path_paused_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        bytes_received: i64,
+    ) {
+        Storage::insert_incoming_path_paused_state(self, transfer_id, path_id, bytes_received).await
+    }
+
+    async fn outgoing_transfers_to_resume(&self) -> Vec<OutgoingTransferToRetry> {
+        Storage::outgoing_transfers_to_resume(self).await
+    }
+
+    async fn incoming_transfers_to_resume(&self) -> Vec<IncomingTransferToRetry> {
+        Storage::incoming_transfers_to_resume(self).await
+    }
+
+    async fn incoming_files_to_resume(&self, transfer_id: Uuid) -> Vec<sync::FileInFlight> {
+        Storage::incoming_files_to_resume(self, transfer_id).await
+    }
+
+    async fn finished_incoming_files(&self, transfer_id: Uuid) -> Vec<FinishedIncomingFile> {
+        Storage::finished_incoming_files(self, transfer_id).await
+    }
+
+    async fn save_checksum(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        algorithm: ChecksumAlgorithm,
+        checksum: &[u8],
+    ) {
+        Storage::save_checksum(self, transfer_id, file_id, algorithm, checksum).await
+    }
+
+    async fn fetch_checksums(&self, transfer_id: Uuid) -> Vec<FileChecksum> {
+        Storage::fetch_checksums(self, transfer_id).await
+    }
+
+    async fn fetch_cached_checksum(&self, key: &ChecksumCacheKey) -> Option<Vec<u8>> {
+        Storage::fetch_cached_checksum(self, key).await
+    }
+
+    async fn save_cached_checksum(&self, key: &ChecksumCacheKey, checksum: &[u8]) {
+        Storage::save_cached_checksum(self, key, checksum).await
+    }
+
+    async fn fetch_temp_locations(&self, transfer_id: Uuid) -> Vec<TempFileLocation> {
+        Storage::fetch_temp_locations(self, transfer_id).await
+    }
+
+    async fn fetch_base_dirs_for_file(&self, transfer_id: Uuid, file_id: &str) -> Vec<String> {
+        Storage::fetch_base_dirs_for_file(self, transfer_id, file_id).await
+    }
+
+    async fn cleanup_garbage_transfers(&self) -> usize {
+        Storage::cleanup_garbage_transfers(self).await
+    }
+
+    async fn apply_retention(
+        &self,
+        max_age: Option<Duration>,
+        max_transfers: Option<usize>,
+        terminal_only: bool,
+    ) -> usize {
+        Storage::apply_retention(self, max_age, max_transfers, terminal_only).await
+    }
+}
@@ -0,0 +1,162 @@
+//! Optional encryption of the database at rest. Requires SQLite built with
+//! SQLCipher, which is linked with the `sqlcipher` feature. Without it, opening
+//! the storage with a key fails instead of silently writing plaintext
+
+use std::{fmt, fs};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use slog::{info, Logger};
+
+use crate::{prepare_sqlite_file, Error, Result};
+
+pub const STORAGE_KEY_LENGTH: usize = 32;
+
+/// Raw key of the database, used without any key derivation
+#[derive(Clone, PartialEq, Eq)]
+pub struct StorageKey([u8; STORAGE_KEY_LENGTH]);
+
+impl StorageKey {
+    // The blob literal makes SQLCipher use the key as is
+    fn literal(&self) -> String {
+        let hex: String = self.0.iter().map(|b| format!("{b:02x}")).collect();
+        format!("\"x'{hex}'\"")
+    }
+}
+
+impl From<[u8; STORAGE_KEY_LENGTH]> for StorageKey {
+    fn from(value: [u8; STORAGE_KEY_LENGTH]) -> Self {
+        Self(value)
+    }
+}
+
+impl TryFrom<&[u8]> for StorageKey {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let key = value.try_into().map_err(|_| {
+            Error::InternalError(format!(
+                "Invalid storage key length {}, expected {STORAGE_KEY_LENGTH}",
+                value.len()
+            ))
+        })?;
+
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StorageKey(****)")
+    }
+}
+
+/// Opens the encrypted database. A plaintext database is encrypted with the
+/// key first
+pub(crate) fn unlock(
+    logger: &Logger,
+    path: &str,
+    flags: OpenFlags,
+    key: &StorageKey,
+) -> Result<Connection> {
+    let conn = Connection::open_with_flags(path, flags)?;
+    apply_key(&conn, key)?;
+
+    if is_readable(&conn) {
+        return Ok(conn);
+    }
+    drop(conn);
+
+    // Either a plaintext database or the key is wrong
+    let plain = Connection::open_with_flags(path, flags)?;
+    if !is_readable(&plain) {
+        return Err(Error::Encryption(
+            "Failed to decrypt the database, the key does not match".to_string(),
+        ));
+    }
+
+    info!(logger, "Encrypting the plaintext database");
+
+    let encrypted = format!("{path}.encrypting");
+    if let Err(err) = fs::remove_file(&encrypted) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return Err(err.into());
+        }
+    }
+    prepare_sqlite_file(&encrypted)?;
+
+    let version: i64 = plain.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    plain.execute(
+        &format!("ATTACH DATABASE ?1 AS encrypted KEY {}", key.literal()),
+        [&encrypted],
+    )?;
+    plain.execute_batch(&format!(
+        "SELECT sqlcipher_export('encrypted'); PRAGMA encrypted.user_version = {version}; \
+         DETACH DATABASE encrypted;"
+    ))?;
+    drop(plain);
+
+    // The plaintext file is replaced at once so that a crash in the middle
+    // leaves one of the two intact
+    fs::rename(&encrypted, path)?;
+
+    let conn = Connection::open_with_flags(path, flags)?;
+    apply_key(&conn, key)?;
+
+    if !is_readable(&conn) {
+        return Err(Error::InternalError(
+            "The encrypted database is not readable".to_string(),
+        ));
+    }
+
+    Ok(conn)
+}
+
+/// Attaches the database under the given name, decrypting it with the key if
+/// there is one
+pub(crate) fn attach(
+    conn: &Connection,
+    path: &str,
+    name: &str,
+    key: Option<&StorageKey>,
+) -> Result<()> {
+    let key = match key {
+        Some(key) => {
+            ensure_supported(conn)?;
+            format!(" KEY {}", key.literal())
+        }
+        None => String::new(),
+    };
+
+    conn.execute(&format!("ATTACH DATABASE ?1 AS {name}{key}"), [path])?;
+    Ok(())
+}
+
+pub(crate) fn apply_key(conn: &Connection, key: &StorageKey) -> Result<()> {
+    ensure_supported(conn)?;
+    conn.execute_batch(&format!("PRAGMA key = {};", key.literal()))?;
+    Ok(())
+}
+
+pub(crate) fn rekey(conn: &Connection, key: &StorageKey) -> Result<()> {
+    conn.execute_batch(&format!("PRAGMA rekey = {};", key.literal()))?;
+    Ok(())
+}
+
+// Plain SQLite ignores the key pragmas, check the library can encrypt at all
+fn ensure_supported(conn: &Connection) -> Result<()> {
+    let version: Option<String> = conn
+        .query_row("PRAGMA cipher_version", [], |row| row.get(0))
+        .optional()?;
+
+    match version {
+        Some(_) => Ok(()),
+        None => Err(Error::Encryption(
+            "Storage encryption requires SQLCipher, build with the `sqlcipher` feature".to_string(),
+        )),
+    }
+}
+
+fn is_readable(conn: &Connection) -> bool {
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .is_ok()
+}
@@ -10,4 +10,14 @@ pub enum Error {
     InvalidUri(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    /// The recovery failed after the broken database was moved aside
+    #[error("Recovery failed, the old DB is kept at {backup_path:?}: {source}")]
+    Recovery {
+        backup_path: Option<std::path::PathBuf>,
+        source: Box<Error>,
+    },
+    /// The key does not open the database or the encryption is not supported.
+    /// The database itself may be intact
+    #[error("Encryption error: {0}")]
+    Encryption(String),
 }
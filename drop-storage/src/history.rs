@@ -0,0 +1,222 @@
+//! Portable dump of the transfer history. The transfers, paths and their
+//! states are written as versioned JSON so that the history can be moved to
+//! another device or attached to a support request
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{
+        DbTransferType, IncomingPath, IncomingPathStateEventData, OutgoingPath,
+        OutgoingPathStateEventData, Transfer, TransferStateEventData, TransferType,
+    },
+    Error, Result,
+};
+
+/// Bump when the format changes in a way the older versions can't read
+pub const HISTORY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct History {
+    pub version: u32,
+    pub transfers: Vec<Transfer>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryHeader {
+    pub version: u32,
+}
+
+/// Inserts the transfer with its full history. Returns `false` if a transfer
+/// with the same ID already exists, in which case nothing is inserted
+pub(crate) fn insert_transfer(conn: &Connection, transfer: &Transfer) -> Result<bool> {
+    let tid = transfer.id.to_string();
+    let transfer_type = match transfer.transfer_type {
+        DbTransferType::Incoming(_) => TransferType::Incoming,
+        DbTransferType::Outgoing(_) => TransferType::Outgoing,
+    };
+
+    let inserted = conn.execute(
+        "INSERT INTO transfers (id, peer, is_outgoing, created_at) VALUES (?1, ?2, ?3, ?4) ON \
+         CONFLICT DO NOTHING",
+        params![
+            tid,
+            transfer.peer_id,
+            transfer_type as u32,
+            transfer.created_at
+        ],
+    )?;
+
+    if inserted < 1 {
+        return Ok(false);
+    }
+
+    for state in &transfer.states {
+        match state.data {
+            TransferStateEventData::Cancel { by_peer } => conn.execute(
+                "INSERT INTO transfer_cancel_states (transfer_id, by_peer, created_at) VALUES \
+                 (?1, ?2, ?3)",
+                params![tid, by_peer, state.created_at],
+            )?,
+            TransferStateEventData::Failed { status_code } => conn.execute(
+                "INSERT INTO transfer_failed_states (transfer_id, status_code, created_at) VALUES \
+                 (?1, ?2, ?3)",
+                params![tid, status_code, state.created_at],
+            )?,
+        };
+    }
+
+    match &transfer.transfer_type {
+        DbTransferType::Incoming(paths) => {
+            for path in paths {
+                insert_incoming_path(conn, &tid, path)?;
+            }
+        }
+        DbTransferType::Outgoing(paths) => {
+            for path in paths {
+                insert_outgoing_path(conn, &tid, path)?;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+fn insert_incoming_path(conn: &Connection, tid: &str, path: &IncomingPath) -> Result<()> {
+    conn.execute(
+        r#"
+        INSERT INTO incoming_paths (transfer_id, relative_path, path_hash, bytes, created_at,
+            checksum, checksum_algorithm, is_archive)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        "#,
+        params![
+            tid,
+            path.relative_path,
+            path.file_id,
+            path.bytes,
+            path.created_at,
+            path.checksum,
+            path.checksum_algorithm.map(|algo| algo.to_string()),
+            path.is_archive
+        ],
+    )?;
+    let path_id = conn.last_insert_rowid();
+
+    for state in &path.states {
+        let created_at = state.created_at;
+
+        match &state.data {
+            IncomingPathStateEventData::Pending { base_dir } => conn.execute(
+                "INSERT INTO incoming_path_pending_states (path_id, base_dir, created_at) VALUES \
+                 (?1, ?2, ?3)",
+                params![path_id, base_dir, created_at],
+            )?,
+            IncomingPathStateEventData::Started { bytes_received } => conn.execute(
+                "INSERT INTO incoming_path_started_states (path_id, bytes_received, created_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![path_id, bytes_received, created_at],
+            )?,
+            IncomingPathStateEventData::Failed {
+                status_code,
+                bytes_received,
+            } => conn.execute(
+                "INSERT INTO incoming_path_failed_states (path_id, status_code, bytes_received, \
+                 created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![path_id, status_code, bytes_received, created_at],
+            )?,
+            IncomingPathStateEventData::Completed { final_path } => conn.execute(
+                "INSERT INTO incoming_path_completed_states (path_id, final_path, created_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![path_id, final_path, created_at],
+            )?,
+            IncomingPathStateEventData::Rejected {
+                by_peer,
+                bytes_received,
+            } => conn.execute(
+                "INSERT INTO incoming_path_reject_states (path_id, by_peer, bytes_received, \
+                 created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![path_id, by_peer, bytes_received, created_at],
+            )?,
+            IncomingPathStateEventData::Paused { bytes_received } => conn.execute(
+                "INSERT INTO incoming_path_paused_states (path_id, bytes_received, created_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![path_id, bytes_received, created_at],
+            )?,
+        };
+    }
+
+    Ok(())
+}
+
+fn insert_outgoing_path(conn: &Connection, tid: &str, path: &OutgoingPath) -> Result<()> {
+    // The dump carries either the content URI or the base directory, the
+    // file URI is rebuilt from the latter
+    let uri = match (&path.content_uri, &path.base_path) {
+        (Some(uri), _) => uri.clone(),
+        (None, Some(base_path)) => url::Url::from_file_path(base_path.join(&path.relative_path))
+            .map_err(|_| Error::InvalidUri(base_path.display().to_string()))?,
+        (None, None) => {
+            return Err(Error::InternalError(format!(
+                "Missing source of the outgoing path {}",
+                path.file_id
+            )))
+        }
+    };
+
+    conn.execute(
+        r#"
+        INSERT INTO outgoing_paths (transfer_id, relative_path, path_hash, bytes, uri, created_at,
+            is_archive)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        "#,
+        params![
+            tid,
+            path.relative_path,
+            path.file_id,
+            path.bytes,
+            uri.as_str(),
+            path.created_at,
+            path.is_archive
+        ],
+    )?;
+    let path_id = conn.last_insert_rowid();
+
+    for state in &path.states {
+        let created_at = state.created_at;
+
+        match state.data {
+            OutgoingPathStateEventData::Started { bytes_sent } => conn.execute(
+                "INSERT INTO outgoing_path_started_states (path_id, bytes_sent, created_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![path_id, bytes_sent, created_at],
+            )?,
+            OutgoingPathStateEventData::Failed {
+                status_code,
+                bytes_sent,
+            } => conn.execute(
+                "INSERT INTO outgoing_path_failed_states (path_id, status_code, bytes_sent, \
+                 created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![path_id, status_code, bytes_sent, created_at],
+            )?,
+            OutgoingPathStateEventData::Completed => conn.execute(
+                "INSERT INTO outgoing_path_completed_states (path_id, created_at) VALUES (?1, ?2)",
+                params![path_id, created_at],
+            )?,
+            OutgoingPathStateEventData::Rejected {
+                by_peer,
+                bytes_sent,
+            } => conn.execute(
+                "INSERT INTO outgoing_path_reject_states (path_id, by_peer, bytes_sent, \
+                 created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![path_id, by_peer, bytes_sent, created_at],
+            )?,
+            OutgoingPathStateEventData::Paused { bytes_sent } => conn.execute(
+                "INSERT INTO outgoing_path_paused_states (path_id, bytes_sent, created_at) \
+                 VALUES (?1, ?2, ?3)",
+                params![path_id, bytes_sent, created_at],
+            )?,
+        };
+    }
+
+    Ok(())
+}
@@ -1,22 +1,32 @@
+mod backend;
+mod cipher;
 pub mod error;
+mod history;
+mod memory;
+mod recovery;
 pub mod sync;
 pub mod types;
+mod worker;
 
 use std::{
     collections::{
         hash_map::Entry::{Occupied, Vacant},
         HashMap,
     },
-    io,
+    fs,
+    io::{self, BufWriter, Write},
     path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
     vec,
 };
 
+use drop_core::ChecksumAlgorithm;
 use include_dir::{include_dir, Dir};
-use rusqlite::{params, Connection, OpenFlags, Transaction};
+use rusqlite::{
+    params, params_from_iter, types::Value, Connection, OpenFlags, OptionalExtension, Transaction,
+};
 use rusqlite_migration::Migrations;
 use slog::{debug, error, trace, warn, Logger};
-use tokio::sync::Mutex;
 use types::{
     DbTransferType, FileSyncState, IncomingFileToRetry, IncomingPath, IncomingPathStateEvent,
     IncomingPathStateEventData, IncomingTransferToRetry, OutgoingFileToRetry, OutgoingPath,
@@ -25,20 +35,139 @@ use types::{
 };
 use uuid::Uuid;
 
+pub use crate::backend::StorageBackend;
+pub use crate::cipher::{StorageKey, STORAGE_KEY_LENGTH};
 use crate::error::Error;
-pub use crate::types::{FileChecksum, FinishedIncomingFile, OutgoingTransferToRetry, TransferInfo};
+pub use crate::history::HISTORY_VERSION;
+pub use crate::memory::MemoryStorage;
+pub use crate::recovery::RecoveryReport;
+pub use crate::types::{
+    ChecksumCacheKey, FileChecksum, FinishedIncomingFile, OutgoingTransferToRetry, PeerStats,
+    SortOrder, TransferCursor, TransferInfo, TransferPage, TransferQuery, TransferStateFilter,
+};
+use crate::worker::{Reader, Writer};
 
 type Result<T> = std::result::Result<T, Error>;
 type QueryResult<T> = std::result::Result<T, rusqlite::Error>;
 
 // SQLite storage wrapper
 pub struct Storage {
-    conn: Mutex<Connection>,
+    writer: Writer,
+    // `None` for the in-memory database, which can't be shared between the
+    // connections. The reads go through the writer then
+    reader: Option<Reader>,
     logger: Logger,
+    path: String,
+    encrypted: bool,
 }
 
 const MIGRATIONS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/migrations");
 
+// A transfer is terminal once it's canceled or failed, or when every one of its
+// files is finished. Expects the transfer to be aliased as `t`
+const TERMINAL_TRANSFER_CONDITION: &str = r#"(
+    exists (select 1 from transfer_cancel_states s where s.transfer_id = t.id)
+    or exists (select 1 from transfer_failed_states s where s.transfer_id = t.id)
+    or not exists (
+        select 1 from outgoing_paths p where p.transfer_id = t.id and not p.is_deleted
+            and p.id not in (select path_id from outgoing_path_completed_states)
+            and p.id not in (select path_id from outgoing_path_reject_states)
+            and p.id not in (select path_id from outgoing_path_failed_states)
+        union all
+        select 1 from incoming_paths p where p.transfer_id = t.id and not p.is_deleted
+            and p.id not in (select path_id from incoming_path_completed_states)
+            and p.id not in (select path_id from incoming_path_reject_states)
+            and p.id not in (select path_id from incoming_path_failed_states)
+    )
+)"#;
+
+// The cached checksums are only useful for the files that can still be sent,
+// i.e. the ones in the history
+const CHECKSUM_CACHE_CLEANUP: &str =
+    "DELETE FROM checksum_cache WHERE uri NOT IN (SELECT uri FROM outgoing_paths)";
+
+// Selects only the latest of the path states matching the condition. On equal
+// timestamps the state inserted last wins
+fn latest_path_states(states: &str, columns: &str, condition: &str) -> String {
+    format!(
+        "select {columns} from (select *, row_number() over (partition by path_id order by \
+         created_at desc, state_rowid desc) as n from {states} where {condition}) where n = 1"
+    )
+}
+
+// Builds a LIKE pattern matching the given text anywhere in the value
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+// Sums up the paths in one direction per peer. Yields the transferred bytes,
+// the completed and failed files, the size of the completed files with the
+// seconds spent on them and the latest state change. The transferred bytes of
+// an unfinished file are the most reported by its states
+fn path_totals_query(direction: &str, bytes_column: &str) -> String {
+    format!(
+        r#"
+        SELECT peer,
+            coalesce(sum(CASE WHEN completed_at IS NULL THEN coalesce(progress, 0) ELSE bytes END), 0),
+            count(completed_at),
+            sum(failed),
+            coalesce(sum(
+                CASE WHEN completed_at IS NULL OR started_at IS NULL THEN 0 ELSE bytes END
+            ), 0),
+            total((julianday(completed_at) - julianday(started_at)) * 86400),
+            max(last_at)
+        FROM (
+            SELECT t.peer AS peer, p.bytes AS bytes,
+                (SELECT min(s.created_at) FROM {direction}_path_completed_states s
+                    WHERE s.path_id = p.id) AS completed_at,
+                (SELECT min(s.created_at) FROM {direction}_path_started_states s
+                    WHERE s.path_id = p.id) AS started_at,
+                EXISTS (SELECT 1 FROM {direction}_path_failed_states s
+                    WHERE s.path_id = p.id) AS failed,
+                (SELECT max(s.{bytes_column}) FROM (
+                    SELECT path_id, {bytes_column} FROM {direction}_path_started_states
+                    UNION ALL SELECT path_id, {bytes_column} FROM {direction}_path_failed_states
+                    UNION ALL SELECT path_id, {bytes_column} FROM {direction}_path_reject_states
+                    UNION ALL SELECT path_id, {bytes_column} FROM {direction}_path_paused_states
+                ) s WHERE s.path_id = p.id) AS progress,
+                (SELECT max(s.created_at) FROM (
+                    SELECT path_id, created_at FROM {direction}_path_started_states
+                    UNION ALL SELECT path_id, created_at FROM {direction}_path_failed_states
+                    UNION ALL SELECT path_id, created_at FROM {direction}_path_reject_states
+                    UNION ALL SELECT path_id, created_at FROM {direction}_path_paused_states
+                    UNION ALL SELECT path_id, created_at FROM {direction}_path_completed_states
+                ) s WHERE s.path_id = p.id) AS last_at
+            FROM {direction}_paths p
+            INNER JOIN transfers t ON t.id = p.transfer_id
+            WHERE NOT t.is_deleted AND NOT p.is_deleted
+        )
+        GROUP BY peer
+        "#
+    )
+}
+
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
+
 #[cfg(unix)]
 fn prepare_sqlite_file(path: &str) -> io::Result<OpenFlags> {
     use std::os::unix::prelude::{OpenOptionsExt, PermissionsExt};
@@ -79,10 +208,48 @@ fn prepare_sqlite_file(_: &str) -> io::Result<OpenFlags> {
     Ok(OpenFlags::default())
 }
 
+fn open_reader(path: &str, key: Option<&StorageKey>) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI,
+    )?;
+
+    if let Some(key) = key {
+        cipher::apply_key(&conn, key)?;
+    }
+
+    Ok(conn)
+}
+
 impl Storage {
     pub fn new(logger: Logger, path: &str) -> Result<Self> {
+        Self::open(logger, path, None)
+    }
+
+    /// Opens the database encrypted with the given key. The plaintext database
+    /// is encrypted on the first open with the key. The in-memory database is
+    /// never encrypted
+    pub fn open(logger: Logger, path: &str, key: Option<&StorageKey>) -> Result<Self> {
+        let key = key.filter(|_| path != ":memory:");
+        let conn = Self::connect(&logger, path, key)?;
+        Self::start(logger, conn, path, key)
+    }
+
+    // Opens the read-write connection with the schema up to date
+    fn connect(logger: &Logger, path: &str, key: Option<&StorageKey>) -> Result<Connection> {
         let flags = prepare_sqlite_file(path)?;
-        let mut conn = Connection::open_with_flags(path, flags)?;
+
+        let mut conn = match key {
+            Some(key) => cipher::unlock(logger, path, flags, key)?,
+            None => Connection::open_with_flags(path, flags)?,
+        };
+
+        if path != ":memory:" {
+            // Lets the reads run next to the writes
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        }
 
         Migrations::from_directory(&MIGRATIONS_DIR)
             .map_err(|e| {
@@ -91,12 +258,125 @@ impl Storage {
             .to_latest(&mut conn)
             .map_err(|e| Error::InternalError(format!("Failed to run migrations: {e}")))?;
 
+        Ok(conn)
+    }
+
+    fn start(
+        logger: Logger,
+        conn: Connection,
+        path: &str,
+        key: Option<&StorageKey>,
+    ) -> Result<Self> {
+        let reader = if path == ":memory:" {
+            None
+        } else {
+            Some(Reader::new(open_reader(path, key)?))
+        };
+
         Ok(Self {
+            writer: Writer::spawn(logger.clone(), conn)?,
+            reader,
             logger,
-            conn: Mutex::new(conn),
+            path: path.to_string(),
+            encrypted: key.is_some(),
         })
     }
 
+    // Reads see everything written or queued before them
+    async fn read<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        match &self.reader {
+            Some(reader) => {
+                self.writer.run(|_| Ok(())).await?;
+                reader.read(job).await
+            }
+            None => self.writer.run(job).await,
+        }
+    }
+
+    /// Used when [`Self::new`] fails. Moves the broken database file aside,
+    /// creates a fresh database in its place and copies over all the rows that
+    /// can still be read
+    pub fn recover(
+        logger: Logger,
+        path: &str,
+        key: Option<&StorageKey>,
+    ) -> Result<(Self, RecoveryReport)> {
+        if path == ":memory:" {
+            return Err(Error::InternalError(
+                "In-memory database can't be recovered".to_string(),
+            ));
+        }
+
+        let integrity_errors = recovery::integrity_check(Path::new(path), key);
+        if !integrity_errors.is_empty() {
+            warn!(logger, "DB integrity check failed"; "errors" => ?integrity_errors);
+        }
+
+        let backup_path = recovery::backup(Path::new(path))?;
+
+        let mut report = RecoveryReport {
+            backup_path,
+            integrity_errors,
+            ..Default::default()
+        };
+
+        let mut recover = || {
+            let conn = Self::connect(&logger, path, key)?;
+
+            if let Some(backup) = report.backup_path.clone() {
+                if let Err(err) = recovery::salvage(&logger, &conn, &backup, key, &mut report) {
+                    warn!(logger, "Failed to salvage the DB: {err}");
+                }
+            }
+
+            debug!(logger, "DB recovered"; "report" => ?report);
+
+            Self::start(logger.clone(), conn, path, key)
+        };
+
+        match recover() {
+            Ok(storage) => Ok((storage, report)),
+            Err(err) => Err(Error::Recovery {
+                backup_path: report.backup_path,
+                source: Box::new(err),
+            }),
+        }
+    }
+
+    /// Re-encrypts the database with the new key. Only the database opened
+    /// with a key can be rotated
+    pub async fn rotate_key(&self, key: &StorageKey) -> Result<()> {
+        if !self.encrypted {
+            return Err(Error::InternalError(
+                "The database is not encrypted".to_string(),
+            ));
+        }
+
+        let new_key = key.clone();
+        self.writer
+            .run(move |conn| cipher::rekey(conn, &new_key))
+            .await?;
+
+        // The open connection keeps the old key
+        if let Some(reader) = &self.reader {
+            let path = self.path.clone();
+            let key = key.clone();
+
+            reader
+                .read(move |conn| {
+                    *conn = open_reader(&path, Some(&key))?;
+                    Ok(())
+                })
+                .await?;
+        }
+
+        debug!(self.logger, "Storage key rotated");
+        Ok(())
+    }
+
     pub async fn insert_transfer(&self, transfer: &TransferInfo) -> Option<()> {
         let transfer_type_int = match &transfer.files {
             TransferFiles::Incoming(_) => TransferType::Incoming as u32,
@@ -111,8 +391,10 @@ impl Storage {
             "transfer_type" => transfer_type_int,
         );
 
-        let task = async {
-            let mut conn = self.conn.lock().await;
+        let transfer = transfer.clone();
+        let logger = self.logger.clone();
+
+        let task = self.writer.run(move |conn| {
             let conn = conn.transaction()?;
 
             let inserted = conn.execute(
@@ -128,26 +410,26 @@ impl Storage {
             let is_incoming = match &transfer.files {
                 TransferFiles::Incoming(files) => {
                     trace!(
-                        self.logger,
+                        logger,
                         "Inserting transfer::Incoming files len {}",
                         files.len()
                     );
 
                     for file in files {
-                        Self::insert_incoming_path(&self.logger, &conn, transfer.id, file);
+                        Self::insert_incoming_path(&logger, &conn, transfer.id, file);
                     }
 
                     true
                 }
                 TransferFiles::Outgoing(files) => {
                     trace!(
-                        self.logger,
+                        logger,
                         "Inserting transfer::Outgoing files len {}",
                         files.len()
                     );
 
                     for file in files {
-                        Self::insert_outgoing_path(&self.logger, &conn, transfer.id, file);
+                        Self::insert_outgoing_path(&logger, &conn, transfer.id, file);
                     }
 
                     false
@@ -159,7 +441,7 @@ impl Storage {
             conn.commit()?;
 
             Ok::<_, Error>(Some(()))
-        };
+        });
 
         match task.await {
             Err(e) => {
@@ -172,22 +454,15 @@ impl Storage {
     }
 
     pub async fn update_transfer_sync_states(&self, transfer_id: Uuid, local: sync::TransferState) {
-        let task = async {
-            let conn = self.conn.lock().await;
-            sync::transfer_set_local_state(&conn, transfer_id, local)?;
-            Ok::<(), Error>(())
-        };
-
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to update transfer sync states"; "error" => %e);
-        }
+        self.writer
+            .queue("Failed to update transfer sync states", move |conn| {
+                sync::transfer_set_local_state(conn, transfer_id, local)?;
+                Ok(())
+            });
     }
 
     pub async fn transfer_sync_state(&self, transfer_id: Uuid) -> Option<sync::Transfer> {
-        let task = async {
-            let conn = self.conn.lock().await;
-            sync::transfer_state(&conn, transfer_id)
-        };
+        let task = self.read(move |conn| sync::transfer_state(conn, transfer_id));
 
         match task.await {
             Ok(state) => state,
@@ -199,10 +474,9 @@ impl Storage {
     }
 
     pub async fn transfer_sync_clear(&self, transfer_id: Uuid) -> Option<()> {
-        let task = async {
-            let conn = self.conn.lock().await;
-            sync::transfer_clear(&conn, transfer_id)
-        };
+        let task = self
+            .writer
+            .run(move |conn| sync::transfer_clear(conn, transfer_id));
 
         match task.await {
             Ok(state) => state,
@@ -219,13 +493,12 @@ impl Storage {
         file_id: &str,
     ) -> Option<FileSyncState> {
         let tid = transfer_id.to_string();
+        let file_id = file_id.to_string();
 
-        let task = async {
-            let mut conn = self.conn.lock().await;
-
+        let task = self.read(move |conn| {
             let conn = conn.transaction()?;
 
-            let sync = sync::outgoing_file_local_state(&conn, transfer_id, file_id)?;
+            let sync = sync::outgoing_file_local_state(&conn, transfer_id, &file_id)?;
 
             let sync = if let Some(sync) = sync {
                 sync
@@ -276,7 +549,7 @@ impl Storage {
             conn.commit()?;
 
             Ok::<_, Error>(Some(res))
-        };
+        });
 
         match task.await {
             Ok(state) => state,
@@ -293,15 +566,13 @@ impl Storage {
         file_id: &str,
         local: sync::FileState,
     ) {
-        let task = async {
-            let conn = self.conn.lock().await;
-            sync::outgoing_file_set_local_state(&conn, transfer_id, file_id, local)?;
-            Ok::<(), Error>(())
-        };
+        let file_id = file_id.to_string();
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to update outgoing file sync states"; "error" => %e);
-        }
+        self.writer
+            .queue("Failed to update outgoing file sync states", move |conn| {
+                sync::outgoing_file_set_local_state(conn, transfer_id, &file_id, local)?;
+                Ok(())
+            });
     }
 
     pub async fn incoming_file_sync_state(
@@ -310,13 +581,12 @@ impl Storage {
         file_id: &str,
     ) -> Option<FileSyncState> {
         let tid = transfer_id.to_string();
+        let file_id = file_id.to_string();
 
-        let task = async {
-            let mut conn = self.conn.lock().await;
-
+        let task = self.read(move |conn| {
             let conn = conn.transaction()?;
 
-            let sync = sync::incoming_file_local_state(&conn, transfer_id, file_id)?;
+            let sync = sync::incoming_file_local_state(&conn, transfer_id, &file_id)?;
             let sync = if let Some(sync) = sync {
                 sync
             } else {
@@ -366,7 +636,7 @@ impl Storage {
             conn.commit()?;
 
             Ok::<_, Error>(Some(res))
-        };
+        });
 
         match task.await {
             Ok(state) => state,
@@ -378,18 +648,23 @@ impl Storage {
     }
 
     pub async fn stop_incoming_file(&self, transfer_id: Uuid, file_id: &str) -> Option<()> {
-        let conn = self.conn.lock().await;
-
-        if let Err(e) = sync::incoming_file_set_local_state(
-            &conn,
-            transfer_id,
-            file_id,
-            sync::FileState::Terminal,
-        ) {
-            error!(self.logger, "Failed to update incoming file sync states"; "error" => %e);
-        }
+        let file_id = file_id.to_string();
+        let logger = self.logger.clone();
+
+        let task = self.writer.run(move |conn| {
+            if let Err(e) = sync::incoming_file_set_local_state(
+                conn,
+                transfer_id,
+                &file_id,
+                sync::FileState::Terminal,
+            ) {
+                error!(logger, "Failed to update incoming file sync states"; "error" => %e);
+            }
 
-        match sync::stop_incoming_file(&conn, transfer_id, file_id) {
+            sync::stop_incoming_file(conn, transfer_id, &file_id)
+        });
+
+        match task.await {
             Ok(state) => state,
             Err(e) => {
                 error!(self.logger, "Failed to stop incoming file sync state"; "error" => %e);
@@ -399,19 +674,22 @@ impl Storage {
     }
 
     pub async fn start_incoming_file(&self, transfer_id: Uuid, file_id: &str, base_dir: &str) {
-        let task = async {
-            let conn = self.conn.lock().await;
-
-            if sync::start_incoming_file(&conn, transfer_id, file_id, base_dir)?.is_some() {
-                Self::insert_incoming_path_pending_state(&conn, transfer_id, file_id, base_dir)?;
-            }
-
-            Result::Ok(())
-        };
+        let file_id = file_id.to_string();
+        let base_dir = base_dir.to_string();
+
+        self.writer
+            .queue("Failed to start incoming file sync state", move |conn| {
+                if sync::start_incoming_file(conn, transfer_id, &file_id, &base_dir)?.is_some() {
+                    Self::insert_incoming_path_pending_state(
+                        conn,
+                        transfer_id,
+                        &file_id,
+                        &base_dir,
+                    )?;
+                }
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to start incoming file sync state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     fn insert_incoming_path(
@@ -424,9 +702,15 @@ impl Storage {
 
         let task = || {
             conn.execute(
-                "INSERT INTO incoming_paths (transfer_id, relative_path, path_hash, bytes)
-            VALUES (?1, ?2, ?3, ?4) ON CONFLICT DO NOTHING",
-                params![tid, path.relative_path, path.file_id, path.size],
+                "INSERT INTO incoming_paths (transfer_id, relative_path, path_hash, bytes, is_archive)
+            VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT DO NOTHING",
+                params![
+                    tid,
+                    path.relative_path,
+                    path.file_id,
+                    path.size,
+                    path.is_archive
+                ],
             )?;
 
             Ok::<(), Error>(())
@@ -449,10 +733,17 @@ impl Storage {
         let task = || {
             conn.execute(
                 r#"
-            INSERT INTO outgoing_paths (transfer_id, relative_path, path_hash, bytes, uri)
-            VALUES (?1, ?2, ?3, ?4, ?5)
+            INSERT INTO outgoing_paths (transfer_id, relative_path, path_hash, bytes, uri, is_archive)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
             "#,
-                params![tid, path.relative_path, path.file_id, path.size, uri,],
+                params![
+                    tid,
+                    path.relative_path,
+                    path.file_id,
+                    path.size,
+                    uri,
+                    path.is_archive
+                ],
             )?;
 
             Ok::<(), Error>(())
@@ -463,7 +754,13 @@ impl Storage {
         }
     }
 
-    pub async fn save_checksum(&self, transfer_id: Uuid, file_id: &str, checksum: &[u8]) {
+    pub async fn save_checksum(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        algorithm: ChecksumAlgorithm,
+        checksum: &[u8],
+    ) {
         let tid = transfer_id.to_string();
 
         trace!(
@@ -471,21 +768,21 @@ impl Storage {
             "Saving checksum";
             "transfer_id" => &tid,
             "file_id" => file_id,
+            "algorithm" => %algorithm,
         );
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let file_id = file_id.to_string();
+        let checksum = checksum.to_vec();
+
+        self.writer.queue("Failed to save checksum", move |conn| {
             conn.execute(
-                "UPDATE incoming_paths SET checksum = ?3 WHERE transfer_id = ?1 AND path_hash = ?2",
-                params![tid, file_id, checksum],
+                "UPDATE incoming_paths SET checksum = ?3, checksum_algorithm = ?4 WHERE \
+                 transfer_id = ?1 AND path_hash = ?2",
+                params![tid, file_id, checksum, algorithm.to_string()],
             )?;
 
-            Ok::<(), Error>(())
-        };
-
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to save checksum"; "error" => %e);
-        }
+            Ok(())
+        });
     }
 
     pub async fn fetch_checksums(&self, transfer_id: Uuid) -> Vec<FileChecksum> {
@@ -495,23 +792,25 @@ impl Storage {
             "Fetching checksums";
             "transfer_id" => &tid);
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let task = self.read(move |conn| {
             let out = conn
                 .prepare(
-                    "SELECT path_hash as file_id, checksum FROM incoming_paths WHERE transfer_id \
-                     = ?1",
+                    "SELECT path_hash as file_id, checksum, checksum_algorithm FROM \
+                     incoming_paths WHERE transfer_id = ?1",
                 )?
                 .query_map(params![tid], |row| {
+                    let algorithm: Option<String> = row.get("checksum_algorithm")?;
+
                     Ok(FileChecksum {
                         file_id: row.get("file_id")?,
                         checksum: row.get("checksum")?,
+                        algorithm: algorithm.and_then(|algo| algo.parse().ok()),
                     })
                 })?
-                .collect::<QueryResult<Vec<_>>>()?;
+                .collect::<rusqlite::Result<Vec<_>>>()?;
 
             Ok::<Vec<_>, Error>(out)
-        };
+        });
 
         match task.await {
             Ok(out) => out,
@@ -522,6 +821,82 @@ impl Storage {
         }
     }
 
+    pub async fn fetch_cached_checksum(&self, key: &ChecksumCacheKey) -> Option<Vec<u8>> {
+        trace!(
+            self.logger,
+            "Fetching cached checksum";
+            "size" => key.size,
+            "limit" => key.limit,
+            "algorithm" => %key.algorithm,
+        );
+
+        let key = key.clone();
+
+        let task = self.read(move |conn| {
+            let checksum = conn
+                .query_row(
+                    "SELECT checksum FROM checksum_cache WHERE uri = ?1 AND size = ?2 AND \
+                     modified_at_ns = ?3 AND limit_bytes = ?4 AND algorithm = ?5",
+                    params![
+                        key.uri.as_str(),
+                        key.size as i64,
+                        key.modified_at_ns,
+                        key.limit as i64,
+                        key.algorithm.to_string(),
+                    ],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            Ok::<_, Error>(checksum)
+        });
+
+        match task.await {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                error!(self.logger, "Failed to fetch cached checksum"; "error" => %e);
+                None
+            }
+        }
+    }
+
+    pub async fn save_cached_checksum(&self, key: &ChecksumCacheKey, checksum: &[u8]) {
+        trace!(
+            self.logger,
+            "Caching checksum";
+            "size" => key.size,
+            "limit" => key.limit,
+            "algorithm" => %key.algorithm,
+        );
+
+        let key = key.clone();
+        let checksum = checksum.to_vec();
+
+        self.writer.queue("Failed to cache checksum", move |conn| {
+            // The entries of the modified file are no longer valid
+            conn.execute(
+                "DELETE FROM checksum_cache WHERE uri = ?1 AND (size != ?2 OR modified_at_ns != \
+                 ?3)",
+                params![key.uri.as_str(), key.size as i64, key.modified_at_ns],
+            )?;
+
+            conn.execute(
+                "INSERT OR REPLACE INTO checksum_cache (uri, size, modified_at_ns, limit_bytes, \
+                 algorithm, checksum) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    key.uri.as_str(),
+                    key.size as i64,
+                    key.modified_at_ns,
+                    key.limit as i64,
+                    key.algorithm.to_string(),
+                    checksum,
+                ],
+            )?;
+
+            Ok(())
+        });
+    }
+
     pub async fn insert_transfer_failed_state(&self, transfer_id: Uuid, error: u32) {
         let tid = transfer_id.to_string();
 
@@ -531,19 +906,15 @@ impl Storage {
             "transfer_id" => &tid,
             "error" => error);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                "INSERT INTO transfer_failed_states (transfer_id, status_code) VALUES (?1, ?2)",
-                params![tid, error],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+        self.writer
+            .queue("Failed to insert transfer failed state", move |conn| {
+                conn.execute(
+                    "INSERT INTO transfer_failed_states (transfer_id, status_code) VALUES (?1, ?2)",
+                    params![tid, error],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert transfer failed state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_transfer_cancel_state(&self, transfer_id: Uuid, by_peer: bool) {
@@ -555,19 +926,15 @@ impl Storage {
             "transfer_id" => &tid,
             "by_peer" => by_peer);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                "INSERT INTO transfer_cancel_states (transfer_id, by_peer) VALUES (?1, ?2)",
-                params![tid, by_peer],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+        self.writer
+            .queue("Failed to insert transfer cancel state", move |conn| {
+                conn.execute(
+                    "INSERT INTO transfer_cancel_states (transfer_id, by_peer) VALUES (?1, ?2)",
+                    params![tid, by_peer],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert transfer cancel state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     fn insert_incoming_path_pending_state(
@@ -604,23 +971,23 @@ impl Storage {
             "transfer_id" => &tid,
             "path_id" => path_id);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer.queue(
+            "Failed to insert outgoing path started state",
+            move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO outgoing_path_started_states (path_id, bytes_sent)
                 SELECT id, ?3
                 FROM outgoing_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, bytes_sent],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, bytes_sent],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert outgoing path started state"; "error" => %e);
-        }
+                Ok(())
+            },
+        );
     }
 
     pub async fn insert_incoming_path_started_state(
@@ -638,23 +1005,23 @@ impl Storage {
         "path_id" => path_id,
         );
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer.queue(
+            "Failed to insert incoming path started state",
+            move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO incoming_path_started_states (path_id, bytes_received)
                 SELECT id, ?3
                 FROM incoming_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, bytes_received],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, bytes_received],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert incoming path started state"; "error" => %e);
-        }
+                Ok(())
+            },
+        );
     }
 
     pub async fn insert_incoming_path_failed_state(
@@ -674,23 +1041,21 @@ impl Storage {
             "error" => error,
             "bytes_received" => bytes_received);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert incoming path failed state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO incoming_path_failed_states (path_id, status_code, bytes_received)
                 SELECT id, ?3, ?4
                 FROM incoming_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, error, bytes_received],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, error, bytes_received],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert incoming path failed state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_outgoing_path_failed_state(
@@ -709,23 +1074,21 @@ impl Storage {
             "error" => error,
             "bytes_sent" => bytes_sent);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert outgoing path failed state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO outgoing_path_failed_states (path_id, status_code, bytes_sent)
                 SELECT id, ?3, ?4
                 FROM outgoing_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, error, bytes_sent],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, error, bytes_sent],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert outgoing path failed state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_outgoing_path_completed_state(&self, transfer_id: Uuid, path_id: &str) {
@@ -736,23 +1099,23 @@ impl Storage {
             "transfer_id" => &tid,
             "path_id" => path_id);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer.queue(
+            "Failed to insert outgoing path completed state",
+            move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO outgoing_path_completed_states (path_id)
                 SELECT id
                 FROM outgoing_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert outgoing path completed state"; "error" => %e);
-        }
+                Ok(())
+            },
+        );
     }
 
     pub async fn insert_incoming_path_completed_state(
@@ -769,23 +1132,24 @@ impl Storage {
             "path_id" => path_id,
             "final_path" => final_path);
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+        let final_path = final_path.to_string();
+
+        self.writer.queue(
+            "Failed to insert incoming path completed state",
+            move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO incoming_path_completed_states (path_id, final_path)
                 SELECT id, ?3
                 FROM incoming_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, final_path],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, final_path],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert incoming path completed state"; "error" => %e);
-        }
+                Ok(())
+            },
+        );
     }
 
     pub async fn insert_outgoing_path_reject_state(
@@ -797,23 +1161,21 @@ impl Storage {
     ) {
         let tid = transfer_id.to_string();
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert outgoing path reject state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO outgoing_path_reject_states (path_id, by_peer, bytes_sent)
                 SELECT id, ?3, ?4
                 FROM outgoing_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, by_peer, bytes_sent],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, by_peer, bytes_sent],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert outgoing path reject state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_incoming_path_reject_state(
@@ -825,23 +1187,21 @@ impl Storage {
     ) {
         let tid = transfer_id.to_string();
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert incoming path reject state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO incoming_path_reject_states (path_id, by_peer, bytes_received)
                 SELECT id, ?3, ?4
                 FROM incoming_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, by_peer, bytes_received],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, by_peer, bytes_received],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert incoming path reject state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_outgoing_path_paused_state(
@@ -852,23 +1212,21 @@ impl Storage {
     ) {
         let tid = transfer_id.to_string();
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert outgoing path paused state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO outgoing_path_paused_states (path_id, bytes_sent)
                 SELECT id, ?3
                 FROM outgoing_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, bytes_sent],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, bytes_sent],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert outgoing path paused state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn insert_incoming_path_paused_state(
@@ -879,23 +1237,21 @@ impl Storage {
     ) {
         let tid = transfer_id.to_string();
 
-        let task = async {
-            let conn = self.conn.lock().await;
-            conn.execute(
-                r#"
+        let path_id = path_id.to_string();
+
+        self.writer
+            .queue("Failed to insert incoming path paused state", move |conn| {
+                conn.execute(
+                    r#"
                 INSERT INTO incoming_path_paused_states (path_id, bytes_received)
                 SELECT id, ?3
                 FROM incoming_paths WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
-                params![tid, path_id, bytes_received],
-            )?;
-
-            Ok::<(), Error>(())
-        };
+                    params![tid, path_id, bytes_received],
+                )?;
 
-        if let Err(e) = task.await {
-            error!(self.logger, "Failed to insert incoming path paused state"; "error" => %e);
-        }
+                Ok(())
+            });
     }
 
     pub async fn purge_transfers_until(&self, until_timestamp: i64) {
@@ -904,8 +1260,7 @@ impl Storage {
             "Purging transfers until timestamp";
             "until_timestamp" => until_timestamp);
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let task = self.writer.run(move |conn| {
             conn.execute(
                 r#"
                 UPDATE transfers SET is_deleted = TRUE
@@ -919,7 +1274,7 @@ impl Storage {
             )?;
 
             Ok::<(), Error>(())
-        };
+        });
 
         if let Err(e) = task.await {
             error!(self.logger, "Failed to purge transfers"; "error" => %e);
@@ -932,10 +1287,11 @@ impl Storage {
             "Purging transfers";
             "transfer_ids" => format!("{:?}", transfer_ids));
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let transfer_ids = transfer_ids.to_vec();
+        let logger = self.logger.clone();
 
-            for id in transfer_ids {
+        let task = self.writer.run(move |conn| {
+            for id in &transfer_ids {
                 let count = conn.execute(
                     r#"
                     UPDATE transfers SET is_deleted = TRUE
@@ -950,14 +1306,14 @@ impl Storage {
 
                 if count < 1 {
                     warn!(
-                        self.logger,
+                        logger,
                         "Failed to purge transfer: {id}. It may not be in the terminal state"
                     );
                 }
             }
 
             Ok::<(), Error>(())
-        };
+        });
 
         if let Err(e) = task.await {
             error!(self.logger, "Failed to purge transfers"; "error" => %e);
@@ -965,8 +1321,7 @@ impl Storage {
     }
 
     pub async fn outgoing_transfers_to_resume(&self) -> Vec<OutgoingTransferToRetry> {
-        let task = async {
-            let mut conn = self.conn.lock().await;
+        let task = self.read(move |conn| {
             let conn = conn.transaction()?;
 
             let rec_transfers = sync::transfers_to_resume(&conn, TransferType::Outgoing)?;
@@ -976,7 +1331,7 @@ impl Storage {
                 let files = conn
                     .prepare(
                         r#"
-                    SELECT relative_path, uri, path_hash, bytes 
+                    SELECT relative_path, uri, path_hash, bytes, is_archive
                     FROM outgoing_paths 
                     WHERE transfer_id = ?1
                     "#,
@@ -987,15 +1342,17 @@ impl Storage {
                             r.get::<_, String>("uri")?,
                             r.get("relative_path")?,
                             r.get("bytes")?,
+                            r.get("is_archive")?,
                         ))
                     })?
                     .map(|row| {
-                        let (file_id, uri, subpath, size) = row?;
+                        let (file_id, uri, subpath, size, is_archive) = row?;
                         Ok(OutgoingFileToRetry {
                             file_id,
                             uri: uri.parse()?,
                             subpath,
                             size,
+                            is_archive,
                         })
                     })
                     .collect::<Result<_>>()?;
@@ -1012,7 +1369,7 @@ impl Storage {
             conn.commit()?;
 
             Ok::<Vec<_>, Error>(out)
-        };
+        });
 
         match task.await {
             Ok(transfers) => transfers,
@@ -1024,8 +1381,7 @@ impl Storage {
     }
 
     pub async fn incoming_transfers_to_resume(&self) -> Vec<IncomingTransferToRetry> {
-        let task = async {
-            let mut conn = self.conn.lock().await;
+        let task = self.read(move |conn| {
             let conn = conn.transaction()?;
 
             let rec_transfers = sync::transfers_to_resume(&conn, TransferType::Incoming)?;
@@ -1035,7 +1391,7 @@ impl Storage {
                 let files = conn
                     .prepare(
                         r#"
-                    SELECT relative_path, path_hash, bytes 
+                    SELECT relative_path, path_hash, bytes, is_archive
                     FROM incoming_paths 
                     WHERE transfer_id = ?1
                     "#,
@@ -1045,6 +1401,7 @@ impl Storage {
                             file_id: r.get("path_hash")?,
                             subpath: r.get("relative_path")?,
                             size: r.get("bytes")?,
+                            is_archive: r.get("is_archive")?,
                         })
                     })?
                     .collect::<QueryResult<_>>()?;
@@ -1060,7 +1417,7 @@ impl Storage {
 
             conn.commit()?;
             Ok::<Vec<_>, Error>(out)
-        };
+        });
 
         match task.await {
             Ok(transfers) => transfers,
@@ -1072,10 +1429,7 @@ impl Storage {
     }
 
     pub async fn incoming_files_to_resume(&self, transfer_id: Uuid) -> Vec<sync::FileInFlight> {
-        let task = async {
-            let conn = self.conn.lock().await;
-            sync::incoming_files_in_flight(&conn, transfer_id)
-        };
+        let task = self.read(move |conn| sync::incoming_files_in_flight(conn, transfer_id));
 
         match task.await {
             Ok(files) => files,
@@ -1087,9 +1441,7 @@ impl Storage {
     }
 
     pub async fn finished_incoming_files(&self, transfer_id: Uuid) -> Vec<FinishedIncomingFile> {
-        let task = async {
-            let conn = self.conn.lock().await;
-
+        let task = self.read(move |conn| {
             let paths = conn
                 .prepare(
                     r#"
@@ -1108,7 +1460,7 @@ impl Storage {
                 .collect::<QueryResult<_>>()?;
 
             Ok::<Vec<_>, Error>(paths)
-        };
+        });
 
         match task.await {
             Ok(paths) => paths,
@@ -1120,7 +1472,255 @@ impl Storage {
     }
 
     pub async fn transfers_since(&self, since_timestamp: i64) -> Vec<Transfer> {
-        // Collect transfers since a given timestamp.
+        trace!(
+        self.logger,
+        "Fetching transfers since timestamp";
+        "since_timestamp" => since_timestamp);
+
+        let logger = self.logger.clone();
+
+        let task = self.read(move |conn| {
+            let tx = conn.transaction()?;
+            let transfers = Self::load_transfers(
+                &logger,
+                &tx,
+                "select id from transfers where not is_deleted and created_at >= \
+                 datetime(?1, 'unixepoch')",
+                &[Value::Integer(since_timestamp)],
+                true,
+            )?;
+
+            Ok::<Vec<_>, Error>(transfers.into_iter().map(|(_, t)| t).collect())
+        });
+
+        match task.await {
+            Ok(transfers) => transfers,
+            Err(e) => {
+                error!(self.logger, "Failed to get transfers since timestamp"; "error" => %e);
+                vec![]
+            }
+        }
+    }
+
+    /// Fetches a single page of the transfers matching the query, see
+    /// [`TransferQuery`]. Unlike [`Self::transfers_since`], the filtering and
+    /// pagination are done by the database so only the requested transfers
+    /// are loaded
+    pub async fn query_transfers(&self, query: &TransferQuery) -> TransferPage {
+        trace!(self.logger, "Querying transfers"; "query" => ?query);
+
+        let task = async {
+            let mut conditions = vec!["not t.is_deleted".to_string()];
+            let mut params = Vec::new();
+
+            if let Some(direction) = query.direction {
+                params.push(Value::Integer(direction as _));
+                conditions.push(format!("t.is_outgoing = ?{}", params.len()));
+            }
+
+            if let Some(peer) = &query.peer {
+                params.push(Value::Text(peer.clone()));
+                conditions.push(format!("t.peer = ?{}", params.len()));
+            }
+
+            match query.state {
+                Some(TransferStateFilter::Terminal) => {
+                    conditions.push(TERMINAL_TRANSFER_CONDITION.to_string())
+                }
+                Some(TransferStateFilter::NonTerminal) => {
+                    conditions.push(format!("not {TERMINAL_TRANSFER_CONDITION}"))
+                }
+                None => (),
+            }
+
+            if let Some(filename) = &query.filename {
+                params.push(Value::Text(like_pattern(filename)));
+                let idx = params.len();
+                conditions.push(format!(
+                    r#"(
+                    exists (select 1 from outgoing_paths p where p.transfer_id = t.id
+                        and not p.is_deleted and p.relative_path like ?{idx} escape '\')
+                    or exists (select 1 from incoming_paths p where p.transfer_id = t.id
+                        and not p.is_deleted and p.relative_path like ?{idx} escape '\')
+                    )"#
+                ));
+            }
+
+            let (cmp, order) = match query.order {
+                SortOrder::Ascending => (">", "asc"),
+                SortOrder::Descending => ("<", "desc"),
+            };
+
+            if let Some(cursor) = query.cursor {
+                params.push(Value::Integer(cursor.0));
+                conditions.push(format!("t.rowid {cmp} ?{}", params.len()));
+            }
+
+            // One extra row tells whether there is a next page. A negative limit
+            // means no limit in SQLite
+            params.push(Value::Integer(
+                query.limit.map_or(-1, |limit| i64::from(limit) + 1),
+            ));
+
+            let selection = format!(
+                "select t.id from transfers t where {} order by t.rowid {order} limit ?{}",
+                conditions.join(" and "),
+                params.len()
+            );
+
+            let logger = self.logger.clone();
+            let path_states = !query.summary;
+
+            let mut transfers = self
+                .read(move |conn| {
+                    let tx = conn.transaction()?;
+                    Self::load_transfers(&logger, &tx, &selection, &params, path_states)
+                })
+                .await?;
+
+            if query.order == SortOrder::Descending {
+                transfers.reverse();
+            }
+
+            let next_cursor = match query.limit {
+                Some(limit) if transfers.len() > limit as usize => {
+                    transfers.truncate(limit as usize);
+                    transfers.last().map(|(rowid, _)| TransferCursor(*rowid))
+                }
+                _ => None,
+            };
+
+            Ok::<_, Error>(TransferPage {
+                transfers: transfers.into_iter().map(|(_, t)| t).collect(),
+                next_cursor,
+            })
+        };
+
+        match task.await {
+            Ok(page) => page,
+            Err(e) => {
+                error!(self.logger, "Failed to query transfers"; "error" => %e);
+                TransferPage::default()
+            }
+        }
+    }
+
+    /// Sums up the transfer history per peer, see [`PeerStats`]. The peers
+    /// seen most recently come first, the ties are ordered by the peer
+    pub async fn peer_stats(&self) -> Vec<PeerStats> {
+        trace!(self.logger, "Fetching peer statistics");
+
+        let task = self.read(move |conn| {
+            let mut peers = HashMap::new();
+
+            conn.prepare(
+                r#"
+                SELECT t.peer, count(*), max(max(
+                    t.created_at,
+                    coalesce((SELECT max(s.created_at) FROM transfer_cancel_states s
+                        WHERE s.transfer_id = t.id), t.created_at),
+                    coalesce((SELECT max(s.created_at) FROM transfer_failed_states s
+                        WHERE s.transfer_id = t.id), t.created_at)
+                ))
+                FROM transfers t
+                WHERE NOT t.is_deleted
+                GROUP BY t.peer
+                "#,
+            )?
+            .query_map([], |row| {
+                let peer: String = row.get(0)?;
+                let stats = PeerStats {
+                    peer: peer.clone(),
+                    transfers: row.get(1)?,
+                    bytes_sent: 0,
+                    bytes_received: 0,
+                    files_sent: 0,
+                    files_received: 0,
+                    files_failed: 0,
+                    last_seen: row.get(2)?,
+                    throughput: None,
+                };
+
+                Ok((peer, stats))
+            })?
+            .try_for_each(|res| {
+                let (peer, stats) = res?;
+                // Completed bytes and the time spent on them
+                peers.insert(peer, (stats, 0i64, 0f64));
+                QueryResult::Ok(())
+            })?;
+
+            for (direction, bytes_column) in
+                [("outgoing", "bytes_sent"), ("incoming", "bytes_received")]
+            {
+                let mut stmt = conn.prepare(&path_totals_query(direction, bytes_column))?;
+                let mut rows = stmt.query([])?;
+
+                while let Some(row) = rows.next()? {
+                    let peer: String = row.get(0)?;
+                    let Some((stats, completed_bytes, duration)) = peers.get_mut(&peer) else {
+                        continue;
+                    };
+
+                    let bytes: i64 = row.get(1)?;
+                    let files: u32 = row.get(2)?;
+                    if direction == "outgoing" {
+                        stats.bytes_sent = bytes;
+                        stats.files_sent = files;
+                    } else {
+                        stats.bytes_received = bytes;
+                        stats.files_received = files;
+                    }
+
+                    stats.files_failed += row.get::<_, u32>(3)?;
+                    *completed_bytes += row.get::<_, i64>(4)?;
+                    *duration += row.get::<_, f64>(5)?;
+
+                    if let Some(last_at) = row.get::<_, Option<chrono::NaiveDateTime>>(6)? {
+                        stats.last_seen = stats.last_seen.max(last_at);
+                    }
+                }
+            }
+
+            let mut peers: Vec<_> = peers
+                .into_values()
+                .map(|(mut stats, completed_bytes, duration)| {
+                    if duration > 0.0 {
+                        stats.throughput = Some(completed_bytes as f64 / duration);
+                    }
+                    stats
+                })
+                .collect();
+            peers.sort_by(|a, b| {
+                b.last_seen
+                    .cmp(&a.last_seen)
+                    .then_with(|| a.peer.cmp(&b.peer))
+            });
+
+            Ok::<_, Error>(peers)
+        });
+
+        match task.await {
+            Ok(peers) => peers,
+            Err(e) => {
+                error!(self.logger, "Failed to fetch peer statistics"; "error" => %e);
+                vec![]
+            }
+        }
+    }
+
+    // Loads the transfers whose ids are yielded by the `selection` query
+    // together with their paths and states. The result is sorted by insertion
+    // order and carries the transfer rowids. With `path_states` unset only the
+    // latest state of each path is loaded, enough to compute the transferred
+    // bytes.
+    fn load_transfers(
+        logger: &Logger,
+        tx: &Transaction,
+        selection: &str,
+        params: &[Value],
+        path_states: bool,
+    ) -> Result<Vec<(i64, Transfer)>> {
         // This performs 3 queries, fetching by insertion order:
         // 1. transfers with their states.
         // 2. outgoing paths with their states
@@ -1132,103 +1732,102 @@ impl Storage {
         // For transfers, their rowid is selected as well and used to sort the
         // transfers. Because its not part of `Transfer` structure, a tuple is
         // used as hashmap value.
-        trace!(
-        self.logger,
-        "Fetching transfers since timestamp";
-        "since_timestamp" => since_timestamp);
-
-        let task = async {
-            let mut conn = self.conn.lock().await;
-            let mut transfers_map: HashMap<Uuid, (u64, Transfer)> = HashMap::new();
-            let tx = conn.transaction()?;
-            // transfer_cancel_states.by_peer shares a type with
-            // transfer_failed_states.status_code and transfer_cancel_states.
-            // created_at with transfer_failed_states.created_at therefore the
-            // same column can be used for them.
-            let _ = tx
-                .prepare(
-                    r#"
-                WITH ts AS  (
+        let mut transfers_map: HashMap<Uuid, (i64, Transfer)> = HashMap::new();
+        let _ = tx
+            .prepare(&format!(
+                r#"
+                WITH sel AS ({selection}),
+                ts AS  (
                     select 1, id, transfer_id, by_peer, created_at from transfer_cancel_states
                     union all
                     select 2, id, transfer_id, status_code, created_at from transfer_failed_states
                 )
-                select t.*, ts.*, t.rowid from transfers t
+                select t.id, t.peer, t.is_outgoing, t.created_at, t.is_deleted, ts.*, t.rowid
+                    from transfers t
                     left join ts on ts.transfer_id = t.id
-                    where not t.is_deleted and t.created_at >= datetime(?1, 'unixepoch')
-                "#,
-                )?
-                .query_map(params![since_timestamp], |row| {
-                    let id = Uuid::parse_str(row.get::<_, String>(0)?.as_str())
-                        .map_err(|_| rusqlite::Error::InvalidQuery)?;
-                    let rowid: u64 = row.get(10)?;
-                    let transfer: &mut Transfer = &mut match transfers_map.entry(id) {
-                        Occupied(e) => e.into_mut(),
-                        Vacant(k) => {
-                            let transfer_type = match row.get::<_, u32>(2)? {
-                                0 => DbTransferType::Incoming(vec![]),
-                                1 => DbTransferType::Outgoing(vec![]),
-                                _ => unreachable!(),
-                            };
-                            let t = Transfer {
-                                id,
-                                peer_id: row.get(1)?,
-                                transfer_type,
-                                created_at: row.get(3)?,
-                                states: vec![],
-                            };
-                            k.insert((rowid, t))
-                        }
+                    where t.id in (select id from sel)
+                "#
+            ))?
+            .query_map(params_from_iter(params), |row| {
+                let id = Uuid::parse_str(row.get::<_, String>(0)?.as_str())
+                    .map_err(|_| rusqlite::Error::InvalidQuery)?;
+                let rowid: i64 = row.get(10)?;
+                let transfer: &mut Transfer = &mut match transfers_map.entry(id) {
+                    Occupied(e) => e.into_mut(),
+                    Vacant(k) => {
+                        let transfer_type = match row.get::<_, u32>(2)? {
+                            0 => DbTransferType::Incoming(vec![]),
+                            1 => DbTransferType::Outgoing(vec![]),
+                            _ => unreachable!(),
+                        };
+                        let t = Transfer {
+                            id,
+                            peer_id: row.get(1)?,
+                            transfer_type,
+                            created_at: row.get(3)?,
+                            states: vec![],
+                        };
+                        k.insert((rowid, t))
                     }
-                    .1;
-                    let status_type: Option<i64> = row.get(5)?;
-                    match status_type {
-                        Some(1) => transfer.states.push(TransferStateEvent {
-                            transfer_id: transfer.id,
-                            created_at: row.get(9)?,
-                            data: types::TransferStateEventData::Cancel {
-                                by_peer: row.get(8)?,
-                            },
-                        }),
-                        Some(2) => transfer.states.push(TransferStateEvent {
-                            transfer_id: transfer.id,
-                            created_at: row.get(9)?,
-                            data: types::TransferStateEventData::Failed {
-                                status_code: row.get(8)?,
-                            },
-                        }),
-                        Some(other) => warn!(
-                                        self.logger,
-                                        "Unexpected union member identifier for transfer state";
-                                        "identifier" => other),
-                        None => {
-                            // This was a transfer without any states.
-                        }
+                }
+                .1;
+                let status_type: Option<i64> = row.get(5)?;
+                match status_type {
+                    Some(1) => transfer.states.push(TransferStateEvent {
+                        transfer_id: transfer.id,
+                        created_at: row.get(9)?,
+                        data: types::TransferStateEventData::Cancel {
+                            by_peer: row.get(8)?,
+                        },
+                    }),
+                    Some(2) => transfer.states.push(TransferStateEvent {
+                        transfer_id: transfer.id,
+                        created_at: row.get(9)?,
+                        data: types::TransferStateEventData::Failed {
+                            status_code: row.get(8)?,
+                        },
+                    }),
+                    Some(other) => warn!(
+                        logger,
+                        "Unexpected union member identifier for transfer state";
+                        "identifier" => other
+                    ),
+                    None => {
+                        // This was a transfer without any states.
                     }
-                    Ok(())
-                })?
-                .count();
-
-            let mut outgoing_paths: HashMap<i64, OutgoingPath> = HashMap::new();
-            // Here is the same situation as before - because the columns after created_at
-            // are all integers, they can be shared.
-            let _ = tx.prepare(r#"
-            WITH ops AS (
-                select 1, path_id, created_at, bytes_sent, null from outgoing_path_started_states
+                }
+                Ok(())
+            })?
+            .count();
+
+        let outgoing_states = if path_states {
+            "select kind, path_id, created_at, a, b from all_ops".to_string()
+        } else {
+            latest_path_states("all_ops", "kind, path_id, created_at, a, b", "true")
+        };
+
+        let mut outgoing_paths: HashMap<i64, OutgoingPath> = HashMap::new();
+        // Here is the same situation as before - because the columns after created_at
+        // are all integers, they can be shared.
+        let _ = tx.prepare(&format!(r#"
+            WITH sel AS ({selection}),
+            all_ops(kind, path_id, created_at, a, b, state_rowid) AS (
+                select 1, path_id, created_at, bytes_sent, null, rowid from outgoing_path_started_states
                 union all
-                select 2, path_id, created_at, status_code, bytes_sent from outgoing_path_failed_states
+                select 2, path_id, created_at, status_code, bytes_sent, rowid from outgoing_path_failed_states
                 union all
-                select 3, path_id, created_at, null, null from outgoing_path_completed_states
+                select 3, path_id, created_at, null, null, rowid from outgoing_path_completed_states
                 union all
-                select 4, path_id, created_at, by_peer, bytes_sent from outgoing_path_reject_states
+                select 4, path_id, created_at, by_peer, bytes_sent, rowid from outgoing_path_reject_states
                 union all
-                select 5, path_id, created_at, bytes_sent, null from outgoing_path_paused_states
-            )
-            SELECT op.*, ops.*, op.rowid from outgoing_paths op
+                select 5, path_id, created_at, bytes_sent, null, rowid from outgoing_path_paused_states
+            ),
+            ops AS ({outgoing_states})
+            SELECT op.id, op.transfer_id, op.relative_path, op.uri, op.path_hash, op.bytes,
+                op.created_at, op.is_deleted, ops.*, op.rowid, op.is_archive from outgoing_paths op
                 left join ops on ops.path_id = op.id
-                left join transfers t on t.id = op.transfer_id and not t.is_deleted and t.created_at >= datetime(?1, 'unixepoch')
-                where not op.is_deleted
-            "#)?.query_map(params![since_timestamp], |row| {
+                where not op.is_deleted and op.transfer_id in (select id from sel)
+            "#))?.query_map(params_from_iter(params), |row| {
                 let path_id: i64 = row.get(0)?;
                 let path = match outgoing_paths.entry(path_id) {
                     Occupied(p) => p.into_mut(),
@@ -1244,6 +1843,7 @@ impl Storage {
                             bytes: row.get(5)?,
                             bytes_sent: 0,
                             created_at: row.get(6)?,
+                            is_archive: row.get(14)?,
                             states: vec![],
                         };
                         let uri_str: String = row.get(3)?;
@@ -1263,7 +1863,7 @@ impl Storage {
                             }
                             unknown => {
                                 warn!(
-                                        self.logger,
+                                        logger,
                                         "Unexpected URI scheme when decoding transfer outgoing path's base_path";
                                         "scheme" => unknown,
                                     "uri" => uri.to_string());
@@ -1314,7 +1914,7 @@ impl Storage {
                             },
                         }),
                         other => warn!(
-                                        self.logger,
+                                        logger,
                                         "Unexpected union member identifier for outgoing path status";
                                         "identifier" => other)
                     }
@@ -1323,46 +1923,59 @@ impl Storage {
                 Ok(())
             })?.count();
 
-            for (_, mut path) in outgoing_paths {
-                path.states.sort_by(|a, b| a.created_at.cmp(&b.created_at));
-
-                path.bytes_sent = path.states.last().map_or(0, |state| match state.data {
-                    OutgoingPathStateEventData::Started { bytes_sent } => bytes_sent,
-                    OutgoingPathStateEventData::Failed { bytes_sent, .. } => bytes_sent,
-                    OutgoingPathStateEventData::Completed => path.bytes,
-                    OutgoingPathStateEventData::Rejected { bytes_sent, .. } => bytes_sent,
-                    OutgoingPathStateEventData::Paused { bytes_sent } => bytes_sent,
-                });
-                if let Some((_, t)) = transfers_map.get_mut(&path.transfer_id) {
-                    if let DbTransferType::Outgoing(pp) = &mut t.transfer_type {
-                        pp.push(path)
-                    }
+        for (_, mut path) in outgoing_paths {
+            path.states.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+            path.bytes_sent = path.states.last().map_or(0, |state| match state.data {
+                OutgoingPathStateEventData::Started { bytes_sent } => bytes_sent,
+                OutgoingPathStateEventData::Failed { bytes_sent, .. } => bytes_sent,
+                OutgoingPathStateEventData::Completed => path.bytes,
+                OutgoingPathStateEventData::Rejected { bytes_sent, .. } => bytes_sent,
+                OutgoingPathStateEventData::Paused { bytes_sent } => bytes_sent,
+            });
+            if !path_states {
+                path.states = vec![];
+            }
+            if let Some((_, t)) = transfers_map.get_mut(&path.transfer_id) {
+                if let DbTransferType::Outgoing(pp) = &mut t.transfer_type {
+                    pp.push(path)
                 }
             }
+        }
+
+        // The pending state carries no byte count
+        let incoming_states = if path_states {
+            "select kind, path_id, created_at, a, b, c from all_ips".to_string()
+        } else {
+            latest_path_states("all_ips", "kind, path_id, created_at, a, b, c", "kind != 1")
+        };
 
-            let mut incoming_paths: HashMap<i64, IncomingPath> = HashMap::new();
-            // And this is more interesting - base_ir and final_patch are text type. For
-            // these fields a separate column will be used.
-            let _ = tx.prepare(r#"
-            WITH ips AS (
-                select 1, path_id, created_at, null, null, base_dir from incoming_path_pending_states
+        let mut incoming_paths: HashMap<i64, IncomingPath> = HashMap::new();
+        // And this is more interesting - base_ir and final_patch are text type. For
+        // these fields a separate column will be used.
+        let _ = tx.prepare(&format!(r#"
+            WITH sel AS ({selection}),
+            all_ips(kind, path_id, created_at, a, b, c, state_rowid) AS (
+                select 1, path_id, created_at, null, null, base_dir, rowid from incoming_path_pending_states
                 union all
-                select 2, path_id, created_at, bytes_received, null, null from incoming_path_started_states
+                select 2, path_id, created_at, bytes_received, null, null, rowid from incoming_path_started_states
                 union all
-                select 3, path_id, created_at, status_code, bytes_received, null from incoming_path_failed_states
+                select 3, path_id, created_at, status_code, bytes_received, null, rowid from incoming_path_failed_states
                 union all
-                select 4, path_id, created_at, null, null, final_path from incoming_path_completed_states
+                select 4, path_id, created_at, null, null, final_path, rowid from incoming_path_completed_states
                 union all
-                select 5, path_id, created_at, by_peer, bytes_received, null from incoming_path_reject_states
+                select 5, path_id, created_at, by_peer, bytes_received, null, rowid from incoming_path_reject_states
                 union all
-                select 6, path_id, created_at, bytes_received, null, null from incoming_path_paused_states
-            )
-            SELECT ip.*, ips.* from incoming_paths ip
+                select 6, path_id, created_at, bytes_received, null, null, rowid from incoming_path_paused_states
+            ),
+            ips AS ({incoming_states})
+            SELECT ip.id, ip.transfer_id, ip.relative_path, ip.path_hash, ip.bytes, ip.created_at,
+                ip.checksum, ip.is_deleted, ips.*, ip.checksum_algorithm, ip.is_archive
+                from incoming_paths ip
                 left join ips on ips.path_id = ip.id
-                left join transfers t on t.id = ip.transfer_id and not t.is_deleted and t.created_at >= datetime(?1, 'unixepoch')
-                where not ip.is_deleted
+                where not ip.is_deleted and ip.transfer_id in (select id from sel)
                 order by ip.rowid
-            "#)?.query_map(params![since_timestamp], |row| {
+            "#))?.query_map(params_from_iter(params), |row| {
                 let path_id: i64 = row.get(0)?;
                 let path = match incoming_paths.entry(path_id) {
                     Occupied(p) => p.into_mut(),
@@ -1376,6 +1989,11 @@ impl Storage {
                             bytes: row.get(4)?,
                             bytes_received: 0,
                             created_at: row.get(5)?,
+                            checksum: row.get(6)?,
+                            checksum_algorithm: row
+                                .get::<_, Option<String>>(14)?
+                                .and_then(|algo| algo.parse().ok()),
+                            is_archive: row.get(15)?,
                             states: vec![],
                         };
                         e.insert(res)
@@ -1437,61 +2055,138 @@ impl Storage {
                 Ok(())
             })?.count();
 
-            for (_, mut path) in incoming_paths {
-                path.states.sort_by(|a, b| a.created_at.cmp(&b.created_at));
-
-                path.bytes_received = path
-                    .states
-                    .iter()
-                    .rev()
-                    .find_map(|state| match state.data {
-                        IncomingPathStateEventData::Pending { .. } => None,
-                        IncomingPathStateEventData::Started { bytes_received, .. } => {
-                            Some(bytes_received)
-                        }
-                        IncomingPathStateEventData::Failed { bytes_received, .. } => {
-                            Some(bytes_received)
-                        }
-                        IncomingPathStateEventData::Completed { .. } => Some(path.bytes),
-                        IncomingPathStateEventData::Rejected { bytes_received, .. } => {
-                            Some(bytes_received)
-                        }
-                        IncomingPathStateEventData::Paused { bytes_received } => {
-                            Some(bytes_received)
-                        }
-                    })
-                    .unwrap_or(0);
-
-                if let Some((_, t)) = transfers_map.get_mut(&path.transfer_id) {
-                    if let DbTransferType::Incoming(ip) = &mut t.transfer_type {
-                        ip.push(path)
+        for (_, mut path) in incoming_paths {
+            path.states.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+            path.bytes_received = path
+                .states
+                .iter()
+                .rev()
+                .find_map(|state| match state.data {
+                    IncomingPathStateEventData::Pending { .. } => None,
+                    IncomingPathStateEventData::Started { bytes_received, .. } => {
+                        Some(bytes_received)
                     }
-                }
+                    IncomingPathStateEventData::Failed { bytes_received, .. } => {
+                        Some(bytes_received)
+                    }
+                    IncomingPathStateEventData::Completed { .. } => Some(path.bytes),
+                    IncomingPathStateEventData::Rejected { bytes_received, .. } => {
+                        Some(bytes_received)
+                    }
+                    IncomingPathStateEventData::Paused { bytes_received } => Some(bytes_received),
+                })
+                .unwrap_or(0);
+            if !path_states {
+                path.states = vec![];
             }
-            drop(tx);
-            drop(conn);
-            let mut transfers: Vec<(u64, Transfer)> = transfers_map.into_values().collect();
-            transfers.sort_by_key(|rt| rt.0);
-            let mut transfers: Vec<Transfer> = transfers.into_iter().map(|rt| rt.1).collect();
-            for transfer in &mut transfers {
-                transfer
-                    .states
-                    .sort_by(|a, b| a.created_at.cmp(&b.created_at));
-                match transfer.transfer_type {
-                    DbTransferType::Incoming(ref mut p) => p.sort_by_key(|ip| ip.id),
-                    DbTransferType::Outgoing(ref mut p) => p.sort_by_key(|op| op.id),
-                };
+
+            if let Some((_, t)) = transfers_map.get_mut(&path.transfer_id) {
+                if let DbTransferType::Incoming(ip) = &mut t.transfer_type {
+                    ip.push(path)
+                }
             }
-            Ok::<Vec<_>, Error>(transfers)
-        };
+        }
 
-        match task.await {
-            Ok(transfers) => transfers,
-            Err(e) => {
-                error!(self.logger, "Failed to get transfers since timestamp"; "error" => %e);
-                vec![]
+        let mut transfers: Vec<(i64, Transfer)> = transfers_map.into_values().collect();
+        transfers.sort_by_key(|rt| rt.0);
+        for (_, transfer) in &mut transfers {
+            transfer
+                .states
+                .sort_by(|a, b| a.created_at.cmp(&b.created_at));
+            match transfer.transfer_type {
+                DbTransferType::Incoming(ref mut p) => p.sort_by_key(|ip| ip.id),
+                DbTransferType::Outgoing(ref mut p) => p.sort_by_key(|op| op.id),
+            };
+        }
+
+        Ok(transfers)
+    }
+
+    /// Writes the whole transfer history, with all the paths and their states,
+    /// into a JSON file at the given path. Returns the number of exported
+    /// transfers
+    pub async fn export_history(&self, path: &Path) -> Result<usize> {
+        trace!(self.logger, "Exporting history"; "path" => ?path);
+
+        let logger = self.logger.clone();
+        let path = path.to_path_buf();
+
+        self.read(move |conn| {
+            let tx = conn.transaction()?;
+            let transfers = Self::load_transfers(
+                &logger,
+                &tx,
+                "select id from transfers where not is_deleted",
+                &[],
+                true,
+            )?;
+
+            let history = history::History {
+                version: HISTORY_VERSION,
+                transfers: transfers.into_iter().map(|(_, t)| t).collect(),
+            };
+
+            let mut writer = BufWriter::new(create_private_file(&path)?);
+            serde_json::to_writer(&mut writer, &history)
+                .map_err(|e| Error::InternalError(format!("Failed to serialize history: {e}")))?;
+            writer.flush()?;
+
+            debug!(logger, "Exported {} transfers", history.transfers.len());
+            Ok(history.transfers.len())
+        })
+        .await
+    }
+
+    /// Reads the history written by [`Self::export_history`]. The transfers
+    /// already present in the database are skipped. Either all the new
+    /// transfers are imported or none. Returns the number of imported
+    /// transfers
+    pub async fn import_history(&self, path: &Path) -> Result<usize> {
+        trace!(self.logger, "Importing history"; "path" => ?path);
+
+        let logger = self.logger.clone();
+        let path = path.to_path_buf();
+
+        self.writer
+            .run(move |conn| Self::import_history_file(&logger, conn, &path))
+            .await
+    }
+
+    fn import_history_file(logger: &Logger, conn: &mut Connection, path: &Path) -> Result<usize> {
+        let data = fs::read_to_string(path)?;
+
+        let header: history::HistoryHeader = serde_json::from_str(&data)
+            .map_err(|e| Error::InternalError(format!("Invalid history header: {e}")))?;
+        if header.version > HISTORY_VERSION {
+            return Err(Error::InternalError(format!(
+                "Unsupported history version {}, the latest known is {HISTORY_VERSION}",
+                header.version
+            )));
+        }
+
+        let history: history::History = serde_json::from_str(&data)
+            .map_err(|e| Error::InternalError(format!("Invalid history: {e}")))?;
+
+        let tx = conn.transaction()?;
+
+        let mut imported = 0;
+        for transfer in &history.transfers {
+            if history::insert_transfer(&tx, transfer)? {
+                imported += 1;
+            } else {
+                debug!(logger, "Skipping already present transfer {}", transfer.id);
             }
         }
+
+        tx.commit()?;
+
+        debug!(
+            logger,
+            "Imported {imported} out of {} transfers",
+            history.transfers.len()
+        );
+        Ok(imported)
     }
 
     pub async fn remove_transfer_file(&self, transfer_id: Uuid, file_id: &str) -> Option<()> {
@@ -1504,9 +2199,10 @@ impl Storage {
             "file_id" => file_id,
         );
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let file_id = file_id.to_string();
+        let logger = self.logger.clone();
 
+        let task = self.writer.run(move |conn| {
             let mut count = 0;
             count += conn
                 .prepare(
@@ -1544,13 +2240,13 @@ impl Storage {
                 1 => Ok(Some(())),
                 _ => {
                     warn!(
-                        self.logger,
+                        logger,
                         "Deleted a file from both outgoing and incoming paths"
                     );
                     Ok(Some(()))
                 }
             }
-        };
+        });
 
         match task.await {
             Ok(res) => res,
@@ -1570,9 +2266,7 @@ impl Storage {
             "transfer_id" => &tid
         );
 
-        let task = async {
-            let conn = self.conn.lock().await;
-
+        let task = self.read(move |conn| {
             let out = conn
                 .prepare(
                     r#"
@@ -1591,7 +2285,7 @@ impl Storage {
                 .collect::<QueryResult<_>>()?;
 
             Ok::<Vec<_>, Error>(out)
-        };
+        });
 
         match task.await {
             Ok(res) => res,
@@ -1611,9 +2305,9 @@ impl Storage {
             "transfer_id" => &tid
         );
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let owned_file_id = file_id.to_string();
 
+        let task = self.read(move |conn| {
             let out = conn
                 .prepare(
                     r#"
@@ -1623,11 +2317,11 @@ impl Storage {
                 WHERE transfer_id = ?1 AND path_hash = ?2
                 "#,
                 )?
-                .query_map(params![tid, file_id], |row| row.get("base_dir"))?
+                .query_map(params![tid, owned_file_id], |row| row.get("base_dir"))?
                 .collect::<QueryResult<_>>()?;
 
             Ok::<Vec<_>, Error>(out)
-        };
+        });
 
         match task.await {
             Ok(res) => res,
@@ -1641,9 +2335,9 @@ impl Storage {
     pub async fn cleanup_garbage_transfers(&self) -> usize {
         trace!(self.logger, "Removing garbage transfers");
 
-        let task = async {
-            let conn = self.conn.lock().await;
+        let logger = self.logger.clone();
 
+        let task = self.writer.run(move |conn| {
             let count = conn.execute(
                 r#"
                 DELETE FROM transfers WHERE id IN (
@@ -1656,9 +2350,13 @@ impl Storage {
                 params![],
             )?;
 
-            debug!(self.logger, "Removed {count} garbage transfers");
+            debug!(logger, "Removed {count} garbage transfers");
+
+            let cached = conn.execute(CHECKSUM_CACHE_CLEANUP, params![])?;
+            debug!(logger, "Removed {cached} stale cached checksums");
+
             Result::Ok(count)
-        };
+        });
 
         match task.await {
             Err(err) => {
@@ -1668,34 +2366,123 @@ impl Storage {
             Ok(count) => count,
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Removes the transfers older than `max_age` and the ones exceeding
+    /// `max_transfers`, newest first. Transfers that can still be resumed are
+    /// always kept, though they count towards the limit. With `terminal_only`
+    /// only the finished transfers are removed. Returns the number of removed
+    /// transfers
+    pub async fn apply_retention(
+        &self,
+        max_age: Option<Duration>,
+        max_transfers: Option<usize>,
+        terminal_only: bool,
+    ) -> usize {
+        trace!(
+            self.logger,
+            "Applying retention policy";
+            "max_age" => ?max_age,
+            "max_transfers" => ?max_transfers,
+            "terminal_only" => terminal_only,
+        );
 
-    #[tokio::test]
-    async fn test_insert_transfer() {
-        let logger = slog::Logger::root(slog::Discard, slog::o!());
-        let storage = Storage::new(logger, ":memory:").unwrap();
+        let mut conditions = vec![
+            "not exists (select 1 from sync_transfer st where st.transfer_id = t.id)".to_string(),
+        ];
+        if terminal_only {
+            conditions.push(TERMINAL_TRANSFER_CONDITION.to_string());
+        }
+        let conditions = conditions.join(" and ");
 
-        let transfer_id_1: Uuid = "23e488a4-0521-11ee-be56-0242ac120002".parse().unwrap();
-        let transfer_id_2: Uuid = "23e48d7c-0521-11ee-be56-0242ac120002".parse().unwrap();
+        let logger = self.logger.clone();
 
-        {
-            let transfer = TransferInfo {
-                id: transfer_id_1,
-                peer: "1.2.3.4".to_string(),
-                files: TransferFiles::Incoming(vec![
-                    TransferIncomingPath {
-                        file_id: "id1".to_string(),
-                        relative_path: "1".to_string(),
-                        size: 1024,
-                    },
-                    TransferIncomingPath {
-                        file_id: "id2".to_string(),
-                        relative_path: "2".to_string(),
+        let task = self.writer.run(move |conn| {
+            let tx = conn.transaction()?;
+
+            let mut count = 0;
+
+            if let Some(max_age) = max_age {
+                let until = SystemTime::now()
+                    .checked_sub(max_age)
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default()
+                    .as_secs() as i64;
+
+                count += tx.execute(
+                    &format!(
+                        r#"
+                        DELETE FROM transfers WHERE id IN (
+                            SELECT t.id FROM transfers t
+                            WHERE t.created_at < datetime(?1, 'unixepoch') and {conditions}
+                        )
+                        "#
+                    ),
+                    params![until],
+                )?;
+            }
+
+            if let Some(max_transfers) = max_transfers {
+                count += tx.execute(
+                    &format!(
+                        r#"
+                        DELETE FROM transfers WHERE id IN (
+                            SELECT t.id FROM transfers t
+                            WHERE t.id NOT IN (
+                                SELECT id FROM transfers WHERE NOT is_deleted
+                                ORDER BY created_at DESC, rowid DESC LIMIT ?1
+                            ) and {conditions}
+                        )
+                        "#
+                    ),
+                    params![max_transfers as i64],
+                )?;
+            }
+
+            tx.execute(CHECKSUM_CACHE_CLEANUP, params![])?;
+            tx.commit()?;
+
+            debug!(logger, "Retention policy removed {count} transfers");
+            Ok::<_, Error>(count)
+        });
+
+        match task.await {
+            Err(err) => {
+                error!(self.logger, "Failed to apply retention policy"; "error" => %err);
+                0
+            }
+            Ok(count) => count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_insert_transfer() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let transfer_id_1: Uuid = "23e488a4-0521-11ee-be56-0242ac120002".parse().unwrap();
+        let transfer_id_2: Uuid = "23e48d7c-0521-11ee-be56-0242ac120002".parse().unwrap();
+
+        {
+            let transfer = TransferInfo {
+                id: transfer_id_1,
+                peer: "1.2.3.4".to_string(),
+                files: TransferFiles::Incoming(vec![
+                    TransferIncomingPath {
+                        file_id: "id1".to_string(),
+                        relative_path: "1".to_string(),
+                        size: 1024,
+                        is_archive: false,
+                    },
+                    TransferIncomingPath {
+                        file_id: "id2".to_string(),
+                        relative_path: "2".to_string(),
                         size: 2048,
+                        is_archive: false,
                     },
                 ]),
             };
@@ -1711,6 +2498,7 @@ mod tests {
                     TransferOutgoingPath {
                         file_id: "id3".to_string(),
                         size: 1024,
+                        is_archive: false,
                         uri: "file:///dir".parse().unwrap(),
                         relative_path: "3".to_string(),
                     },
@@ -1719,6 +2507,7 @@ mod tests {
                         relative_path: "4".to_string(),
                         uri: "file:///dir".parse().unwrap(),
                         size: 2048,
+                        is_archive: false,
                     },
                 ]),
             };
@@ -1777,18 +2566,21 @@ mod tests {
                 TransferOutgoingPath {
                     file_id: "id1".to_string(),
                     size: 1024,
+                    is_archive: false,
                     uri: "file:///dir".parse().unwrap(),
                     relative_path: "1".to_string(),
                 },
                 TransferOutgoingPath {
                     file_id: "id2".to_string(),
                     size: 1024,
+                    is_archive: false,
                     uri: "file:///dir".parse().unwrap(),
                     relative_path: "2".to_string(),
                 },
                 TransferOutgoingPath {
                     file_id: "id3".to_string(),
                     size: 1024,
+                    is_archive: false,
                     uri: "file:///dir".parse().unwrap(),
                     relative_path: "3".to_string(),
                 },
@@ -1797,6 +2589,7 @@ mod tests {
                     relative_path: "4".to_string(),
                     uri: "file:///dir".parse().unwrap(),
                     size: 2048,
+                    is_archive: false,
                 },
             ]),
         };
@@ -1864,21 +2657,25 @@ mod tests {
                     file_id: "id1".to_string(),
                     size: 1024,
                     relative_path: "1".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "id2".to_string(),
                     size: 1024,
                     relative_path: "2".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "id3".to_string(),
                     size: 1024,
                     relative_path: "3".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "id4".to_string(),
                     relative_path: "4".to_string(),
                     size: 2048,
+                    is_archive: false,
                 },
             ]),
         };
@@ -1947,21 +2744,25 @@ mod tests {
                     file_id: "idi1".to_string(),
                     size: 1024,
                     relative_path: "1".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "idi2".to_string(),
                     size: 1024,
                     relative_path: "2".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "idi3".to_string(),
                     size: 1024,
                     relative_path: "3".to_string(),
+                    is_archive: false,
                 },
                 TransferIncomingPath {
                     file_id: "idi4".to_string(),
                     relative_path: "4".to_string(),
                     size: 2048,
+                    is_archive: false,
                 },
             ]),
         };
@@ -1994,24 +2795,28 @@ mod tests {
                     relative_path: "1".to_string(),
                     uri: "file:///dir/1".parse().unwrap(),
                     size: 1024,
+                    is_archive: false,
                 },
                 TransferOutgoingPath {
                     file_id: "ido2".to_string(),
                     relative_path: "2".to_string(),
                     uri: "file:///dir/2".parse().unwrap(),
                     size: 1024,
+                    is_archive: false,
                 },
                 TransferOutgoingPath {
                     file_id: "ido3".to_string(),
                     relative_path: "3".to_string(),
                     uri: "file:///dir/3".parse().unwrap(),
                     size: 1024,
+                    is_archive: false,
                 },
                 TransferOutgoingPath {
                     file_id: "ido4".to_string(),
                     relative_path: "4".to_string(),
                     uri: "file:///dir/4".parse().unwrap(),
                     size: 2048,
+                    is_archive: false,
                 },
             ]),
         };
@@ -2233,4 +3038,917 @@ mod tests {
         assert_eq!(transfers.len(), 1);
         assert_eq!(transfers[0].id, transfer_id_2);
     }
+
+    #[tokio::test]
+    async fn saving_checksums() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let transfer_id: Uuid = "23e488a4-0521-11ee-be56-0242ac120002".parse().unwrap();
+
+        let transfer = TransferInfo {
+            id: transfer_id,
+            peer: "1.2.3.4".to_string(),
+            files: TransferFiles::Incoming(vec![
+                TransferIncomingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: "1".to_string(),
+                    size: 1024,
+                    is_archive: false,
+                },
+                TransferIncomingPath {
+                    file_id: "id2".to_string(),
+                    relative_path: "2".to_string(),
+                    size: 2048,
+                    is_archive: false,
+                },
+            ]),
+        };
+        storage.insert_transfer(&transfer).await;
+
+        storage
+            .save_checksum(transfer_id, "id1", ChecksumAlgorithm::Blake3, &[1, 2, 3])
+            .await;
+
+        let mut checksums = storage.fetch_checksums(transfer_id).await;
+        checksums.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums[0].file_id, "id1");
+        assert_eq!(checksums[0].checksum.as_deref(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(checksums[0].algorithm, Some(ChecksumAlgorithm::Blake3));
+        assert_eq!(checksums[1].file_id, "id2");
+        assert!(checksums[1].checksum.is_none());
+        assert!(checksums[1].algorithm.is_none());
+    }
+
+    #[tokio::test]
+    async fn checksum_cache() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let key = ChecksumCacheKey {
+            uri: "file:///dir/file.txt".parse().unwrap(),
+            size: 1024,
+            modified_at_ns: 1000,
+            limit: 1024,
+            algorithm: ChecksumAlgorithm::Sha256,
+        };
+
+        assert!(storage.fetch_cached_checksum(&key).await.is_none());
+
+        storage.save_cached_checksum(&key, &[1, 2, 3]).await;
+        assert_eq!(
+            storage.fetch_cached_checksum(&key).await.as_deref(),
+            Some(&[1u8, 2, 3][..])
+        );
+
+        // Different algorithm is a separate entry
+        let blake3 = ChecksumCacheKey {
+            algorithm: ChecksumAlgorithm::Blake3,
+            ..key.clone()
+        };
+        assert!(storage.fetch_cached_checksum(&blake3).await.is_none());
+
+        // So is the checksum of the file prefix
+        let prefix = ChecksumCacheKey {
+            limit: 512,
+            ..key.clone()
+        };
+        assert!(storage.fetch_cached_checksum(&prefix).await.is_none());
+        storage.save_cached_checksum(&prefix, &[3, 2, 1]).await;
+        assert_eq!(
+            storage.fetch_cached_checksum(&prefix).await.as_deref(),
+            Some(&[3u8, 2, 1][..])
+        );
+        assert_eq!(
+            storage.fetch_cached_checksum(&key).await.as_deref(),
+            Some(&[1u8, 2, 3][..])
+        );
+
+        // Caching the checksum of the modified file drops the stale entries
+        let modified = ChecksumCacheKey {
+            modified_at_ns: 2000,
+            ..key.clone()
+        };
+        storage.save_cached_checksum(&modified, &[4, 5, 6]).await;
+
+        assert!(storage.fetch_cached_checksum(&key).await.is_none());
+        assert!(storage.fetch_cached_checksum(&prefix).await.is_none());
+        assert_eq!(
+            storage.fetch_cached_checksum(&modified).await.as_deref(),
+            Some(&[4u8, 5, 6][..])
+        );
+
+        // Only the checksums of the files in the history are kept
+        let transfer = TransferInfo {
+            id: "23e488a4-0521-11ee-be56-0242ac120002".parse().unwrap(),
+            peer: "1.2.3.4".to_string(),
+            files: TransferFiles::Outgoing(vec![TransferOutgoingPath {
+                file_id: "id1".to_string(),
+                relative_path: "file.txt".to_string(),
+                uri: "file:///dir/file.txt".parse().unwrap(),
+                size: 1024,
+                is_archive: false,
+            }]),
+        };
+        storage.insert_transfer(&transfer).await;
+
+        let other = ChecksumCacheKey {
+            uri: "file:///dir/other.txt".parse().unwrap(),
+            ..key.clone()
+        };
+        storage.save_cached_checksum(&other, &[7, 8, 9]).await;
+
+        storage.cleanup_garbage_transfers().await;
+
+        assert!(storage.fetch_cached_checksum(&other).await.is_none());
+        assert_eq!(
+            storage.fetch_cached_checksum(&modified).await.as_deref(),
+            Some(&[4u8, 5, 6][..])
+        );
+    }
+
+    #[tokio::test]
+    async fn query_transfers() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+
+        for (i, id) in ids.iter().enumerate() {
+            let peer = if i % 2 == 0 { "1.2.3.4" } else { "5.6.7.8" };
+            let files = if i < 2 {
+                TransferFiles::Outgoing(vec![TransferOutgoingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: format!("dir/photo_{i}.jpg"),
+                    uri: "file:///dir".parse().unwrap(),
+                    size: 1024,
+                    is_archive: false,
+                }])
+            } else {
+                TransferFiles::Incoming(vec![TransferIncomingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: format!("dir/notes_{i}.txt"),
+                    size: 1024,
+                    is_archive: false,
+                }])
+            };
+
+            storage
+                .insert_transfer(&TransferInfo {
+                    id: *id,
+                    peer: peer.to_string(),
+                    files,
+                })
+                .await;
+        }
+
+        storage
+            .insert_outgoing_path_started_state(ids[0], "id1", 0)
+            .await;
+        storage
+            .insert_outgoing_path_completed_state(ids[0], "id1")
+            .await;
+        storage
+            .insert_incoming_path_started_state(ids[2], "id1", 0)
+            .await;
+        storage
+            .insert_incoming_path_paused_state(ids[2], "id1", 512)
+            .await;
+        storage.insert_transfer_cancel_state(ids[3], false).await;
+
+        let query_ids =
+            |page: &TransferPage| -> Vec<Uuid> { page.transfers.iter().map(|t| t.id).collect() };
+
+        let page = storage.query_transfers(&TransferQuery::default()).await;
+        assert_eq!(query_ids(&page), ids);
+        assert!(page.next_cursor.is_none());
+
+        match &page.transfers[2].transfer_type {
+            DbTransferType::Incoming(paths) => {
+                assert_eq!(paths[0].states.len(), 2);
+                assert_eq!(paths[0].bytes_received, 512);
+            }
+            _ => panic!("Unexpected transfer type"),
+        }
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                direction: Some(TransferType::Incoming),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(query_ids(&page), &ids[2..]);
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                peer: Some("5.6.7.8".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(query_ids(&page), [ids[1], ids[3]]);
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                state: Some(TransferStateFilter::Terminal),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(query_ids(&page), [ids[0], ids[3]]);
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                state: Some(TransferStateFilter::NonTerminal),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(query_ids(&page), [ids[1], ids[2]]);
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                filename: Some("PHOTO".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(query_ids(&page), &ids[..2]);
+
+        // The wildcards are matched literally
+        let page = storage
+            .query_transfers(&TransferQuery {
+                filename: Some("%".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(page.transfers.is_empty());
+
+        // Paging backwards
+        let mut query = TransferQuery {
+            order: SortOrder::Descending,
+            limit: Some(3),
+            summary: true,
+            ..Default::default()
+        };
+
+        let page = storage.query_transfers(&query).await;
+        assert_eq!(query_ids(&page), [ids[3], ids[2], ids[1]]);
+
+        match &page.transfers[1].transfer_type {
+            DbTransferType::Incoming(paths) => {
+                assert!(paths[0].states.is_empty());
+                assert_eq!(paths[0].bytes_received, 512);
+            }
+            _ => panic!("Unexpected transfer type"),
+        }
+
+        let cursor = page.next_cursor.expect("Missing cursor");
+        assert_eq!(cursor.to_string().parse::<TransferCursor>(), Ok(cursor));
+
+        query.cursor = Some(cursor);
+        let page = storage.query_transfers(&query).await;
+        assert_eq!(query_ids(&page), [ids[0]]);
+        assert!(page.next_cursor.is_none());
+
+        // The summary skips the file states but keeps the progress
+        match &page.transfers[0].transfer_type {
+            DbTransferType::Outgoing(paths) => {
+                assert!(paths[0].states.is_empty());
+                assert_eq!(paths[0].bytes_sent, 1024);
+            }
+            _ => panic!("Unexpected transfer type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn query_transfers_summary_state_ties() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let id = Uuid::new_v4();
+        storage
+            .insert_transfer(&TransferInfo {
+                id,
+                peer: "1.2.3.4".to_string(),
+                files: TransferFiles::Incoming(vec![TransferIncomingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: "a/1".to_string(),
+                    size: 1024,
+                    is_archive: false,
+                }]),
+            })
+            .await;
+
+        for offset in [0, 512, 256] {
+            storage
+                .insert_incoming_path_started_state(id, "id1", offset)
+                .await;
+        }
+        storage
+            .writer
+            .run(|conn| {
+                conn.execute(
+                    "UPDATE incoming_path_started_states SET created_at = '2024-01-01 \
+                     00:00:00.000'",
+                    [],
+                )?;
+                Ok(())
+            })
+            .await
+            .unwrap();
+
+        let page = storage
+            .query_transfers(&TransferQuery {
+                summary: true,
+                ..Default::default()
+            })
+            .await;
+
+        // The state inserted last wins
+        match &page.transfers[0].transfer_type {
+            DbTransferType::Incoming(paths) => assert_eq!(paths[0].bytes_received, 256),
+            _ => panic!("Unexpected transfer type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn peer_stats() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let outgoing_id = Uuid::new_v4();
+        let incoming_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+
+        let incoming_files = || {
+            TransferFiles::Incoming(vec![TransferIncomingPath {
+                file_id: "id1".to_string(),
+                relative_path: "a/1".to_string(),
+                size: 1024,
+                is_archive: false,
+            }])
+        };
+
+        storage
+            .insert_transfer(&TransferInfo {
+                id: outgoing_id,
+                peer: "1.2.3.4".to_string(),
+                files: TransferFiles::Outgoing(vec![
+                    TransferOutgoingPath {
+                        file_id: "id1".to_string(),
+                        relative_path: "a/1".to_string(),
+                        uri: "file:///dir/a/1".parse().unwrap(),
+                        size: 1024,
+                        is_archive: false,
+                    },
+                    TransferOutgoingPath {
+                        file_id: "id2".to_string(),
+                        relative_path: "a/2".to_string(),
+                        uri: "file:///dir/a/2".parse().unwrap(),
+                        size: 1024,
+                        is_archive: false,
+                    },
+                ]),
+            })
+            .await;
+        storage
+            .insert_transfer(&TransferInfo {
+                id: incoming_id,
+                peer: "1.2.3.4".to_string(),
+                files: incoming_files(),
+            })
+            .await;
+        storage
+            .insert_transfer(&TransferInfo {
+                id: other_id,
+                peer: "5.6.7.8".to_string(),
+                files: incoming_files(),
+            })
+            .await;
+
+        storage
+            .insert_outgoing_path_started_state(outgoing_id, "id1", 0)
+            .await;
+        storage
+            .insert_outgoing_path_completed_state(outgoing_id, "id1")
+            .await;
+        storage
+            .insert_outgoing_path_started_state(outgoing_id, "id2", 0)
+            .await;
+        storage
+            .insert_outgoing_path_failed_state(outgoing_id, "id2", 1, 100)
+            .await;
+
+        storage
+            .insert_incoming_path_started_state(incoming_id, "id1", 0)
+            .await;
+        storage
+            .insert_incoming_path_paused_state(incoming_id, "id1", 512)
+            .await;
+
+        storage
+            .insert_incoming_path_started_state(other_id, "id1", 0)
+            .await;
+        storage
+            .insert_incoming_path_completed_state(other_id, "id1", "/recv/a/1")
+            .await;
+
+        let set_created_at = |table: &'static str, created_at: &'static str| {
+            storage.writer.run(move |conn| {
+                conn.execute(
+                    &format!("UPDATE {table} SET created_at = ?1"),
+                    params![created_at],
+                )?;
+                Ok(())
+            })
+        };
+
+        // The first file takes 2 seconds
+        for (table, created_at) in [
+            ("transfers", "2024-01-01 00:00:00.000"),
+            ("outgoing_path_started_states", "2024-01-01 00:00:00.000"),
+            ("outgoing_path_completed_states", "2024-01-01 00:00:02.000"),
+            ("outgoing_path_failed_states", "2024-01-01 00:00:03.000"),
+            ("incoming_path_started_states", "2024-01-01 00:00:04.000"),
+            ("incoming_path_paused_states", "2024-01-01 00:00:05.000"),
+            ("incoming_path_completed_states", "2024-01-01 00:00:06.000"),
+        ] {
+            set_created_at(table, created_at).await.unwrap();
+        }
+
+        let stats = storage.peer_stats().await;
+        assert_eq!(stats.len(), 2);
+
+        // The most recent peer comes first
+        let other = &stats[0];
+        assert_eq!(other.peer, "5.6.7.8");
+        assert_eq!(other.transfers, 1);
+        assert_eq!(other.bytes_sent, 0);
+        assert_eq!(other.bytes_received, 1024);
+        assert_eq!(other.files_received, 1);
+        assert_eq!(other.files_failed, 0);
+
+        let peer = &stats[1];
+        assert_eq!(peer.peer, "1.2.3.4");
+        assert_eq!(peer.transfers, 2);
+        assert_eq!(peer.bytes_sent, 1024 + 100);
+        assert_eq!(peer.bytes_received, 512);
+        assert_eq!(peer.files_sent, 1);
+        assert_eq!(peer.files_received, 0);
+        assert_eq!(peer.files_failed, 1);
+
+        let throughput = peer.throughput.expect("Missing throughput");
+        assert!((throughput - 512.0).abs() < 0.01, "{throughput}");
+
+        // The peers seen at the same time are ordered by the address
+        set_created_at("incoming_path_completed_states", "2024-01-01 00:00:05.000")
+            .await
+            .unwrap();
+        let stats = storage.peer_stats().await;
+        assert_eq!(stats[0].peer, "1.2.3.4");
+        assert_eq!(stats[1].peer, "5.6.7.8");
+
+        // The removed transfers are not counted
+        storage.purge_transfers(&[other_id.to_string()]).await;
+        let stats = storage.peer_stats().await;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].peer, "1.2.3.4");
+    }
+
+    #[tokio::test]
+    async fn export_import_history() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let source = Storage::new(logger.clone(), ":memory:").unwrap();
+
+        let incoming_id = Uuid::new_v4();
+        let outgoing_id = Uuid::new_v4();
+
+        source
+            .insert_transfer(&TransferInfo {
+                id: incoming_id,
+                peer: "1.2.3.4".to_string(),
+                files: TransferFiles::Incoming(vec![TransferIncomingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: "a/1".to_string(),
+                    size: 1024,
+                    is_archive: true,
+                }]),
+            })
+            .await;
+        source
+            .insert_transfer(&TransferInfo {
+                id: outgoing_id,
+                peer: "5.6.7.8".to_string(),
+                files: TransferFiles::Outgoing(vec![
+                    TransferOutgoingPath {
+                        file_id: "id2".to_string(),
+                        relative_path: "b/2".to_string(),
+                        uri: "file:///dir/b/2".parse().unwrap(),
+                        size: 2048,
+                        is_archive: true,
+                    },
+                    TransferOutgoingPath {
+                        file_id: "id3".to_string(),
+                        relative_path: "3".to_string(),
+                        uri: "content://provider/3".parse().unwrap(),
+                        size: 4096,
+                        is_archive: false,
+                    },
+                ]),
+            })
+            .await;
+
+        source
+            .start_incoming_file(incoming_id, "id1", "/downloads")
+            .await;
+        source
+            .insert_incoming_path_started_state(incoming_id, "id1", 0)
+            .await;
+        source
+            .save_checksum(incoming_id, "id1", ChecksumAlgorithm::Blake3, &[0, 1, 254])
+            .await;
+        source
+            .insert_incoming_path_completed_state(incoming_id, "id1", "/downloads/a/1")
+            .await;
+        source
+            .insert_outgoing_path_started_state(outgoing_id, "id2", 0)
+            .await;
+        source
+            .insert_outgoing_path_failed_state(outgoing_id, "id2", 3, 100)
+            .await;
+        source
+            .insert_outgoing_path_reject_state(outgoing_id, "id3", true, 0)
+            .await;
+        source
+            .insert_transfer_cancel_state(outgoing_id, false)
+            .await;
+
+        let path = std::env::temp_dir().join(format!("history-{}.json", Uuid::new_v4()));
+        assert_eq!(source.export_history(&path).await.unwrap(), 2);
+
+        let target = Storage::new(logger, ":memory:").unwrap();
+        assert_eq!(target.import_history(&path).await.unwrap(), 2);
+        // The duplicates are skipped
+        assert_eq!(target.import_history(&path).await.unwrap(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let exported = serde_json::to_value(source.transfers_since(0).await).unwrap();
+        let imported = target.transfers_since(0).await;
+
+        match &imported[0].transfer_type {
+            DbTransferType::Incoming(paths) => {
+                assert_eq!(paths[0].checksum.as_deref(), Some(&[0, 1, 254][..]));
+                assert_eq!(paths[0].checksum_algorithm, Some(ChecksumAlgorithm::Blake3));
+                assert!(paths[0].is_archive);
+            }
+            _ => panic!("Unexpected transfer type"),
+        }
+        match &imported[1].transfer_type {
+            DbTransferType::Outgoing(paths) => {
+                assert!(paths[0].is_archive);
+                assert!(!paths[1].is_archive);
+            }
+            _ => panic!("Unexpected transfer type"),
+        }
+
+        let imported = serde_json::to_value(imported).unwrap();
+        assert_eq!(exported, imported);
+
+        // The imported transfers are history only
+        assert!(target.incoming_transfers_to_resume().await.is_empty());
+        assert!(target.outgoing_transfers_to_resume().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recover_database() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir().join(format!("recover-{}.sqlite", Uuid::new_v4()));
+        let path_str = path.to_str().unwrap();
+
+        {
+            let storage = Storage::new(logger.clone(), path_str).unwrap();
+            storage
+                .insert_transfer(&TransferInfo {
+                    id: Uuid::new_v4(),
+                    peer: "1.2.3.4".to_string(),
+                    files: TransferFiles::Incoming(vec![TransferIncomingPath {
+                        file_id: "id1".to_string(),
+                        relative_path: "1".to_string(),
+                        size: 1024,
+                        is_archive: false,
+                    }]),
+                })
+                .await;
+
+            // Pretend the database was left by a newer version
+            storage
+                .writer
+                .run(|conn| Ok(conn.pragma_update(None, "user_version", 1000)?))
+                .await
+                .unwrap();
+        }
+
+        assert!(Storage::new(logger.clone(), path_str).is_err());
+
+        let (storage, report) = Storage::recover(logger.clone(), path_str, None).unwrap();
+        assert!(report.integrity_errors.is_empty());
+        assert!(report.lost_tables.is_empty());
+        assert_eq!(report.recovered_transfers, 1);
+        assert_eq!(report.resumable_transfers, 1);
+        assert_eq!(storage.transfers_since(0).await.len(), 1);
+        assert_eq!(storage.incoming_transfers_to_resume().await.len(), 1);
+
+        let backup = report.backup_path.unwrap();
+        assert!(backup.exists());
+        std::fs::remove_file(backup).unwrap();
+        drop(storage);
+
+        // Nothing to salvage from garbage
+        std::fs::write(&path, b"this is not a database").unwrap();
+        assert!(Storage::new(logger.clone(), path_str).is_err());
+
+        let (storage, report) = Storage::recover(logger, path_str, None).unwrap();
+        assert!(!report.integrity_errors.is_empty());
+        assert_eq!(report.recovered_transfers, 0);
+        assert!(storage.transfers_since(0).await.is_empty());
+
+        std::fs::remove_file(report.backup_path.unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn encryption_requires_sqlcipher() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir().join(format!("plain-{}.sqlite", Uuid::new_v4()));
+
+        let key = StorageKey::from([7; STORAGE_KEY_LENGTH]);
+        assert!(matches!(
+            Storage::open(logger, path.to_str().unwrap(), Some(&key)),
+            Err(Error::Encryption(..))
+        ));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[tokio::test]
+    async fn encrypt_and_rotate_key() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir().join(format!("cipher-{}.sqlite", Uuid::new_v4()));
+        let path_str = path.to_str().unwrap();
+
+        {
+            let storage = Storage::new(logger.clone(), path_str).unwrap();
+            storage
+                .insert_transfer(&TransferInfo {
+                    id: Uuid::new_v4(),
+                    peer: "1.2.3.4".to_string(),
+                    files: TransferFiles::Incoming(vec![TransferIncomingPath {
+                        file_id: "id1".to_string(),
+                        relative_path: "1".to_string(),
+                        size: 1024,
+                        is_archive: false,
+                    }]),
+                })
+                .await;
+
+            // Plaintext can't be rotated
+            let key = StorageKey::from([1; STORAGE_KEY_LENGTH]);
+            assert!(storage.rotate_key(&key).await.is_err());
+        }
+
+        let old_key = StorageKey::from([1; STORAGE_KEY_LENGTH]);
+        let new_key = StorageKey::from([2; STORAGE_KEY_LENGTH]);
+
+        // The plaintext database is encrypted transparently
+        {
+            let storage = Storage::open(logger.clone(), path_str, Some(&old_key)).unwrap();
+            assert_eq!(storage.transfers_since(0).await.len(), 1);
+        }
+
+        assert!(Storage::new(logger.clone(), path_str).is_err());
+
+        {
+            let storage = Storage::open(logger.clone(), path_str, Some(&old_key)).unwrap();
+            storage.rotate_key(&new_key).await.unwrap();
+        }
+
+        // The wrong key leaves the database untouched
+        assert!(matches!(
+            Storage::open(logger.clone(), path_str, Some(&old_key)),
+            Err(Error::Encryption(..))
+        ));
+        assert!(path.exists());
+
+        let storage = Storage::open(logger, path_str, Some(&new_key)).unwrap();
+        assert_eq!(storage.transfers_since(0).await.len(), 1);
+
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn apply_retention() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+
+        for id in &ids {
+            storage
+                .insert_transfer(&TransferInfo {
+                    id: *id,
+                    peer: "1.2.3.4".to_string(),
+                    files: TransferFiles::Outgoing(vec![TransferOutgoingPath {
+                        file_id: "id1".to_string(),
+                        relative_path: "dir/file.txt".to_string(),
+                        uri: "file:///dir".parse().unwrap(),
+                        size: 1024,
+                        is_archive: false,
+                    }]),
+                })
+                .await;
+        }
+
+        for id in &ids[..3] {
+            let tid = id.to_string();
+
+            storage
+                .writer
+                .run(move |conn| {
+                    conn.execute(
+                        "UPDATE transfers SET created_at = datetime('now', '-10 days') WHERE id \
+                         = ?1",
+                        params![tid],
+                    )?;
+                    Ok(())
+                })
+                .await
+                .unwrap();
+        }
+
+        // The second transfer stays resumable, the third one is not finished
+        for id in [ids[0], ids[2], ids[3], ids[4]] {
+            storage.transfer_sync_clear(id).await;
+        }
+        for id in [ids[0], ids[3], ids[4]] {
+            storage.insert_transfer_cancel_state(id, false).await;
+        }
+
+        let day = Some(Duration::from_secs(24 * 60 * 60));
+        let remaining = |transfers: Vec<Transfer>| -> Vec<Uuid> {
+            transfers.into_iter().map(|t| t.id).collect()
+        };
+
+        assert_eq!(storage.apply_retention(None, None, false).await, 0);
+
+        assert_eq!(storage.apply_retention(day, None, true).await, 1);
+        assert_eq!(remaining(storage.transfers_since(0).await), &ids[1..]);
+
+        assert_eq!(storage.apply_retention(day, None, false).await, 1);
+        assert_eq!(
+            remaining(storage.transfers_since(0).await),
+            [ids[1], ids[3], ids[4]]
+        );
+
+        // The resumable transfer counts towards the limit but is kept
+        assert_eq!(storage.apply_retention(None, Some(1), true).await, 1);
+        assert_eq!(
+            remaining(storage.transfers_since(0).await),
+            [ids[1], ids[4]]
+        );
+    }
+
+    fn create_scratch(conn: &mut Connection) -> Result<()> {
+        conn.execute("CREATE TABLE scratch (value INTEGER NOT NULL UNIQUE)", [])?;
+        Ok(())
+    }
+
+    fn count_scratch(conn: &mut Connection) -> Result<usize> {
+        Ok(conn.query_row("SELECT count(*) FROM scratch", [], |r| r.get(0))?)
+    }
+
+    fn queue_value(storage: &Storage, value: usize) {
+        storage
+            .writer
+            .queue("Failed to insert the value", move |conn| {
+                conn.execute("INSERT INTO scratch (value) VALUES (?1)", params![value])?;
+                Ok(())
+            });
+    }
+
+    // Holds the writer thread in an insert committed on its own, until the
+    // returned sender is dropped
+    fn block_writer(storage: &Storage) -> std::sync::mpsc::Sender<()> {
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+        storage
+            .writer
+            .queue("Failed to block the writer", move |_| {
+                let _ = started_tx.send(());
+                let _ = release_rx.recv();
+                Ok(())
+            });
+
+        started_rx.recv().unwrap();
+        release_tx
+    }
+
+    #[tokio::test]
+    async fn writer_commits_in_batches() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir().join(format!("batch-{}.sqlite", Uuid::new_v4()));
+        let storage = Storage::new(logger, path.to_str().unwrap()).unwrap();
+        storage.writer.run(create_scratch).await.unwrap();
+
+        let release = block_writer(&storage);
+
+        for value in 0..worker::MAX_BATCH_SIZE {
+            queue_value(&storage, value);
+        }
+
+        // The first insert over the limit waits in the second batch
+        let (waiting_tx, waiting_rx) = std::sync::mpsc::channel();
+        let (finish_tx, finish_rx) = std::sync::mpsc::channel::<()>();
+        storage
+            .writer
+            .queue("Failed to insert the last value", move |conn| {
+                let _ = waiting_tx.send(());
+                let _ = finish_rx.recv();
+                conn.execute("INSERT INTO scratch (value) VALUES (-1)", [])?;
+                Ok(())
+            });
+
+        drop(release);
+        waiting_rx.recv().unwrap();
+
+        // Bypasses the wait for the writer in `Storage::read()`
+        let reader = storage.reader.as_ref().unwrap();
+        assert_eq!(
+            reader.read(count_scratch).await.unwrap(),
+            worker::MAX_BATCH_SIZE
+        );
+
+        drop(finish_tx);
+        assert_eq!(
+            storage.read(count_scratch).await.unwrap(),
+            worker::MAX_BATCH_SIZE + 1
+        );
+
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writer_rolls_back_failing_insert() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = Storage::new(logger, ":memory:").unwrap();
+        storage.writer.run(create_scratch).await.unwrap();
+
+        let release = block_writer(&storage);
+
+        queue_value(&storage, 1);
+        storage
+            .writer
+            .queue("Failed to insert the duplicate", |conn| {
+                conn.execute("INSERT INTO scratch (value) VALUES (2)", [])?;
+                conn.execute("INSERT INTO scratch (value) VALUES (1)", [])?;
+                Ok(())
+            });
+        queue_value(&storage, 3);
+
+        drop(release);
+
+        let values = storage
+            .read(|conn| {
+                let mut stmt = conn.prepare("SELECT value FROM scratch ORDER BY value")?;
+                let values = stmt
+                    .query_map([], |r| r.get::<_, i64>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(values)
+            })
+            .await
+            .unwrap();
+
+        // The partial insert is gone, the rest of the batch is kept
+        assert_eq!(values, [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn read_sees_queued_inserts() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let path = std::env::temp_dir().join(format!("queued-{}.sqlite", Uuid::new_v4()));
+        let storage = Storage::new(logger, path.to_str().unwrap()).unwrap();
+        storage.writer.run(create_scratch).await.unwrap();
+
+        for value in 0..10 {
+            queue_value(&storage, value);
+            assert_eq!(storage.read(count_scratch).await.unwrap(), value + 1);
+        }
+
+        drop(storage);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,690 @@
+//! Pure Rust storage backend keeping the transfers in memory. Nothing is
+//! written to the disk and no history is kept, only the state needed to resume
+//! the transfers while the process lives
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use drop_core::ChecksumAlgorithm;
+use uuid::Uuid;
+
+use crate::{
+    backend::StorageBackend,
+    sync,
+    types::{
+        FileSyncState, IncomingFileToRetry, IncomingTransferToRetry, OutgoingFileToRetry,
+        TempFileLocation, TransferFiles,
+    },
+    ChecksumCacheKey, FileChecksum, FinishedIncomingFile, OutgoingTransferToRetry, TransferInfo,
+};
+
+// The temporary files are removed after the transfer is gone from the storage,
+// so the locations of the few most recent ones outlive the transfers
+const MAX_CLEARED_TRANSFERS: usize = 64;
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    transfers: Mutex<HashMap<Uuid, MemoryTransfer>>,
+    cleared: Mutex<VecDeque<(Uuid, Vec<TempFileLocation>)>>,
+}
+
+struct MemoryTransfer {
+    peer: String,
+    is_outgoing: bool,
+    // `None` once the transfer can't be resumed anymore
+    sync: Option<sync::TransferState>,
+    files: Vec<MemoryFile>,
+}
+
+struct MemoryFile {
+    file_id: String,
+    subpath: String,
+    size: i64,
+    // Set for the outgoing files only
+    uri: Option<url::Url>,
+    is_archive: bool,
+    sync: sync::FileState,
+    // The base directory of the download in flight
+    in_flight: Option<String>,
+    // Every base directory the file was downloaded into
+    base_dirs: Vec<String>,
+    checksum: Option<(ChecksumAlgorithm, Vec<u8>)>,
+    // Set for the incoming files only
+    final_path: Option<String>,
+    is_completed: bool,
+    is_failed: bool,
+    is_rejected: bool,
+}
+
+impl MemoryTransfer {
+    fn temp_locations(&self) -> Vec<TempFileLocation> {
+        self.files
+            .iter()
+            .flat_map(|file| {
+                file.base_dirs.iter().map(|base_dir| TempFileLocation {
+                    file_id: file.file_id.clone(),
+                    base_path: base_dir.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl MemoryFile {
+    fn new(file_id: &str, subpath: &str, size: i64) -> Self {
+        Self {
+            file_id: file_id.to_string(),
+            subpath: subpath.to_string(),
+            size,
+            uri: None,
+            is_archive: false,
+            sync: sync::FileState::Alive,
+            in_flight: None,
+            base_dirs: Vec::new(),
+            checksum: None,
+            final_path: None,
+            is_completed: false,
+            is_failed: false,
+            is_rejected: false,
+        }
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, MemoryTransfer>> {
+        self.transfers.lock().expect("Poisoned lock")
+    }
+
+    fn cleared(&self) -> MutexGuard<'_, VecDeque<(Uuid, Vec<TempFileLocation>)>> {
+        self.cleared.lock().expect("Poisoned lock")
+    }
+
+    fn update_file<T>(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        update: impl FnOnce(&mut MemoryFile) -> Option<T>,
+    ) -> Option<T> {
+        let mut transfers = self.lock();
+        let file = transfers
+            .get_mut(&transfer_id)?
+            .files
+            .iter_mut()
+            .find(|file| file.file_id == file_id)?;
+
+        update(file)
+    }
+
+    // Like `update_file()`, but only for the transfers that can be resumed
+    fn update_synced_file<T>(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        update: impl FnOnce(&mut MemoryFile) -> Option<T>,
+    ) -> Option<T> {
+        let mut transfers = self.lock();
+        let transfer = transfers.get_mut(&transfer_id)?;
+        transfer.sync?;
+
+        let file = transfer
+            .files
+            .iter_mut()
+            .find(|file| file.file_id == file_id)?;
+
+        update(file)
+    }
+
+    fn file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        is_outgoing: bool,
+    ) -> Option<FileSyncState> {
+        let transfers = self.lock();
+        let transfer = transfers.get(&transfer_id)?;
+        transfer.sync?;
+
+        if transfer.is_outgoing != is_outgoing {
+            return None;
+        }
+
+        let file = transfer.files.iter().find(|file| file.file_id == file_id)?;
+
+        Some(FileSyncState {
+            sync: file.sync,
+            is_rejected: file.is_rejected,
+            is_success: file.is_completed,
+            is_failed: file.is_failed,
+        })
+    }
+
+    fn transfers_to_resume<T>(
+        &self,
+        is_outgoing: bool,
+        file: impl Fn(&MemoryFile) -> Option<T>,
+    ) -> Vec<(Uuid, String, Vec<T>)> {
+        self.lock()
+            .iter()
+            .filter(|(_, transfer)| transfer.is_outgoing == is_outgoing && transfer.sync.is_some())
+            .map(|(id, transfer)| {
+                let files = transfer.files.iter().filter_map(&file).collect();
+                (*id, transfer.peer.clone(), files)
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn insert_transfer(&self, transfer: &TransferInfo) -> Option<()> {
+        let mut transfers = self.lock();
+        if transfers.contains_key(&transfer.id) {
+            return None;
+        }
+
+        let (is_outgoing, files) = match &transfer.files {
+            TransferFiles::Incoming(files) => (
+                false,
+                files
+                    .iter()
+                    .map(|file| MemoryFile {
+                        is_archive: file.is_archive,
+                        ..MemoryFile::new(&file.file_id, &file.relative_path, file.size)
+                    })
+                    .collect(),
+            ),
+            TransferFiles::Outgoing(files) => (
+                true,
+                files
+                    .iter()
+                    .map(|file| MemoryFile {
+                        uri: Some(file.uri.clone()),
+                        is_archive: file.is_archive,
+                        ..MemoryFile::new(&file.file_id, &file.relative_path, file.size)
+                    })
+                    .collect(),
+            ),
+        };
+
+        transfers.insert(
+            transfer.id,
+            MemoryTransfer {
+                peer: transfer.peer.clone(),
+                is_outgoing,
+                sync: Some(sync::TransferState::New),
+                files,
+            },
+        );
+
+        Some(())
+    }
+
+    async fn update_transfer_sync_states(&self, transfer_id: Uuid, local: sync::TransferState) {
+        if let Some(transfer) = self.lock().get_mut(&transfer_id) {
+            if let Some(sync) = &mut transfer.sync {
+                *sync = local;
+            }
+        }
+    }
+
+    async fn transfer_sync_state(&self, transfer_id: Uuid) -> Option<sync::Transfer> {
+        let transfers = self.lock();
+        let transfer = transfers.get(&transfer_id)?;
+
+        Some(sync::Transfer {
+            local_state: transfer.sync?,
+            is_outgoing: transfer.is_outgoing,
+        })
+    }
+
+    // Without the history there is nothing left to keep once the transfer
+    // can't be resumed
+    async fn transfer_sync_clear(&self, transfer_id: Uuid) -> Option<()> {
+        let transfer = self.lock().remove(&transfer_id)?;
+
+        let locations = transfer.temp_locations();
+
+        if !locations.is_empty() {
+            let mut cleared = self.cleared();
+            if cleared.len() == MAX_CLEARED_TRANSFERS {
+                cleared.pop_front();
+            }
+            cleared.push_back((transfer_id, locations));
+        }
+
+        transfer.sync.map(|_| ())
+    }
+
+    async fn outgoing_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState> {
+        self.file_sync_state(transfer_id, file_id, true)
+    }
+
+    async fn update_outgoing_file_sync_states(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        local: sync::FileState,
+    ) {
+        self.update_synced_file(transfer_id, file_id, |file| {
+            file.sync = local;
+            Some(())
+        });
+    }
+
+    async fn incoming_file_sync_state(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+    ) -> Option<FileSyncState> {
+        self.file_sync_state(transfer_id, file_id, false)
+    }
+
+    async fn start_incoming_file(&self, transfer_id: Uuid, file_id: &str, base_dir: &str) {
+        self.update_synced_file(transfer_id, file_id, |file| {
+            if !file.base_dirs.iter().any(|dir| dir == base_dir) {
+                file.base_dirs.push(base_dir.to_string());
+            }
+            file.in_flight = Some(base_dir.to_string());
+            Some(())
+        });
+    }
+
+    async fn stop_incoming_file(&self, transfer_id: Uuid, file_id: &str) -> Option<()> {
+        self.update_synced_file(transfer_id, file_id, |file| {
+            file.sync = sync::FileState::Terminal;
+            file.in_flight.take().map(|_| ())
+        })
+    }
+
+    // The transfer states are part of the history only, which is not kept
+    async fn insert_transfer_failed_state(&self, _transfer_id: Uuid, _error: u32) {}
+
+    async fn insert_transfer_cancel_state(&self, _transfer_id: Uuid, _by_peer: bool) {}
+
+    async fn insert_outgoing_path_started_state(
+        &self,
+        _transfer_id: Uuid,
+        _path_id: &str,
+        _bytes_sent: i64,
+    ) {
+    }
+
+    async fn insert_incoming_path_started_state(
+        &self,
+        _transfer_id: Uuid,
+        _path_id: &str,
+        _bytes_received: i64,
+    ) {
+    }
+
+    async fn insert_outgoing_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        _error: u32,
+        _bytes_sent: i64,
+    ) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_failed = true;
+            Some(())
+        });
+    }
+
+    async fn insert_incoming_path_failed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        _error: u32,
+        _bytes_received: i64,
+    ) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_failed = true;
+            Some(())
+        });
+    }
+
+    async fn insert_outgoing_path_completed_state(&self, transfer_id: Uuid, path_id: &str) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_completed = true;
+            Some(())
+        });
+    }
+
+    async fn insert_incoming_path_completed_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        final_path: &str,
+    ) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_completed = true;
+            file.final_path = Some(final_path.to_string());
+            Some(())
+        });
+    }
+
+    async fn insert_outgoing_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        _by_peer: bool,
+        _bytes_sent: i64,
+    ) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_rejected = true;
+            Some(())
+        });
+    }
+
+    async fn insert_incoming_path_reject_state(
+        &self,
+        transfer_id: Uuid,
+        path_id: &str,
+        _by_peer: bool,
+        _bytes_received: i64,
+    ) {
+        self.update_file(transfer_id, path_id, |file| {
+            file.is_rejected = true;
+            Some(())
+        });
+    }
+
+    async fn insert_outgoing_path_paused_state(
+        &self,
+        _transfer_id: Uuid,
+        _path_id: &str,
+        _bytes_sent: i64,
+    ) {
+    }
+
+    async fn insert_incoming_path_paused_state(
+        &self,
+        _transfer_id: Uuid,
+        _path_id: &str,
+        _bytes_received: i64,
+    ) {
+    }
+
+    async fn outgoing_transfers_to_resume(&self) -> Vec<OutgoingTransferToRetry> {
+        self.transfers_to_resume(true, |file| {
+            Some(OutgoingFileToRetry {
+                file_id: file.file_id.clone(),
+                subpath: file.subpath.clone(),
+                uri: file.uri.clone()?,
+                size: file.size,
+                is_archive: file.is_archive,
+            })
+        })
+        .into_iter()
+        .map(|(uuid, peer, files)| OutgoingTransferToRetry { uuid, peer, files })
+        .collect()
+    }
+
+    async fn incoming_transfers_to_resume(&self) -> Vec<IncomingTransferToRetry> {
+        self.transfers_to_resume(false, |file| {
+            Some(IncomingFileToRetry {
+                file_id: file.file_id.clone(),
+                subpath: file.subpath.clone(),
+                size: file.size as _,
+                is_archive: file.is_archive,
+            })
+        })
+        .into_iter()
+        .map(|(uuid, peer, files)| IncomingTransferToRetry { uuid, peer, files })
+        .collect()
+    }
+
+    async fn incoming_files_to_resume(&self, transfer_id: Uuid) -> Vec<sync::FileInFlight> {
+        let transfers = self.lock();
+
+        match transfers.get(&transfer_id) {
+            Some(transfer) if transfer.sync.is_some() => transfer
+                .files
+                .iter()
+                .filter(|file| matches!(file.sync, sync::FileState::Alive))
+                .filter_map(|file| {
+                    Some(sync::FileInFlight {
+                        base_dir: file.in_flight.clone()?,
+                        file_id: file.file_id.clone(),
+                    })
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    async fn finished_incoming_files(&self, transfer_id: Uuid) -> Vec<FinishedIncomingFile> {
+        let transfers = self.lock();
+
+        let Some(transfer) = transfers.get(&transfer_id) else {
+            return vec![];
+        };
+
+        transfer
+            .files
+            .iter()
+            .filter_map(|file| {
+                Some(FinishedIncomingFile {
+                    subpath: file.subpath.clone(),
+                    final_path: file.final_path.clone()?,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_checksum(
+        &self,
+        transfer_id: Uuid,
+        file_id: &str,
+        algorithm: ChecksumAlgorithm,
+        checksum: &[u8],
+    ) {
+        self.update_file(transfer_id, file_id, |file| {
+            file.checksum = Some((algorithm, checksum.to_vec()));
+            Some(())
+        });
+    }
+
+    async fn fetch_checksums(&self, transfer_id: Uuid) -> Vec<FileChecksum> {
+        let transfers = self.lock();
+
+        match transfers.get(&transfer_id) {
+            Some(transfer) if !transfer.is_outgoing => transfer
+                .files
+                .iter()
+                .map(|file| FileChecksum {
+                    file_id: file.file_id.clone(),
+                    checksum: file.checksum.as_ref().map(|(_, csum)| csum.clone()),
+                    algorithm: file.checksum.as_ref().map(|(algo, _)| *algo),
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // The checksums of the outgoing files are not cached between the transfers
+    async fn fetch_cached_checksum(&self, _key: &ChecksumCacheKey) -> Option<Vec<u8>> {
+        None
+    }
+
+    async fn save_cached_checksum(&self, _key: &ChecksumCacheKey, _checksum: &[u8]) {}
+
+    async fn fetch_temp_locations(&self, transfer_id: Uuid) -> Vec<TempFileLocation> {
+        {
+            let mut cleared = self.cleared();
+            if let Some(pos) = cleared.iter().position(|(id, _)| *id == transfer_id) {
+                return cleared
+                    .remove(pos)
+                    .map(|(_, locations)| locations)
+                    .unwrap_or_default();
+            }
+        }
+
+        self.lock()
+            .get(&transfer_id)
+            .map(MemoryTransfer::temp_locations)
+            .unwrap_or_default()
+    }
+
+    async fn fetch_base_dirs_for_file(&self, transfer_id: Uuid, file_id: &str) -> Vec<String> {
+        let transfers = self.lock();
+
+        transfers
+            .get(&transfer_id)
+            .and_then(|transfer| transfer.files.iter().find(|file| file.file_id == file_id))
+            .map(|file| file.base_dirs.clone())
+            .unwrap_or_default()
+    }
+
+    // Nothing outlives the process
+    async fn cleanup_garbage_transfers(&self) -> usize {
+        0
+    }
+
+    async fn apply_retention(
+        &self,
+        _max_age: Option<Duration>,
+        _max_transfers: Option<usize>,
+        _terminal_only: bool,
+    ) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{TransferIncomingPath, TransferOutgoingPath};
+
+    #[tokio::test]
+    async fn resume_incoming() {
+        let storage = MemoryStorage::new();
+        let id = Uuid::new_v4();
+
+        let transfer = TransferInfo {
+            id,
+            peer: "1.2.3.4".to_string(),
+            files: TransferFiles::Incoming(vec![
+                TransferIncomingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: "dir/a.txt".to_string(),
+                    size: 1024,
+                    is_archive: false,
+                },
+                TransferIncomingPath {
+                    file_id: "id2".to_string(),
+                    relative_path: "dir/b.txt".to_string(),
+                    size: 2048,
+                    is_archive: false,
+                },
+            ]),
+        };
+
+        assert!(storage.insert_transfer(&transfer).await.is_some());
+        assert!(storage.insert_transfer(&transfer).await.is_none());
+
+        storage
+            .update_transfer_sync_states(id, sync::TransferState::Active)
+            .await;
+        storage.start_incoming_file(id, "id1", "/tmp").await;
+        storage.start_incoming_file(id, "id2", "/tmp").await;
+        assert!(storage.stop_incoming_file(id, "id2").await.is_some());
+        storage
+            .insert_incoming_path_completed_state(id, "id2", "/tmp/dir/b.txt")
+            .await;
+
+        let transfers = storage.incoming_transfers_to_resume().await;
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].uuid, id);
+        assert_eq!(transfers[0].files.len(), 2);
+        assert!(storage.outgoing_transfers_to_resume().await.is_empty());
+
+        let sync = storage.transfer_sync_state(id).await.unwrap();
+        assert!(matches!(sync.local_state, sync::TransferState::Active));
+        assert!(!sync.is_outgoing);
+
+        let in_flight = storage.incoming_files_to_resume(id).await;
+        assert_eq!(in_flight.len(), 1);
+        assert_eq!(in_flight[0].file_id, "id1");
+        assert_eq!(in_flight[0].base_dir, "/tmp");
+
+        let state = storage.incoming_file_sync_state(id, "id2").await.unwrap();
+        assert!(matches!(state.sync, sync::FileState::Terminal));
+        assert!(state.is_success);
+        assert!(storage.outgoing_file_sync_state(id, "id2").await.is_none());
+
+        storage
+            .save_checksum(id, "id2", ChecksumAlgorithm::Blake3, &[1, 2, 3])
+            .await;
+        let checksums = storage.fetch_checksums(id).await;
+        assert_eq!(checksums.len(), 2);
+        let checksum = checksums.iter().find(|csum| csum.file_id == "id2").unwrap();
+        assert_eq!(checksum.checksum.as_deref(), Some(&[1, 2, 3][..]));
+        assert_eq!(checksum.algorithm, Some(ChecksumAlgorithm::Blake3));
+        assert_eq!(storage.fetch_base_dirs_for_file(id, "id1").await, ["/tmp"]);
+
+        let finished = storage.finished_incoming_files(id).await;
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].final_path, "/tmp/dir/b.txt");
+
+        assert!(storage.transfer_sync_clear(id).await.is_some());
+        assert!(storage.incoming_transfers_to_resume().await.is_empty());
+        assert!(storage.incoming_file_sync_state(id, "id1").await.is_none());
+        assert!(storage.incoming_files_to_resume(id).await.is_empty());
+        assert!(storage.finished_incoming_files(id).await.is_empty());
+
+        // The temporary files are cleaned up after the transfer is gone
+        let locations = storage.fetch_temp_locations(id).await;
+        assert_eq!(locations.len(), 2);
+        assert!(locations.iter().all(|loc| loc.base_path == "/tmp"));
+        assert!(storage.fetch_temp_locations(id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resume_outgoing() {
+        let storage = MemoryStorage::new();
+        let id = Uuid::new_v4();
+
+        storage
+            .insert_transfer(&TransferInfo {
+                id,
+                peer: "1.2.3.4".to_string(),
+                files: TransferFiles::Outgoing(vec![TransferOutgoingPath {
+                    file_id: "id1".to_string(),
+                    relative_path: "a.txt".to_string(),
+                    uri: "file:///dir/a.txt".parse().unwrap(),
+                    size: 1024,
+                    is_archive: true,
+                }]),
+            })
+            .await;
+
+        storage
+            .insert_outgoing_path_reject_state(id, "id1", true, 0)
+            .await;
+        storage
+            .update_outgoing_file_sync_states(id, "id1", sync::FileState::Terminal)
+            .await;
+
+        let transfers = storage.outgoing_transfers_to_resume().await;
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].files[0].uri.as_str(), "file:///dir/a.txt");
+        assert!(transfers[0].files[0].is_archive);
+
+        let state = storage.outgoing_file_sync_state(id, "id1").await.unwrap();
+        assert!(matches!(state.sync, sync::FileState::Terminal));
+        assert!(state.is_rejected);
+        assert!(!state.is_success);
+    }
+}
@@ -0,0 +1,162 @@
+//! Salvages what's possible out of a database that can't be opened anymore.
+//! The broken file is kept next to the original one and the rows that are
+//! still readable are copied into a fresh database
+
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, OpenFlags};
+use slog::{warn, Logger};
+
+use crate::{cipher, QueryResult, StorageKey};
+
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// Where the broken database was moved, `None` if there was no file
+    pub backup_path: Option<PathBuf>,
+    /// Problems found by the SQLite integrity check, empty if it passed
+    pub integrity_errors: Vec<String>,
+    pub recovered_transfers: usize,
+    /// The recovered transfers that can still be resumed
+    pub resumable_transfers: usize,
+    /// Tables that could not be copied
+    pub lost_tables: Vec<String>,
+}
+
+pub(crate) fn integrity_check(path: &Path, key: Option<&StorageKey>) -> Vec<String> {
+    let check = || {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if let Some(key) = key {
+            cipher::apply_key(&conn, key)?;
+        }
+
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<QueryResult<Vec<_>>>()?;
+        Ok::<_, crate::Error>(rows)
+    };
+
+    match check() {
+        Ok(rows) => rows.into_iter().filter(|row| row != "ok").collect(),
+        Err(err) => vec![err.to_string()],
+    }
+}
+
+/// Moves the database file together with its journal aside
+pub(crate) fn backup(path: &Path) -> io::Result<Option<PathBuf>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let with_suffix = |path: &Path, suffix: &str| {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+
+    let backup = with_suffix(path, &format!(".corrupted-{timestamp}"));
+    fs::rename(path, &backup)?;
+
+    // The journal is needed to read the backup consistently
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let file = with_suffix(path, suffix);
+        if file.exists() {
+            fs::rename(&file, with_suffix(&backup, suffix))?;
+        }
+    }
+
+    Ok(Some(backup))
+}
+
+/// Copies the rows of every table from the backup into the freshly created
+/// database. Only the columns present in both schemas are copied so that a
+/// database left by a failed migration can be salvaged too
+pub(crate) fn salvage(
+    logger: &Logger,
+    conn: &Connection,
+    backup: &Path,
+    key: Option<&StorageKey>,
+    report: &mut RecoveryReport,
+) -> crate::Result<()> {
+    cipher::attach(conn, &backup.to_string_lossy(), "old", key)?;
+
+    let res = copy_tables(logger, conn, report);
+
+    conn.execute_batch("PRAGMA foreign_keys = ON; DETACH DATABASE old")?;
+    res?;
+
+    report.recovered_transfers = conn.query_row(
+        "SELECT count(*) FROM transfers WHERE NOT is_deleted",
+        [],
+        |row| row.get(0),
+    )?;
+    report.resumable_transfers =
+        conn.query_row("SELECT count(*) FROM sync_transfer", [], |row| row.get(0))?;
+
+    Ok(())
+}
+
+fn copy_tables(logger: &Logger, conn: &Connection, report: &mut RecoveryReport) -> QueryResult<()> {
+    // Fails early if the file is not a database at all
+    conn.query_row("SELECT count(*) FROM old.sqlite_master", [], |_| Ok(()))?;
+
+    // Some of the rows might be orphaned, it's better to keep them than to lose
+    // the whole table
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+
+    // The creation order puts the referenced tables first
+    let tables = conn
+        .prepare(
+            "SELECT name FROM main.sqlite_master WHERE type = 'table' AND name NOT LIKE \
+             'sqlite_%' ORDER BY rowid",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<QueryResult<Vec<_>>>()?;
+
+    for table in tables {
+        if let Err(err) = copy_table(conn, &table) {
+            warn!(logger, "Failed to salvage table"; "table" => &table, "error" => %err);
+            report.lost_tables.push(table);
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_table(conn: &Connection, table: &str) -> QueryResult<usize> {
+    let columns = |schema: &str| {
+        conn.prepare(&format!("PRAGMA {schema}.table_info(\"{table}\")"))?
+            .query_map([], |row| row.get::<_, String>("name"))?
+            .collect::<QueryResult<Vec<_>>>()
+    };
+
+    let old = columns("old")?;
+    let common: Vec<_> = columns("main")?
+        .into_iter()
+        .filter(|column| old.contains(column))
+        .map(|column| format!("\"{column}\""))
+        .collect();
+
+    // The table does not exist in the old database
+    if common.is_empty() {
+        return Ok(0);
+    }
+
+    let common = common.join(", ");
+    conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO main.\"{table}\" ({common}) SELECT {common} FROM old.\"{table}\""
+        ),
+        [],
+    )
+}
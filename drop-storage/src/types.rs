@@ -1,7 +1,7 @@
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, str::FromStr};
 
 use chrono::NaiveDateTime;
-use serde::Serialize;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
 use crate::sync;
 
@@ -15,7 +15,55 @@ where
     serializer.serialize_i64(timestamp.and_utc().timestamp_millis())
 }
 
-#[derive(Serialize)]
+fn deserialize_datetime<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let millis = i64::deserialize(deserializer)?;
+
+    chrono::DateTime::from_timestamp(
+        millis.div_euclid(1000),
+        (millis.rem_euclid(1000) * 1_000_000) as u32,
+    )
+    .map(|datetime| datetime.naive_utc())
+    .ok_or_else(|| D::Error::custom(format!("Timestamp out of range: {millis}")))
+}
+
+// The checksums are written as hex strings
+fn serialize_checksum<S>(checksum: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    match checksum {
+        Some(checksum) => {
+            let hex: String = checksum.iter().map(|byte| format!("{byte:02x}")).collect();
+            serializer.serialize_some(&hex)
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_checksum<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(hex) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(D::Error::custom(format!("Invalid checksum: {hex}")));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(|_| D::Error::custom(format!("Invalid checksum: {hex}")))
+}
+
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "state")]
 pub enum OutgoingPathStateEventData {
     #[serde(rename = "started")]
@@ -30,7 +78,7 @@ pub enum OutgoingPathStateEventData {
     Paused { bytes_sent: i64 },
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "state")]
 pub enum IncomingPathStateEventData {
     #[serde(rename = "pending")]
@@ -50,27 +98,33 @@ pub enum IncomingPathStateEventData {
     Paused { bytes_received: i64 },
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct OutgoingPathStateEvent {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub path_id: i64,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     #[serde(flatten)]
     pub data: OutgoingPathStateEventData,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct IncomingPathStateEvent {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub path_id: i64,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     #[serde(flatten)]
     pub data: IncomingPathStateEventData,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "state")]
 pub enum TransferStateEventData {
     #[serde(rename = "cancel")]
@@ -79,41 +133,50 @@ pub enum TransferStateEventData {
     Failed { status_code: i64 },
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct TransferStateEvent {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub transfer_id: TransferId,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     #[serde(flatten)]
     pub data: TransferStateEventData,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u32)]
 pub enum TransferType {
     Incoming = 0,
     Outgoing = 1,
 }
 
+#[derive(Clone)]
 pub struct TransferIncomingPath {
     pub file_id: FileId,
     pub relative_path: String,
     pub size: i64,
+    pub is_archive: bool,
 }
 
+#[derive(Clone)]
 pub struct TransferOutgoingPath {
     pub file_id: FileId,
     pub relative_path: String,
     pub uri: url::Url,
     pub size: i64,
+    pub is_archive: bool,
 }
 
+#[derive(Clone)]
 pub enum TransferFiles {
     Incoming(Vec<TransferIncomingPath>),
     Outgoing(Vec<TransferOutgoingPath>),
 }
 
+#[derive(Clone)]
 pub struct TransferInfo {
     pub id: TransferId,
     pub peer: String,
@@ -123,12 +186,26 @@ pub struct TransferInfo {
 pub struct FileChecksum {
     pub file_id: FileId,
     pub checksum: Option<Vec<u8>>,
+    // The algorithm the checksum was calculated with
+    pub algorithm: Option<drop_core::ChecksumAlgorithm>,
+}
+
+// Identifies the content of the outgoing file the checksum was calculated for.
+// The `limit` is the number of the leading bytes covered by the checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumCacheKey {
+    pub uri: url::Url,
+    pub size: u64,
+    pub modified_at_ns: i64,
+    pub limit: u64,
+    pub algorithm: drop_core::ChecksumAlgorithm,
 }
 
 pub struct IncomingFileToRetry {
     pub file_id: String,
     pub subpath: String,
     pub size: u64,
+    pub is_archive: bool,
 }
 
 pub struct IncomingTransferToRetry {
@@ -147,6 +224,7 @@ pub struct OutgoingFileToRetry {
     pub subpath: String,
     pub uri: url::Url,
     pub size: i64,
+    pub is_archive: bool,
 }
 
 pub struct OutgoingTransferToRetry {
@@ -167,7 +245,7 @@ pub struct FileSyncState {
     pub is_failed: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "type", content = "paths")]
 pub enum DbTransferType {
     #[serde(rename = "incoming")]
@@ -176,10 +254,13 @@ pub enum DbTransferType {
     Outgoing(Vec<OutgoingPath>),
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Transfer {
     pub id: TransferId,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     pub peer_id: String,
     pub states: Vec<TransferStateEvent>,
@@ -187,11 +268,102 @@ pub struct Transfer {
     pub transfer_type: DbTransferType,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStateFilter {
+    /// Canceled or failed transfers and the ones with all the files finished
+    Terminal,
+    /// Transfers that can still make progress
+    NonTerminal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Oldest first
+    #[default]
+    Ascending,
+    /// Newest first
+    Descending,
+}
+
+/// Marks the position of the last transfer on a page. Opaque to the callers,
+/// can be passed around as a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferCursor(pub(crate) i64);
+
+impl fmt::Display for TransferCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for TransferCursor {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// Filters of the transfer history query. All the filters are optional and are
+/// combined with AND
+#[derive(Debug, Clone, Default)]
+pub struct TransferQuery {
+    pub direction: Option<TransferType>,
+    pub peer: Option<String>,
+    pub state: Option<TransferStateFilter>,
+    /// Matches transfers with any file path containing this text. The match is
+    /// case insensitive for ASCII characters
+    pub filename: Option<String>,
+    /// The transfers are ordered by creation
+    pub order: SortOrder,
+    /// Continues after the last transfer of the previous page
+    pub cursor: Option<TransferCursor>,
+    /// The maximum number of transfers on the page, unlimited if not set
+    pub limit: Option<u32>,
+    /// Leaves out the state history of the files. The transferred bytes are
+    /// still reported
+    pub summary: bool,
+}
+
+#[derive(Default)]
+pub struct TransferPage {
+    pub transfers: Vec<Transfer>,
+    /// Set when there are more transfers matching the query
+    pub next_cursor: Option<TransferCursor>,
+}
+
+/// Totals of the transfer history with a single peer. Only the transfers still
+/// in the history are counted
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStats {
+    pub peer: String,
+    pub transfers: u32,
+    /// Includes the partially sent files
+    pub bytes_sent: i64,
+    /// Includes the partially received files
+    pub bytes_received: i64,
+    /// The number of files sent completely
+    pub files_sent: u32,
+    /// The number of files received completely
+    pub files_received: u32,
+    /// The number of files that failed, in both directions
+    pub files_failed: u32,
+    /// The latest transfer or file state change
+    pub last_seen: NaiveDateTime,
+    /// The average speed of the completed files in bytes per second, measured
+    /// from the first start of a file to its completion. `None` if no file was
+    /// completed
+    pub throughput: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct OutgoingPath {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub id: i64,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     pub transfer_id: TransferId,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -202,19 +374,35 @@ pub struct OutgoingPath {
     pub file_id: String,
     pub bytes: i64,
     pub bytes_sent: i64,
+    #[serde(default)]
+    pub is_archive: bool,
     pub states: Vec<OutgoingPathStateEvent>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct IncomingPath {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing, default)]
     pub id: i64,
-    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
     pub created_at: NaiveDateTime,
     pub transfer_id: TransferId,
     pub relative_path: String,
     pub file_id: String,
     pub bytes: i64,
     pub bytes_received: i64,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_checksum",
+        deserialize_with = "deserialize_checksum"
+    )]
+    pub checksum: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_algorithm: Option<drop_core::ChecksumAlgorithm>,
+    #[serde(default)]
+    pub is_archive: bool,
     pub states: Vec<IncomingPathStateEvent>,
 }
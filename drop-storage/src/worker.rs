@@ -0,0 +1,177 @@
+//! Keeps the SQLite work off the async runtime. All the writes go through a
+//! dedicated thread owning the read-write connection, where the queued inserts
+//! are committed in batches. The reads are done on the blocking pool with a
+//! separate read-only connection, which doesn't block the writer in WAL mode
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use rusqlite::Connection;
+use slog::{error, Logger};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{Error, Result};
+
+// Upper bound of the inserts committed in a single transaction
+pub(crate) const MAX_BATCH_SIZE: usize = 256;
+
+type Insert = Box<dyn FnOnce(&Connection) -> Result<()> + Send>;
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
+
+enum Task {
+    Insert {
+        // Logged when the insert fails
+        context: &'static str,
+        insert: Insert,
+    },
+    Job(Job),
+}
+
+pub(crate) struct Writer {
+    tx: Option<mpsc::UnboundedSender<Task>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Writer {
+    pub(crate) fn spawn(logger: Logger, conn: Connection) -> Result<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let thread = thread::Builder::new()
+            .name("drop-storage".to_string())
+            .spawn(move || run(logger, conn, rx))?;
+
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        })
+    }
+
+    /// Queues the insert without waiting for it. The queued inserts are
+    /// committed together, a failing one is rolled back alone
+    pub(crate) fn queue(
+        &self,
+        context: &'static str,
+        insert: impl FnOnce(&Connection) -> Result<()> + Send + 'static,
+    ) {
+        self.send(Task::Insert {
+            context,
+            insert: Box::new(insert),
+        });
+    }
+
+    /// Runs the job once all the previously queued inserts are committed
+    pub(crate) async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send(Task::Job(Box::new(move |conn| {
+            let _ = tx.send(job(conn));
+        })));
+
+        rx.await
+            .map_err(|_| Error::InternalError("The storage writer is stopped".to_string()))?
+    }
+
+    fn send(&self, task: Task) {
+        if let Some(tx) = &self.tx {
+            // The thread is gone only if it panicked, the caller of `run()`
+            // gets an error in such case
+            let _ = tx.send(task);
+        }
+    }
+}
+
+impl Drop for Writer {
+    // Waits for the queued inserts so that the database is closed once the
+    // storage is dropped
+    fn drop(&mut self) {
+        self.tx.take();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(logger: Logger, mut conn: Connection, mut rx: mpsc::UnboundedReceiver<Task>) {
+    let mut next = rx.blocking_recv();
+
+    while let Some(task) = next.take() {
+        match task {
+            Task::Job(job) => job(&mut conn),
+            Task::Insert { context, insert } => {
+                let mut batch = vec![(context, insert)];
+
+                while batch.len() < MAX_BATCH_SIZE {
+                    match rx.try_recv() {
+                        Ok(Task::Insert { context, insert }) => batch.push((context, insert)),
+                        Ok(job) => {
+                            next = Some(job);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+
+                commit(&logger, &mut conn, batch);
+            }
+        }
+
+        if next.is_none() {
+            next = rx.blocking_recv();
+        }
+    }
+}
+
+fn commit(logger: &Logger, conn: &mut Connection, batch: Vec<(&'static str, Insert)>) {
+    let task = || {
+        let mut tx = conn.transaction()?;
+
+        for (context, insert) in batch {
+            let savepoint = tx.savepoint()?;
+
+            match insert(&savepoint) {
+                Ok(()) => savepoint.commit()?,
+                Err(e) => error!(logger, "{context}"; "error" => %e),
+            }
+        }
+
+        tx.commit()?;
+        Ok::<(), Error>(())
+    };
+
+    if let Err(e) = task() {
+        error!(logger, "Failed to commit the queued inserts"; "error" => %e);
+    }
+}
+
+/// The read-only connection used next to the [`Writer`]
+pub(crate) struct Reader {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Reader {
+    pub(crate) fn new(conn: Connection) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    pub(crate) async fn read<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("Poisoned lock");
+            job(&mut conn)
+        })
+        .await
+        .map_err(|e| Error::InternalError(format!("The storage read failed: {e}")))?
+    }
+}
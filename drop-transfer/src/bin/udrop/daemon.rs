@@ -0,0 +1,663 @@
+//! JSON-RPC 2.0 control interface over a Unix domain socket. The requests and
+//! the responses are single line JSON objects terminated with `\n`. The methods
+//! mirror the `NordDrop` API:
+//!
+//! * `new_transfer {peer, paths}` returns the transfer ID
+//! * `download_file {transfer_id, file_id, destination}`
+//! * `reject_file {transfer_id, file_id}`
+//! * `finalize_transfer {transfer_id}`
+//! * `remove_file {transfer_id, file_id}`
+//! * `get_transfer {transfer_id}` and `active_transfers`
+//! * `transfers_since {since}`
+//! * `purge_transfers {transfer_ids}` and `purge_transfers_until {until}`
+//! * `export_history {path}` returns the number of exported transfers
+//! * `network_refresh`
+//!
+//! After `subscribe {events}` the client receives the service events as the
+//! `event` notifications, optionally only the listed event names. A client not
+//! keeping up gets the `events_lost` notification with the number of skipped
+//! events. `shutdown` stops the service gracefully, same as SIGINT and SIGTERM.
+
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use clap::ArgMatches;
+use drop_config::DropConfig;
+use drop_storage::Storage;
+use drop_transfer::{
+    file,
+    snapshot::{Direction, FileActivity, TransferSnapshot},
+    OutgoingTransfer, Service,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use slog_scope::{info, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    output::{self, Output},
+    Instance,
+};
+
+// The number of events buffered for each subscriber
+const EVENT_BUFFER: usize = 1024;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// The range -32000 to -32099 is reserved for the server errors
+const SERVER_ERROR: i64 = -32000;
+const NOT_RUNNING: i64 = -32001;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn server(err: impl Display) -> Self {
+        Self::new(SERVER_ERROR, err)
+    }
+
+    fn invalid_params(err: impl Display) -> Self {
+        Self::new(INVALID_PARAMS, err)
+    }
+
+    fn not_running() -> Self {
+        Self::new(NOT_RUNNING, "The service is shutting down")
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    // Missing for the notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct NewTransfer {
+    peer: String,
+    paths: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FileParams {
+    transfer_id: Uuid,
+    file_id: String,
+}
+
+#[derive(Deserialize)]
+struct DownloadFile {
+    transfer_id: Uuid,
+    file_id: String,
+    destination: String,
+}
+
+#[derive(Deserialize)]
+struct TransferParams {
+    transfer_id: Uuid,
+}
+
+#[derive(Deserialize)]
+struct TransfersSince {
+    since: i64,
+}
+
+#[derive(Deserialize)]
+struct PurgeTransfers {
+    transfer_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PurgeTransfersUntil {
+    until: i64,
+}
+
+#[derive(Deserialize)]
+struct ExportHistory {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Subscribe {
+    events: Option<HashSet<String>>,
+}
+
+struct Subscription {
+    rx: broadcast::Receiver<Arc<Value>>,
+    // All the events when not set
+    events: Option<HashSet<String>>,
+}
+
+impl Subscription {
+    fn accepts(&self, ev: &Value) -> bool {
+        match (&self.events, ev["event"].as_str()) {
+            (None, _) => true,
+            (Some(events), Some(name)) => events.contains(name),
+            (Some(_), None) => false,
+        }
+    }
+
+    async fn next(&mut self) -> Value {
+        loop {
+            match self.rx.recv().await {
+                Ok(ev) if self.accepts(&ev) => return notification("event", (*ev).clone()),
+                Ok(_) => (),
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    return notification("events_lost", json!({ "count": count }))
+                }
+                Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+            }
+        }
+    }
+}
+
+struct Daemon {
+    // Taken out on shutdown
+    service: Mutex<Option<Service>>,
+    storage: Arc<Storage>,
+    config: Arc<DropConfig>,
+    events: broadcast::Sender<Arc<Value>>,
+    shutdown: CancellationToken,
+    // Any user with the access to the socket file when empty
+    allowed_uids: HashSet<u32>,
+}
+
+impl Daemon {
+    fn is_allowed(&self, stream: &UnixStream) -> bool {
+        if self.allowed_uids.is_empty() {
+            return true;
+        }
+
+        match stream.peer_cred() {
+            Ok(cred) => self.allowed_uids.contains(&cred.uid()),
+            Err(err) => {
+                warn!("Failed to get the control client credentials: {err}");
+                false
+            }
+        }
+    }
+
+    async fn serve(self: Arc<Self>, stream: UnixStream) {
+        if !self.is_allowed(&stream) {
+            warn!("Refusing the control connection of a not allowed user");
+            return;
+        }
+
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut sub: Option<Subscription> = None;
+
+        loop {
+            let msg = tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => match self.handle(&line, &mut sub).await {
+                        Some(resp) => resp,
+                        None => continue,
+                    },
+                    Ok(None) => break,
+                    Err(err) => {
+                        warn!("Failed to read the control request: {err}");
+                        break;
+                    }
+                },
+                ev = async {
+                    match &mut sub {
+                        Some(sub) => sub.next().await,
+                        None => std::future::pending().await,
+                    }
+                } => ev,
+                _ = self.shutdown.cancelled() => break,
+            };
+
+            if let Err(err) = write_line(&mut writer, &msg).await {
+                warn!("Failed to write to the control connection: {err}");
+                break;
+            }
+        }
+    }
+
+    async fn handle(&self, line: &str, sub: &mut Option<Subscription>) -> Option<Value> {
+        let req: Value = match serde_json::from_str(line) {
+            Ok(req) => req,
+            Err(err) => return Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, err)))),
+        };
+
+        let req: Request = match serde_json::from_value(req) {
+            Ok(req) if req.jsonrpc == "2.0" => req,
+            Ok(_) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(
+                        INVALID_REQUEST,
+                        "Only JSON-RPC 2.0 is supported",
+                    )),
+                ))
+            }
+            Err(err) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(INVALID_REQUEST, err)),
+                ))
+            }
+        };
+
+        let result = self.call(&req.method, req.params, sub).await;
+        if let Err(err) = &result {
+            warn!("Control call {:?} failed: {}", req.method, err.message);
+        }
+
+        req.id.map(|id| response(id, result))
+    }
+
+    async fn call(&self, method: &str, params: Value, sub: &mut Option<Subscription>) -> RpcResult {
+        match method {
+            "new_transfer" => self.new_transfer(parse(params)?).await,
+            "download_file" => {
+                let params: DownloadFile = parse(params)?;
+
+                let mut service = self.service.lock().await;
+                service
+                    .as_mut()
+                    .ok_or_else(RpcError::not_running)?
+                    .download(
+                        params.transfer_id,
+                        &params.file_id.into(),
+                        &params.destination,
+                    )
+                    .await
+                    .map_err(RpcError::server)?;
+
+                Ok(Value::Null)
+            }
+            "reject_file" => {
+                let params: FileParams = parse(params)?;
+
+                let service = self.service.lock().await;
+                service
+                    .as_ref()
+                    .ok_or_else(RpcError::not_running)?
+                    .reject(params.transfer_id, params.file_id.into())
+                    .await
+                    .map_err(RpcError::server)?;
+
+                Ok(Value::Null)
+            }
+            "finalize_transfer" => {
+                let params: TransferParams = parse(params)?;
+
+                let mut service = self.service.lock().await;
+                service
+                    .as_mut()
+                    .ok_or_else(RpcError::not_running)?
+                    .cancel_all(params.transfer_id)
+                    .await
+                    .map_err(RpcError::server)?;
+
+                Ok(Value::Null)
+            }
+            "remove_file" => {
+                let params: FileParams = parse(params)?;
+
+                self.storage
+                    .remove_transfer_file(params.transfer_id, &params.file_id)
+                    .await
+                    .ok_or_else(|| RpcError::invalid_params("Cannot remove the file"))?;
+
+                Ok(Value::Null)
+            }
+            "get_transfer" => {
+                let params: TransferParams = parse(params)?;
+
+                let service = self.service.lock().await;
+                let snapshot = service
+                    .as_ref()
+                    .ok_or_else(RpcError::not_running)?
+                    .transfer_snapshot(params.transfer_id)
+                    .await;
+
+                Ok(snapshot.as_ref().map_or(Value::Null, snapshot_json))
+            }
+            "active_transfers" => {
+                let service = self.service.lock().await;
+                let snapshots = service
+                    .as_ref()
+                    .ok_or_else(RpcError::not_running)?
+                    .active_transfers()
+                    .await;
+
+                Ok(snapshots.iter().map(snapshot_json).collect())
+            }
+            "transfers_since" => {
+                let params: TransfersSince = parse(params)?;
+                Ok(json!(self.storage.transfers_since(params.since).await))
+            }
+            "purge_transfers" => {
+                let params: PurgeTransfers = parse(params)?;
+                self.storage.purge_transfers(&params.transfer_ids).await;
+                Ok(Value::Null)
+            }
+            "purge_transfers_until" => {
+                let params: PurgeTransfersUntil = parse(params)?;
+                self.storage.purge_transfers_until(params.until).await;
+                Ok(Value::Null)
+            }
+            "export_history" => {
+                let params: ExportHistory = parse(params)?;
+
+                let count = self
+                    .storage
+                    .export_history(&params.path)
+                    .await
+                    .map_err(RpcError::server)?;
+
+                Ok(count.into())
+            }
+            "network_refresh" => {
+                let mut service = self.service.lock().await;
+                service
+                    .as_mut()
+                    .ok_or_else(RpcError::not_running)?
+                    .network_refresh();
+
+                Ok(Value::Null)
+            }
+            "subscribe" => {
+                let params: Subscribe = parse(params)?;
+
+                *sub = Some(Subscription {
+                    rx: self.events.subscribe(),
+                    events: params.events,
+                });
+
+                Ok(Value::Null)
+            }
+            "unsubscribe" => {
+                *sub = None;
+                Ok(Value::Null)
+            }
+            "shutdown" => {
+                self.shutdown.cancel();
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method:?}"),
+            )),
+        }
+    }
+
+    async fn new_transfer(&self, params: NewTransfer) -> RpcResult {
+        let peer = tokio::net::lookup_host((params.peer.as_str(), drop_config::PORT))
+            .await
+            .map_err(RpcError::invalid_params)?
+            .next()
+            .ok_or_else(|| RpcError::invalid_params("The peer address did not resolve"))?;
+
+        let mut files = file::GatherCtx::new(&self.config);
+        for path in &params.paths {
+            files.gather_from_path(path).map_err(RpcError::server)?;
+        }
+
+        let xfer = OutgoingTransfer::new(peer.ip(), files.take(), &self.config)
+            .map_err(RpcError::server)?;
+        let transfer_id = xfer.id();
+
+        let mut service = self.service.lock().await;
+        service
+            .as_mut()
+            .ok_or_else(RpcError::not_running)?
+            .send_request(xfer)
+            .await;
+
+        Ok(transfer_id.to_string().into())
+    }
+}
+
+pub async fn run(inst: Instance, out: &mut Output, matches: &ArgMatches) -> anyhow::Result<()> {
+    let socket = matches
+        .get_one::<PathBuf>("socket")
+        .expect("Missing `socket` flag");
+    let mode = *matches
+        .get_one::<u32>("socket-mode")
+        .expect("Missing `socket-mode` flag");
+
+    let mut allowed_uids: HashSet<u32> = matches
+        .get_many::<u32>("allow-uid")
+        .map(|uids| uids.copied().collect())
+        .unwrap_or_default();
+    if !allowed_uids.is_empty() {
+        // SAFETY: getuid() has no preconditions and never fails
+        allowed_uids.insert(unsafe { libc::getuid() });
+    }
+
+    let setup = signal(SignalKind::terminate())
+        .context("Failed to handle SIGTERM")
+        .and_then(|sigterm| Ok((sigterm, bind(socket, mode)?)));
+    let (mut sigterm, listener) = match setup {
+        Ok(setup) => setup,
+        Err(err) => {
+            inst.stop(out).await;
+            return Err(err);
+        }
+    };
+
+    let Instance {
+        service,
+        storage,
+        mut rx,
+        config,
+    } = inst;
+
+    let daemon = Arc::new(Daemon {
+        service: Mutex::new(Some(service)),
+        storage,
+        config,
+        events: broadcast::channel(EVENT_BUFFER).0,
+        shutdown: CancellationToken::new(),
+        allowed_uids,
+    });
+
+    out.info(&format!("Accepting the control connections on {socket:?}"));
+
+    // Ends when the service is stopped and the event channel closes
+    let event_loop = async {
+        let mut dispatch = drop_transfer::StorageDispatch::new(&*daemon.storage);
+
+        while let Some((ev, _)) = rx.recv().await {
+            dispatch.handle_event(&ev).await;
+            out.event(&ev);
+
+            // Fails only when nobody is subscribed
+            let _ = daemon.events.send(Arc::new(output::event_json(&ev)));
+        }
+    };
+
+    let control = async {
+        loop {
+            tokio::select! {
+                conn = listener.accept() => match conn {
+                    Ok((stream, _)) => {
+                        tokio::spawn(daemon.clone().serve(stream));
+                    }
+                    Err(err) => warn!("Failed to accept the control connection: {err}"),
+                },
+                _ = daemon.shutdown.cancelled() => break,
+                _ = tokio::signal::ctrl_c() => break,
+                _ = sigterm.recv() => break,
+            }
+        }
+
+        info!("Stopping the service");
+
+        // Disconnects the clients as well
+        daemon.shutdown.cancel();
+
+        let service = daemon.service.lock().await.take();
+        if let Some(service) = service {
+            service.stop().await;
+        }
+    };
+
+    tokio::join!(event_loop, control);
+
+    fs::remove_file(socket).with_context(|| format!("Failed to remove socket {socket:?}"))?;
+    Ok(())
+}
+
+/// Creates the socket with the given permissions. A socket left by a daemon
+/// which did not exit cleanly is replaced, any other file is not touched
+fn bind(path: &Path, mode: u32) -> anyhow::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) => {
+            anyhow::ensure!(
+                meta.file_type().is_socket(),
+                "{path:?} exists and is not a socket"
+            );
+            anyhow::ensure!(
+                std::os::unix::net::UnixStream::connect(path).is_err(),
+                "Another daemon is listening on {path:?}"
+            );
+
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {path:?}"))?;
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err).with_context(|| format!("Failed to inspect {path:?}")),
+    }
+
+    // The socket gets the permissions from the umask when bound. Nobody else can
+    // reach it inside the private directory until it's moved in place with the
+    // requested mode
+    let name = path.file_name().context("Socket path has no file name")?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .with_context(|| format!("Failed to create directory {private_dir:?}"))?;
+
+    let tmp_path = private_dir.join(name);
+    let res: anyhow::Result<_> = (|| {
+        let listener = UnixListener::bind(&tmp_path)
+            .with_context(|| format!("Failed to bind socket {path:?}"))?;
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set the permissions of {path:?}"))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to move the socket to {path:?}"))?;
+
+        Ok(listener)
+    })();
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    let _ = fs::remove_dir(&private_dir);
+
+    res
+}
+
+/// Parses the `--socket-mode` octal permissions, e.g. `660`
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("Invalid octal permissions {mode:?}"))
+}
+
+fn parse<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // The params may be omitted when all of them are optional
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+fn response(id: Value, result: RpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+async fn write_line(writer: &mut OwnedWriteHalf, msg: &Value) -> std::io::Result<()> {
+    let mut line = msg.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+fn snapshot_json(xfer: &TransferSnapshot) -> Value {
+    let files: Vec<_> = xfer
+        .files
+        .iter()
+        .map(|file| {
+            json!({
+                "id": file.id.to_string(),
+                "subpath": file.subpath,
+                "size": file.size,
+                "transferred": file.transferred,
+                "activity": match file.activity {
+                    FileActivity::Idle => "idle",
+                    FileActivity::Pending => "pending",
+                    FileActivity::Throttled => "throttled",
+                    FileActivity::Preparing => "preparing",
+                    FileActivity::InFlight => "in_flight",
+                    FileActivity::Completed => "completed",
+                    FileActivity::Rejected => "rejected",
+                    FileActivity::Failed => "failed",
+                },
+            })
+        })
+        .collect();
+
+    json!({
+        "id": xfer.id.to_string(),
+        "peer": xfer.peer.to_string(),
+        "direction": match xfer.direction {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+        },
+        "connected": xfer.connected,
+        "protocol_version": xfer.protocol_version,
+        "reconnects": xfer.reconnects,
+        "connection_retries": xfer.connection_retries,
+        "files": files,
+    })
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use anyhow::Context;
+use drop_storage::{
+    types::{
+        DbTransferType, IncomingPathStateEventData, OutgoingPathStateEventData, Transfer,
+        TransferStateEventData, TransferType,
+    },
+    SortOrder, Storage, TransferQuery,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::output::{human_bytes, Output};
+
+pub async fn list(storage: &Storage, out: &mut Output, query: TransferQuery) {
+    let page = storage.query_transfers(&query).await;
+
+    if out.is_json() {
+        out.value(json!(page.transfers));
+        return;
+    }
+
+    for xfer in &page.transfers {
+        let (direction, files, size, transferred) = totals(xfer);
+        out.info(&format!(
+            "{} {} {:<8} {:<15} {:<9} {files} file(s) {}/{}",
+            xfer.id,
+            xfer.created_at.format("%Y-%m-%d %H:%M:%S"),
+            direction,
+            xfer.peer_id,
+            transfer_state(xfer),
+            human_bytes(transferred),
+            human_bytes(size),
+        ));
+    }
+}
+
+pub async fn status(storage: &Storage, out: &mut Output, transfer_id: Uuid) -> anyhow::Result<()> {
+    let xfer = storage
+        .transfers_since(0)
+        .await
+        .into_iter()
+        .find(|xfer| xfer.id == transfer_id)
+        .with_context(|| format!("Transfer {transfer_id} is not in the history"))?;
+
+    if out.is_json() {
+        out.value(json!(xfer));
+        return Ok(());
+    }
+
+    let (direction, _, size, transferred) = totals(&xfer);
+    out.info(&format!("Transfer:    {}", xfer.id));
+    out.info(&format!("Peer:        {}", xfer.peer_id));
+    out.info(&format!("Direction:   {direction}"));
+    out.info(&format!(
+        "Created:     {}",
+        xfer.created_at.format("%Y-%m-%d %H:%M:%S")
+    ));
+    out.info(&format!("State:       {}", transfer_state(&xfer)));
+    out.info(&format!(
+        "Transferred: {} of {}",
+        human_bytes(transferred),
+        human_bytes(size)
+    ));
+
+    match &xfer.transfer_type {
+        DbTransferType::Incoming(paths) => {
+            for path in paths {
+                out.info(&format!(
+                    "  {} {} {:<9} {}/{}",
+                    path.file_id,
+                    path.relative_path,
+                    incoming_path_state(path.states.last().map(|s| &s.data)),
+                    human_bytes(path.bytes_received as _),
+                    human_bytes(path.bytes as _),
+                ));
+            }
+        }
+        DbTransferType::Outgoing(paths) => {
+            for path in paths {
+                out.info(&format!(
+                    "  {} {} {:<9} {}/{}",
+                    path.file_id,
+                    path.relative_path,
+                    outgoing_path_state(path.states.last().map(|s| &s.data)),
+                    human_bytes(path.bytes_sent as _),
+                    human_bytes(path.bytes as _),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn purge(storage: &Storage, transfer_ids: &[String], until: Option<i64>) {
+    if !transfer_ids.is_empty() {
+        storage.purge_transfers(transfer_ids).await;
+    }
+    if let Some(until) = until {
+        storage.purge_transfers_until(until).await;
+    }
+}
+
+pub async fn export(storage: &Storage, out: &mut Output, path: &Path) -> anyhow::Result<()> {
+    let count = storage
+        .export_history(path)
+        .await
+        .context("Failed to export the history")?;
+
+    if out.is_json() {
+        out.value(json!({ "exported": count, "path": path }));
+    } else {
+        out.info(&format!("Exported {count} transfer(s) to {path:?}"));
+    }
+
+    Ok(())
+}
+
+pub fn query(
+    direction: Option<TransferType>,
+    peer: Option<String>,
+    limit: Option<u32>,
+) -> TransferQuery {
+    TransferQuery {
+        direction,
+        peer,
+        limit,
+        order: SortOrder::Descending,
+        ..Default::default()
+    }
+}
+
+fn totals(xfer: &Transfer) -> (&'static str, usize, u64, u64) {
+    match &xfer.transfer_type {
+        DbTransferType::Incoming(paths) => (
+            "incoming",
+            paths.len(),
+            paths.iter().map(|p| p.bytes as u64).sum(),
+            paths.iter().map(|p| p.bytes_received as u64).sum(),
+        ),
+        DbTransferType::Outgoing(paths) => (
+            "outgoing",
+            paths.len(),
+            paths.iter().map(|p| p.bytes as u64).sum(),
+            paths.iter().map(|p| p.bytes_sent as u64).sum(),
+        ),
+    }
+}
+
+fn transfer_state(xfer: &Transfer) -> &'static str {
+    match xfer.states.last().map(|s| &s.data) {
+        Some(TransferStateEventData::Cancel { .. }) => return "canceled",
+        Some(TransferStateEventData::Failed { .. }) => return "failed",
+        None => (),
+    }
+
+    let finished = match &xfer.transfer_type {
+        DbTransferType::Incoming(paths) => paths.iter().all(|p| {
+            matches!(
+                p.states.last().map(|s| &s.data),
+                Some(
+                    IncomingPathStateEventData::Completed { .. }
+                        | IncomingPathStateEventData::Failed { .. }
+                        | IncomingPathStateEventData::Rejected { .. }
+                )
+            )
+        }),
+        DbTransferType::Outgoing(paths) => paths.iter().all(|p| {
+            matches!(
+                p.states.last().map(|s| &s.data),
+                Some(
+                    OutgoingPathStateEventData::Completed
+                        | OutgoingPathStateEventData::Failed { .. }
+                        | OutgoingPathStateEventData::Rejected { .. }
+                )
+            )
+        }),
+    };
+
+    if finished {
+        "finished"
+    } else {
+        "active"
+    }
+}
+
+fn incoming_path_state(state: Option<&IncomingPathStateEventData>) -> &'static str {
+    match state {
+        None => "new",
+        Some(IncomingPathStateEventData::Pending { .. }) => "pending",
+        Some(IncomingPathStateEventData::Started { .. }) => "started",
+        Some(IncomingPathStateEventData::Failed { .. }) => "failed",
+        Some(IncomingPathStateEventData::Completed { .. }) => "completed",
+        Some(IncomingPathStateEventData::Rejected { .. }) => "rejected",
+        Some(IncomingPathStateEventData::Paused { .. }) => "paused",
+    }
+}
+
+fn outgoing_path_state(state: Option<&OutgoingPathStateEventData>) -> &'static str {
+    match state {
+        None => "new",
+        Some(OutgoingPathStateEventData::Started { .. }) => "started",
+        Some(OutgoingPathStateEventData::Failed { .. }) => "failed",
+        Some(OutgoingPathStateEventData::Completed) => "completed",
+        Some(OutgoingPathStateEventData::Rejected { .. }) => "rejected",
+        Some(OutgoingPathStateEventData::Paused { .. }) => "paused",
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, fs, net::IpAddr, path::Path};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD_NO_PAD as BASE64, Engine};
+use drop_auth::{PublicKey, SecretKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use drop_transfer::auth;
+
+/// The public keys of the peers. Read from a file with one `<IP> <KEY>` pair
+/// per line, `*` in place of the IP matches any peer. Empty lines and lines
+/// starting with `#` are skipped
+#[derive(Default)]
+struct PeerKeys {
+    peers: HashMap<IpAddr, [u8; PUBLIC_KEY_LENGTH]>,
+    any: Option<[u8; PUBLIC_KEY_LENGTH]>,
+}
+
+impl PeerKeys {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read peer keys from {path:?}"))?;
+
+        let mut keys = Self::default();
+        for (no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (peer, key) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("{path:?}:{}: expected `<IP> <KEY>`", no + 1))?;
+            let key = decode_key(key.trim().as_bytes())
+                .with_context(|| format!("{path:?}:{}: invalid public key", no + 1))?;
+
+            if peer == "*" {
+                keys.any = Some(key);
+            } else {
+                let peer = peer
+                    .parse()
+                    .with_context(|| format!("{path:?}:{}: invalid peer IP", no + 1))?;
+                keys.peers.insert(peer, key);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    fn get(&self, peer: IpAddr) -> Option<PublicKey> {
+        self.peers
+            .get(&peer)
+            .or(self.any.as_ref())
+            .map(|key| PublicKey::from(*key))
+    }
+}
+
+/// Builds the authentication context from the private key file, holding the
+/// raw 32 bytes or the base64 encoded key, and the peer keys file
+pub fn load(privkey: &Path, peers: &Path) -> anyhow::Result<auth::Context> {
+    let raw =
+        fs::read(privkey).with_context(|| format!("Failed to read private key {privkey:?}"))?;
+    let privkey: [u8; SECRET_KEY_LENGTH] =
+        decode_key(&raw).with_context(|| format!("Invalid private key in {privkey:?}"))?;
+
+    let peers = PeerKeys::load(peers)?;
+
+    Ok(auth::Context::new(
+        move || Some(SecretKey::from(privkey)),
+        move |peer| peers.get(peer),
+    ))
+}
+
+fn decode_key<const N: usize>(raw: &[u8]) -> anyhow::Result<[u8; N]> {
+    if let Ok(key) = raw.try_into() {
+        return Ok(key);
+    }
+
+    let text = std::str::from_utf8(raw).context("The key is neither raw nor base64")?;
+    let decoded = BASE64
+        .decode(text.trim().trim_end_matches('='))
+        .context("Invalid base64")?;
+
+    decoded
+        .try_into()
+        .map_err(|key: Vec<u8>| anyhow::anyhow!("Expected {N} bytes, got {}", key.len()))
+}
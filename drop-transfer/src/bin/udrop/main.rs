@@ -0,0 +1,494 @@
+//! Command line client of libdrop. Sends and receives files, inspects and
+//! manages the transfer history kept in the storage.
+//!
+//! The key material is read from files. The private key file holds the raw 32
+//! bytes or the base64 encoded key. The peers file holds one `<IP> <KEY>` pair
+//! per line with `*` matching any peer.
+//!
+//! libdrop always listens on the port 49111, run several instances on the
+//! different IP addresses, e.g. `127.0.0.2` and `127.0.0.3`, to test locally.
+
+#[cfg(unix)]
+mod daemon;
+mod history;
+mod keys;
+mod output;
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use clap::{arg, command, value_parser, ArgAction, ArgMatches, Command};
+use drop_config::DropConfig;
+use drop_storage::{types::TransferType, Storage};
+use drop_transfer::{file, Event, File, FileId, OutgoingTransfer, Service, Transfer};
+use output::Output;
+use slog::{o, Drain, Logger};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+type EventRx = mpsc::UnboundedReceiver<(Event, SystemTime)>;
+
+// How long to wait for the peer to be notified of the cancellation or
+// rejection before exiting
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn cli() -> Command {
+    let service_args = |cmd: Command| {
+        cmd.arg(
+            arg!(-a --addr <ADDR> "Listen address")
+                .required(true)
+                .value_parser(value_parser!(IpAddr)),
+        )
+        .arg(
+            arg!(-k --key <FILE> "Private key file")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-p --peers <FILE> "Peer public keys file")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+    };
+
+    let cmd = command!()
+        .subcommand_required(true)
+        .arg(
+            arg!(-s --storage <FILE> "Storage file name")
+                .global(true)
+                .default_value(":memory:")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(arg!(--json "Print JSON, one object per line").global(true))
+        .arg(
+            arg!(--"log-level" <LEVEL> "critical, error, warn, info, debug or trace")
+                .global(true)
+                .default_value("warn")
+                .value_parser(value_parser!(slog::Level)),
+        )
+        .subcommand(
+            service_args(Command::new("listen").about("Receive the files until interrupted")).arg(
+                arg!(-o --output <DIR> "Download the incoming files into the directory")
+                    .value_parser(value_parser!(PathBuf)),
+            ),
+        )
+        .subcommand(
+            service_args(
+                Command::new("send").about("Send the files and wait for the transfers to finish"),
+            )
+            .arg(
+                arg!(-t --to <ADDR> "Peer address, can be repeated")
+                    .required(true)
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(IpAddr)),
+            )
+            .arg(arg!(<FILE> ... "Files and directories to send").action(ArgAction::Append)),
+        )
+        .subcommand(
+            service_args(
+                Command::new("cancel").about("Cancel the transfer and notify the peer if online"),
+            )
+            .arg(arg!(<TRANSFER_ID>).value_parser(value_parser!(Uuid))),
+        )
+        .subcommand(
+            service_args(Command::new("reject").about("Reject a single file of the transfer"))
+                .arg(arg!(<TRANSFER_ID>).value_parser(value_parser!(Uuid)))
+                .arg(arg!(<FILE_ID>)),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the transfers in the history, newest first")
+                .arg(arg!(--peer <ADDR> "Only the transfers with the peer"))
+                .arg(
+                    arg!(--direction <DIRECTION> "Only the incoming or outgoing transfers")
+                        .value_parser(["incoming", "outgoing"]),
+                )
+                .arg(
+                    arg!(--limit <N> "The maximum number of transfers")
+                        .value_parser(value_parser!(u32)),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Show the transfer and its files")
+                .arg(arg!(<TRANSFER_ID>).value_parser(value_parser!(Uuid))),
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Remove the transfers from the history")
+                .arg(
+                    arg!(--until <TIMESTAMP> "Remove the transfers older than this UNIX timestamp")
+                        .value_parser(value_parser!(i64)),
+                )
+                .arg(arg!([TRANSFER_ID] ... "Remove these transfers").action(ArgAction::Append)),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export the history into a JSON file")
+                .arg(arg!(<FILE>).value_parser(value_parser!(PathBuf))),
+        );
+
+    #[cfg(unix)]
+    let cmd = cmd.subcommand(
+        service_args(
+            Command::new("daemon")
+                .about("Run the service controlled with JSON-RPC over a Unix socket"),
+        )
+        .arg(
+            arg!(--socket <PATH> "Control socket path")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"socket-mode" <MODE> "Octal permissions of the control socket")
+                .default_value("600")
+                .value_parser(daemon::parse_mode),
+        )
+        .arg(
+            arg!(--"allow-uid" <UID> "Accept the control connections of this user only, can be repeated")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(u32)),
+        ),
+    );
+
+    cmd
+}
+
+fn create_logger(level: slog::Level) -> Logger {
+    let start = Instant::now();
+
+    Logger::root(
+        slog_async::Async::new(
+            slog::LevelFilter::new(
+                slog_term::FullFormat::new(slog_term::TermDecorator::new().stderr().build())
+                    .use_file_location()
+                    .use_custom_timestamp(move |writer: &mut dyn Write| {
+                        let ts = start.elapsed();
+
+                        let secs = ts.as_secs();
+                        let millis = ts.subsec_millis();
+
+                        write!(writer, "{secs:04}.{millis:03}")
+                    })
+                    .build()
+                    .fuse(),
+                level,
+            )
+            .fuse(),
+        )
+        .build()
+        .fuse(),
+        o!(),
+    )
+}
+
+/// The running service along with its event stream
+struct Instance {
+    service: Service,
+    storage: Arc<Storage>,
+    rx: EventRx,
+    config: Arc<DropConfig>,
+}
+
+impl Instance {
+    async fn start(
+        matches: &ArgMatches,
+        storage: Arc<Storage>,
+        logger: Logger,
+    ) -> anyhow::Result<Self> {
+        let addr = *matches
+            .get_one::<IpAddr>("addr")
+            .expect("Missing `addr` flag");
+        let auth = keys::load(
+            matches
+                .get_one::<PathBuf>("key")
+                .expect("Missing `key` flag"),
+            matches
+                .get_one::<PathBuf>("peers")
+                .expect("Missing `peers` flag"),
+        )?;
+
+        let config = Arc::new(DropConfig::default());
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let service = Service::start(
+            addr,
+            storage.clone(),
+            tx,
+            logger,
+            config.clone(),
+            drop_analytics::moose_mock(),
+            Arc::new(auth),
+            Instant::now(),
+            None,
+            #[cfg(unix)]
+            None,
+        )
+        .await
+        .context("Failed to start service")?;
+
+        Ok(Self {
+            service,
+            storage,
+            rx,
+            config,
+        })
+    }
+
+    /// Handles the events until `done` returns true for one of them
+    async fn run(
+        &mut self,
+        out: &mut Output,
+        download_dir: Option<&str>,
+        mut done: impl FnMut(&Event) -> bool,
+    ) -> anyhow::Result<()> {
+        let mut dispatch = drop_transfer::StorageDispatch::new(&*self.storage);
+
+        while let Some((ev, _)) = self.rx.recv().await {
+            dispatch.handle_event(&ev).await;
+            out.event(&ev);
+
+            if let (Event::RequestReceived(xfer), Some(dir)) = (&ev, download_dir) {
+                for file in xfer.files().values() {
+                    self.service
+                        .download(xfer.id(), file.id(), dir)
+                        .await
+                        .context("Cannot issue download call")?;
+                }
+            }
+
+            if done(&ev) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn stop(mut self, out: &mut Output) {
+        self.service.stop().await;
+
+        // Drain the events emitted on stop
+        let mut dispatch = drop_transfer::StorageDispatch::new(&*self.storage);
+        while let Some((ev, _)) = self.rx.recv().await {
+            dispatch.handle_event(&ev).await;
+            out.event(&ev);
+        }
+    }
+}
+
+async fn listen(mut inst: Instance, out: &mut Output, matches: &ArgMatches) -> anyhow::Result<()> {
+    let dir = matches
+        .get_one::<PathBuf>("output")
+        .map(|dir| dir.to_string_lossy().into_owned());
+
+    out.info(&match &dir {
+        Some(dir) => format!("Listening, downloading into {dir:?}"),
+        None => "Listening, the incoming requests are not accepted".to_string(),
+    });
+
+    let result = tokio::select! {
+        result = inst.run(out, dir.as_deref(), |_| false) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    inst.stop(out).await;
+    result
+}
+
+async fn send(mut inst: Instance, out: &mut Output, matches: &ArgMatches) -> anyhow::Result<()> {
+    let mut pending = HashSet::new();
+
+    for peer in matches
+        .get_many::<IpAddr>("to")
+        .context("Missing peer list")?
+    {
+        let mut files = file::GatherCtx::new(&inst.config);
+        for path in matches
+            .get_many::<String>("FILE")
+            .context("Missing path list")?
+        {
+            files
+                .gather_from_path(path)
+                .with_context(|| format!("Cannot gather the files from {path:?}"))?;
+        }
+
+        let xfer = OutgoingTransfer::new(*peer, files.take(), &inst.config)?;
+        pending.insert(xfer.id());
+        inst.service.send_request(xfer).await;
+    }
+
+    let result = tokio::select! {
+        result = inst.run(out, None, |ev| {
+            match ev {
+                Event::OutgoingTransferCanceled(xfer, _)
+                | Event::OutgoingTransferFailed(xfer, _, _) => {
+                    pending.remove(&xfer.id());
+                }
+                _ => (),
+            }
+            pending.is_empty()
+        }) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    inst.stop(out).await;
+    result
+}
+
+async fn cancel(mut inst: Instance, out: &mut Output, transfer_id: Uuid) -> anyhow::Result<()> {
+    inst.service
+        .cancel_all(transfer_id)
+        .await
+        .context("Failed to cancel the transfer")?;
+
+    let wait = inst.run(out, None, |ev| match ev {
+        Event::IncomingTransferCanceled(xfer, false) => xfer.id() == transfer_id,
+        Event::OutgoingTransferCanceled(xfer, false) => xfer.id() == transfer_id,
+        _ => false,
+    });
+    let result = tokio::time::timeout(NOTIFY_TIMEOUT, wait)
+        .await
+        .unwrap_or(Ok(()));
+
+    inst.stop(out).await;
+    result
+}
+
+async fn reject(
+    mut inst: Instance,
+    out: &mut Output,
+    transfer_id: Uuid,
+    file_id: FileId,
+) -> anyhow::Result<()> {
+    inst.service
+        .reject(transfer_id, file_id.clone())
+        .await
+        .context("Failed to reject the file")?;
+
+    let wait = inst.run(out, None, |ev| match ev {
+        Event::FileUploadRejected {
+            transfer_id: id,
+            file_id: file,
+            by_peer: false,
+        }
+        | Event::FileDownloadRejected {
+            transfer_id: id,
+            file_id: file,
+            by_peer: false,
+        } => *id == transfer_id && *file == file_id,
+        _ => false,
+    });
+    let result = tokio::time::timeout(NOTIFY_TIMEOUT, wait)
+        .await
+        .unwrap_or(Ok(()));
+
+    inst.stop(out).await;
+    result
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let matches = cli().get_matches();
+
+    let logger = create_logger(
+        *matches
+            .get_one::<slog::Level>("log-level")
+            .expect("Missing `log-level` flag"),
+    );
+    let _guard = slog_scope::set_global_logger(logger.clone());
+
+    let mut out = Output::new(matches.get_flag("json"));
+
+    let storage_file = matches
+        .get_one::<String>("storage")
+        .expect("Missing `storage` flag");
+    let storage = Arc::new(
+        Storage::new(logger.clone(), storage_file)
+            .with_context(|| format!("Failed to open the storage {storage_file:?}"))?,
+    );
+
+    match matches.subcommand() {
+        Some(("listen", matches)) => {
+            let inst = Instance::start(matches, storage, logger).await?;
+            listen(inst, &mut out, matches).await
+        }
+        #[cfg(unix)]
+        Some(("daemon", matches)) => {
+            let inst = Instance::start(matches, storage, logger).await?;
+            daemon::run(inst, &mut out, matches).await
+        }
+        Some(("send", matches)) => {
+            let inst = Instance::start(matches, storage, logger).await?;
+            send(inst, &mut out, matches).await
+        }
+        Some(("cancel", matches)) => {
+            let transfer_id = *matches
+                .get_one::<Uuid>("TRANSFER_ID")
+                .expect("Missing `TRANSFER_ID`");
+            let inst = Instance::start(matches, storage, logger).await?;
+            cancel(inst, &mut out, transfer_id).await
+        }
+        Some(("reject", matches)) => {
+            let transfer_id = *matches
+                .get_one::<Uuid>("TRANSFER_ID")
+                .expect("Missing `TRANSFER_ID`");
+            let file_id = FileId::from(
+                matches
+                    .get_one::<String>("FILE_ID")
+                    .expect("Missing `FILE_ID`")
+                    .as_str(),
+            );
+            let inst = Instance::start(matches, storage, logger).await?;
+            reject(inst, &mut out, transfer_id, file_id).await
+        }
+        Some(("list", matches)) => {
+            let direction = matches
+                .get_one::<String>("direction")
+                .map(|dir| match dir.as_str() {
+                    "incoming" => TransferType::Incoming,
+                    _ => TransferType::Outgoing,
+                });
+            let query = history::query(
+                direction,
+                matches.get_one::<String>("peer").cloned(),
+                matches.get_one::<u32>("limit").copied(),
+            );
+
+            history::list(&storage, &mut out, query).await;
+            Ok(())
+        }
+        Some(("status", matches)) => {
+            let transfer_id = *matches
+                .get_one::<Uuid>("TRANSFER_ID")
+                .expect("Missing `TRANSFER_ID`");
+            history::status(&storage, &mut out, transfer_id).await
+        }
+        Some(("purge", matches)) => {
+            let ids: Vec<String> = matches
+                .get_many::<String>("TRANSFER_ID")
+                .map(|ids| ids.cloned().collect())
+                .unwrap_or_default();
+            let until = matches.get_one::<i64>("until").copied();
+
+            anyhow::ensure!(
+                !ids.is_empty() || until.is_some(),
+                "Pass the transfer IDs or `--until`"
+            );
+            history::purge(&storage, &ids, until).await;
+            Ok(())
+        }
+        Some(("export", matches)) => {
+            let path = matches.get_one::<PathBuf>("FILE").expect("Missing `FILE`");
+            history::export(&storage, &mut out, path).await
+        }
+        _ => unreachable!("The subcommand is required"),
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal, Write},
+};
+
+use drop_transfer::{event::Throughput, Event, File, FileId, Transfer};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+const BAR_WIDTH: usize = 30;
+
+/// Prints the service events either as the human readable lines, with the
+/// progress bars when stderr is a terminal, or as one JSON object per line
+pub struct Output {
+    json: bool,
+    bars: bool,
+    sizes: HashMap<(Uuid, FileId), u64>,
+    // A progress bar is drawn on the last line of stderr
+    bar_drawn: bool,
+}
+
+struct Described {
+    name: &'static str,
+    transfer_id: Uuid,
+    file_id: Option<FileId>,
+    fields: Map<String, Value>,
+}
+
+impl Output {
+    pub fn new(json: bool) -> Self {
+        Self {
+            json,
+            bars: !json && io::stderr().is_terminal(),
+            sizes: HashMap::new(),
+            bar_drawn: false,
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    pub fn event(&mut self, ev: &Event) {
+        self.remember_sizes(ev);
+        let desc = describe(ev);
+
+        if self.json {
+            println!("{}", desc.into_json());
+            return;
+        }
+
+        // The progress events are too frequent to print them line by line, only the
+        // file progress is shown as a bar
+        if let Some(transferred) = progress_bytes(ev) {
+            if let (true, Some(file_id)) = (self.bars, &desc.file_id) {
+                let size = self
+                    .sizes
+                    .get(&(desc.transfer_id, file_id.clone()))
+                    .copied();
+                self.draw_bar(file_id, transferred, size, throughput(ev));
+            }
+            return;
+        }
+
+        let mut line = format!("[{}] {}", desc.name, desc.transfer_id);
+        if let Some(file_id) = &desc.file_id {
+            line.push_str(&format!(" {file_id}"));
+        }
+        for (key, value) in &desc.fields {
+            line.push_str(&format!(" {key}={value}"));
+        }
+        self.line(&line);
+    }
+
+    /// A message not related to the events, goes to stderr in the JSON mode
+    pub fn info(&mut self, msg: &str) {
+        if self.json {
+            eprintln!("{msg}");
+        } else {
+            self.line(msg);
+        }
+    }
+
+    pub fn value(&mut self, value: Value) {
+        println!("{value}");
+    }
+
+    fn line(&mut self, line: &str) {
+        if self.bar_drawn {
+            eprintln!();
+            self.bar_drawn = false;
+        }
+        println!("{line}");
+    }
+
+    fn draw_bar(
+        &mut self,
+        file_id: &FileId,
+        transferred: u64,
+        size: Option<u64>,
+        rate: Option<u64>,
+    ) {
+        let (bar, percent) = match size {
+            Some(size) if size > 0 => {
+                let ratio = (transferred as f64 / size as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                (
+                    format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled)),
+                    format!("{:3.0}%", ratio * 100.0),
+                )
+            }
+            _ => ("?".repeat(BAR_WIDTH), "  ?%".to_string()),
+        };
+
+        let rate = rate.map_or_else(String::new, |rate| format!(" {}/s", human_bytes(rate)));
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r{file_id} [{bar}] {percent} {}{rate}\x1b[K",
+            human_bytes(transferred)
+        );
+        let _ = stderr.flush();
+        self.bar_drawn = true;
+    }
+
+    fn remember_sizes(&mut self, ev: &Event) {
+        let mut insert = |id, files: Vec<(&FileId, u64)>| {
+            for (file_id, size) in files {
+                self.sizes.insert((id, file_id.clone()), size);
+            }
+        };
+
+        match ev {
+            Event::RequestReceived(xfer) => insert(
+                xfer.id(),
+                xfer.files().values().map(|f| (f.id(), f.size())).collect(),
+            ),
+            Event::RequestQueued(xfer) => insert(
+                xfer.id(),
+                xfer.files().values().map(|f| (f.id(), f.size())).collect(),
+            ),
+            _ => (),
+        }
+    }
+}
+
+impl Described {
+    fn into_json(self) -> Value {
+        let mut obj = Map::new();
+        obj.insert("event".into(), self.name.into());
+        obj.insert("transfer_id".into(), self.transfer_id.to_string().into());
+        if let Some(file_id) = &self.file_id {
+            obj.insert("file_id".into(), file_id.to_string().into());
+        }
+        obj.extend(self.fields);
+
+        Value::Object(obj)
+    }
+}
+
+/// The event as a JSON object with the `event` name, `transfer_id`, optional
+/// `file_id` and the event specific fields
+pub fn event_json(ev: &Event) -> Value {
+    describe(ev).into_json()
+}
+
+fn progress_bytes(ev: &Event) -> Option<u64> {
+    match ev {
+        Event::FileUploadProgress(_, _, transferred, _)
+        | Event::FileDownloadProgress(_, _, transferred, _)
+        | Event::TransferProgress { transferred, .. } => Some(*transferred),
+        Event::FinalizeChecksumProgress { progress, .. }
+        | Event::VerifyChecksumProgress { progress, .. } => Some(*progress),
+        _ => None,
+    }
+}
+
+fn throughput(ev: &Event) -> Option<u64> {
+    match ev {
+        Event::FileUploadProgress(_, _, _, throughput)
+        | Event::FileDownloadProgress(_, _, _, throughput)
+        | Event::TransferProgress { throughput, .. } => Some(throughput.bytes_per_sec),
+        _ => None,
+    }
+}
+
+fn throughput_fields(throughput: &Throughput) -> [(&'static str, Value); 2] {
+    [
+        ("bytes_per_second", throughput.bytes_per_sec.into()),
+        (
+            "eta_ms",
+            throughput
+                .eta
+                .map_or(Value::Null, |eta| (eta.as_millis() as u64).into()),
+        ),
+    ]
+}
+
+fn files_json<T: Transfer>(xfer: &T) -> Value {
+    xfer.files()
+        .values()
+        .map(|file| {
+            json!({
+                "id": file.id().to_string(),
+                "path": file.subpath().to_string(),
+                "size": file.size(),
+            })
+        })
+        .collect()
+}
+
+fn describe(ev: &Event) -> Described {
+    let mut fields = Map::new();
+    let mut field = |key: &str, value: Value| {
+        fields.insert(key.to_string(), value);
+    };
+
+    let (name, transfer_id, file_id) = match ev {
+        Event::RequestReceived(xfer) => {
+            field("peer", xfer.peer().to_string().into());
+            field("files", files_json(&**xfer));
+            ("RequestReceived", xfer.id(), None)
+        }
+        Event::RequestQueued(xfer) => {
+            field("peer", xfer.peer().to_string().into());
+            field("files", files_json(&**xfer));
+            ("RequestQueued", xfer.id(), None)
+        }
+        Event::FileUploadStarted(xfer, file_id, offset) => {
+            field("offset", (*offset).into());
+            ("FileUploadStarted", xfer.id(), Some(file_id))
+        }
+        Event::FileDownloadStarted(xfer, file_id, base_dir, offset) => {
+            field("base_dir", base_dir.as_str().into());
+            field("offset", (*offset).into());
+            ("FileDownloadStarted", xfer.id(), Some(file_id))
+        }
+        Event::FileDownloadPending {
+            transfer_id,
+            file_id,
+            base_dir,
+        } => {
+            field("base_dir", base_dir.as_str().into());
+            ("FileDownloadPending", *transfer_id, Some(file_id))
+        }
+        Event::FileUploadProgress(xfer, file_id, transferred, throughput) => {
+            field("transferred", (*transferred).into());
+            throughput_fields(throughput)
+                .into_iter()
+                .for_each(|(k, v)| field(k, v));
+            ("FileUploadProgress", xfer.id(), Some(file_id))
+        }
+        Event::FileDownloadProgress(xfer, file_id, transferred, throughput) => {
+            field("transferred", (*transferred).into());
+            throughput_fields(throughput)
+                .into_iter()
+                .for_each(|(k, v)| field(k, v));
+            ("FileDownloadProgress", xfer.id(), Some(file_id))
+        }
+        Event::TransferProgress {
+            transfer_id,
+            transferred,
+            throughput,
+        } => {
+            field("transferred", (*transferred).into());
+            throughput_fields(throughput)
+                .into_iter()
+                .for_each(|(k, v)| field(k, v));
+            ("TransferProgress", *transfer_id, None)
+        }
+        Event::FileUploadSuccess(xfer, file_id) => ("FileUploadSuccess", xfer.id(), Some(file_id)),
+        Event::FileDownloadSuccess(xfer, info) => {
+            field("final_path", info.final_path.to_string_lossy().into());
+            ("FileDownloadSuccess", xfer.id(), Some(&info.id))
+        }
+        Event::FileUploadFailed(xfer, file_id, err) => {
+            field("error", err.to_string().into());
+            ("FileUploadFailed", xfer.id(), Some(file_id))
+        }
+        Event::FileDownloadFailed(xfer, file_id, err) => {
+            field("error", err.to_string().into());
+            ("FileDownloadFailed", xfer.id(), Some(file_id))
+        }
+        Event::FileUploadPaused {
+            transfer_id,
+            file_id,
+        } => ("FileUploadPaused", *transfer_id, Some(file_id)),
+        Event::FileDownloadPaused {
+            transfer_id,
+            file_id,
+        } => ("FileDownloadPaused", *transfer_id, Some(file_id)),
+        Event::FileUploadRejected {
+            transfer_id,
+            file_id,
+            by_peer,
+        } => {
+            field("by_peer", (*by_peer).into());
+            ("FileUploadRejected", *transfer_id, Some(file_id))
+        }
+        Event::FileDownloadRejected {
+            transfer_id,
+            file_id,
+            by_peer,
+        } => {
+            field("by_peer", (*by_peer).into());
+            ("FileDownloadRejected", *transfer_id, Some(file_id))
+        }
+        Event::FileUploadThrottled {
+            transfer_id,
+            file_id,
+            transferred,
+        } => {
+            field("transferred", (*transferred).into());
+            ("FileUploadThrottled", *transfer_id, Some(file_id))
+        }
+        Event::IncomingTransferCanceled(xfer, by_peer) => {
+            field("by_peer", (*by_peer).into());
+            ("IncomingTransferCanceled", xfer.id(), None)
+        }
+        Event::OutgoingTransferCanceled(xfer, by_peer) => {
+            field("by_peer", (*by_peer).into());
+            ("OutgoingTransferCanceled", xfer.id(), None)
+        }
+        Event::OutgoingTransferFailed(xfer, err, by_peer) => {
+            field("error", err.to_string().into());
+            field("by_peer", (*by_peer).into());
+            ("OutgoingTransferFailed", xfer.id(), None)
+        }
+        Event::OutgoingTransferDeferred { transfer, error } => {
+            field("error", error.to_string().into());
+            ("OutgoingTransferDeferred", transfer.id(), None)
+        }
+        Event::FinalizeChecksumStarted {
+            transfer_id,
+            file_id,
+            size,
+        } => {
+            field("size", (*size).into());
+            ("FinalizeChecksumStarted", *transfer_id, Some(file_id))
+        }
+        Event::FinalizeChecksumFinished {
+            transfer_id,
+            file_id,
+        } => ("FinalizeChecksumFinished", *transfer_id, Some(file_id)),
+        Event::FinalizeChecksumProgress {
+            transfer_id,
+            file_id,
+            progress,
+        } => {
+            field("progress", (*progress).into());
+            ("FinalizeChecksumProgress", *transfer_id, Some(file_id))
+        }
+        Event::VerifyChecksumStarted {
+            transfer_id,
+            file_id,
+            size,
+        } => {
+            field("size", (*size).into());
+            ("VerifyChecksumStarted", *transfer_id, Some(file_id))
+        }
+        Event::VerifyChecksumFinished {
+            transfer_id,
+            file_id,
+        } => ("VerifyChecksumFinished", *transfer_id, Some(file_id)),
+        Event::VerifyChecksumProgress {
+            transfer_id,
+            file_id,
+            progress,
+        } => {
+            field("progress", (*progress).into());
+            ("VerifyChecksumProgress", *transfer_id, Some(file_id))
+        }
+    };
+
+    Described {
+        name,
+        transfer_id,
+        file_id: file_id.cloned(),
+        fields,
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
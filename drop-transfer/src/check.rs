@@ -31,7 +31,7 @@ pub(crate) fn spawn(
     tokio::spawn(async move {
         let _guard = guard;
         let mut backoff =
-            utils::RetryTrigger::new(refresh_trigger, state.config.connection_retries);
+            utils::RetryTrigger::new(refresh_trigger, state.config().connection_retries);
 
         let task = async {
             while run(&state, &xfer, &logger).await.is_continue() {
@@ -76,7 +76,7 @@ async fn ask_server_if_alive(state: &State, xfer: &IncomingTransfer, logger: &Lo
 
     let client = hyper::Client::builder().build::<_, hyper::Body>(connector);
 
-    let versions_to_try = [protocol::Version::V6];
+    let versions_to_try = [protocol::Version::V7, protocol::Version::V6];
 
     for version in versions_to_try {
         match make_request(
@@ -3,7 +3,7 @@ use std::io::{Error as IoError, ErrorKind};
 use drop_analytics::MOOSE_STATUS_SUCCESS;
 use tokio_tungstenite::tungstenite;
 
-use crate::manager::FileTerminalState;
+use crate::{file::ChecksumAlgorithm, manager::FileTerminalState};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -55,6 +55,8 @@ pub enum Error {
     ConnectionClosedByPeer,
     #[error("Peer responded with too many requests status")]
     TooManyRequests,
+    #[error("Checksum algorithm {0} is not supported by the peer")]
+    UnsupportedChecksumAlgorithm(ChecksumAlgorithm),
 }
 
 impl Error {
@@ -108,6 +110,7 @@ impl From<&Error> for drop_core::Status {
             Error::EmptyTransfer => Status::EmptyTransfer,
             Error::ConnectionClosedByPeer => Status::ConnectionClosedByPeer,
             Error::TooManyRequests => Status::TooManyRequests,
+            Error::UnsupportedChecksumAlgorithm(_) => Status::BadTransferState,
         }
     }
 }
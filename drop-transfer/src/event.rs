@@ -1,4 +1,4 @@
-use std::{path::Path, sync::Arc};
+use std::{path::Path, sync::Arc, time::Duration};
 
 use uuid::Uuid;
 
@@ -15,6 +15,14 @@ pub struct DownloadSuccess {
     pub final_path: Hidden<Box<Path>>,
 }
 
+/// Smoothed transfer rate along with the estimated time remaining
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Throughput {
+    pub bytes_per_sec: u64,
+    /// `None` when the rate is not known yet
+    pub eta: Option<Duration>,
+}
+
 #[derive(Debug)]
 pub enum Event {
     RequestReceived(Arc<IncomingTransfer>),
@@ -29,8 +37,15 @@ pub enum Event {
         base_dir: String,
     },
 
-    FileUploadProgress(Arc<OutgoingTransfer>, FileId, u64),
-    FileDownloadProgress(Arc<IncomingTransfer>, FileId, u64),
+    FileUploadProgress(Arc<OutgoingTransfer>, FileId, u64, Throughput),
+    FileDownloadProgress(Arc<IncomingTransfer>, FileId, u64, Throughput),
+
+    /// Aggregated progress of all the files within the transfer
+    TransferProgress {
+        transfer_id: Uuid,
+        transferred: u64,
+        throughput: Throughput,
+    },
 
     FileUploadSuccess(Arc<OutgoingTransfer>, FileId),
     FileDownloadSuccess(Arc<IncomingTransfer>, DownloadSuccess),
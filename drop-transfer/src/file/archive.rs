@@ -0,0 +1,285 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use drop_config::DropConfig;
+use once_cell::sync::OnceCell;
+use walkdir::WalkDir;
+
+use crate::{utils::Hidden, Error};
+
+const BLOCK_SIZE: u64 = 512;
+// Longer member names are stored with the GNU long name extension
+const NAME_FIELD_LEN: usize = 100;
+// The archive ends with two zero blocks
+const TRAILER_SIZE: u64 = 2 * BLOCK_SIZE;
+
+/// A directory tree sent as a single tar archive. The archive is never stored
+/// on disk, instead it is generated on the fly while reading. The layout is
+/// deterministic so that any part of the archive can be read again, e.g. when
+/// the download is resumed
+#[derive(Debug)]
+pub struct Archive {
+    root: Hidden<PathBuf>,
+    dir_depth_limit: usize,
+    layout: OnceCell<Layout>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Layout {
+    pub members: Vec<Member>,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Member {
+    // Position of the member within the archive
+    pub offset: u64,
+    // Includes the GNU long name entry, if any
+    pub header: Vec<u8>,
+    // `None` for directories
+    pub path: Option<Hidden<PathBuf>>,
+    pub size: u64,
+    // The modification time seen by the walk, the member must not change
+    // afterwards
+    pub modified: Option<SystemTime>,
+}
+
+impl Archive {
+    pub(crate) fn new(root: PathBuf, config: &DropConfig) -> Self {
+        Self {
+            root: Hidden(root),
+            dir_depth_limit: config.dir_depth_limit,
+            layout: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Walks the directory tree on the first call. This is a blocking
+    /// operation
+    pub(crate) fn layout(&self) -> crate::Result<&Layout> {
+        self.layout
+            .get_or_try_init(|| Layout::walk(&self.root, self.dir_depth_limit))
+    }
+}
+
+impl Layout {
+    // The receiver applies the same limits when unpacking
+    fn walk(root: &Path, dir_depth_limit: usize) -> crate::Result<Self> {
+        let mut members = Vec::new();
+        let mut offset = 0;
+
+        // Sorting makes the layout stable between the walks
+        for entry in WalkDir::new(root).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if entry.depth() > dir_depth_limit {
+                return Err(Error::TransferLimitsExceeded);
+            }
+
+            let relpath = entry
+                .path()
+                .strip_prefix(root)
+                .map_err(|err| Error::BadPath(err.to_string()))?;
+
+            let mut name = relpath
+                .iter()
+                .map(|cmp| {
+                    cmp.to_str()
+                        .ok_or_else(|| Error::BadPath("Paths should be valid UTF8".into()))
+                })
+                .collect::<crate::Result<Vec<_>>>()?
+                .join("/");
+
+            let mut header = tar::Header::new_gnu();
+
+            let (path, size) = if meta.is_dir() {
+                name.push('/');
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                (None, 0)
+            } else if meta.is_file() {
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(0o644);
+                (Some(Hidden(entry.into_path())), meta.len())
+            } else {
+                // Symlinks and special files are skipped, same as in the
+                // ordinary directory transfers
+                continue;
+            };
+
+            let modified = meta.modified().ok();
+            let mtime = modified
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+
+            header.set_size(size);
+            header.set_mtime(mtime);
+
+            let member = Member {
+                offset,
+                header: encode_header(header, &name),
+                path,
+                size,
+                modified,
+            };
+
+            if members.len() >= drop_config::MAX_ARCHIVE_MEMBERS {
+                return Err(Error::TransferLimitsExceeded);
+            }
+
+            offset += member.len();
+            members.push(member);
+        }
+
+        Ok(Self {
+            members,
+            size: offset + TRAILER_SIZE,
+        })
+    }
+}
+
+impl Member {
+    /// The length of the member within the archive, including the padding
+    pub(crate) fn len(&self) -> u64 {
+        self.header.len() as u64 + self.size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+    }
+}
+
+fn encode_header(mut header: tar::Header, name: &str) -> Vec<u8> {
+    let name = name.as_bytes();
+    let mut out = Vec::new();
+
+    if name.len() > NAME_FIELD_LEN {
+        // The name, including the NUL terminator, is the data of the preceding
+        // entry
+        let data_len = name.len() as u64 + 1;
+
+        let mut long_name = tar::Header::new_gnu();
+        long_name.set_entry_type(tar::EntryType::GNULongName);
+        long_name.set_mode(0o644);
+        long_name.set_mtime(0);
+        long_name.set_size(data_len);
+        finish_header(&mut long_name, b"././@LongLink");
+
+        out.extend_from_slice(long_name.as_bytes());
+        out.extend_from_slice(name);
+        out.resize(
+            (BLOCK_SIZE + data_len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE) as usize,
+            0,
+        );
+    }
+
+    finish_header(&mut header, &name[..name.len().min(NAME_FIELD_LEN)]);
+    out.extend_from_slice(header.as_bytes());
+    out
+}
+
+fn finish_header(header: &mut tar::Header, name: &[u8]) {
+    header.set_uid(0);
+    header.set_gid(0);
+
+    let gnu = header.as_gnu_mut().expect("Expected GNU header");
+    gnu.name[..name.len()].copy_from_slice(name);
+
+    header.set_cksum();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::Read,
+        path::Path,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use drop_config::DropConfig;
+
+    use crate::{file::FileToSend, File};
+
+    fn read_all(file: &FileToSend, offset: u64) -> Vec<u8> {
+        let mut reader = file.open(offset).unwrap();
+
+        let mut data = Vec::new();
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            data.extend_from_slice(chunk);
+        }
+        data
+    }
+
+    #[test]
+    fn archive_layout() {
+        let dir = tempfile::tempdir().expect("Failed to create tmp dir");
+        let root = dir.path().join("root");
+        let long_name = "x".repeat(120);
+
+        fs::create_dir_all(root.join("sub").join("empty")).unwrap();
+        fs::write(root.join("a.txt"), b"hello").unwrap();
+        fs::write(root.join("sub").join(&long_name), vec![7; 1000]).unwrap();
+
+        let file = FileToSend::archive_from_path(&root, Path::new("root"), &DropConfig::default())
+            .unwrap();
+        assert!(file.is_archive());
+
+        let data = read_all(&file, 0);
+        assert_eq!(data.len() as u64, file.size());
+
+        // Resumed download receives the same data
+        assert_eq!(read_all(&file, 1000), data[1000..]);
+
+        let members: Vec<_> = tar::Archive::new(&data[..])
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().to_string_lossy().into_owned();
+
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (path, content)
+            })
+            .collect();
+
+        assert_eq!(
+            members,
+            vec![
+                ("a.txt".to_string(), b"hello".to_vec()),
+                ("sub/".to_string(), vec![]),
+                ("sub/empty/".to_string(), vec![]),
+                (format!("sub/{long_name}"), vec![7; 1000]),
+            ]
+        );
+
+        let read_to_end = || {
+            let mut reader = file.open(0).unwrap();
+            loop {
+                match reader.read_chunk() {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            }
+        };
+
+        // Files modified after the archive was created are detected, even when
+        // the size stays the same
+        fs::write(root.join("a.txt"), b"HELLO").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(root.join("a.txt"))
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        assert!(read_to_end().is_err());
+
+        // So are the resized ones
+        fs::write(root.join("a.txt"), b"hi").unwrap();
+        assert!(read_to_end().is_err());
+    }
+}
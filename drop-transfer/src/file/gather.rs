@@ -71,8 +71,13 @@ impl<'a> GatherCtx<'a> {
         if meta.is_dir() {
             let name = self.fetch_free_dir_name(path)?;
 
-            let batch = super::FileToSend::walk(path, &name, self.config)?;
-            self.files.extend(batch);
+            if self.config.archive_directories {
+                let file = super::FileToSend::archive_from_path(path, &name, self.config)?;
+                self.files.push(file);
+            } else {
+                let batch = super::FileToSend::walk(path, &name, self.config)?;
+                self.files.extend(batch);
+            }
         } else {
             let file = super::FileToSend::from_path(path, meta.len())?;
             self.files.push(file);
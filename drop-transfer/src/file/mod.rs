@@ -1,25 +1,36 @@
+mod archive;
 mod gather;
 mod id;
 mod reader;
+mod sink;
 
+#[cfg(unix)]
+use std::os::unix::prelude::*;
 use std::{
     fmt,
     future::Future,
-    io::{self, BufRead, Read, Write},
+    io::{self, BufRead, Read},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
 };
-#[cfg(unix)]
-use std::{os::unix::prelude::*, sync::Arc};
 
 use drop_analytics::TransferDirection;
 use drop_config::DropConfig;
+pub use drop_core::ChecksumAlgorithm;
+use drop_storage::{ChecksumCacheKey, StorageBackend};
 pub use gather::*;
 pub use id::{FileId, FileSubPath};
 use once_cell::sync::OnceCell;
 pub use reader::FileReader;
 use sha2::Digest;
+pub(crate) use sink::temp_file_name;
+pub use sink::{
+    DownloadSink, DownloadSinkFactory, FsDownloadSink, FsDownloadSinkFactory, ProgressCallback,
+};
 use walkdir::WalkDir;
 
+use self::archive::Archive;
 use crate::{utils::Hidden, Error};
 
 pub struct FileInfo {
@@ -40,6 +51,8 @@ pub trait File {
     fn subpath(&self) -> &FileSubPath;
     fn size(&self) -> u64;
     fn mime_type(&self) -> &str;
+    // The file is a tar archive of the directory tree named by the subpath
+    fn is_archive(&self) -> bool;
 
     fn direction() -> TransferDirection;
 
@@ -65,10 +78,12 @@ pub struct FileToRecv {
     file_id: FileId,
     subpath: FileSubPath,
     size: u64,
+    is_archive: bool,
 }
 
 pub enum FileSource {
     Path(Hidden<PathBuf>),
+    Archive(Arc<Archive>),
     #[cfg(unix)]
     Fd {
         fd: OnceCell<RawFd>,
@@ -77,10 +92,24 @@ pub enum FileSource {
     },
 }
 
+impl FileSource {
+    pub(crate) fn uri(&self) -> Option<url::Url> {
+        match self {
+            FileSource::Path(fullpath) => url::Url::from_file_path(&fullpath.0).ok(),
+            FileSource::Archive(archive) => url::Url::from_file_path(archive.root()).ok(),
+            #[cfg(unix)]
+            FileSource::Fd { content_uri, .. } => Some(content_uri.clone()),
+        }
+    }
+}
+
 impl fmt::Debug for FileSource {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             FileSource::Path(path) => f.debug_tuple("FileSource::Path").field(path).finish(),
+            FileSource::Archive(archive) => {
+                f.debug_tuple("FileSource::Archive").field(archive).finish()
+            }
             #[cfg(unix)]
             FileSource::Fd {
                 fd, content_uri, ..
@@ -117,6 +146,10 @@ impl File for FileToSend {
             .unwrap_or(UNKNOWN_STR)
     }
 
+    fn is_archive(&self) -> bool {
+        matches!(self.source, FileSource::Archive(_))
+    }
+
     fn direction() -> TransferDirection {
         TransferDirection::Upload
     }
@@ -139,25 +172,31 @@ impl File for FileToRecv {
         UNKNOWN_STR
     }
 
+    fn is_archive(&self) -> bool {
+        self.is_archive
+    }
+
     fn direction() -> TransferDirection {
         TransferDirection::Download
     }
 }
 
 impl FileToRecv {
-    pub fn new(file_id: FileId, subpath: FileSubPath, size: u64) -> Self {
+    pub fn new(file_id: FileId, subpath: FileSubPath, size: u64, is_archive: bool) -> Self {
         Self {
             file_id,
             subpath,
             size,
+            is_archive,
         }
     }
 }
 
 impl FileToSend {
     pub fn base_dir(&self) -> Option<&str> {
-        let fullpath = match &self.source {
+        let fullpath: &Path = match &self.source {
             FileSource::Path(fullpath) => fullpath,
+            FileSource::Archive(archive) => archive.root(),
             #[cfg(unix)]
             FileSource::Fd { .. } => return None,
         };
@@ -191,6 +230,42 @@ impl FileToSend {
         }
     }
 
+    fn archive_from_path(path: &Path, subname: &Path, config: &DropConfig) -> crate::Result<Self> {
+        let abspath = crate::utils::make_path_absolute(path)?;
+        let file_id = file_id_from_path(&abspath)?;
+
+        let archive = Archive::new(abspath, config);
+        let size = archive.layout()?.size;
+
+        Ok(Self {
+            file_id,
+            subpath: FileSubPath::from_path(subname)?,
+            size,
+            source: FileSource::Archive(Arc::new(archive)),
+            mime_type: OnceCell::new(),
+        })
+    }
+
+    /// Creates the archive of the directory tree without walking it. The
+    /// `size` is the one announced to the peer
+    pub(crate) fn new_archive(
+        subpath: FileSubPath,
+        abspath: PathBuf,
+        size: u64,
+        file_id: FileId,
+        config: &DropConfig,
+    ) -> Self {
+        assert!(abspath.is_absolute(), "Expecting absolute path only");
+
+        Self {
+            file_id,
+            subpath,
+            size,
+            source: FileSource::Archive(Arc::new(Archive::new(abspath, config))),
+            mime_type: OnceCell::new(),
+        }
+    }
+
     #[cfg(unix)]
     fn from_fd(
         path: &Path,
@@ -301,14 +376,21 @@ impl FileToSend {
         let mut reader = reader::open(&self.source)?;
         let meta = reader.meta()?;
 
+        // The archive is generated again after restart and the directory tree
+        // might have changed in the meantime
+        if self.is_archive() && meta.len != self.size {
+            return Err(Error::MismatchedSize);
+        }
+
         reader.seek(io::SeekFrom::Start(offset))?;
         FileReader::new(reader, meta)
     }
 
-    /// Calculate sha2 of a file. This is a blocking operation
+    /// Calculate the checksum of a file. This is a blocking operation
     pub(crate) async fn checksum<F, Fut>(
         &self,
         limit: u64,
+        algorithm: ChecksumAlgorithm,
         progress_cb: Option<F>,
         event_granularity: Option<u64>,
     ) -> crate::Result<[u8; 32]>
@@ -317,15 +399,84 @@ impl FileToSend {
         Fut: Future<Output = ()>,
     {
         let reader = reader::open(&self.source)?.take(limit);
-        let csum = checksum(reader, progress_cb, event_granularity).await?;
+        let csum = checksum(reader, algorithm, progress_cb, event_granularity).await?;
+        Ok(csum)
+    }
+
+    /// Calculate the checksum of a file or reuse the cached one if the file
+    /// was not modified since. This is a blocking operation
+    pub(crate) async fn checksum_cached(
+        &self,
+        storage: &dyn StorageBackend,
+        limit: u64,
+        algorithm: ChecksumAlgorithm,
+    ) -> crate::Result<[u8; 32]> {
+        let key = self.checksum_cache_key(limit, algorithm);
+
+        if let Some(key) = &key {
+            if let Some(csum) = storage
+                .fetch_cached_checksum(key)
+                .await
+                .and_then(|csum| csum.try_into().ok())
+            {
+                return Ok(csum);
+            }
+        }
+
+        let csum = self
+            .checksum(
+                limit,
+                algorithm,
+                None::<fn(u64) -> futures::future::Ready<()>>,
+                None,
+            )
+            .await?;
+
+        // Do not cache the checksum if the file was modified while reading it
+        if let Some(key) = key {
+            if self.checksum_cache_key(limit, algorithm).as_ref() == Some(&key) {
+                storage.save_cached_checksum(&key, &csum).await;
+            }
+        }
+
         Ok(csum)
     }
+
+    // Returns `None` when the file cannot be reliably identified, in which case
+    // the cache is not used
+    fn checksum_cache_key(
+        &self,
+        limit: u64,
+        algorithm: ChecksumAlgorithm,
+    ) -> Option<ChecksumCacheKey> {
+        let uri = self.source.uri()?;
+        let meta = reader::open(&self.source)
+            .and_then(|mut reader| reader.meta())
+            .ok()?;
+
+        let modified_at_ns = meta
+            .modified?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos()
+            .try_into()
+            .ok()?;
+
+        Some(ChecksumCacheKey {
+            uri,
+            size: meta.len,
+            modified_at_ns,
+            limit,
+            algorithm,
+        })
+    }
 }
 
 /// This function performs buffering internally. No need to use buffered
 /// readers.
 pub async fn checksum<F, Fut>(
     reader: impl io::Read,
+    algorithm: ChecksumAlgorithm,
     mut progress_cb: Option<F>,
     event_granularity: Option<u64>,
 ) -> io::Result<[u8; 32]>
@@ -333,7 +484,7 @@ where
     F: FnMut(u64) -> Fut + Send + Sync,
     Fut: Future<Output = ()>,
 {
-    let mut csum = sha2::Sha256::new();
+    let mut csum = Hasher::new(algorithm);
 
     let mut reader = io::BufReader::with_capacity(CHECKSUM_CHUNK_SIZE, reader);
 
@@ -354,7 +505,7 @@ where
             break;
         }
 
-        csum.write_all(buf)?;
+        csum.update(buf);
 
         let n = buf.len();
         reader.consume(n);
@@ -373,7 +524,37 @@ where
         tokio::task::yield_now().await;
     }
 
-    Ok(csum.finalize().into())
+    Ok(csum.finalize())
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            ChecksumAlgorithm::Blake3 => Self::Blake3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(buf),
+            Self::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+        }
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().into(),
+            Self::Blake3(hasher) => hasher.finalize().into(),
+        }
+    }
 }
 
 fn file_id_from_path(path: impl AsRef<Path>) -> crate::Result<FileId> {
@@ -402,6 +583,7 @@ mod tests {
     async fn checksum() {
         let csum = super::checksum(
             &mut &TEST[..],
+            super::ChecksumAlgorithm::Sha256,
             None::<fn(u64) -> futures::future::Ready<()>>,
             None,
         )
@@ -410,6 +592,21 @@ mod tests {
         assert_eq!(csum.as_slice(), EXPECTED);
     }
 
+    #[tokio::test]
+    async fn blake3_checksum() {
+        const EXPECTED_BLAKE3: &[u8] = b"\x64\x37\xb3\xac\x38\x46\x51\x33\xff\xb6\x3b\x75\x27\x3a\x8d\xb5\x48\xc5\x58\x46\x5d\x79\xdb\x03\xfd\x35\x9c\x6c\xd5\xbd\x9d\x85";
+
+        let csum = super::checksum(
+            &mut &TEST[..],
+            super::ChecksumAlgorithm::Blake3,
+            None::<fn(u64) -> futures::future::Ready<()>>,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(csum.as_slice(), EXPECTED_BLAKE3);
+    }
+
     #[tokio::test]
     async fn file_checksum() {
         use std::io::Write;
@@ -420,14 +617,45 @@ mod tests {
 
             let size = TEST.len() as _;
             let file = super::FileToSend::from_path(tmp.path(), size).unwrap();
-            file.checksum(size, None::<fn(u64) -> futures::future::Ready<()>>, None)
-                .await
-                .unwrap()
+            file.checksum(
+                size,
+                super::ChecksumAlgorithm::Sha256,
+                None::<fn(u64) -> futures::future::Ready<()>>,
+                None,
+            )
+            .await
+            .unwrap()
         };
 
         assert_eq!(csum.as_slice(), EXPECTED);
     }
 
+    #[tokio::test]
+    async fn cached_file_checksum() {
+        use std::io::Write;
+
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let storage = drop_storage::Storage::new(logger, ":memory:").unwrap();
+
+        let mut tmp = tempfile::NamedTempFile::new().expect("Failed to create tmp file");
+        tmp.write_all(TEST).unwrap();
+
+        let size = TEST.len() as _;
+        let file = super::FileToSend::from_path(tmp.path(), size).unwrap();
+        let key = file
+            .checksum_cache_key(size, super::ChecksumAlgorithm::Sha256)
+            .expect("Missing cache key");
+
+        let csum = file
+            .checksum_cached(&storage, size, super::ChecksumAlgorithm::Sha256)
+            .await
+            .unwrap();
+        assert_eq!(csum.as_slice(), EXPECTED);
+
+        let cached = storage.fetch_cached_checksum(&key).await;
+        assert_eq!(cached.as_deref(), Some(EXPECTED));
+    }
+
     #[test]
     fn checksum_yielding() {
         use std::{
@@ -452,6 +680,7 @@ mod tests {
         let mut cursor = io::Cursor::new(&buf);
         let mut future = super::checksum(
             &mut cursor,
+            super::ChecksumAlgorithm::Sha256,
             None::<fn(u64) -> futures::future::Ready<()>>,
             None,
         );
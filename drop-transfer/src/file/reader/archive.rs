@@ -0,0 +1,140 @@
+use std::{
+    io::{self, Read, Seek},
+    sync::Arc,
+};
+
+use crate::file::archive::Archive;
+
+// Generates the tar archive of the directory tree while reading
+pub struct FileReader {
+    archive: Arc<Archive>,
+    pos: u64,
+    // The member file currently read from, with its index
+    member: Option<(usize, super::path::FileReader)>,
+}
+
+impl FileReader {
+    pub fn new(archive: Arc<Archive>) -> crate::Result<Self> {
+        archive.layout()?;
+
+        Ok(Self {
+            archive,
+            pos: 0,
+            member: None,
+        })
+    }
+
+    fn member_file(&mut self, index: usize) -> io::Result<&mut super::path::FileReader> {
+        if !matches!(self.member, Some((current, _)) if current == index) {
+            let member = &self.layout().members[index];
+            let path = member.path.as_ref().ok_or(io::ErrorKind::InvalidInput)?;
+
+            let file = super::path::FileReader::new(path)?;
+            let meta = file.metadata()?;
+            if meta.len() != member.size || meta.modified().ok() != member.modified {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Archived file has changed",
+                ));
+            }
+
+            self.member = Some((index, file));
+        }
+
+        let (_, file) = self.member.as_mut().expect("Member file is opened above");
+        Ok(file)
+    }
+
+    fn layout(&self) -> &crate::file::archive::Layout {
+        self.archive
+            .layout()
+            .expect("Layout is initialized in the constructor")
+    }
+}
+
+impl io::Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = self.archive.clone();
+        let layout = archive
+            .layout()
+            .expect("Layout is initialized in the constructor");
+
+        if buf.is_empty() || self.pos >= layout.size {
+            return Ok(0);
+        }
+
+        let index = layout
+            .members
+            .partition_point(|member| member.offset <= self.pos)
+            .checked_sub(1);
+
+        let n = match index.map(|index| (index, &layout.members[index])) {
+            Some((index, member)) if self.pos < member.offset + member.len() => {
+                let local = self.pos - member.offset;
+                let header_len = member.header.len() as u64;
+
+                if local < header_len {
+                    let header = &member.header[local as usize..];
+                    let n = header.len().min(buf.len());
+                    buf[..n].copy_from_slice(&header[..n]);
+                    n
+                } else if local < header_len + member.size {
+                    let data_pos = local - header_len;
+                    let len = (member.size - data_pos).min(buf.len() as u64) as usize;
+
+                    let file = self.member_file(index)?;
+                    file.seek(io::SeekFrom::Start(data_pos))?;
+
+                    let n = file.read(&mut buf[..len])?;
+                    if n == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Archived file size has changed",
+                        ));
+                    }
+                    n
+                } else {
+                    // Padding up to the block boundary
+                    zero_fill(buf, member.offset + member.len() - self.pos)
+                }
+            }
+            // The trailer
+            _ => zero_fill(buf, layout.size - self.pos),
+        };
+
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl io::Seek for FileReader {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            io::SeekFrom::Start(off) => off,
+            io::SeekFrom::End(off) => self.layout().size.wrapping_add(off as _),
+            io::SeekFrom::Current(off) => self.pos.wrapping_add(off as _),
+        };
+
+        Ok(self.pos)
+    }
+}
+
+impl super::Reader for FileReader {
+    fn bytes_read(&self) -> u64 {
+        self.pos
+    }
+
+    fn meta(&mut self) -> crate::Result<super::Meta> {
+        // The members are verified separately when opened
+        Ok(super::Meta {
+            len: self.layout().size,
+            modified: None,
+        })
+    }
+}
+
+fn zero_fill(buf: &mut [u8], len: u64) -> usize {
+    let n = len.min(buf.len() as u64) as usize;
+    buf[..n].fill(0);
+    n
+}
@@ -49,8 +49,8 @@ impl super::Reader for FileReader {
         self.pos
     }
 
-    fn meta(&mut self) -> crate::Result<fs::Metadata> {
+    fn meta(&mut self) -> crate::Result<super::Meta> {
         let meta = self.file.metadata()?;
-        Ok(meta)
+        Ok(meta.into())
     }
 }
@@ -1,9 +1,10 @@
+mod archive;
 #[cfg(unix)]
 mod fd;
 
 mod path;
 
-use std::{fs, io};
+use std::{fs, io, time::SystemTime};
 
 use crate::Error;
 
@@ -13,12 +14,19 @@ const CHUNK_SIZE: usize = 1024 * 1024;
 pub struct FileReader {
     inner: Box<dyn Reader>,
     buffer: Box<[u8]>,
-    meta: fs::Metadata,
+    meta: Meta,
+}
+
+pub(super) struct Meta {
+    pub len: u64,
+    // `None` if the source does not track the modification time
+    pub modified: Option<SystemTime>,
 }
 
 pub(super) fn open(source: &super::FileSource) -> crate::Result<Box<dyn Reader>> {
     let reader: Box<dyn Reader> = match source {
         super::FileSource::Path(path) => Box::new(path::FileReader::new(path)?),
+        super::FileSource::Archive(archive) => Box::new(archive::FileReader::new(archive.clone())?),
         #[cfg(unix)]
         super::FileSource::Fd {
             fd,
@@ -41,7 +49,7 @@ pub(super) fn open(source: &super::FileSource) -> crate::Result<Box<dyn Reader>>
 }
 
 impl FileReader {
-    pub(super) fn new(reader: Box<dyn Reader>, meta: fs::Metadata) -> crate::Result<Self> {
+    pub(super) fn new(reader: Box<dyn Reader>, meta: Meta) -> crate::Result<Self> {
         Ok(Self {
             inner: reader,
             buffer: vec![0u8; CHUNK_SIZE].into_boxed_slice(),
@@ -61,14 +69,14 @@ impl FileReader {
         if n == 0 {
             // File size might have been reduced while in the loop which
             // will result in an error
-            if total_read != self.meta.len() {
+            if total_read != self.meta.len {
                 return Err(Error::MismatchedSize);
             } else {
                 return Ok(None);
             }
         }
 
-        if total_read > self.meta.len() {
+        if total_read > self.meta.len {
             return Err(Error::MismatchedSize);
         }
 
@@ -77,8 +85,8 @@ impl FileReader {
     }
 
     fn is_mtime_ok(&mut self) -> crate::Result<bool> {
-        let mtime_orig = self.meta.modified()?;
-        let mtime_act = self.inner.meta()?.modified()?;
+        let mtime_orig = self.meta.modified;
+        let mtime_act = self.inner.meta()?.modified;
 
         Ok(mtime_orig == mtime_act)
     }
@@ -86,5 +94,14 @@ impl FileReader {
 
 pub(super) trait Reader: io::Read + io::Seek + Send + Sync {
     fn bytes_read(&self) -> u64;
-    fn meta(&mut self) -> crate::Result<fs::Metadata>;
+    fn meta(&mut self) -> crate::Result<Meta>;
+}
+
+impl From<fs::Metadata> for Meta {
+    fn from(meta: fs::Metadata) -> Self {
+        Self {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        }
+    }
 }
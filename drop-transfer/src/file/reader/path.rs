@@ -23,6 +23,10 @@ impl FileReader {
 
         Ok(Self { file, pos: 0 })
     }
+
+    pub fn metadata(&self) -> io::Result<fs::Metadata> {
+        self.file.metadata()
+    }
 }
 
 impl io::Read for FileReader {
@@ -45,8 +49,8 @@ impl super::Reader for FileReader {
         self.pos
     }
 
-    fn meta(&mut self) -> crate::Result<fs::Metadata> {
+    fn meta(&mut self) -> crate::Result<super::Meta> {
         let meta = self.file.metadata()?;
-        Ok(meta)
+        Ok(meta.into())
     }
 }
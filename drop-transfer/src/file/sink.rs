@@ -0,0 +1,438 @@
+use std::{
+    fs,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use futures::future::BoxFuture;
+use slog::{debug, error, warn, Logger};
+use uuid::Uuid;
+
+use super::{ChecksumAlgorithm, FileId, FileToRecv};
+use crate::{quarantine::PathExt, utils::Hidden, File};
+
+/// Callback receiving the number of bytes checksummed so far
+pub type ProgressCallback = Box<dyn FnMut(u64) -> BoxFuture<'static, ()> + Send + Sync>;
+
+// Blocks of zeros of this size are not written to the file but left as holes
+const HOLE_BLOCK_SIZE: usize = 4 * 1024;
+const ZEROS_BUF_SIZE: u64 = 64 * 1024;
+
+/// The destination of the data received from the peer. A single sink is
+/// created for every download attempt of a file.
+#[async_trait::async_trait]
+pub trait DownloadSink: Send + Sync {
+    /// Returns the number of bytes left by the previous, interrupted download
+    /// or `None` if there is nothing to resume from
+    async fn resume_offset(&mut self) -> crate::Result<Option<u64>>;
+
+    /// Calculates the checksum of all the data currently stored in the sink
+    async fn checksum(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+        progress_cb: Option<ProgressCallback>,
+        event_granularity: Option<u64>,
+    ) -> crate::Result<[u8; 32]>;
+
+    /// Prepares the sink for writing. Any data past the `offset` is dropped,
+    /// the `0` offset means the download starts from scratch
+    async fn open(&mut self, offset: u64) -> crate::Result<()>;
+
+    /// Reserves the space for the whole file of `size` bytes, so that lack of
+    /// space is detected before receiving any data. The default does nothing
+    async fn preallocate(&mut self, _size: u64) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Appends the next chunk of the file
+    async fn append(&mut self, chunk: &[u8]) -> crate::Result<()>;
+
+    /// Appends a run of `len` zero bytes signalled by the sender instead of
+    /// the data. The default writes the zeros with `append()`
+    async fn append_zeros(&mut self, mut len: u64) -> crate::Result<()> {
+        let zeros = vec![0u8; len.min(ZEROS_BUF_SIZE) as usize];
+
+        while len > 0 {
+            let n = len.min(zeros.len() as u64);
+            self.append(&zeros[..n as usize]).await?;
+            len -= n;
+        }
+
+        Ok(())
+    }
+
+    /// Called once the whole file is received and its checksum validated.
+    /// The `dst` is the destination path composed by libdrop. Returns the final
+    /// location of the file, reported back with the success event
+    async fn finalize(&mut self, dst: &Path) -> crate::Result<PathBuf>;
+
+    /// Opens the received data for reading. Called instead of `finalize()` for
+    /// the directory archives, which are unpacked by libdrop and then
+    /// discarded. Archives are not supported by default
+    async fn reader(&mut self) -> crate::Result<Box<dyn io::Read + Send>> {
+        Err(crate::Error::BadTransferState(
+            "Download sink does not support archives".into(),
+        ))
+    }
+
+    /// Removes the partially received data after an unrecoverable failure
+    async fn discard(&mut self) -> crate::Result<()>;
+}
+
+/// Creates sinks for the files being downloaded. The `base_dir` is the
+/// directory passed to the `Service::download()` call.
+pub trait DownloadSinkFactory: Send + Sync {
+    fn create(
+        &self,
+        transfer_id: Uuid,
+        file: &FileToRecv,
+        base_dir: &Path,
+    ) -> crate::Result<Box<dyn DownloadSink>>;
+}
+
+/// The default sink, writing into a temporary file inside the destination
+/// directory and moving it into place once finished
+pub struct FsDownloadSink {
+    logger: Logger,
+    tmp_location: Hidden<PathBuf>,
+    file: Option<fs::File>,
+    // Number of bytes in the file, including the holes
+    pos: u64,
+    // Indicates the file cursor is behind `pos` because of the hole
+    in_hole: bool,
+}
+
+pub struct FsDownloadSinkFactory {
+    logger: Logger,
+}
+
+impl FsDownloadSink {
+    pub fn new(logger: Logger, tmp_location: PathBuf) -> Self {
+        Self {
+            logger,
+            tmp_location: Hidden(tmp_location),
+            file: None,
+            pos: 0,
+            in_hole: false,
+        }
+    }
+
+    fn opened_file(&mut self) -> crate::Result<&mut fs::File> {
+        self.file
+            .as_mut()
+            .ok_or_else(|| crate::Error::BadTransferState("Sink is not opened".into()))
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadSink for FsDownloadSink {
+    async fn resume_offset(&mut self) -> crate::Result<Option<u64>> {
+        match fs::metadata(&self.tmp_location.0) {
+            Ok(meta) => Ok(Some(meta.len())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Blocking operation
+    async fn checksum(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+        progress_cb: Option<ProgressCallback>,
+        event_granularity: Option<u64>,
+    ) -> crate::Result<[u8; 32]> {
+        let file = fs::File::open(&self.tmp_location.0)?;
+        let csum = super::checksum(file, algorithm, progress_cb, event_granularity).await?;
+        Ok(csum)
+    }
+
+    async fn open(&mut self, offset: u64) -> crate::Result<()> {
+        let file = if offset == 0 {
+            fs::File::create(&self.tmp_location.0)?
+        } else {
+            let mut file = fs::File::options().write(true).open(&self.tmp_location.0)?;
+            file.set_len(offset)?;
+            file.seek(io::SeekFrom::Start(offset))?;
+            file
+        };
+
+        self.file = Some(file);
+        self.pos = offset;
+        self.in_hole = false;
+        Ok(())
+    }
+
+    async fn preallocate(&mut self, size: u64) -> crate::Result<()> {
+        let file = self.opened_file()?;
+
+        if let Err(err) = preallocate(file, size) {
+            if err.raw_os_error() == Some(libc::ENOSPC) {
+                return Err(err.into());
+            }
+
+            // The filesystem might not support it, not a big deal
+            debug!(self.logger, "Failed to preallocate download file: {err}");
+        }
+
+        Ok(())
+    }
+
+    async fn append(&mut self, chunk: &[u8]) -> crate::Result<()> {
+        let mut pos = self.pos;
+        let mut in_hole = self.in_hole;
+        let file = self.opened_file()?;
+
+        for block in chunk.chunks(HOLE_BLOCK_SIZE) {
+            if block.len() == HOLE_BLOCK_SIZE && block.iter().all(|&b| b == 0) {
+                in_hole = true;
+            } else {
+                if in_hole {
+                    file.seek(io::SeekFrom::Start(pos))?;
+                    in_hole = false;
+                }
+
+                file.write_all(block)?;
+            }
+
+            pos += block.len() as u64;
+        }
+
+        // The trailing hole needs to be reflected in the file size
+        if in_hole {
+            file.set_len(pos)?;
+        }
+
+        self.pos = pos;
+        self.in_hole = in_hole;
+        Ok(())
+    }
+
+    async fn append_zeros(&mut self, len: u64) -> crate::Result<()> {
+        let pos = self.pos + len;
+        self.opened_file()?.set_len(pos)?;
+
+        self.pos = pos;
+        self.in_hole = true;
+        Ok(())
+    }
+
+    async fn finalize(&mut self, dst: &Path) -> crate::Result<PathBuf> {
+        // Close the file handle
+        self.file = None;
+
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        move_tmp_to_dst(&self.tmp_location, Hidden(dst), &self.logger)
+    }
+
+    async fn reader(&mut self) -> crate::Result<Box<dyn io::Read + Send>> {
+        // Close the file handle
+        self.file = None;
+
+        let file = fs::File::open(&self.tmp_location.0)?;
+        Ok(Box::new(io::BufReader::new(file)))
+    }
+
+    async fn discard(&mut self) -> crate::Result<()> {
+        self.file = None;
+
+        fs::remove_file(&self.tmp_location.0)?;
+        Ok(())
+    }
+}
+
+impl FsDownloadSinkFactory {
+    pub fn new(logger: Logger) -> Self {
+        Self { logger }
+    }
+}
+
+impl DownloadSinkFactory for FsDownloadSinkFactory {
+    fn create(
+        &self,
+        transfer_id: Uuid,
+        file: &FileToRecv,
+        base_dir: &Path,
+    ) -> crate::Result<Box<dyn DownloadSink>> {
+        let tmp_location = base_dir.join(temp_file_name(transfer_id, file.id()));
+        Ok(Box::new(FsDownloadSink::new(
+            self.logger.clone(),
+            tmp_location,
+        )))
+    }
+}
+
+// Reserves the disk blocks without changing the file size, so that the
+// interrupted download is still resumed from the right offset
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn preallocate(file: &fs::File, size: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let res = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            0,
+            size as libc::off_t,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn preallocate(_file: &fs::File, _size: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub(crate) fn temp_file_name(transfer_id: Uuid, file_id: &FileId) -> String {
+    format!("{}-{file_id}.dropdl-part", transfer_id.as_simple(),)
+}
+
+fn move_tmp_to_dst(
+    tmp_location: &Hidden<PathBuf>,
+    absolute_path: Hidden<&Path>,
+    logger: &Logger,
+) -> crate::Result<PathBuf> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+
+    let mut iter = crate::utils::filepath_variants(absolute_path.0)?;
+    let dst_location = loop {
+        let path = iter.next().expect("File paths iterator should never end");
+
+        match opts.open(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                continue;
+            }
+            Err(err) => {
+                // On Win the permissions error is returned in case there's a
+                // directory with the same name. Let's do it for all OSes since
+                // there should be no harm.
+                if path.exists() {
+                    continue;
+                }
+
+                error!(logger, "Failed to crate destination file: {err}");
+                return Err(err.into());
+            }
+            Ok(file) => {
+                drop(file); // Close the file
+                break path;
+            }
+        }
+    };
+
+    if let Err(err) = fs::rename(&tmp_location.0, &dst_location) {
+        if let Err(err) = fs::remove_file(&dst_location) {
+            warn!(
+                logger,
+                "Failed to remove touched destination file on move error: {err}"
+            );
+        }
+        return Err(err.into());
+    }
+
+    if let Err(err) = dst_location.quarantine() {
+        error!(logger, "Failed to quarantine downloaded file: {err}");
+    }
+
+    Ok(dst_location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fs_sink_resume_and_finalize() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let dir = tempfile::tempdir().expect("Failed to create tmp dir");
+
+        let tmp_location = dir.path().join("file.dropdl-part");
+        let mut sink = FsDownloadSink::new(logger.clone(), tmp_location.clone());
+        assert_eq!(sink.resume_offset().await.unwrap(), None);
+
+        sink.open(0).await.unwrap();
+        sink.append(b"abcdef").await.unwrap();
+
+        // Resume with only part of the data
+        let mut sink = FsDownloadSink::new(logger, tmp_location.clone());
+        assert_eq!(sink.resume_offset().await.unwrap(), Some(6));
+
+        sink.open(3).await.unwrap();
+        sink.append(b"xyz").await.unwrap();
+        assert_eq!(std::fs::read(&tmp_location).unwrap(), b"abcxyz");
+
+        // Destination already taken
+        std::fs::write(dir.path().join("file.txt"), b"").unwrap();
+
+        let dst = sink.finalize(&dir.path().join("file.txt")).await.unwrap();
+        assert_eq!(dst, dir.path().join("file(1).txt"));
+        assert_eq!(std::fs::read(&dst).unwrap(), b"abcxyz");
+        assert!(!tmp_location.exists());
+    }
+
+    #[tokio::test]
+    async fn fs_sink_holes() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let dir = tempfile::tempdir().expect("Failed to create tmp dir");
+
+        let tmp_location = dir.path().join("file.dropdl-part");
+        let mut sink = FsDownloadSink::new(logger, tmp_location.clone());
+
+        let mut expected = b"abc".to_vec();
+        expected.extend_from_slice(&[0; HOLE_BLOCK_SIZE * 2]);
+        expected.extend_from_slice(b"def");
+
+        sink.open(0).await.unwrap();
+        sink.preallocate(4 * HOLE_BLOCK_SIZE as u64).await.unwrap();
+        sink.append(&expected[..3 + HOLE_BLOCK_SIZE]).await.unwrap();
+        sink.append(&expected[3 + HOLE_BLOCK_SIZE..]).await.unwrap();
+        sink.append_zeros(10).await.unwrap();
+        expected.extend_from_slice(&[0; 10]);
+
+        assert_eq!(std::fs::read(&tmp_location).unwrap(), expected);
+        assert_eq!(
+            sink.resume_offset().await.unwrap(),
+            Some(expected.len() as u64)
+        );
+
+        // Trailing hole
+        sink.append(&[0; HOLE_BLOCK_SIZE]).await.unwrap();
+        expected.extend_from_slice(&[0; HOLE_BLOCK_SIZE]);
+
+        assert_eq!(std::fs::read(&tmp_location).unwrap(), expected);
+    }
+
+    // Without the preallocation the holes take no disk space
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[tokio::test]
+    async fn fs_sink_holes_are_sparse() {
+        use std::os::unix::fs::MetadataExt;
+
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let dir = tempfile::tempdir().expect("Failed to create tmp dir");
+
+        let tmp_location = dir.path().join("file.dropdl-part");
+        let mut sink = FsDownloadSink::new(logger, tmp_location.clone());
+
+        let hole_len = 256 * HOLE_BLOCK_SIZE;
+
+        sink.open(0).await.unwrap();
+        sink.append(b"abc").await.unwrap();
+        sink.append(&vec![0; hole_len]).await.unwrap();
+        sink.append_zeros(hole_len as u64).await.unwrap();
+        sink.append(b"def").await.unwrap();
+
+        let meta = std::fs::metadata(&tmp_location).unwrap();
+        assert_eq!(meta.len(), 6 + 2 * hole_len as u64);
+        assert!(meta.blocks() * 512 < meta.len());
+    }
+}
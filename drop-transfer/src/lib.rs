@@ -7,6 +7,7 @@ mod manager;
 mod protocol;
 mod quarantine;
 pub mod service;
+pub mod snapshot;
 mod storage_dispatch;
 mod tasks;
 pub mod transfer;
@@ -19,7 +20,7 @@ pub(crate) use crate::manager::TransferManager;
 pub use crate::{
     error::Error,
     event::Event,
-    file::{File, FileId, FileToRecv, FileToSend},
+    file::{DownloadSink, DownloadSinkFactory, File, FileId, FileToRecv, FileToSend},
     service::Service,
     storage_dispatch::StorageDispatch,
     transfer::{IncomingTransfer, OutgoingTransfer, Transfer, TransferData},
@@ -7,8 +7,8 @@ use std::{
 
 use anyhow::Context;
 use drop_config::DropConfig;
-use drop_storage::{sync, types::OutgoingFileToRetry, Storage};
-use slog::{debug, error, info, trace, warn, Logger};
+use drop_storage::{sync, types::OutgoingFileToRetry, StorageBackend};
+use slog::{debug, error, info, o, trace, warn, Logger};
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
@@ -16,7 +16,9 @@ use uuid::Uuid;
 use crate::{
     check,
     file::FileSubPath,
+    protocol,
     service::State,
+    snapshot::{self, FileActivity, FileSnapshot, TransferSnapshot},
     tasks::AliveGuard,
     transfer::{IncomingTransfer, OutgoingTransfer},
     ws::{
@@ -79,9 +81,18 @@ enum OutgoingLocalFileState {
     Terminal(FileTerminalState),
 }
 
+#[derive(Default)]
+struct ConnectionInfo {
+    version: Option<protocol::Version>,
+    connected_once: bool,
+    reconnects: u32,
+    retries: u32,
+}
+
 pub struct IncomingState {
     pub xfer: Arc<IncomingTransfer>,
     conn: Option<UnboundedSender<ServerReq>>,
+    conn_info: ConnectionInfo,
     pub dir_mappings: DirMapping,
     xfer_sync: sync::TransferState,
     file_sync: HashMap<FileId, IncomingLocalFileState>,
@@ -92,6 +103,7 @@ pub struct IncomingState {
 pub struct OutgoingState {
     pub xfer: Arc<OutgoingTransfer>,
     conn: Option<UnboundedSender<ClientReq>>,
+    conn_info: ConnectionInfo,
     xfer_sync: sync::TransferState,
     file_sync: HashMap<FileId, OutgoingLocalFileState>,
     file_events: HashMap<FileId, Arc<OutgoingFileEventTx>>,
@@ -103,7 +115,7 @@ pub struct OutgoingState {
 pub struct TransferManager {
     pub incoming: Mutex<HashMap<Uuid, IncomingState>>,
     pub outgoing: Mutex<HashMap<Uuid, OutgoingState>>,
-    storage: Arc<Storage>,
+    storage: Arc<dyn StorageBackend>,
     logger: Logger,
     event_factory: EventTxFactory,
 }
@@ -114,7 +126,11 @@ pub struct DirMapping {
 }
 
 impl TransferManager {
-    pub fn new(storage: Arc<Storage>, event_factory: EventTxFactory, logger: Logger) -> Self {
+    pub fn new(
+        storage: Arc<dyn StorageBackend>,
+        event_factory: EventTxFactory,
+        logger: Logger,
+    ) -> Self {
         Self {
             incoming: Default::default(),
             outgoing: Default::default(),
@@ -124,12 +140,19 @@ impl TransferManager {
         }
     }
 
+    /// Attaches the transfer ID to the log records
+    fn xfer_logger(&self, transfer_id: Uuid) -> Logger {
+        self.logger.new(o!("transfer_id" => %transfer_id))
+    }
+
     /// Returns `Some()` if the transfer is new one
     pub async fn register_incoming(
         &self,
         xfer: Arc<IncomingTransfer>,
         conn: UnboundedSender<ServerReq>,
+        version: protocol::Version,
     ) -> anyhow::Result<IncomingRegistered> {
+        let logger = self.xfer_logger(xfer.id());
         let mut lock = self.incoming.lock().await;
 
         match lock.entry(xfer.id()) {
@@ -139,7 +162,7 @@ impl TransferManager {
                 ensure_resume_matches_existing_transfer(&*xfer, &*state.xfer)?;
 
                 info!(
-                    self.logger,
+                    logger,
                     "Transfer {} resume. Resuming started files",
                     xfer.id()
                 );
@@ -151,22 +174,23 @@ impl TransferManager {
                     );
                 }
 
-                info!(self.logger, "Issuing pending requests for: {}", xfer.id());
-                state.issue_pending_requests(&conn, &self.logger);
+                info!(logger, "Issuing pending requests for: {}", xfer.id());
+                state.issue_pending_requests(&conn, &logger);
 
                 match state.xfer_sync {
                     sync::TransferState::Canceled => {
-                        debug!(self.logger, "Incoming transfer is locally cancelled");
+                        debug!(logger, "Incoming transfer is locally cancelled");
                         if let Err(e) = conn.send(ServerReq::Close) {
-                            warn!(self.logger, "Failed to send close request: {}", e);
+                            warn!(logger, "Failed to send close request: {}", e);
                         }
                         drop(conn)
                     }
                     _ => {
                         state.conn = Some(conn);
+                        state.conn_info.connected(version);
 
                         let was_cancelled = state
-                            .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+                            .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
                             .await;
 
                         match was_cancelled {
@@ -187,9 +211,9 @@ impl TransferManager {
                     .await
                     .is_none()
                 {
-                    warn!(self.logger, "Transfer was closed already");
+                    warn!(logger, "Transfer was closed already");
                     if let Err(e) = conn.send(ServerReq::Close) {
-                        warn!(self.logger, "Failed to send close request: {}", e);
+                        warn!(logger, "Failed to send close request: {}", e);
                     }
                     return Ok(IncomingRegistered::Continue);
                 }
@@ -198,9 +222,15 @@ impl TransferManager {
                     .update_transfer_sync_states(xfer.id(), sync::TransferState::Active)
                     .await;
 
+                let xfer_events = self.event_factory.transfer(xfer.clone(), false);
+
+                let mut conn_info = ConnectionInfo::default();
+                conn_info.connected(version);
+
                 let state = vacc.insert(IncomingState {
                     xfer: xfer.clone(),
                     conn: Some(conn),
+                    conn_info,
                     dir_mappings: Default::default(),
                     xfer_sync: sync::TransferState::Active,
                     file_sync: xfer
@@ -214,11 +244,11 @@ impl TransferManager {
                         .map(|file_id| {
                             (
                                 file_id.clone(),
-                                Arc::new(self.event_factory.file(xfer.clone(), file_id.clone())),
+                                Arc::new(self.event_factory.file(&xfer_events, file_id.clone())),
                             )
                         })
                         .collect(),
-                    xfer_events: Arc::new(self.event_factory.transfer(xfer, false)),
+                    xfer_events: Arc::new(xfer_events),
                 });
 
                 Ok(IncomingRegistered::IsNew {
@@ -237,7 +267,9 @@ impl TransferManager {
         &self,
         transfer_id: Uuid,
         conn: UnboundedSender<ClientReq>,
+        version: protocol::Version,
     ) -> crate::Result<OutgoingConnected> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.outgoing.lock().await;
         let state = lock
             .get_mut(&transfer_id)
@@ -253,18 +285,19 @@ impl TransferManager {
 
         match state.xfer_sync {
             sync::TransferState::Canceled => {
-                debug!(self.logger, "Outgoing transfer is locally cancelled");
+                debug!(logger, "Outgoing transfer is locally cancelled");
                 if let Err(e) = conn.send(ClientReq::Close) {
-                    warn!(self.logger, "Failed to send close request: {}", e);
+                    warn!(logger, "Failed to send close request: {}", e);
                 }
                 drop(conn);
             }
             _ => {
-                state.issue_pending_requests(&conn, &self.logger);
+                state.issue_pending_requests(&conn, &logger);
                 state.conn = Some(conn);
+                state.conn_info.connected(version);
 
                 let was_cancelled = state
-                    .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+                    .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
                     .await;
 
                 match was_cancelled {
@@ -283,15 +316,12 @@ impl TransferManager {
         &self,
         xfer: Arc<OutgoingTransfer>,
     ) -> crate::Result<Arc<OutgoingTransferEventTx>> {
+        let logger = self.xfer_logger(xfer.id());
         let mut lock = self.outgoing.lock().await;
 
         let state = match lock.entry(xfer.id()) {
             Entry::Occupied(_) => {
-                warn!(
-                    self.logger,
-                    "Outgoing transfer UUID colision: {}",
-                    xfer.id()
-                );
+                warn!(logger, "Outgoing transfer UUID colision: {}", xfer.id());
 
                 return Err(crate::Error::BadTransferState(
                     "Transfer already exists".into(),
@@ -300,9 +330,12 @@ impl TransferManager {
             Entry::Vacant(entry) => {
                 self.storage.insert_transfer(&xfer.storage_info()).await;
 
+                let xfer_events = self.event_factory.transfer(xfer.clone(), false);
+
                 entry.insert(OutgoingState {
                     xfer: xfer.clone(),
                     conn: None,
+                    conn_info: Default::default(),
                     xfer_sync: sync::TransferState::New,
                     file_sync: xfer
                         .files()
@@ -315,11 +348,11 @@ impl TransferManager {
                         .map(|file_id| {
                             (
                                 file_id.clone(),
-                                Arc::new(self.event_factory.file(xfer.clone(), file_id.clone())),
+                                Arc::new(self.event_factory.file(&xfer_events, file_id.clone())),
                             )
                         })
                         .collect(),
-                    xfer_events: Arc::new(self.event_factory.transfer(xfer, false)),
+                    xfer_events: Arc::new(xfer_events),
                 })
             }
         };
@@ -364,6 +397,7 @@ impl TransferManager {
         transfer_id: Uuid,
         file_id: &FileId,
     ) -> crate::Result<FinishResult<OutgoingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.outgoing.lock().await;
 
         let state = lock
@@ -386,20 +420,20 @@ impl TransferManager {
 
         if let Some(conn) = &state.conn {
             debug!(
-                self.logger,
+                logger,
                 "Pushing outgoing rejection request: file_id {file_id}"
             );
 
             if let Err(e) = conn.send(ClientReq::Reject {
                 file: file_id.clone(),
             }) {
-                warn!(self.logger, "Failed to send reject request: {}", e);
+                warn!(logger, "Failed to send reject request: {}", e);
             };
         }
 
         Ok(FinishResult {
             xfer_state: state
-                .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+                .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
                 .await,
             file_events: state.file_events(file_id)?.clone(),
         })
@@ -411,6 +445,7 @@ impl TransferManager {
         file_id: &FileId,
         file_state: FileTerminalState,
     ) -> crate::Result<Option<FinishResult<OutgoingTransfer>>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.outgoing.lock().await;
 
         let state = lock
@@ -429,7 +464,7 @@ impl TransferManager {
                 .await;
 
             let xfer_state = state
-                .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+                .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
                 .await;
 
             Some(FinishResult {
@@ -448,6 +483,7 @@ impl TransferManager {
         transfer_id: Uuid,
         file_id: &FileId,
     ) -> crate::Result<FinishResult<IncomingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.incoming.lock().await;
 
         let state = lock
@@ -465,19 +501,19 @@ impl TransferManager {
 
         if let Some(conn) = &state.conn {
             debug!(
-                self.logger,
+                logger,
                 "Pushing incoming rejection request: file_id {file_id}"
             );
 
             if let Err(e) = conn.send(ServerReq::Reject {
                 file: file_id.clone(),
             }) {
-                warn!(self.logger, "Failed to send reject request: {}", e);
+                warn!(logger, "Failed to send reject request: {}", e);
             };
         }
 
         let xfer_state = state
-            .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+            .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
             .await;
 
         Ok(FinishResult {
@@ -487,7 +523,8 @@ impl TransferManager {
     }
 
     pub async fn incoming_remove(&self, transfer_id: Uuid) -> Option<IncomingState> {
-        debug!(self.logger, "Removing incoming transfer: {transfer_id}");
+        let logger = self.xfer_logger(transfer_id);
+        debug!(logger, "Removing incoming transfer: {transfer_id}");
         let mut lock = self.incoming.lock().await;
 
         let state = lock.remove(&transfer_id)?;
@@ -510,6 +547,7 @@ impl TransferManager {
         file_id: &FileId,
         success: Result<(), String>,
     ) -> crate::Result<FinishTransferState<IncomingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.incoming.lock().await;
 
         let state = lock
@@ -537,14 +575,14 @@ impl TransferManager {
                 Err(msg) => ("FAIL", ServerReq::Fail { file, msg }),
             };
 
-            debug!(self.logger, "Pushing file {name} message");
+            debug!(logger, "Pushing file {name} message");
             if let Err(e) = conn.send(serv_req) {
-                warn!(self.logger, "Failed to send {name} message: {e}");
+                warn!(logger, "Failed to send {name} message: {e}");
             };
         }
 
         let xfer_state = state
-            .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+            .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
             .await;
 
         Ok(xfer_state)
@@ -556,6 +594,7 @@ impl TransferManager {
         file_id: &FileId,
         file_state: FileTerminalState,
     ) -> crate::Result<Option<FinishResult<IncomingTransfer>>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.incoming.lock().await;
 
         let state = lock
@@ -570,7 +609,7 @@ impl TransferManager {
                 .await;
 
             let xfer_state = state
-                .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+                .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
                 .await;
 
             Some(FinishResult {
@@ -590,6 +629,7 @@ impl TransferManager {
         file_id: &FileId,
         msg: String,
     ) -> crate::Result<FinishResult<OutgoingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.outgoing.lock().await;
 
         let state = lock
@@ -610,17 +650,17 @@ impl TransferManager {
             .await;
 
         if let Some(conn) = &state.conn {
-            debug!(self.logger, "Pushing file FAIL message");
+            debug!(logger, "Pushing file FAIL message");
             if let Err(e) = conn.send(ClientReq::Fail {
                 file: file_id.clone(),
                 msg: msg.to_string(),
             }) {
-                warn!(self.logger, "Failed to send FAIL message: {e}");
+                warn!(logger, "Failed to send FAIL message: {e}");
             };
         }
 
         let xfer_state = state
-            .cancel_transfer_if_all_files_terminated(&self.logger, &self.storage)
+            .cancel_transfer_if_all_files_terminated(&logger, &self.storage)
             .await;
 
         Ok(FinishResult {
@@ -633,6 +673,7 @@ impl TransferManager {
         &self,
         transfer_id: Uuid,
     ) -> crate::Result<CloseResult<IncomingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.incoming.lock().await;
 
         let state = lock
@@ -640,7 +681,7 @@ impl TransferManager {
             .ok_or(crate::Error::BadTransfer)?;
 
         state.ensure_not_cancelled()?;
-        state.cancel_transfer(&self.logger, &self.storage).await;
+        state.cancel_transfer(&logger, &self.storage).await;
 
         for val in state.file_sync.values_mut() {
             if let IncomingLocalFileState::InFlight { .. } = &*val {
@@ -660,6 +701,7 @@ impl TransferManager {
         &self,
         transfer_id: Uuid,
     ) -> crate::Result<CloseResult<OutgoingTransfer>> {
+        let logger = self.xfer_logger(transfer_id);
         let mut lock = self.outgoing.lock().await;
 
         let state = lock
@@ -681,7 +723,7 @@ impl TransferManager {
                 Ok(res)
             }
             sync::TransferState::Active => {
-                state.cancel_transfer(&self.logger, &self.storage).await;
+                state.cancel_transfer(&logger, &self.storage).await;
 
                 Ok(CloseResult {
                     file_events: state.file_events.values().cloned().collect(),
@@ -708,7 +750,8 @@ impl TransferManager {
     }
 
     pub async fn outgoing_remove(&self, transfer_id: Uuid) -> Option<OutgoingState> {
-        debug!(self.logger, "Removing outgoing transfer: {transfer_id}");
+        let logger = self.xfer_logger(transfer_id);
+        debug!(logger, "Removing outgoing transfer: {transfer_id}");
         let mut lock = self.outgoing.lock().await;
 
         let state = lock.remove(&transfer_id)?;
@@ -727,27 +770,147 @@ impl TransferManager {
 
     pub async fn incoming_disconnect(&self, transfer_id: Uuid) -> crate::Result<()> {
         let mut lock = self.incoming.lock().await;
-        let _ = lock
+        let state = lock
             .get_mut(&transfer_id)
-            .ok_or(crate::Error::BadTransfer)?
-            .conn
-            .take();
+            .ok_or(crate::Error::BadTransfer)?;
+
+        state.conn.take();
+        state.conn_info.version = None;
         Ok(())
     }
 
     pub async fn outgoing_disconnect(&self, transfer_id: Uuid) -> crate::Result<()> {
-        trace!(self.logger, "outgoing_disconnect: {}", transfer_id);
+        let logger = self.xfer_logger(transfer_id);
+        trace!(logger, "outgoing_disconnect: {}", transfer_id);
         let mut lock = self.outgoing.lock().await;
-        let _ = lock
+        let state = lock
             .get_mut(&transfer_id)
-            .ok_or(crate::Error::BadTransfer)?
-            .conn
-            .take();
+            .ok_or(crate::Error::BadTransfer)?;
+
+        state.conn.take();
+        state.conn_info.version = None;
         Ok(())
     }
+
+    pub async fn outgoing_connection_failed(&self, transfer_id: Uuid) {
+        let mut lock = self.outgoing.lock().await;
+        if let Some(state) = lock.get_mut(&transfer_id) {
+            state.conn_info.retries += 1;
+        }
+    }
+
+    pub async fn snapshot(&self, transfer_id: Uuid) -> Option<TransferSnapshot> {
+        if let Some(state) = self.incoming.lock().await.get(&transfer_id) {
+            return Some(state.snapshot().await);
+        }
+
+        if let Some(state) = self.outgoing.lock().await.get(&transfer_id) {
+            return Some(state.snapshot().await);
+        }
+
+        None
+    }
+
+    pub async fn snapshots(&self) -> Vec<TransferSnapshot> {
+        let mut snapshots = Vec::new();
+
+        for state in self.incoming.lock().await.values() {
+            snapshots.push(state.snapshot().await);
+        }
+        for state in self.outgoing.lock().await.values() {
+            snapshots.push(state.snapshot().await);
+        }
+
+        snapshots
+    }
+}
+
+impl ConnectionInfo {
+    fn connected(&mut self, version: protocol::Version) {
+        if self.connected_once {
+            self.reconnects += 1;
+        }
+
+        self.connected_once = true;
+        self.version = Some(version);
+        self.retries = 0;
+    }
+
+    fn snapshot<T: Transfer>(
+        &self,
+        xfer: &T,
+        direction: snapshot::Direction,
+        connected: bool,
+        files: Vec<FileSnapshot>,
+    ) -> TransferSnapshot {
+        TransferSnapshot {
+            id: xfer.id(),
+            peer: xfer.peer(),
+            direction,
+            connected,
+            protocol_version: self.version.map(i32::from),
+            reconnects: self.reconnects,
+            connection_retries: self.retries,
+            files,
+        }
+    }
+}
+
+impl From<FileTerminalState> for FileActivity {
+    fn from(value: FileTerminalState) -> Self {
+        match value {
+            FileTerminalState::Rejected => Self::Rejected,
+            FileTerminalState::Completed => Self::Completed,
+            FileTerminalState::Failed => Self::Failed,
+        }
+    }
+}
+
+async fn file_snapshot<T: Transfer>(
+    xfer: &T,
+    events: &FileEventTx<T>,
+    terminal: Option<FileTerminalState>,
+    requested: bool,
+) -> FileSnapshot {
+    let file = &xfer.files()[events.file_id()];
+    let (activity, transferred) = events.activity().await;
+
+    let activity = match (terminal, activity) {
+        (Some(terminal), _) => terminal.into(),
+        (None, FileActivity::Idle) if requested => FileActivity::Pending,
+        (None, activity) => activity,
+    };
+
+    FileSnapshot {
+        id: file.id().clone(),
+        subpath: file.subpath().to_string(),
+        size: file.size(),
+        transferred,
+        activity,
+    }
 }
 
 impl OutgoingState {
+    async fn snapshot(&self) -> TransferSnapshot {
+        let mut files = Vec::with_capacity(self.file_events.len());
+
+        for (file_id, events) in &self.file_events {
+            let terminal = match self.file_sync.get(file_id) {
+                Some(OutgoingLocalFileState::Terminal(term)) => Some(*term),
+                _ => None,
+            };
+
+            files.push(file_snapshot(&*self.xfer, events, terminal, false).await);
+        }
+
+        self.conn_info.snapshot(
+            &*self.xfer,
+            snapshot::Direction::Outgoing,
+            self.conn.as_ref().is_some_and(|conn| !conn.is_closed()),
+            files,
+        )
+    }
+
     fn issue_pending_requests(&self, conn: &UnboundedSender<ClientReq>, logger: &Logger) {
         let iter = self
             .file_sync
@@ -798,7 +961,7 @@ impl OutgoingState {
     async fn cancel_transfer_if_all_files_terminated(
         &mut self,
         logger: &Logger,
-        storage: &Storage,
+        storage: &dyn StorageBackend,
     ) -> FinishTransferState<OutgoingTransfer> {
         let all_terminated = self
             .file_sync
@@ -821,7 +984,7 @@ impl OutgoingState {
         }
     }
 
-    async fn cancel_transfer(&mut self, logger: &Logger, storage: &Storage) {
+    async fn cancel_transfer(&mut self, logger: &Logger, storage: &dyn StorageBackend) {
         storage
             .update_transfer_sync_states(
                 self.xfer.id(),
@@ -841,6 +1004,27 @@ impl OutgoingState {
 }
 
 impl IncomingState {
+    async fn snapshot(&self) -> TransferSnapshot {
+        let mut files = Vec::with_capacity(self.file_events.len());
+
+        for (file_id, events) in &self.file_events {
+            let (terminal, requested) = match self.file_sync.get(file_id) {
+                Some(IncomingLocalFileState::Terminal(term)) => (Some(*term), false),
+                Some(IncomingLocalFileState::InFlight { .. }) => (None, true),
+                _ => (None, false),
+            };
+
+            files.push(file_snapshot(&*self.xfer, events, terminal, requested).await);
+        }
+
+        self.conn_info.snapshot(
+            &*self.xfer,
+            snapshot::Direction::Incoming,
+            self.conn.as_ref().is_some_and(|conn| !conn.is_closed()),
+            files,
+        )
+    }
+
     /// Returs `true` when the new download can be started and `false` in case
     /// the downaload is already happening
     pub fn validate_for_download(&self, file_id: &FileId) -> crate::Result<bool> {
@@ -860,7 +1044,7 @@ impl IncomingState {
 
     pub async fn start_download(
         &mut self,
-        storage: &Storage,
+        storage: &dyn StorageBackend,
         file_id: &FileId,
         parent_dir: &Path,
         logger: &Logger,
@@ -965,7 +1149,7 @@ impl IncomingState {
     async fn cancel_transfer_if_all_files_terminated(
         &mut self,
         logger: &Logger,
-        storage: &Storage,
+        storage: &dyn StorageBackend,
     ) -> FinishTransferState<IncomingTransfer> {
         let all_terminated = self
             .file_sync
@@ -988,7 +1172,7 @@ impl IncomingState {
         }
     }
 
-    async fn cancel_transfer(&mut self, logger: &Logger, storage: &Storage) {
+    async fn cancel_transfer(&mut self, logger: &Logger, storage: &dyn StorageBackend) {
         storage
             .update_transfer_sync_states(self.xfer.id(), sync::TransferState::Canceled)
             .await;
@@ -1033,33 +1217,7 @@ impl DirMapping {
 
         let mapped = match next {
             Some(next) => {
-                // Check if dir exists and is known to us
-                let name = match self.mappings.entry(dest_dir.join(probe)) {
-                    // Dir is known, reuse
-                    Entry::Occupied(occ) => occ.get().clone(),
-                    // Dir in new, check if there is name conflict and add to known
-                    Entry::Vacant(vacc) => {
-                        let mapped = crate::utils::filepath_variants(vacc.key())?.find(|dst_location| {
-                                // Skip if there is already a file with the same name.
-                                // Additionaly there could be a dangling symlink with the same name,
-                                // the `symlink_metadata()` ensures we can catch that.
-                                matches!(dst_location.symlink_metadata() , Err(err) if err.kind() == io::ErrorKind::NotFound)
-                            })
-                            .expect("The filepath variants iterator should never end");
-
-                        let value = vacc.insert(
-                            mapped
-                                .file_name()
-                                .ok_or_else(|| crate::Error::BadPath("Missing file name".into()))?
-                                .to_str()
-                                .ok_or_else(|| crate::Error::BadPath("Invalid UTF8 path".into()))?
-                                .to_string(),
-                        );
-
-                        value.clone()
-                    }
-                };
-
+                let name = self.map_root_dir(dest_dir, probe)?;
                 [name, next].into_iter().chain(iter).collect()
             }
             None => {
@@ -1071,6 +1229,55 @@ impl DirMapping {
         Ok(mapped)
     }
 
+    /// Composes the path of the directory into which the archive is unpacked.
+    /// The archive subpath names the directory and it is mapped the same way
+    /// as the root directories in `compose_final_path()`
+    pub fn compose_archive_root(
+        &mut self,
+        dest_dir: &Path,
+        file_subpath: &FileSubPath,
+    ) -> crate::Result<PathBuf> {
+        let mut iter = file_subpath.iter().map(crate::utils::normalize_filename);
+
+        let probe = iter.next().ok_or_else(|| {
+            crate::Error::BadPath("Path should contain at least one component".into())
+        })?;
+
+        let name = self.map_root_dir(dest_dir, probe)?;
+        Ok([name].into_iter().chain(iter).collect())
+    }
+
+    fn map_root_dir(&mut self, dest_dir: &Path, probe: String) -> crate::Result<String> {
+        // Check if dir exists and is known to us
+        let name = match self.mappings.entry(dest_dir.join(probe)) {
+            // Dir is known, reuse
+            Entry::Occupied(occ) => occ.get().clone(),
+            // Dir in new, check if there is name conflict and add to known
+            Entry::Vacant(vacc) => {
+                let mapped = crate::utils::filepath_variants(vacc.key())?.find(|dst_location| {
+                        // Skip if there is already a file with the same name.
+                        // Additionaly there could be a dangling symlink with the same name,
+                        // the `symlink_metadata()` ensures we can catch that.
+                        matches!(dst_location.symlink_metadata() , Err(err) if err.kind() == io::ErrorKind::NotFound)
+                    })
+                    .expect("The filepath variants iterator should never end");
+
+                let value = vacc.insert(
+                    mapped
+                        .file_name()
+                        .ok_or_else(|| crate::Error::BadPath("Missing file name".into()))?
+                        .to_str()
+                        .ok_or_else(|| crate::Error::BadPath("Invalid UTF8 path".into()))?
+                        .to_string(),
+                );
+
+                value.clone()
+            }
+        };
+
+        Ok(name)
+    }
+
     fn register_preexisting_final_path(
         &mut self,
         file_subpath: &FileSubPath,
@@ -1122,8 +1329,8 @@ impl OutgoingLocalFileState {
 pub(crate) async fn restore_transfers_state(state: &Arc<State>, logger: &Logger) {
     let incoming = restore_incoming(
         &state.transfer_manager.event_factory,
-        &state.storage,
-        &state.config,
+        &*state.storage,
+        &state.config(),
         logger,
     )
     .await;
@@ -1175,7 +1382,7 @@ pub(crate) async fn resume(
 
 async fn restore_incoming(
     factory: &EventTxFactory,
-    storage: &Storage,
+    storage: &dyn StorageBackend,
     config: &DropConfig,
     logger: &Logger,
 ) -> HashMap<Uuid, IncomingState> {
@@ -1188,7 +1395,12 @@ async fn restore_incoming(
                 .files
                 .into_iter()
                 .map(|dbfile| {
-                    FileToRecv::new(dbfile.file_id.into(), dbfile.subpath.into(), dbfile.size)
+                    FileToRecv::new(
+                        dbfile.file_id.into(),
+                        dbfile.subpath.into(),
+                        dbfile.size,
+                        dbfile.is_archive,
+                    )
                 })
                 .collect();
 
@@ -1244,9 +1456,15 @@ async fn restore_incoming(
             }
 
             let xfer = Arc::new(xfer);
+            let xfer_events = factory.transfer(
+                xfer.clone(),
+                matches!(sync.local_state, sync::TransferState::Canceled),
+            );
+
             let mut xstate = IncomingState {
                 xfer: xfer.clone(),
                 conn: None,
+                conn_info: Default::default(),
                 dir_mappings: Default::default(),
                 xfer_sync: sync.local_state,
                 file_sync,
@@ -1256,14 +1474,11 @@ async fn restore_incoming(
                     .map(|file_id| {
                         (
                             file_id.clone(),
-                            Arc::new(factory.file(xfer.clone(), file_id.clone())),
+                            Arc::new(factory.file(&xfer_events, file_id.clone())),
                         )
                     })
                     .collect(),
-                xfer_events: Arc::new(factory.transfer(
-                    xfer,
-                    matches!(sync.local_state, sync::TransferState::Canceled),
-                )),
+                xfer_events: Arc::new(xfer_events),
             };
 
             debug!(
@@ -1281,6 +1496,15 @@ async fn restore_incoming(
                     .register_preexisting_final_path(&subpath, &path.final_path);
             }
 
+            for (file_id, file_state) in &xstate.file_sync {
+                if let IncomingLocalFileState::Terminal(term) = file_state {
+                    xstate
+                        .xfer_events
+                        .file_finished(file_id, matches!(term, FileTerminalState::Completed))
+                        .await;
+                }
+            }
+
             anyhow::Ok(xstate)
         };
 
@@ -1316,7 +1540,7 @@ async fn restore_outgoing(state: &Arc<State>, logger: &Logger) -> HashMap<Uuid,
                 transfer.peer.parse().context("Failed to parse peer IP")?,
                 files,
                 transfer.uuid,
-                &state.config,
+                &state.config(),
             )
             .context("Failed to create transfer")?;
 
@@ -1353,9 +1577,16 @@ async fn restore_outgoing(state: &Arc<State>, logger: &Logger) -> HashMap<Uuid,
             }
 
             let xfer = Arc::new(xfer);
+            let factory = &state.transfer_manager.event_factory;
+            let xfer_events = factory.transfer(
+                xfer.clone(),
+                matches!(sync.local_state, sync::TransferState::Canceled),
+            );
+
             let xstate = OutgoingState {
                 xfer: xfer.clone(),
                 conn: None,
+                conn_info: Default::default(),
                 xfer_sync: sync.local_state,
                 file_sync,
                 file_events: xfer
@@ -1364,20 +1595,22 @@ async fn restore_outgoing(state: &Arc<State>, logger: &Logger) -> HashMap<Uuid,
                     .map(|file_id| {
                         (
                             file_id.clone(),
-                            Arc::new(
-                                state
-                                    .transfer_manager
-                                    .event_factory
-                                    .file(xfer.clone(), file_id.clone()),
-                            ),
+                            Arc::new(factory.file(&xfer_events, file_id.clone())),
                         )
                     })
                     .collect(),
-                xfer_events: Arc::new(state.transfer_manager.event_factory.transfer(
-                    xfer,
-                    matches!(sync.local_state, sync::TransferState::Canceled),
-                )),
+                xfer_events: Arc::new(xfer_events),
             };
+
+            for (file_id, file_state) in &xstate.file_sync {
+                if let OutgoingLocalFileState::Terminal(term) = file_state {
+                    xstate
+                        .xfer_events
+                        .file_finished(file_id, matches!(term, FileTerminalState::Completed))
+                        .await;
+                }
+            }
+
             anyhow::Ok(xstate)
         };
 
@@ -1411,7 +1644,11 @@ fn restore_outgoing_file(state: &State, dbfile: OutgoingFileToRetry) -> anyhow::
                 .ok()
                 .context("Failed to extract file path")?;
 
-            FileToSend::new(subpath, fullpath, size, file_id)
+            if dbfile.is_archive {
+                FileToSend::new_archive(subpath, fullpath, size, file_id, &state.config())
+            } else {
+                FileToSend::new(subpath, fullpath, size, file_id)
+            }
         }
         #[cfg(unix)]
         "content" => {
@@ -1466,7 +1703,10 @@ fn ensure_resume_matches_existing_transfer<T: Transfer>(
             .files()
             .iter()
             .all(|(key, val)| existing.files().get(key).map_or(false, |v| {
-                val.id() == v.id() && val.size() == v.size() && val.mime_type() == v.mime_type()
+                val.id() == v.id()
+                    && val.size() == v.size()
+                    && val.mime_type() == v.mime_type()
+                    && val.is_archive() == v.is_archive()
             })),
         "Files do not match"
     );
@@ -1478,6 +1718,23 @@ fn ensure_resume_matches_existing_transfer<T: Transfer>(
 mod tests {
     use super::*;
 
+    #[test]
+    fn connection_counters() {
+        let mut info = ConnectionInfo::default();
+
+        info.retries = 2;
+        info.connected(protocol::Version::V7);
+        assert_eq!(info.reconnects, 0);
+        assert_eq!(info.retries, 0);
+        assert!(matches!(info.version, Some(protocol::Version::V7)));
+
+        info.version = None;
+        info.retries = 1;
+        info.connected(protocol::Version::V6);
+        assert_eq!(info.reconnects, 1);
+        assert_eq!(info.retries, 0);
+    }
+
     #[test]
     fn extracting_dir_mapping() {
         let (path, name) = extract_directory_mapping(
@@ -1,6 +1,7 @@
 pub mod v6;
+pub mod v7;
 
-#[derive(Copy, Clone, strum::Display, strum::EnumString)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, strum::Display, strum::EnumString)]
 pub enum Version {
     // Versions V1 and V2 were yanked because these lacked the client
     // authentication, which is a security flaw.
@@ -12,12 +13,15 @@ pub enum Version {
     // authentication. Yanked on the security grounds.
     #[strum(serialize = "v6")]
     V6,
+    #[strum(serialize = "v7")]
+    V7,
 }
 
 impl From<Version> for i32 {
     fn from(version: Version) -> Self {
         match version {
             Version::V6 => 6,
+            Version::V7 => 7,
         }
     }
 }
@@ -0,0 +1,503 @@
+//! # Protocol V7
+//!
+//! The file download flow is the same as in V6. The only difference is the
+//! checksum algorithm negotiation. The server (receiver) decides on the
+//! algorithm with every checksum request and the client (sender) reports the
+//! checksum calculated with the requested algorithm
+//! * server (receiver) ->   client (sender): `ReqChsum (file, algorithm)`
+//! * client (sender)   -> server (receiver): `ReportChsum (file, algorithm)`
+//!
+//! Instead of a `Chunk` consisting of zero bytes only, the client can send the
+//! length of the zero run. The server is free to store it as a hole in the file
+//! * client (sender)   -> server (receiver): `Zeros (file, len)`
+//!
+//! The `TransferRequest` can contain files marked as `archive`. Such a file is
+//! a tar archive of the whole directory tree named by the file path, which the
+//! server (receiver) unpacks once downloaded. The V6 peers receive these as
+//! ordinary `.tar` files.
+//!
+//! The V6 peers implicitly use SHA-256. The handlers speak V7 messages
+//! internally and these are mapped into V6 ones on the wire when the V6
+//! connection is established.
+
+use serde::{Deserialize, Serialize};
+
+pub use super::v6::{Cancel, Chunk, Done, Error, Progress, Reject, Start};
+use super::{v6, Version};
+use crate::{
+    file::{ChecksumAlgorithm, File as _, FileSubPath},
+    transfer::Transfer,
+    FileId, OutgoingTransfer,
+};
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+pub struct File {
+    pub path: FileSubPath,
+    pub id: FileId,
+    pub size: u64,
+    // Not available in V6
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archive: bool,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+pub struct TransferRequest {
+    pub files: Vec<File>,
+    pub id: uuid::Uuid,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+pub struct ReqChsum {
+    pub file: FileId,
+    // Up to which point calculate checksum
+    pub limit: u64,
+    pub algorithm: ChecksumAlgorithm,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+pub struct ReportChsum {
+    pub file: FileId,
+    pub limit: u64,
+    pub algorithm: ChecksumAlgorithm,
+    #[serde(serialize_with = "hex::serialize")]
+    #[serde(deserialize_with = "hex::deserialize")]
+    pub checksum: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+pub struct Zeros {
+    pub file: FileId,
+    // Number of zero bytes replacing the chunk
+    pub len: u64,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+#[serde(tag = "type")]
+pub enum ServerMsg {
+    Progress(Progress<FileId>),
+    Done(Done),
+    Error(Error<FileId>),
+    ReqChsum(ReqChsum),
+    Start(Start),
+    Cancel(Cancel),
+    Reject(Reject),
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq)]
+#[serde(tag = "type")]
+pub enum ClientMsg {
+    ReportChsum(ReportChsum),
+    Error(Error<FileId>),
+    Cancel(Cancel),
+    Reject(Reject),
+    // Not available in V6
+    Zeros(Zeros),
+}
+
+impl TransferRequest {
+    /// Serializes the request into JSON of the given protocol version
+    pub fn to_json(&self, version: Version) -> String {
+        let json = match version {
+            Version::V6 => serde_json::to_string(&v6::TransferRequest {
+                files: self
+                    .files
+                    .iter()
+                    .map(|file| v6::File {
+                        path: if file.archive {
+                            archive_subpath_v6(&file.path)
+                        } else {
+                            file.path.clone()
+                        },
+                        id: file.id.clone(),
+                        size: file.size,
+                    })
+                    .collect(),
+                id: self.id,
+            }),
+            Version::V7 => serde_json::to_string(self),
+        };
+
+        json.expect("Failed to serialize transfer request")
+    }
+
+    /// Deserializes the request from JSON of the given protocol version
+    pub fn from_json(json: &str, version: Version) -> serde_json::Result<Self> {
+        match version {
+            Version::V6 => serde_json::from_str::<v6::TransferRequest>(json).map(Into::into),
+            Version::V7 => serde_json::from_str(json),
+        }
+    }
+}
+
+// V6 peers cannot unpack archives, so let them at least see it's a tarball
+fn archive_subpath_v6(path: &FileSubPath) -> FileSubPath {
+    let mut path = path.clone();
+    if let Some(name) = path.iter_mut().next_back() {
+        name.push_str(".tar");
+    }
+    path
+}
+
+// V6 has no way to name the algorithm, the peer would take the digest as SHA-256
+fn ensure_v6_algorithm(algorithm: ChecksumAlgorithm) -> crate::Result<()> {
+    if algorithm == ChecksumAlgorithm::Sha256 {
+        Ok(())
+    } else {
+        Err(crate::Error::UnsupportedChecksumAlgorithm(algorithm))
+    }
+}
+
+impl ServerMsg {
+    /// Serializes the message into JSON of the given protocol version. Fails
+    /// when the message cannot be expressed in that version
+    pub fn to_json(&self, version: Version) -> crate::Result<String> {
+        let json = match (version, self) {
+            (
+                Version::V6,
+                Self::ReqChsum(ReqChsum {
+                    file,
+                    limit,
+                    algorithm,
+                }),
+            ) => {
+                ensure_v6_algorithm(*algorithm)?;
+
+                serde_json::to_string(&v6::ServerMsg::ReqChsum(v6::ReqChsum {
+                    file: file.clone(),
+                    limit: *limit,
+                }))
+            }
+            // The rest of the messages is shared with V6
+            _ => serde_json::to_string(self),
+        };
+
+        Ok(json.expect("Failed to serialize server message"))
+    }
+
+    /// Deserializes the message from JSON of the given protocol version
+    pub fn from_json(json: &str, version: Version) -> serde_json::Result<Self> {
+        match version {
+            Version::V6 => serde_json::from_str::<v6::ServerMsg>(json).map(Into::into),
+            Version::V7 => serde_json::from_str(json),
+        }
+    }
+}
+
+impl ClientMsg {
+    /// Serializes the message into JSON of the given protocol version. Fails
+    /// when the message cannot be expressed in that version
+    pub fn to_json(&self, version: Version) -> crate::Result<String> {
+        let json = match (version, self) {
+            (
+                Version::V6,
+                Self::ReportChsum(ReportChsum {
+                    file,
+                    limit,
+                    algorithm,
+                    checksum,
+                }),
+            ) => {
+                ensure_v6_algorithm(*algorithm)?;
+
+                serde_json::to_string(&v6::ClientMsg::ReportChsum(v6::ReportChsum {
+                    file: file.clone(),
+                    limit: *limit,
+                    checksum: *checksum,
+                }))
+            }
+            // The rest of the messages is shared with V6
+            _ => serde_json::to_string(self),
+        };
+
+        Ok(json.expect("Failed to serialize client message"))
+    }
+
+    /// Deserializes the message from JSON of the given protocol version
+    pub fn from_json(json: &str, version: Version) -> serde_json::Result<Self> {
+        match version {
+            Version::V6 => serde_json::from_str::<v6::ClientMsg>(json).map(Into::into),
+            Version::V7 => serde_json::from_str(json),
+        }
+    }
+}
+
+impl From<&OutgoingTransfer> for TransferRequest {
+    fn from(value: &OutgoingTransfer) -> Self {
+        Self {
+            files: value
+                .files()
+                .values()
+                .map(|f| File {
+                    path: f.subpath().clone(),
+                    id: f.id().clone(),
+                    size: f.size(),
+                    archive: f.is_archive(),
+                })
+                .collect(),
+            id: value.id(),
+        }
+    }
+}
+
+impl From<v6::TransferRequest> for TransferRequest {
+    fn from(value: v6::TransferRequest) -> Self {
+        Self {
+            files: value
+                .files
+                .into_iter()
+                .map(|v6::File { path, id, size }| File {
+                    path,
+                    id,
+                    size,
+                    archive: false,
+                })
+                .collect(),
+            id: value.id,
+        }
+    }
+}
+
+impl From<v6::ServerMsg> for ServerMsg {
+    fn from(value: v6::ServerMsg) -> Self {
+        match value {
+            v6::ServerMsg::Progress(msg) => Self::Progress(msg),
+            v6::ServerMsg::Done(msg) => Self::Done(msg),
+            v6::ServerMsg::Error(msg) => Self::Error(msg),
+            v6::ServerMsg::ReqChsum(v6::ReqChsum { file, limit }) => Self::ReqChsum(ReqChsum {
+                file,
+                limit,
+                algorithm: ChecksumAlgorithm::Sha256,
+            }),
+            v6::ServerMsg::Start(msg) => Self::Start(msg),
+            v6::ServerMsg::Cancel(msg) => Self::Cancel(msg),
+            v6::ServerMsg::Reject(msg) => Self::Reject(msg),
+        }
+    }
+}
+
+impl From<v6::ClientMsg> for ClientMsg {
+    fn from(value: v6::ClientMsg) -> Self {
+        match value {
+            v6::ClientMsg::ReportChsum(v6::ReportChsum {
+                file,
+                limit,
+                checksum,
+            }) => Self::ReportChsum(ReportChsum {
+                file,
+                limit,
+                algorithm: ChecksumAlgorithm::Sha256,
+                checksum,
+            }),
+            v6::ClientMsg::Error(msg) => Self::Error(msg),
+            v6::ClientMsg::Cancel(msg) => Self::Cancel(msg),
+            v6::ClientMsg::Reject(msg) => Self::Reject(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECKSUM: [u8; 32] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31,
+    ];
+
+    fn assert_json(json: &str, expected: &str) {
+        let json_msg: serde_json::Value = serde_json::from_str(json).expect("Invalid json");
+        let json_exp: serde_json::Value =
+            serde_json::from_str(expected).expect("Failed to convert expected json to value");
+        assert_eq!(json_msg, json_exp);
+    }
+
+    #[test]
+    fn client_json_messages() {
+        let msg = ClientMsg::ReportChsum(ReportChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Blake3,
+            checksum: CHECKSUM,
+        });
+        let expected = r#"
+            {
+              "type": "ReportChsum",
+              "file": "TESTID",
+              "limit": 41,
+              "algorithm": "blake3",
+              "checksum": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            }
+            "#;
+
+        assert_json(&msg.to_json(Version::V7).unwrap(), expected);
+        assert!(ClientMsg::from_json(expected, Version::V7).unwrap() == msg);
+
+        let msg = ClientMsg::Zeros(Zeros {
+            file: FileId::from("TESTID"),
+            len: 1048576,
+        });
+        let expected = r#"
+            {
+              "type": "Zeros",
+              "file": "TESTID",
+              "len": 1048576
+            }
+            "#;
+
+        assert_json(&msg.to_json(Version::V7).unwrap(), expected);
+        assert!(ClientMsg::from_json(expected, Version::V7).unwrap() == msg);
+    }
+
+    #[test]
+    fn server_json_messages() {
+        let msg = ServerMsg::ReqChsum(ReqChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Blake3,
+        });
+        let expected = r#"
+            {
+              "type": "ReqChsum",
+              "file": "TESTID",
+              "limit": 41,
+              "algorithm": "blake3"
+            }"#;
+
+        assert_json(&msg.to_json(Version::V7).unwrap(), expected);
+        assert!(ServerMsg::from_json(expected, Version::V7).unwrap() == msg);
+    }
+
+    #[test]
+    fn transfer_request() {
+        let req = TransferRequest {
+            files: vec![
+                File {
+                    path: FileSubPath::from("dir"),
+                    id: FileId::from("ID1"),
+                    size: 1024,
+                    archive: true,
+                },
+                File {
+                    path: FileSubPath::from("file.txt"),
+                    id: FileId::from("ID2"),
+                    size: 10,
+                    archive: false,
+                },
+            ],
+            id: uuid::Uuid::nil(),
+        };
+
+        let expected = r#"
+            {
+              "files": [
+                {
+                  "path": "dir",
+                  "id": "ID1",
+                  "size": 1024,
+                  "archive": true
+                },
+                {
+                  "path": "file.txt",
+                  "id": "ID2",
+                  "size": 10
+                }
+              ],
+              "id": "00000000-0000-0000-0000-000000000000"
+            }"#;
+
+        assert_json(&req.to_json(Version::V7), expected);
+        assert!(TransferRequest::from_json(expected, Version::V7).unwrap() == req);
+
+        // The archives are announced as tarballs to V6 peers
+        let expected = r#"
+            {
+              "files": [
+                {
+                  "path": "dir.tar",
+                  "id": "ID1",
+                  "size": 1024
+                },
+                {
+                  "path": "file.txt",
+                  "id": "ID2",
+                  "size": 10
+                }
+              ],
+              "id": "00000000-0000-0000-0000-000000000000"
+            }"#;
+
+        assert_json(&req.to_json(Version::V6), expected);
+    }
+
+    #[test]
+    fn v6_compatibility() {
+        let msg = ServerMsg::ReqChsum(ReqChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Sha256,
+        });
+        let expected = r#"
+            {
+              "type": "ReqChsum",
+              "file": "TESTID",
+              "limit": 41
+            }"#;
+
+        assert_json(&msg.to_json(Version::V6).unwrap(), expected);
+        assert!(ServerMsg::from_json(expected, Version::V6).unwrap() == msg);
+
+        let msg = ClientMsg::ReportChsum(ReportChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Sha256,
+            checksum: CHECKSUM,
+        });
+        let expected = r#"
+            {
+              "type": "ReportChsum",
+              "file": "TESTID",
+              "limit": 41,
+              "checksum": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            }
+            "#;
+
+        assert_json(&msg.to_json(Version::V6).unwrap(), expected);
+        assert!(ClientMsg::from_json(expected, Version::V6).unwrap() == msg);
+
+        // Messages shared with V6 are serialized the same way
+        let msg = ServerMsg::Start(Start {
+            file: FileId::from("TESTID"),
+            offset: 41,
+        });
+        assert_eq!(
+            msg.to_json(Version::V6).unwrap(),
+            msg.to_json(Version::V7).unwrap()
+        );
+
+        // V6 cannot carry any other algorithm than SHA-256
+        let msg = ServerMsg::ReqChsum(ReqChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Blake3,
+        });
+        assert!(matches!(
+            msg.to_json(Version::V6),
+            Err(crate::Error::UnsupportedChecksumAlgorithm(
+                ChecksumAlgorithm::Blake3
+            ))
+        ));
+
+        let msg = ClientMsg::ReportChsum(ReportChsum {
+            file: FileId::from("TESTID"),
+            limit: 41,
+            algorithm: ChecksumAlgorithm::Blake3,
+            checksum: CHECKSUM,
+        });
+        assert!(matches!(
+            msg.to_json(Version::V6),
+            Err(crate::Error::UnsupportedChecksumAlgorithm(
+                ChecksumAlgorithm::Blake3
+            ))
+        ));
+    }
+}
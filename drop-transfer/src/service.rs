@@ -2,14 +2,14 @@ use std::{
     fs,
     net::IpAddr,
     path::{Component, Path},
-    sync::Arc,
+    sync::{Arc, RwLock},
     time::{Duration, Instant, SystemTime},
 };
 
 use drop_analytics::{InitEventData, Moose, TransferStateEventData};
-use drop_config::DropConfig;
+use drop_config::{DropConfig, RetentionPolicy};
 use drop_core::Status;
-use drop_storage::Storage;
+use drop_storage::StorageBackend;
 use slog::{debug, info, trace, Logger};
 use tokio::sync::{mpsc, Semaphore};
 use tokio_util::sync::CancellationToken;
@@ -18,7 +18,9 @@ use uuid::Uuid;
 use crate::{
     auth,
     error::ResultExt,
+    file::{DownloadSinkFactory, FsDownloadSinkFactory},
     manager::{self},
+    snapshot::TransferSnapshot,
     tasks::{AliveGuard, AliveWaiter},
     transfer::Transfer,
     ws::{self, EventTxFactory},
@@ -30,15 +32,20 @@ pub(super) struct State {
     pub(super) transfer_manager: TransferManager,
     pub(crate) moose: Arc<dyn Moose>,
     pub(crate) auth: Arc<auth::Context>,
-    pub(crate) config: Arc<DropConfig>,
-    pub(crate) storage: Arc<Storage>,
+    config: RwLock<Arc<DropConfig>>,
+    pub(crate) storage: Arc<dyn StorageBackend>,
     pub(crate) throttle: Arc<Semaphore>,
+    pub(crate) download_sinks: Arc<dyn DownloadSinkFactory>,
     pub(crate) addr: IpAddr,
     #[cfg(unix)]
     pub fdresolv: Option<Arc<crate::file::FdResolver>>,
 }
 
 impl State {
+    pub(crate) fn config(&self) -> Arc<DropConfig> {
+        self.config.read().expect("Poisoned lock").clone()
+    }
+
     pub fn emit_event(&self, event: crate::Event) {
         self.event_tx
             .send((event, SystemTime::now()))
@@ -53,19 +60,21 @@ pub struct Service {
     pub(super) logger: Logger,
 
     refresh_trigger: tokio::sync::watch::Sender<()>,
+    auto_retry_stop: Option<CancellationToken>,
 }
 
 impl Service {
     #[allow(clippy::too_many_arguments)]
     pub async fn start(
         addr: IpAddr,
-        storage: Arc<Storage>,
+        storage: Arc<dyn StorageBackend>,
         event_tx: mpsc::UnboundedSender<(Event, SystemTime)>,
         logger: Logger,
         config: Arc<DropConfig>,
         moose: Arc<dyn Moose>,
         auth: Arc<auth::Context>,
         init_time: Instant,
+        download_sinks: Option<Arc<dyn DownloadSinkFactory>>,
         #[cfg(unix)] fdresolv: Option<Arc<crate::FdResolver>>,
     ) -> Result<Self, Error> {
         let task = async {
@@ -73,14 +82,16 @@ impl Service {
                 throttle: Arc::new(Semaphore::new(drop_config::MAX_UPLOADS_IN_FLIGHT)),
                 transfer_manager: TransferManager::new(
                     storage.clone(),
-                    EventTxFactory::new(event_tx.clone(), moose.clone()),
+                    EventTxFactory::new(event_tx.clone(), moose.clone(), &config),
                     logger.clone(),
                 ),
                 event_tx,
                 moose: moose.clone(),
-                config,
+                config: RwLock::new(config),
                 auth: auth.clone(),
                 storage,
+                download_sinks: download_sinks
+                    .unwrap_or_else(|| Arc::new(FsDownloadSinkFactory::new(logger.clone()))),
                 addr,
                 #[cfg(unix)]
                 fdresolv,
@@ -93,6 +104,20 @@ impl Service {
 
             state.storage.cleanup_garbage_transfers().await;
 
+            let config = state.config();
+            let retention = &config.retention;
+            if retention.is_enabled() {
+                apply_retention(&state.storage, retention).await;
+
+                spawn_retention_loop(
+                    state.storage.clone(),
+                    retention.clone(),
+                    logger.clone(),
+                    guard.clone(),
+                    stop.clone(),
+                );
+            }
+
             manager::restore_transfers_state(&state, &logger).await;
 
             let refresh_trigger = tokio::sync::watch::channel(()).0;
@@ -106,15 +131,17 @@ impl Service {
 
             manager::resume(&refresh_trigger.subscribe(), &state, &logger, &guard, &stop).await;
 
-            if let Some(interval) = state.config.auto_retry_interval {
+            let auto_retry_stop = config.auto_retry_interval.map(|interval| {
+                let auto_retry_stop = stop.child_token();
                 spawn_auto_retry_loop(
                     refresh_trigger.clone(),
                     interval,
                     logger.clone(),
                     guard.clone(),
-                    stop.clone(),
+                    auto_retry_stop.clone(),
                 );
-            }
+                auto_retry_stop
+            });
 
             Ok(Self {
                 refresh_trigger,
@@ -122,6 +149,7 @@ impl Service {
                 stop,
                 waiter,
                 logger,
+                auto_retry_stop,
             })
         };
 
@@ -140,8 +168,43 @@ impl Service {
         self.waiter.wait_for_all().await;
     }
 
-    pub fn storage(&self) -> &Storage {
-        &self.state.storage
+    /// Replaces the configuration of the running service. The new values are
+    /// used by the connections and files started from now on, the auto retry
+    /// loop is restarted if its interval changed. The storage path, the
+    /// progress events and the retention settings are read only on start
+    pub fn update_config(&mut self, config: Arc<DropConfig>) {
+        let old = std::mem::replace(
+            &mut *self.state.config.write().expect("Poisoned lock"),
+            config.clone(),
+        );
+
+        if old.auto_retry_interval != config.auto_retry_interval {
+            if let Some(auto_retry_stop) = self.auto_retry_stop.take() {
+                auto_retry_stop.cancel();
+            }
+
+            self.auto_retry_stop = config.auto_retry_interval.map(|interval| {
+                let auto_retry_stop = self.stop.child_token();
+                spawn_auto_retry_loop(
+                    self.refresh_trigger.clone(),
+                    interval,
+                    self.logger.clone(),
+                    self.waiter.guard(),
+                    auto_retry_stop.clone(),
+                );
+                auto_retry_stop
+            });
+        }
+    }
+
+    /// The live state of the transfer, `None` if it is not tracked anymore
+    pub async fn transfer_snapshot(&self, transfer_id: Uuid) -> Option<TransferSnapshot> {
+        self.state.transfer_manager.snapshot(transfer_id).await
+    }
+
+    /// The live state of all the transfers which are not finished yet
+    pub async fn active_transfers(&self) -> Vec<TransferSnapshot> {
+        self.state.transfer_manager.snapshots().await
     }
 
     pub fn network_refresh(&mut self) {
@@ -168,6 +231,7 @@ impl Service {
                         transfer_id: xfer.id().to_string(),
                         result: i32::from(&err),
                         protocol_version: 0,
+                        reconnects: 0,
                     });
 
                 self.state
@@ -379,3 +443,43 @@ fn spawn_auto_retry_loop(
         }
     });
 }
+
+async fn apply_retention(storage: &dyn StorageBackend, policy: &RetentionPolicy) {
+    storage
+        .apply_retention(policy.max_age, policy.max_transfers, policy.terminal_only)
+        .await;
+}
+
+fn spawn_retention_loop(
+    storage: Arc<dyn StorageBackend>,
+    policy: RetentionPolicy,
+    logger: Logger,
+    guard: AliveGuard,
+    stop: CancellationToken,
+) {
+    info!(
+        logger,
+        "Starting retention loop with interval: {}s",
+        drop_config::RETENTION_INTERVAL.as_secs()
+    );
+
+    tokio::spawn(async move {
+        let _guard = guard;
+
+        let task = async {
+            loop {
+                tokio::time::sleep(drop_config::RETENTION_INTERVAL).await;
+                apply_retention(&storage, &policy).await;
+            }
+        };
+
+        tokio::select! {
+            biased;
+
+            _ = stop.cancelled() => {
+                debug!(logger, "Stopping retention loop");
+            },
+            _ = task => (),
+        }
+    });
+}
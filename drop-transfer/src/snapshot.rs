@@ -0,0 +1,59 @@
+//! Point-in-time view of the transfers tracked by the running service. Unlike
+//! the storage, which records the history, this reflects the live connection
+//! and the files currently in flight
+
+use std::net::IpAddr;
+
+use uuid::Uuid;
+
+use crate::FileId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileActivity {
+    /// Nothing is happening with the file yet
+    Idle,
+    /// The download is requested but did not start yet, e.g. the peer is
+    /// offline
+    Pending,
+    /// The upload waits for a free slot
+    Throttled,
+    /// The partially downloaded data is being verified before resuming
+    Preparing,
+    InFlight,
+    Completed,
+    Rejected,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileSnapshot {
+    pub id: FileId,
+    pub subpath: String,
+    pub size: u64,
+    /// The last reported offset
+    pub transferred: u64,
+    pub activity: FileActivity,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferSnapshot {
+    pub id: Uuid,
+    pub peer: IpAddr,
+    pub direction: Direction,
+    pub connected: bool,
+    /// The protocol version of the current connection
+    pub protocol_version: Option<i32>,
+    /// The number of times the connection was established again after it was
+    /// lost
+    pub reconnects: u32,
+    /// The number of failed connection attempts since the last successful one.
+    /// Only the sender connects, the receiver always reports 0
+    pub connection_retries: u32,
+    pub files: Vec<FileSnapshot>,
+}
@@ -1,17 +1,17 @@
 use std::collections::HashMap;
 
-use drop_storage::Storage;
+use drop_storage::StorageBackend;
 use uuid::Uuid;
 
 use crate::{transfer::Transfer, FileId};
 
 pub struct StorageDispatch<'a> {
-    storage: &'a drop_storage::Storage,
+    storage: &'a dyn StorageBackend,
     file_progress: HashMap<Uuid, HashMap<FileId, i64>>,
 }
 
 impl<'a> StorageDispatch<'a> {
-    pub fn new(storage: &'a Storage) -> Self {
+    pub fn new(storage: &'a dyn StorageBackend) -> Self {
         Self {
             storage,
             file_progress: HashMap::new(),
@@ -92,10 +92,10 @@ impl<'a> StorageDispatch<'a> {
                     )
                     .await
             }
-            crate::Event::FileUploadProgress(transfer, file_id, progress) => {
+            crate::Event::FileUploadProgress(transfer, file_id, progress, _) => {
                 self.store_progres(transfer.id(), file_id, *progress as _)
             }
-            crate::Event::FileDownloadProgress(transfer, file_id, progress) => {
+            crate::Event::FileDownloadProgress(transfer, file_id, progress, _) => {
                 self.store_progres(transfer.id(), file_id, *progress as _)
             }
             crate::Event::FileUploadRejected {
@@ -155,6 +155,7 @@ impl<'a> StorageDispatch<'a> {
             crate::Event::RequestReceived(_) => (),
             crate::Event::RequestQueued(_) => (),
             crate::Event::FileUploadThrottled { .. } => (),
+            crate::Event::TransferProgress { .. } => (),
 
             crate::Event::OutgoingTransferDeferred { .. } => (),
 
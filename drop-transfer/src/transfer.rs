@@ -6,7 +6,7 @@ use drop_storage::TransferInfo as StorageInfo;
 use uuid::Uuid;
 
 use crate::{
-    file::{File, FileId, FileSource, FileSubPath, FileToRecv, FileToSend},
+    file::{File, FileId, FileSubPath, FileToRecv, FileToSend},
     utils, Error,
 };
 
@@ -132,6 +132,7 @@ impl IncomingTransfer {
                 file_id: f.id().to_string(),
                 relative_path: f.subpath().to_string(),
                 size: f.size() as _,
+                is_archive: f.is_archive(),
             })
             .collect();
 
@@ -149,17 +150,14 @@ impl OutgoingTransfer {
             .files
             .values()
             .filter_map(|f| {
-                let uri = match &f.source {
-                    FileSource::Path(fullpath) => url::Url::from_file_path(&fullpath.0).ok()?,
-                    #[cfg(unix)]
-                    FileSource::Fd { content_uri, .. } => content_uri.clone(),
-                };
+                let uri = f.source.uri()?;
 
                 Some(drop_storage::types::TransferOutgoingPath {
                     file_id: f.id().to_string(),
                     relative_path: f.subpath().to_string(),
                     uri,
                     size: f.size() as _,
+                    is_archive: f.is_archive(),
                 })
             })
             .collect();
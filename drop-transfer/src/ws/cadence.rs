@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+/// Decides which of the progress updates are reported as events. An update
+/// passes once both the byte step and the time interval since the previously
+/// reported one are exceeded
+pub(super) struct Cadence {
+    step: u64,
+    interval: Duration,
+    last: Option<(Instant, u64)>,
+}
+
+impl Cadence {
+    pub fn new(step: u64, interval: Duration) -> Self {
+        Self {
+            step,
+            interval,
+            last: None,
+        }
+    }
+
+    /// Makes the next update pass unconditionally
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Marks the progress as reported by other means, e.g. along with the start
+    pub fn reported(&mut self, now: Instant, progress: u64) {
+        self.last = Some((now, progress));
+    }
+
+    /// The `last` update passes regardless of the cadence, unless it was
+    /// reported already
+    pub fn tick(&mut self, now: Instant, progress: u64, last: bool) -> bool {
+        let pass = match self.last {
+            None => true,
+            Some((_, reported)) if last => progress != reported,
+            Some((time, reported)) => {
+                progress >= reported.saturating_add(self.step)
+                    && now.saturating_duration_since(time) >= self.interval
+            }
+        };
+
+        if pass {
+            self.reported(now, progress);
+        }
+
+        pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_and_interval() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut cadence = Cadence::new(100, Duration::from_millis(500));
+
+        // The first update always passes
+        assert!(cadence.tick(at(0), 0, false));
+
+        // Not enough bytes
+        assert!(!cadence.tick(at(1000), 99, false));
+        // Not enough time
+        assert!(!cadence.tick(at(100), 1000, false));
+        assert!(cadence.tick(at(500), 1000, false));
+
+        // The final update is reported once
+        assert!(cadence.tick(at(600), 1010, true));
+        assert!(!cadence.tick(at(700), 1010, true));
+
+        cadence.reset();
+        assert!(cadence.tick(at(800), 0, false));
+    }
+
+    #[test]
+    fn unlimited() {
+        let start = Instant::now();
+        let mut cadence = Cadence::new(0, Duration::ZERO);
+
+        assert!(cadence.tick(start, 10, false));
+        assert!(cadence.tick(start, 10, false));
+        assert!(cadence.tick(start, 20, false));
+    }
+}
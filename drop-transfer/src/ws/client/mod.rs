@@ -12,7 +12,7 @@ use std::{
 
 use anyhow::Context;
 use hyper::{Request, Response, StatusCode};
-use slog::{debug, error, info, warn, Logger};
+use slog::{debug, error, info, o, warn, Logger};
 use tokio::{
     net::TcpStream,
     sync::mpsc::{self, UnboundedReceiver},
@@ -52,6 +52,7 @@ struct RunContext<'a> {
     logger: &'a slog::Logger,
     state: &'a Arc<State>,
     xfer: &'a Arc<OutgoingTransfer>,
+    version: protocol::Version,
 }
 
 enum WsConnection {
@@ -77,10 +78,11 @@ pub(crate) fn spawn(
     stop: CancellationToken,
 ) {
     let id = xfer.id();
+    let logger = logger.new(o!("transfer_id" => %id, "peer" => %xfer.peer()));
 
     tokio::spawn(async move {
         let mut backoff =
-            utils::RetryTrigger::new(refresh_trigger, state.config.connection_retries);
+            utils::RetryTrigger::new(refresh_trigger, state.config().connection_retries);
 
         let task = async {
             loop {
@@ -91,6 +93,10 @@ pub(crate) fn spawn(
                 }
 
                 backoff.backoff().await;
+
+                if let Some(tx) = state.transfer_manager.outgoing_event_tx(id).await {
+                    tx.reconnecting().await;
+                }
             }
         };
 
@@ -118,6 +124,11 @@ async fn connect_to_peer(
         WsConnection::Recoverable(error) => {
             info!(logger, "Transfer deferred {}: {error}", xfer.id());
 
+            state
+                .transfer_manager
+                .outgoing_connection_failed(xfer.id())
+                .await;
+
             if let Some(tx) = state.transfer_manager.outgoing_event_tx(xfer.id()).await {
                 tx.deferred(error).await;
             }
@@ -138,17 +149,19 @@ async fn connect_to_peer(
         tx.connected(ver.into()).await;
     }
     info!(logger, "Client connected, using version: {ver}");
+    let logger = &logger.new(o!("protocol_version" => %ver));
 
     let ctx = RunContext {
         logger,
         state,
         xfer,
+        version: ver,
     };
 
     use protocol::Version;
     let control = match ver {
-        Version::V6 => {
-            ctx.run(socket, v6::HandlerInit::new(state, logger, alive))
+        Version::V6 | Version::V7 => {
+            ctx.run(socket, v6::HandlerInit::new(ver, state, logger, alive))
                 .await
         }
     };
@@ -174,7 +187,7 @@ async fn establish_ws_conn(
         }
     };
 
-    let mut versions_to_try = [protocol::Version::V6].into_iter();
+    let mut versions_to_try = [protocol::Version::V7, protocol::Version::V6].into_iter();
 
     let ver = loop {
         let ver = if let Some(ver) = versions_to_try.next() {
@@ -297,7 +310,7 @@ impl RunContext<'_> {
         match self
             .state
             .transfer_manager
-            .outgoing_connected(self.xfer.id(), tx)
+            .outgoing_connected(self.xfer.id(), tx, self.version)
             .await
         {
             Ok(OutgoingConnected::Continue) => (),
@@ -500,6 +513,7 @@ async fn start_upload(
         .await?;
 
     let offset = uploader.offset();
+    let logger = logger.new(o!("file_id" => %file_id));
 
     let permit = throttle::init(&logger, &state, &events, offset)
         .await
@@ -17,17 +17,25 @@ use super::{
     WebSocket,
 };
 use crate::{
-    manager::FileTerminalState, protocol::v6 as prot, service::State, tasks::AliveGuard,
-    transfer::Transfer, ws::events::FileEventTx, FileId, OutgoingTransfer,
+    file::ChecksumAlgorithm,
+    manager::FileTerminalState,
+    protocol::{v7 as prot, Version},
+    service::State,
+    tasks::AliveGuard,
+    transfer::Transfer,
+    ws::events::FileEventTx,
+    FileId, OutgoingTransfer,
 };
 
 pub struct HandlerInit<'a> {
+    version: Version,
     state: &'a Arc<State>,
     logger: &'a slog::Logger,
     alive: &'a AliveGuard,
 }
 
 pub struct HandlerLoop<'a> {
+    version: Version,
     state: &'a Arc<State>,
     logger: &'a slog::Logger,
     alive: &'a AliveGuard,
@@ -42,6 +50,7 @@ struct FileTask {
 }
 
 struct Uploader {
+    version: Version,
     sink: Sender<MsgToSend>,
     file_id: FileId,
     offset: u64,
@@ -49,11 +58,13 @@ struct Uploader {
 
 impl<'a> HandlerInit<'a> {
     pub(crate) fn new(
+        version: Version,
         state: &'a Arc<State>,
         logger: &'a slog::Logger,
         alive: &'a AliveGuard,
     ) -> Self {
         Self {
+            version,
             state,
             logger,
             alive,
@@ -72,18 +83,22 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
         xfer: &OutgoingTransfer,
     ) -> crate::Result<()> {
         let req = prot::TransferRequest::from(xfer);
-        socket.send(Message::from(&req)).await?;
+        socket
+            .send(Message::Text(req.to_json(self.version)))
+            .await?;
         Ok(())
     }
 
     fn upgrade(self, upload_tx: Sender<MsgToSend>, xfer: Arc<OutgoingTransfer>) -> Self::Loop {
         let Self {
+            version,
             state,
             logger,
             alive,
         } = self;
 
         HandlerLoop {
+            version,
             state,
             alive,
             logger,
@@ -154,7 +169,14 @@ impl HandlerLoop<'_> {
         self.stop_task(&file_id, Status::FileFinished).await;
     }
 
-    fn on_checksum(&self, jobs: &mut JoinSet<()>, file_id: FileId, limit: u64) {
+    fn on_checksum(
+        &self,
+        jobs: &mut JoinSet<()>,
+        file_id: FileId,
+        limit: u64,
+        algorithm: ChecksumAlgorithm,
+    ) {
+        let version = self.version;
         let state = self.state.clone();
         let msg_tx = self.upload_tx.clone();
         let xfer = self.xfer.clone();
@@ -171,26 +193,22 @@ impl HandlerLoop<'_> {
                     .await?;
 
                 let checksum = xfer.files()[&file_id]
-                    .checksum::<_, futures::future::Ready<()>>(
-                        limit,
-                        None::<fn(u64) -> futures::future::Ready<()>>,
-                        None,
-                    )
+                    .checksum_cached(&state.storage, limit, algorithm)
                     .await?;
 
-                crate::Result::Ok(prot::ReportChsum {
+                let report = prot::ClientMsg::ReportChsum(prot::ReportChsum {
                     file: file_id.clone(),
                     limit,
+                    algorithm,
                     checksum,
-                })
+                });
+
+                report.to_json(version)
             };
 
             match make_report.await {
                 Ok(report) => {
-                    if let Err(e) = msg_tx
-                        .send(MsgToSend::from(&prot::ClientMsg::ReportChsum(report)))
-                        .await
-                    {
+                    if let Err(e) = msg_tx.send(MsgToSend::from(Message::Text(report))).await {
                         warn!(logger, "Failed to send checksum report: {:?}", e);
                     };
                 }
@@ -232,6 +250,7 @@ impl HandlerLoop<'_> {
 
             let start = || {
                 let uploader = Uploader {
+                    version: self.version,
                     sink: self.upload_tx.clone(),
                     file_id: file_id.clone(),
                     offset,
@@ -278,7 +297,9 @@ impl HandlerLoop<'_> {
             };
 
             socket
-                .send(Message::from(&prot::ClientMsg::Error(msg)))
+                .send(Message::Text(
+                    prot::ClientMsg::Error(msg).to_json(self.version)?,
+                ))
                 .await
                 .context("Failed to report error")?;
         }
@@ -309,7 +330,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         let msg = prot::ClientMsg::Reject(prot::Reject {
             file: file_id.clone(),
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::Text(msg.to_json(self.version)?))
+            .await?;
 
         self.stop_task(&file_id, Status::FileRejected).await;
 
@@ -326,7 +349,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             file: Some(file_id),
             msg,
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::Text(msg.to_json(self.version)?))
+            .await?;
 
         Ok(())
     }
@@ -342,8 +367,8 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         jobs: &mut JoinSet<()>,
         text: String,
     ) -> anyhow::Result<()> {
-        let msg: prot::ServerMsg =
-            serde_json::from_str(&text).context("Failed to deserialize server message")?;
+        let msg = prot::ServerMsg::from_json(&text, self.version)
+            .context("Failed to deserialize server message")?;
 
         match msg {
             prot::ServerMsg::Progress(prot::Progress {
@@ -355,9 +380,11 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
                 bytes_transfered: _,
             }) => self.on_done(file).await,
             prot::ServerMsg::Error(prot::Error { file, msg }) => self.on_error(file, msg).await,
-            prot::ServerMsg::ReqChsum(prot::ReqChsum { file, limit }) => {
-                self.on_checksum(jobs, file, limit)
-            }
+            prot::ServerMsg::ReqChsum(prot::ReqChsum {
+                file,
+                limit,
+                algorithm,
+            }) => self.on_checksum(jobs, file, limit, algorithm),
             prot::ServerMsg::Start(prot::Start { file, offset }) => {
                 self.on_start(socket, jobs, file, offset).await?
             }
@@ -395,15 +422,24 @@ impl Drop for HandlerLoop<'_> {
 #[async_trait::async_trait]
 impl handler::Uploader for Uploader {
     async fn chunk(&mut self, chunk: &[u8]) -> Result<(), crate::Error> {
-        let msg = prot::Chunk {
-            file: self.file_id.clone(),
-            data: chunk.to_vec(),
+        // No need to send the zeros over the wire if the peer understands
+        // zero runs
+        let msg = if self.version >= Version::V7 && chunk.iter().all(|&b| b == 0) {
+            let msg = prot::ClientMsg::Zeros(prot::Zeros {
+                file: self.file_id.clone(),
+                len: chunk.len() as u64,
+            });
+
+            Message::Text(msg.to_json(self.version)?)
+        } else {
+            Message::from(prot::Chunk {
+                file: self.file_id.clone(),
+                data: chunk.to_vec(),
+            })
         };
 
         self.sink
-            .send(MsgToSend {
-                msg: Message::from(msg),
-            })
+            .send(MsgToSend { msg })
             .await
             .map_err(|_| crate::Error::Canceled)?;
 
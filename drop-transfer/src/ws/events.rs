@@ -5,11 +5,17 @@ use std::{
 };
 
 use drop_analytics::{Moose, TransferFileEventData, TransferStateEventData, MOOSE_STATUS_SUCCESS};
+use drop_config::DropConfig;
 use drop_core::Status;
 use tokio::sync::{mpsc::UnboundedSender, Mutex};
 
+use super::{
+    cadence::Cadence,
+    throughput::{RateEstimator, TransferMeter},
+};
 use crate::{
-    file::FileInfo, utils, Event, File, FileId, IncomingTransfer, OutgoingTransfer, Transfer,
+    event::Throughput, snapshot::FileActivity, utils, Event, File, FileId, IncomingTransfer,
+    OutgoingTransfer, Transfer,
 };
 
 struct FileEventTxInner {
@@ -17,11 +23,26 @@ struct FileEventTxInner {
     moose: Arc<dyn Moose>,
     state: FileState,
     transferred: u64,
+    rate: RateEstimator,
+    progress_cadence: Cadence,
+    checksum_cadence: Cadence,
+    // Size of the data being checksummed, the last checksum progress
+    checksum_size: u64,
+    // Reported with the next file analytics event
+    metrics: FileMetrics,
+}
+
+#[derive(Default)]
+struct FileMetrics {
+    resume_offset: u64,
+    throttle_time: Duration,
+    verify_checksum_time: Duration,
+    finalize_checksum_time: Duration,
 }
 
 enum FileState {
     Idle,
-    Throttled,
+    Throttled { since: Instant },
     Preflight,
     InFlight { started: Instant },
     Terminal,
@@ -34,16 +55,28 @@ pub struct FileEventTx<T: Transfer> {
     inner: Mutex<FileEventTxInner>,
     xfer: Arc<T>,
     file_id: FileId,
+    // Shared with the other files of the transfer
+    meter: Arc<Mutex<SharedProgress>>,
+}
+
+// The progress of the whole transfer, reported on its own cadence so that the
+// files progressing in parallel don't multiply the events
+struct SharedProgress {
+    meter: TransferMeter,
+    cadence: Cadence,
 }
 
 pub struct EventTxFactory {
     events: UnboundedSender<(Event, SystemTime)>,
     moose: Arc<dyn Moose>,
+    progress_granularity: u64,
+    progress_interval: Duration,
 }
 
 pub struct TransferEventTx<T: Transfer> {
     inner: Mutex<TransferEventTxInner>,
     pub xfer: Arc<T>,
+    meter: Arc<Mutex<SharedProgress>>,
 }
 
 pub type IncomingTransferEventTx = TransferEventTx<IncomingTransfer>;
@@ -58,6 +91,7 @@ struct TransferEventTxInner {
     tx: UnboundedSender<(Event, SystemTime)>,
     moose: Arc<dyn Moose>,
     state: TransferState,
+    reconnects: u32,
 }
 
 trait EventTx {
@@ -73,20 +107,40 @@ impl EventTx for UnboundedSender<(Event, SystemTime)> {
 }
 
 impl EventTxFactory {
-    pub fn new(events: UnboundedSender<(Event, SystemTime)>, moose: Arc<dyn Moose>) -> Self {
-        Self { events, moose }
+    pub fn new(
+        events: UnboundedSender<(Event, SystemTime)>,
+        moose: Arc<dyn Moose>,
+        config: &DropConfig,
+    ) -> Self {
+        Self {
+            events,
+            moose,
+            progress_granularity: config.progress_events_granularity,
+            progress_interval: config.progress_events_interval,
+        }
     }
 
-    pub fn file<T: Transfer>(&self, xfer: Arc<T>, file_id: FileId) -> FileEventTx<T> {
+    pub fn file<T: Transfer>(
+        &self,
+        xfer_events: &TransferEventTx<T>,
+        file_id: FileId,
+    ) -> FileEventTx<T> {
         FileEventTx {
             inner: Mutex::new(FileEventTxInner {
                 tx: self.events.clone(),
                 moose: self.moose.clone(),
                 state: FileState::Idle,
                 transferred: 0,
+                rate: RateEstimator::default(),
+                progress_cadence: Cadence::new(self.progress_granularity, self.progress_interval),
+                // The byte step is applied by the checksum calculation
+                checksum_cadence: Cadence::new(0, self.progress_interval),
+                checksum_size: 0,
+                metrics: FileMetrics::default(),
             }),
-            xfer,
+            xfer: xfer_events.xfer.clone(),
             file_id,
+            meter: xfer_events.meter.clone(),
         }
     }
 
@@ -100,18 +154,67 @@ impl EventTxFactory {
                 } else {
                     TransferState::Ongoing
                 },
+                reconnects: 0,
             }),
+            meter: Arc::new(Mutex::new(SharedProgress {
+                meter: TransferMeter::new(&*xfer),
+                cadence: Cadence::new(self.progress_granularity, self.progress_interval),
+            })),
             xfer,
         }
     }
 }
 
 impl<T: Transfer> FileEventTx<T> {
-    fn file_info(&self) -> FileInfo {
-        self.xfer.files()[&self.file_id].info()
+    fn file_size(&self) -> u64 {
+        self.xfer.files()[&self.file_id].size()
+    }
+
+    // Takes the metrics gathered since the previous event. Doesn't borrow the
+    // whole `self` so that it can be used on drop
+    fn file_event(
+        xfer: &T,
+        file_id: &FileId,
+        inner: &mut FileEventTxInner,
+        phase: drop_analytics::TransferFilePhase,
+        elapsed: Duration,
+        result: i32,
+    ) -> TransferFileEventData {
+        let file = &xfer.files()[file_id];
+        let file_info = file.info();
+        let metrics = std::mem::take(&mut inner.metrics);
+
+        TransferFileEventData {
+            phase,
+            transfer_id: xfer.id().to_string(),
+            transfer_time: elapsed.as_millis() as i32,
+            path_id: file_info.path_id,
+            direction: file_info.direction,
+            transferred: utils::to_kb(inner.transferred),
+            result,
+            resume_offset: utils::to_kb(metrics.resume_offset),
+            file_size: utils::to_kb(file.size()),
+            throttle_time: metrics.throttle_time.as_millis() as i32,
+            verify_checksum_time: metrics.verify_checksum_time.as_millis() as i32,
+            finalize_checksum_time: metrics.finalize_checksum_time.as_millis() as i32,
+        }
     }
 
     async fn emit_in_flight(&self, event: Event) {
+        let lock = self.inner.lock().await;
+
+        if !(matches!(lock.state, FileState::Preflight { .. })
+            || matches!(lock.state, FileState::InFlight { .. }))
+        {
+            return;
+        }
+
+        lock.tx.emit(event);
+    }
+
+    // Emits the file progress and the progress of the whole transfer, each on
+    // its own cadence
+    async fn emit_progress(&self, transferred: u64, event: impl FnOnce(Throughput) -> Event) {
         let mut lock = self.inner.lock().await;
 
         if !(matches!(lock.state, FileState::Preflight { .. })
@@ -120,39 +223,103 @@ impl<T: Transfer> FileEventTx<T> {
             return;
         }
 
-        match event {
-            Event::FileUploadProgress(_, _, progress)
-            | Event::FileDownloadProgress(_, _, progress) => {
-                lock.transferred = progress;
-            }
-            _ => {}
+        let now = Instant::now();
+        let size = self.file_size();
+
+        // The rate is measured on every update, even the ones not reported
+        lock.transferred = transferred;
+        let bytes_per_sec = lock.rate.update(now, transferred);
+
+        if lock
+            .progress_cadence
+            .tick(now, transferred, transferred >= size)
+        {
+            lock.tx.emit(event(Throughput::new(
+                bytes_per_sec,
+                size.saturating_sub(transferred),
+            )));
         }
 
-        lock.tx.emit(event);
+        let mut progress = self.meter.lock().await;
+        progress.meter.progress(now, &self.file_id, transferred);
+
+        let transferred = progress.meter.transferred();
+        let last = progress.meter.remaining() == 0;
+        if progress.cadence.tick(now, transferred, last) {
+            lock.tx.emit(Event::TransferProgress {
+                transfer_id: self.xfer.id(),
+                transferred,
+                throughput: progress.meter.throughput(),
+            });
+        }
     }
 
-    async fn start_inner(&self, events: impl IntoIterator<Item = Event>) {
+    async fn start_inner(&self, offset: u64, events: impl IntoIterator<Item = Event>) {
         let mut lock = self.inner.lock().await;
 
         if matches!(lock.state, FileState::Terminal) {
             return;
         }
 
-        lock.state = FileState::InFlight {
-            started: Instant::now(),
-        };
+        let now = Instant::now();
+
+        if let FileState::Throttled { since } =
+            std::mem::replace(&mut lock.state, FileState::InFlight { started: now })
+        {
+            lock.metrics.throttle_time += now.duration_since(since);
+        }
+        lock.metrics.resume_offset = offset;
+
+        lock.rate.reset(now, offset);
+        lock.progress_cadence.reset();
+        self.meter.lock().await.meter.start(&self.file_id, offset);
 
         for event in events.into_iter() {
             lock.tx.emit(event);
         }
     }
 
+    async fn emit_checksum_start(&self, size: u64, event: Event) {
+        let mut lock = self.inner.lock().await;
+
+        if !(matches!(lock.state, FileState::Preflight { .. })
+            || matches!(lock.state, FileState::InFlight { .. }))
+        {
+            return;
+        }
+
+        lock.checksum_size = size;
+        lock.checksum_cadence.reset();
+
+        lock.tx.emit(event);
+    }
+
+    async fn emit_checksum_progress(&self, progress: u64, event: Event) {
+        let mut lock = self.inner.lock().await;
+
+        if !(matches!(lock.state, FileState::Preflight { .. })
+            || matches!(lock.state, FileState::InFlight { .. }))
+        {
+            return;
+        }
+
+        let last = progress >= lock.checksum_size;
+        if !lock.checksum_cadence.tick(Instant::now(), progress, last) {
+            return;
+        }
+
+        lock.tx.emit(event);
+    }
+
     async fn stop(&self, event: Event, status: Result<(), i32>) {
         let mut lock = self.inner.lock().await;
 
         let elapsed = match std::mem::replace(&mut lock.state, FileState::Idle) {
             FileState::Idle => return,
-            FileState::Throttled => Duration::ZERO,
+            FileState::Throttled { since } => {
+                lock.metrics.throttle_time += since.elapsed();
+                Duration::ZERO
+            }
             FileState::InFlight { started } => started.elapsed(),
             FileState::Preflight => Duration::ZERO,
             FileState::Terminal => return,
@@ -170,17 +337,8 @@ impl<T: Transfer> FileEventTx<T> {
             Err(err) => err,
         };
 
-        let file_info = self.file_info();
-
-        lock.moose.event_transfer_file(TransferFileEventData {
-            phase,
-            transfer_id: self.xfer.id().to_string(),
-            transfer_time: elapsed.as_millis() as i32,
-            path_id: file_info.path_id,
-            direction: file_info.direction,
-            transferred: utils::to_kb(lock.transferred),
-            result,
-        });
+        let data = Self::file_event(&self.xfer, &self.file_id, &mut lock, phase, elapsed, result);
+        lock.moose.event_transfer_file(data);
 
         lock.tx.emit(event);
     }
@@ -190,12 +348,21 @@ impl<T: Transfer> FileEventTx<T> {
 
         let elapsed = match std::mem::replace(&mut lock.state, FileState::Terminal) {
             FileState::Idle => Duration::ZERO,
-            FileState::Throttled => Duration::ZERO,
+            FileState::Throttled { since } => {
+                lock.metrics.throttle_time += since.elapsed();
+                Duration::ZERO
+            }
             FileState::InFlight { started } => started.elapsed(),
             FileState::Preflight => Duration::ZERO,
             FileState::Terminal => return,
         };
 
+        self.meter
+            .lock()
+            .await
+            .meter
+            .finish(&self.file_id, status.is_ok());
+
         let phase = match event {
             Event::FileUploadPaused { .. } | Event::FileDownloadPaused { .. } => {
                 drop_analytics::TransferFilePhase::Paused
@@ -208,17 +375,8 @@ impl<T: Transfer> FileEventTx<T> {
             Err(err) => err,
         };
 
-        let file_info = self.file_info();
-
-        lock.moose.event_transfer_file(TransferFileEventData {
-            phase,
-            transfer_id: self.xfer.id().to_string(),
-            transfer_time: elapsed.as_millis() as i32,
-            path_id: file_info.path_id,
-            direction: file_info.direction,
-            transferred: utils::to_kb(lock.transferred),
-            result,
-        });
+        let data = Self::file_event(&self.xfer, &self.file_id, &mut lock, phase, elapsed, result);
+        lock.moose.event_transfer_file(data);
 
         lock.tx.emit(event);
     }
@@ -228,30 +386,46 @@ impl<T: Transfer> FileEventTx<T> {
 
         let elapsed = match std::mem::replace(&mut lock.state, FileState::Idle) {
             FileState::Idle => None,
-            FileState::Throttled => Some(Duration::ZERO),
+            FileState::Throttled { since } => {
+                lock.metrics.throttle_time += since.elapsed();
+                Some(Duration::ZERO)
+            }
             FileState::InFlight { started } => Some(started.elapsed()),
             FileState::Preflight => Some(Duration::ZERO),
             FileState::Terminal => return,
         };
 
         if let Some(elapsed) = elapsed {
-            let file_info = self.file_info();
-
-            lock.moose.event_transfer_file(TransferFileEventData {
-                phase: drop_analytics::TransferFilePhase::Finished,
-                transfer_id: self.xfer.id().to_string(),
-                transfer_time: elapsed.as_millis() as i32,
-                path_id: file_info.path_id,
-                direction: file_info.direction,
-                transferred: utils::to_kb(lock.transferred),
-                result: status as _,
-            });
+            let data = Self::file_event(
+                &self.xfer,
+                &self.file_id,
+                &mut lock,
+                drop_analytics::TransferFilePhase::Finished,
+                elapsed,
+                status as _,
+            );
+            lock.moose.event_transfer_file(data);
         }
     }
 
     pub fn file_id(&self) -> &FileId {
         &self.file_id
     }
+
+    /// The activity as seen by the events along with the last reported offset.
+    /// The terminal states are tracked by the transfer manager
+    pub async fn activity(&self) -> (FileActivity, u64) {
+        let lock = self.inner.lock().await;
+
+        let activity = match lock.state {
+            FileState::Idle | FileState::Terminal => FileActivity::Idle,
+            FileState::Throttled { .. } => FileActivity::Throttled,
+            FileState::Preflight => FileActivity::Preparing,
+            FileState::InFlight { .. } => FileActivity::InFlight,
+        };
+
+        (activity, lock.transferred)
+    }
 }
 
 impl FileEventTx<IncomingTransfer> {
@@ -270,11 +444,14 @@ impl FileEventTx<IncomingTransfer> {
     }
 
     pub async fn finalize_checksum_start(&self, size: u64) {
-        self.emit_in_flight(crate::Event::FinalizeChecksumStarted {
-            transfer_id: self.xfer.id(),
-            file_id: self.file_id.clone(),
+        self.emit_checksum_start(
             size,
-        })
+            crate::Event::FinalizeChecksumStarted {
+                transfer_id: self.xfer.id(),
+                file_id: self.file_id.clone(),
+                size,
+            },
+        )
         .await
     }
 
@@ -287,20 +464,26 @@ impl FileEventTx<IncomingTransfer> {
     }
 
     pub async fn finalize_checksum_progress(&self, progress: u64) {
-        self.emit_in_flight(crate::Event::FinalizeChecksumProgress {
-            transfer_id: self.xfer.id(),
-            file_id: self.file_id.clone(),
+        self.emit_checksum_progress(
             progress,
-        })
+            crate::Event::FinalizeChecksumProgress {
+                transfer_id: self.xfer.id(),
+                file_id: self.file_id.clone(),
+                progress,
+            },
+        )
         .await
     }
 
     pub async fn verify_checksum_start(&self, size: u64) {
-        self.emit_in_flight(crate::Event::VerifyChecksumStarted {
-            transfer_id: self.xfer.id(),
-            file_id: self.file_id.clone(),
+        self.emit_checksum_start(
             size,
-        })
+            crate::Event::VerifyChecksumStarted {
+                transfer_id: self.xfer.id(),
+                file_id: self.file_id.clone(),
+                size,
+            },
+        )
         .await
     }
 
@@ -312,31 +495,52 @@ impl FileEventTx<IncomingTransfer> {
         .await
     }
 
+    /// Accounts for the time spent checking the partially downloaded data,
+    /// whether the checksum events are emitted or not
+    pub async fn verify_checksum_time(&self, elapsed: Duration) {
+        self.inner.lock().await.metrics.verify_checksum_time += elapsed;
+    }
+
+    /// Accounts for the time spent checking the downloaded file, whether the
+    /// checksum events are emitted or not
+    pub async fn finalize_checksum_time(&self, elapsed: Duration) {
+        self.inner.lock().await.metrics.finalize_checksum_time += elapsed;
+    }
+
     pub async fn verify_checksum_progress(&self, progress: u64) {
-        self.emit_in_flight(crate::Event::VerifyChecksumProgress {
-            transfer_id: self.xfer.id(),
-            file_id: self.file_id.clone(),
+        self.emit_checksum_progress(
             progress,
-        })
+            crate::Event::VerifyChecksumProgress {
+                transfer_id: self.xfer.id(),
+                file_id: self.file_id.clone(),
+                progress,
+            },
+        )
         .await
     }
 
     pub async fn progress(&self, transfered: u64) {
-        self.emit_in_flight(crate::Event::FileDownloadProgress(
-            self.xfer.clone(),
-            self.file_id.clone(),
-            transfered,
-        ))
+        self.emit_progress(transfered, |throughput| {
+            crate::Event::FileDownloadProgress(
+                self.xfer.clone(),
+                self.file_id.clone(),
+                transfered,
+                throughput,
+            )
+        })
         .await
     }
 
     pub async fn start(&self, base_dir: impl Into<String>, offset: u64) {
-        self.start_inner([crate::Event::FileDownloadStarted(
-            self.xfer.clone(),
-            self.file_id.clone(),
-            base_dir.into(),
+        self.start_inner(
             offset,
-        )])
+            [crate::Event::FileDownloadStarted(
+                self.xfer.clone(),
+                self.file_id.clone(),
+                base_dir.into(),
+                offset,
+            )],
+        )
         .await
     }
 
@@ -400,24 +604,35 @@ impl FileEventTx<OutgoingTransfer> {
             offset,
         )];
 
-        self.start_inner(events).await
+        self.start_inner(offset, events).await
     }
 
     pub async fn start_with_progress(&self, offset: u64) {
+        // The rate is not known yet
+        let throughput = Throughput::new(0, self.file_size().saturating_sub(offset));
+
         let events = [
             crate::Event::FileUploadStarted(self.xfer.clone(), self.file_id.clone(), offset),
-            crate::Event::FileUploadProgress(self.xfer.clone(), self.file_id.clone(), offset),
+            crate::Event::FileUploadProgress(
+                self.xfer.clone(),
+                self.file_id.clone(),
+                offset,
+                throughput,
+            ),
         ];
 
-        self.start_inner(events).await
+        self.start_inner(offset, events).await
     }
 
     pub async fn progress(&self, transfered: u64) {
-        self.emit_in_flight(crate::Event::FileUploadProgress(
-            self.xfer.clone(),
-            self.file_id.clone(),
-            transfered,
-        ))
+        self.emit_progress(transfered, |throughput| {
+            crate::Event::FileUploadProgress(
+                self.xfer.clone(),
+                self.file_id.clone(),
+                transfered,
+                throughput,
+            )
+        })
         .await
     }
 
@@ -432,9 +647,11 @@ impl FileEventTx<OutgoingTransfer> {
                     transferred,
                 });
 
-                lock.state = FileState::Throttled;
+                lock.state = FileState::Throttled {
+                    since: Instant::now(),
+                };
             }
-            FileState::Throttled => (),
+            FileState::Throttled { .. } => (),
             FileState::InFlight { .. } => (),
             FileState::Preflight => (),
             FileState::Terminal => (),
@@ -483,6 +700,12 @@ impl FileEventTx<OutgoingTransfer> {
 }
 
 impl<T: Transfer> TransferEventTx<T> {
+    /// Accounts for the file which has finished before the transfer was
+    /// restored, so that it does not count towards the remaining bytes
+    pub async fn file_finished(&self, file_id: &FileId, success: bool) {
+        self.meter.lock().await.meter.finish(file_id, success);
+    }
+
     async fn emit_ongoing(&self, event: Event) {
         let lock = self.inner.lock().await;
 
@@ -525,6 +748,7 @@ impl TransferEventTx<OutgoingTransfer> {
             protocol_version: 0,
             transfer_id: self.xfer.id().to_string(),
             result: i32::from(&err),
+            reconnects: lock.reconnects as _,
         });
 
         lock.tx.emit(Event::OutgoingTransferFailed(
@@ -542,6 +766,11 @@ impl TransferEventTx<OutgoingTransfer> {
         .await;
     }
 
+    /// Counts the connection attempt made after the first one
+    pub async fn reconnecting(&self) {
+        self.inner.lock().await.reconnects += 1;
+    }
+
     pub async fn connected(&self, protocol_version: i32) {
         let lock = self.inner.lock().await;
 
@@ -553,6 +782,7 @@ impl TransferEventTx<OutgoingTransfer> {
             protocol_version,
             transfer_id: self.xfer.id().to_string(),
             result: MOOSE_STATUS_SUCCESS,
+            reconnects: lock.reconnects as _,
         });
     }
 
@@ -584,31 +814,29 @@ impl TransferEventTx<IncomingTransfer> {
 
 impl<T: Transfer> Drop for FileEventTx<T> {
     fn drop(&mut self) {
-        let elapsed = match self.inner.get_mut().state {
+        let inner = self.inner.get_mut();
+
+        let elapsed = match inner.state {
             FileState::Idle => None,
-            FileState::Throttled => Some(Duration::ZERO),
+            FileState::Throttled { since } => {
+                inner.metrics.throttle_time += since.elapsed();
+                Some(Duration::ZERO)
+            }
             FileState::InFlight { started } => Some(started.elapsed()),
             FileState::Preflight => Some(Duration::ZERO),
             FileState::Terminal => return,
         };
 
         if let Some(elapsed) = elapsed {
-            let file_info = self.file_info();
-
-            let transferred = self.inner.get_mut().transferred;
-
-            self.inner
-                .get_mut()
-                .moose
-                .event_transfer_file(TransferFileEventData {
-                    phase: drop_analytics::TransferFilePhase::Finished,
-                    transfer_id: self.xfer.id().to_string(),
-                    transfer_time: elapsed.as_millis() as i32,
-                    path_id: file_info.path_id,
-                    direction: file_info.direction,
-                    transferred: utils::to_kb(transferred),
-                    result: Status::Finalized as _,
-                });
+            let data = Self::file_event(
+                &self.xfer,
+                &self.file_id,
+                inner,
+                drop_analytics::TransferFilePhase::Finished,
+                elapsed,
+                Status::Finalized as _,
+            );
+            inner.moose.event_transfer_file(data);
         }
     }
 }
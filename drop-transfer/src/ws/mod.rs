@@ -1,6 +1,8 @@
+mod cadence;
 pub mod client;
 mod events;
 pub mod server;
+mod throughput;
 mod utils;
 
 pub use events::*;
@@ -1,12 +1,12 @@
-use std::{fs, future::Future, path::PathBuf, sync::Arc, time::Duration};
+use std::{sync::Arc, time::Duration};
 
 use tokio::{sync::mpsc::Sender, task::JoinSet};
 use warp::ws::Message;
 
 use super::{socket::WebSocket, TmpFileState};
 use crate::{
+    file::ChecksumAlgorithm,
     transfer::IncomingTransfer,
-    utils::Hidden,
     ws::{self},
     FileId,
 };
@@ -77,17 +77,9 @@ pub trait Downloader {
         task: &super::FileXferTask,
         tmp_file: Option<TmpFileState>,
     ) -> crate::Result<DownloadInit>;
-    async fn open(&mut self, tmp_location: &Hidden<PathBuf>) -> crate::Result<fs::File>;
+    fn checksum_algorithm(&self) -> ChecksumAlgorithm;
     async fn progress(&mut self, bytes: u64) -> crate::Result<()>;
-    async fn validate<F, Fut>(
-        &mut self,
-        location: &Hidden<PathBuf>,
-        progress_cb: Option<F>,
-        event_granularity: Option<u64>,
-    ) -> crate::Result<()>
-    where
-        F: FnMut(u64) -> Fut + Send + Sync,
-        Fut: Future<Output = ()> + Send + Sync;
+    async fn validate(&mut self, checksum: &[u8; 32]) -> crate::Result<()>;
 }
 
 impl<T> From<T> for MsgToSend
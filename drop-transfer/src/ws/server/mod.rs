@@ -6,20 +6,20 @@ mod v6;
 use std::{
     borrow::Borrow,
     collections::HashMap,
-    fs,
-    future::Future,
-    io::{self, Write},
+    fs, io,
     net::SocketAddr,
     ops::ControlFlow,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::Arc,
+    time::Instant,
 };
 
 use anyhow::Context;
 use drop_auth::Nonce;
+use futures::FutureExt;
 use handler::{Downloader, HandlerInit, HandlerLoop};
 use hyper::StatusCode;
-use slog::{debug, error, info, warn, Logger};
+use slog::{debug, error, info, o, warn, Logger};
 use tokio::{
     sync::{
         mpsc::{self, UnboundedReceiver},
@@ -34,7 +34,7 @@ use self::socket::{WebSocket, WsStream};
 use super::{events::FileEventTx, IncomingFileEventTx};
 use crate::{
     check,
-    file::{self, FileSubPath, FileToRecv},
+    file::{self, ChecksumAlgorithm, DownloadSink, FileSubPath, FileToRecv},
     manager::{FinishTransferState, IncomingRegistered},
     protocol,
     quarantine::PathExt,
@@ -50,8 +50,10 @@ use crate::{
 };
 
 const MAX_FILENAME_LENGTH: usize = 255;
-const MAX_FILE_SUFFIX_LEN: usize = 5; // Assume that the suffix will fit into 5 characters e.g.
-                                      // `<filename>(999).<ext>`
+// Assume that the suffix will fit into 5 characters e.g. `<filename>(999).<ext>`
+const MAX_FILE_SUFFIX_LEN: usize = 5;
+// The step of the progress reports sent to the sender. The local events follow
+// the configured cadence instead
 const REPORT_PROGRESS_THRESHOLD: u64 = 1024 * 64;
 
 pub enum ServerReq {
@@ -79,16 +81,22 @@ pub struct FileStreamCtx<'a> {
 }
 
 pub struct TmpFileState {
-    meta: fs::Metadata,
+    len: u64,
     csum: [u8; 32],
 }
 
+pub enum FileChunk {
+    Data(Vec<u8>),
+    // Run of zero bytes signalled by the sender instead of the data
+    Zeros(u64),
+}
+
 struct StreamCtx<'a> {
     logger: &'a Logger,
     state: &'a State,
-    tmp_loc: &'a Hidden<PathBuf>,
-    stream: &'a mut UnboundedReceiver<Vec<u8>>,
-    events: &'a FileEventTx<IncomingTransfer>,
+    sink: &'a mut dyn DownloadSink,
+    stream: &'a mut UnboundedReceiver<FileChunk>,
+    events: &'a Arc<FileEventTx<IncomingTransfer>>,
 }
 
 #[derive(Debug)]
@@ -299,19 +307,22 @@ async fn websocket_start(
     logger: Logger,
     refresh_trigger: tokio::sync::watch::Receiver<()>,
 ) {
+    let logger = logger.new(o!("peer" => %peer.ip(), "protocol_version" => %version));
+
     let ctx = RunContext {
         logger: &logger,
         state: state.clone(),
         stop: &stop,
         alive: &alive,
         refresh_trigger: &refresh_trigger,
+        version,
     };
 
     match version {
-        protocol::Version::V6 => {
+        protocol::Version::V6 | protocol::Version::V7 => {
             ctx.run(
                 socket,
-                v6::HandlerInit::new(peer.ip(), state, &logger, &alive),
+                v6::HandlerInit::new(version, peer.ip(), state, &logger, &alive),
             )
             .await
         }
@@ -383,6 +394,7 @@ struct RunContext<'a> {
     refresh_trigger: &'a tokio::sync::watch::Receiver<()>,
     stop: &'a CancellationToken,
     alive: &'a AliveGuard,
+    version: protocol::Version,
 }
 
 impl RunContext<'_> {
@@ -430,11 +442,17 @@ impl RunContext<'_> {
         let xfer = Arc::new(xfer);
         let xfer_id = xfer.id();
 
+        let logger = self.logger.new(o!("transfer_id" => %xfer_id));
+        let this = RunContext {
+            logger: &logger,
+            ..self
+        };
+
         let job = async {
-            self.client_loop(socket, handler, xfer).await;
+            this.client_loop(socket, handler, xfer).await;
 
             // The error indicates the transfer is already finished. That's fine
-            let _ = self
+            let _ = this
                 .state
                 .transfer_manager
                 .incoming_disconnect(xfer_id)
@@ -444,8 +462,8 @@ impl RunContext<'_> {
         tokio::select! {
             biased;
 
-            _ = self.stop.cancelled() => {
-                debug!(self.logger, "Server job stop: {xfer_id}");
+            _ = this.stop.cancelled() => {
+                debug!(this.logger, "Server job stop: {xfer_id}");
             }
             _ = job => (),
         }
@@ -542,7 +560,7 @@ impl RunContext<'_> {
         let registered = self
             .state
             .transfer_manager
-            .register_incoming(xfer.clone(), req_send)
+            .register_incoming(xfer.clone(), req_send, self.version)
             .await?;
 
         match registered {
@@ -640,6 +658,23 @@ impl RunContext<'_> {
     }
 }
 
+impl FileChunk {
+    fn len(&self) -> u64 {
+        match self {
+            Self::Data(data) => data.len() as u64,
+            Self::Zeros(len) => *len,
+        }
+    }
+}
+
+// The chunk length comes from the peer, so a zero run can claim any size
+fn received_after_chunk(received: u64, chunk: &FileChunk, size: u64) -> crate::Result<u64> {
+    received
+        .checked_add(chunk.len())
+        .filter(|&total| total <= size)
+        .ok_or(crate::Error::MismatchedSize)
+}
+
 impl FileXferTask {
     pub fn new(file: FileToRecv, xfer: Arc<IncomingTransfer>, base_dir: PathBuf) -> Self {
         Self {
@@ -654,7 +689,7 @@ impl FileXferTask {
         StreamCtx {
             logger,
             state,
-            tmp_loc,
+            sink,
             stream,
             events,
         }: StreamCtx<'_>,
@@ -663,17 +698,27 @@ impl FileXferTask {
         emit_checksum_events: bool,
         checksum_events_granularity: u64,
     ) -> crate::Result<PathBuf> {
-        let mut out_file = match downloader.open(tmp_loc).await {
-            Ok(out_file) => out_file,
-            Err(err) => {
+        if let Err(err) = sink.open(offset).await {
+            error!(
+                logger,
+                "Could not open sink of {} for downloading: {err}",
+                self.file.id()
+            );
+
+            return Err(err);
+        }
+
+        if state.config().preallocate_downloads {
+            if let Err(err) = sink.preallocate(self.file.size()).await {
                 error!(
                     logger,
-                    "Could not create {tmp_loc:?} for downloading: {err}"
+                    "Could not preallocate space for {}: {err}",
+                    self.file.id()
                 );
 
                 return Err(err);
             }
-        };
+        }
 
         let consume_file_chunks = async {
             let mut bytes_received = offset;
@@ -686,56 +731,72 @@ impl FileXferTask {
             while bytes_received < self.file.size() {
                 let chunk = stream.recv().await.ok_or(crate::Error::Canceled)?;
 
-                let chunk_size = chunk.len();
-                if chunk_size as u64 + bytes_received > self.file.size() {
-                    return Err(crate::Error::MismatchedSize);
+                let received = received_after_chunk(bytes_received, &chunk, self.file.size())?;
+
+                match chunk {
+                    FileChunk::Data(data) => sink.append(&data).await?,
+                    FileChunk::Zeros(len) => sink.append_zeros(len).await?,
                 }
 
-                out_file.write_all(&chunk)?;
+                bytes_received = received;
 
-                bytes_received += chunk_size as u64;
+                // The events are limited by the configured cadence
+                events.progress(bytes_received).await;
 
                 if last_progress + REPORT_PROGRESS_THRESHOLD <= bytes_received {
-                    // send progress to the caller
+                    // send progress to the sender
                     downloader.progress(bytes_received).await?;
-                    events.progress(bytes_received).await;
 
                     last_progress = bytes_received;
                 }
             }
 
-            // Close the file handle
-            drop(out_file);
-
             if bytes_received > self.file.size() {
                 return Err(crate::Error::UnexpectedData);
             }
 
+            let checksum_started = Instant::now();
+
             if emit_checksum_events {
                 events.finalize_checksum_start(self.file.size()).await;
-                let progress_cb = {
-                    move |progress_bytes: u64| async move {
-                        events.finalize_checksum_progress(progress_bytes).await;
-                    }
+
+                let progress_cb: file::ProgressCallback = {
+                    let events = events.clone();
+                    Box::new(move |progress_bytes: u64| {
+                        let events = events.clone();
+                        async move {
+                            events.finalize_checksum_progress(progress_bytes).await;
+                        }
+                        .boxed()
+                    })
                 };
 
-                downloader
-                    .validate(
-                        tmp_loc,
+                let csum = sink
+                    .checksum(
+                        downloader.checksum_algorithm(),
                         Some(progress_cb),
                         Some(checksum_events_granularity),
                     )
                     .await?;
+                let validation = downloader.validate(&csum).await;
+
+                // Failed validations still count towards the metric
+                events
+                    .finalize_checksum_time(checksum_started.elapsed())
+                    .await;
+                validation?;
 
                 events.finalize_checksum_finish().await;
             } else {
-                downloader
-                    .validate::<_, futures::future::Ready<()>>(
-                        tmp_loc,
-                        None::<fn(u64) -> futures::future::Ready<()>>,
-                        None,
-                    )
+                let csum = sink
+                    .checksum(downloader.checksum_algorithm(), None, None)
                     .await?;
+                let validation = downloader.validate(&csum).await;
+
+                events
+                    .finalize_checksum_time(checksum_started.elapsed())
+                    .await;
+                validation?;
             }
 
             Ok(())
@@ -746,11 +807,12 @@ impl FileXferTask {
             // when cancelled. We might
             // resume
             Err(err) => {
-                if let Err(ioerr) = fs::remove_file(&tmp_loc.0) {
+                if let Err(discard_err) = sink.discard().await {
                     error!(
                         logger,
-                        "Could not remove temporary file {tmp_loc:?} after failed download: {}",
-                        ioerr
+                        "Could not discard sink of {} after failed download: {}",
+                        self.file.id(),
+                        discard_err
                     );
                 }
 
@@ -759,11 +821,11 @@ impl FileXferTask {
             _ => (),
         };
 
-        let dst = match self.place_file_into_dest(state, logger, tmp_loc).await {
+        let dst = match self.place_file_into_dest(logger, state, sink).await {
             Ok(dst) => {
                 info!(
                     logger,
-                    "Sucesfully placed file for id {} into destination: {tmp_loc:?} -> {:?}",
+                    "Sucesfully placed file for id {} into destination: {:?}",
                     self.file.id(),
                     Hidden(&dst)
                 );
@@ -773,7 +835,7 @@ impl FileXferTask {
             Err(err) => {
                 error!(
                     logger,
-                    "Could not rename temporary file of {} after downloading: {err}",
+                    "Could not finalize sink of {} after downloading: {err}",
                     self.file.id(),
                 );
                 return Err(err);
@@ -801,63 +863,113 @@ impl FileXferTask {
 
     async fn place_file_into_dest(
         &self,
-        state: &State,
         logger: &Logger,
-        tmp_location: &Hidden<PathBuf>,
+        state: &State,
+        sink: &mut dyn DownloadSink,
     ) -> crate::Result<PathBuf> {
-        let abs_path = self.prepare_abs_path(state).await?;
-        if let Some(parent) = abs_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        if self.file.is_archive() {
+            return self.unpack_archive_into_dest(logger, state, sink).await;
         }
 
-        let dst = move_tmp_to_dst(tmp_location, Hidden(&abs_path), logger)?;
+        let abs_path = self.prepare_abs_path(state).await?;
+        sink.finalize(&abs_path).await
+    }
 
-        Ok(dst)
+    async fn unpack_archive_into_dest(
+        &self,
+        logger: &Logger,
+        state: &State,
+        sink: &mut dyn DownloadSink,
+    ) -> crate::Result<PathBuf> {
+        let root = {
+            let mut lock = state.transfer_manager.incoming.lock().await;
+
+            let state = lock
+                .get_mut(&self.xfer.id())
+                .ok_or(crate::Error::Canceled)?;
+
+            let mapping = state
+                .dir_mappings
+                .compose_archive_root(&self.base_dir, self.file.subpath())?;
+
+            self.base_dir.join(mapping)
+        };
+
+        let reader = sink.reader().await?;
+
+        let task = {
+            let logger = logger.clone();
+            let subpath = self.file.subpath().clone();
+            let root = root.clone();
+            let limits = ArchiveLimits {
+                dir_depth_limit: state.config().dir_depth_limit,
+                max_members: drop_config::MAX_ARCHIVE_MEMBERS,
+            };
+            move || unpack_archive(&logger, reader, &subpath, &root, limits)
+        };
+
+        tokio::task::spawn_blocking(task).await.map_err(|err| {
+            crate::Error::BadTransferState(format!("Failed to unpack the archive: {err}"))
+        })??;
+
+        // The archive itself is not needed anymore
+        sink.discard().await?;
+
+        Ok(root)
     }
 
     async fn handle_tmp_file(
         &mut self,
         logger: &Logger,
-        events: &FileEventTx<IncomingTransfer>,
-        tmp_location: &Hidden<PathBuf>,
+        events: &Arc<FileEventTx<IncomingTransfer>>,
+        sink: &mut dyn DownloadSink,
+        algorithm: ChecksumAlgorithm,
         emit_checksum_events: bool,
         checksum_events_granularity: u64,
     ) -> Option<TmpFileState> {
-        // TODO: we load the file's metadata to check if we should emit checksum events
-        // based on size threshold. However TmpFileState::load also does the
-        // same thing. We should refactor this to avoid double loading.
-        let tmp_size = fs::File::open(&tmp_location.0)
-            .and_then(|file| file.metadata())
-            .map(|metadata| metadata.len())
-            .ok();
+        let len = match sink.resume_offset().await {
+            Ok(Some(len)) => len,
+            Ok(None) => return None,
+            Err(err) => {
+                debug!(logger, "Failed to load temporary file info: {err}");
+                return None;
+            }
+        };
 
-        let will_emit_checksum_events = emit_checksum_events && tmp_size.is_some();
+        let checksum_started = Instant::now();
 
-        let cb = if will_emit_checksum_events {
-            let size = tmp_size.unwrap_or(0);
-            events.verify_checksum_start(size).await;
+        let cb: Option<file::ProgressCallback> = if emit_checksum_events {
+            events.verify_checksum_start(len).await;
 
-            Some(|progress_bytes| events.verify_checksum_progress(progress_bytes))
+            let events = events.clone();
+            Some(Box::new(move |progress_bytes: u64| {
+                let events = events.clone();
+                async move {
+                    events.verify_checksum_progress(progress_bytes).await;
+                }
+                .boxed()
+            }))
         } else {
             None
         };
 
         // Check if we can resume the temporary file
-        let tmp_file_state = match TmpFileState::load(
-            &tmp_location.0,
-            cb,
-            Some(checksum_events_granularity),
-        )
-        .await
-        {
-            Ok(tmp_file_state) => {
+        let csum = sink
+            .checksum(algorithm, cb, Some(checksum_events_granularity))
+            .await;
+
+        events
+            .verify_checksum_time(checksum_started.elapsed())
+            .await;
+
+        let tmp_file_state = match csum {
+            Ok(csum) => {
                 debug!(
                     logger,
-                    "Found temporary file: {:?}, of size: {}",
-                    tmp_location.0,
-                    tmp_file_state.meta.len()
+                    "Found partially downloaded data of {}, of size: {len}",
+                    self.file.id()
                 );
-                Some(tmp_file_state)
+                Some(TmpFileState { len, csum })
             }
             Err(err) => {
                 debug!(logger, "Failed to load temporary file info: {err}");
@@ -865,7 +977,7 @@ impl FileXferTask {
             }
         };
 
-        if will_emit_checksum_events {
+        if emit_checksum_events {
             events.verify_checksum_finish().await;
         }
 
@@ -878,7 +990,7 @@ impl FileXferTask {
         state: Arc<State>,
         events: Arc<FileEventTx<IncomingTransfer>>,
         mut downloader: impl Downloader,
-        mut stream: UnboundedReceiver<Vec<u8>>,
+        mut stream: UnboundedReceiver<FileChunk>,
         req_send: mpsc::UnboundedSender<ServerReq>,
         logger: Logger,
         guard: AliveGuard,
@@ -888,26 +1000,27 @@ impl FileXferTask {
             validate_file_id_for_download(self.file.id())?;
 
             let emit_checksum_events = {
-                if let Some(threshold) = state.config.checksum_events_size_threshold {
+                if let Some(threshold) = state.config().checksum_events_size_threshold {
                     self.file.size() >= threshold as u64
                 } else {
                     false
                 }
             };
-            let checksum_events_granularity = state.config.checksum_events_granularity;
+            let checksum_events_granularity = state.config().checksum_events_granularity;
 
             events.preflight().await;
 
-            let tmp_location: Hidden<PathBuf> = Hidden(
-                self.base_dir
-                    .join(temp_file_name(self.xfer.id(), self.file.id())),
-            );
+            let mut sink =
+                state
+                    .download_sinks
+                    .create(self.xfer.id(), &self.file, &self.base_dir)?;
 
             let tmp_file_state = self
                 .handle_tmp_file(
                     &logger,
                     &events,
-                    &tmp_location,
+                    sink.as_mut(),
+                    downloader.checksum_algorithm(),
                     emit_checksum_events,
                     checksum_events_granularity,
                 )
@@ -934,7 +1047,7 @@ impl FileXferTask {
                         StreamCtx {
                             logger: &logger,
                             state: &state,
-                            tmp_loc: &tmp_location,
+                            sink: sink.as_mut(),
                             stream: &mut stream,
                             events: &events,
                         },
@@ -1002,82 +1115,11 @@ impl FileXferTask {
     }
 }
 
-impl TmpFileState {
-    // Blocking operation
-    async fn load<F, Fut>(
-        path: &Path,
-        progress_cb: Option<F>,
-        event_granularity: Option<u64>,
-    ) -> io::Result<Self>
-    where
-        F: Fn(u64) -> Fut + Sync + Send,
-        Fut: Future<Output = ()>,
-    {
-        let file = fs::File::open(path)?;
-
-        let meta = file.metadata()?;
-
-        let csum = file::checksum(file, progress_cb, event_granularity).await?;
-        Ok(TmpFileState { meta, csum })
-    }
-}
-
-fn move_tmp_to_dst(
-    tmp_location: &Hidden<PathBuf>,
-    absolute_path: Hidden<&Path>,
-    logger: &Logger,
-) -> crate::Result<PathBuf> {
-    let mut opts = fs::OpenOptions::new();
-    opts.write(true).create_new(true);
-
-    let mut iter = crate::utils::filepath_variants(absolute_path.0)?;
-    let dst_location = loop {
-        let path = iter.next().expect("File paths iterator should never end");
-
-        match opts.open(&path) {
-            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
-                continue;
-            }
-            Err(err) => {
-                // On Win the permissions error is returned in case there's a
-                // directory with the same name. Let's do it for all OSes since
-                // there should be no harm.
-                if path.exists() {
-                    continue;
-                }
-
-                error!(logger, "Failed to crate destination file: {err}");
-                return Err(err.into());
-            }
-            Ok(file) => {
-                drop(file); // Close the file
-                break path;
-            }
-        }
-    };
-
-    if let Err(err) = fs::rename(&tmp_location.0, &dst_location) {
-        if let Err(err) = fs::remove_file(&dst_location) {
-            warn!(
-                logger,
-                "Failed to remove touched destination file on move error: {err}"
-            );
-        }
-        return Err(err.into());
-    }
-
-    if let Err(err) = dst_location.quarantine() {
-        error!(logger, "Failed to quarantine downloaded file: {err}");
-    }
-
-    Ok(dst_location)
-}
-
 impl<'a> FileStreamCtx<'a> {
     async fn start(
         self,
         downloader: impl Downloader + Send + 'static,
-        stream: UnboundedReceiver<Vec<u8>>,
+        stream: UnboundedReceiver<FileChunk>,
     ) -> anyhow::Result<(AbortHandle, Arc<IncomingFileEventTx>)> {
         let events = self
             .state
@@ -1097,6 +1139,8 @@ impl<'a> FileStreamCtx<'a> {
                 task,
             } = self;
 
+            let logger = logger.new(o!("file_id" => %task.file.id()));
+
             jobs.spawn(async move {
                 let _guard = guard.clone();
 
@@ -1119,7 +1163,7 @@ pub fn remove_temp_files<P, I>(
 {
     for (base, file_id) in iter.into_iter() {
         let file_id = file_id.borrow();
-        let location = base.into().join(temp_file_name(transfer_id, file_id));
+        let location = base.into().join(file::temp_file_name(transfer_id, file_id));
         let location = Hidden(location);
 
         debug!(logger, "Removing temporary file: {location:?}");
@@ -1136,10 +1180,6 @@ pub fn remove_temp_files<P, I>(
     }
 }
 
-fn temp_file_name(transfer_id: uuid::Uuid, file_id: &FileId) -> String {
-    format!("{}-{file_id}.dropdl-part", transfer_id.as_simple(),)
-}
-
 /// Check file and dir names are shorter then MAX and contain illegal values
 fn validate_subpath_for_download(subpath: &FileSubPath) -> crate::Result<()> {
     const DISALLOWED: &[&str] = &[".."];
@@ -1159,6 +1199,147 @@ fn validate_subpath_for_download(subpath: &FileSubPath) -> crate::Result<()> {
     Ok(())
 }
 
+#[derive(Clone, Copy)]
+struct ArchiveLimits {
+    dir_depth_limit: usize,
+    max_members: usize,
+}
+
+/// Unpack the directory archive into `dst`. Every member path is validated the
+/// same way as the subpaths of ordinary files and only the directories and
+/// regular files are created, so nothing can be written outside of `dst`. The
+/// depth of the members and their number are limited. On failure everything
+/// unpacked so far is removed
+fn unpack_archive(
+    logger: &Logger,
+    reader: impl io::Read,
+    subpath: &FileSubPath,
+    dst: &Path,
+    limits: ArchiveLimits,
+) -> crate::Result<()> {
+    // Every file and directory created, in the creation order
+    let mut created = Vec::new();
+
+    let res = unpack_archive_members(logger, reader, subpath, dst, limits, &mut created);
+
+    if res.is_err() {
+        for path in created.iter().rev() {
+            let res = if path.is_dir() {
+                fs::remove_dir(path)
+            } else {
+                fs::remove_file(path)
+            };
+
+            if let Err(err) = res {
+                warn!(logger, "Failed to remove unpacked archive member: {err}");
+            }
+        }
+    }
+
+    res
+}
+
+fn unpack_archive_members(
+    logger: &Logger,
+    reader: impl io::Read,
+    subpath: &FileSubPath,
+    dst: &Path,
+    limits: ArchiveLimits,
+    created: &mut Vec<PathBuf>,
+) -> crate::Result<()> {
+    create_dirs(dst, created)?;
+
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+
+    let mut archive = tar::Archive::new(reader);
+
+    for (i, entry) in archive.entries()?.enumerate() {
+        if i >= limits.max_members {
+            return Err(Error::TransferLimitsExceeded);
+        }
+
+        let mut entry = entry?;
+        let member = entry.path()?.into_owned();
+
+        let mut member_subpath = subpath.clone();
+        let mut location = dst.to_path_buf();
+        let mut depth = 0;
+
+        for cmp in member.components() {
+            match cmp {
+                Component::Normal(name) => {
+                    member_subpath = member_subpath.append_file_name(name)?;
+                    location.push(crate::utils::normalize_filename(member_subpath.name()));
+                    depth += 1;
+                }
+                Component::CurDir => (),
+                _ => {
+                    return Err(Error::BadPath(
+                        "Archive member path contains disallowed element".into(),
+                    ))
+                }
+            }
+        }
+
+        // The root directory itself
+        if location == dst {
+            continue;
+        }
+
+        if depth > limits.dir_depth_limit {
+            return Err(Error::TransferLimitsExceeded);
+        }
+
+        validate_subpath_for_download(&member_subpath)?;
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => create_dirs(&location, created)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                if let Some(parent) = location.parent() {
+                    create_dirs(parent, created)?;
+                }
+
+                // Do not overwrite anything, same as with the ordinary files
+                let (mut file, location) = crate::utils::filepath_variants(&location)?
+                    .find_map(|path| match opts.open(&path) {
+                        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => None,
+                        res => Some(res.map(|file| (file, path))),
+                    })
+                    .expect("File paths iterator should never end")?;
+
+                created.push(location.clone());
+                io::copy(&mut entry, &mut file)?;
+
+                if let Err(err) = location.quarantine() {
+                    error!(logger, "Failed to quarantine unpacked file: {err}");
+                }
+            }
+            kind => {
+                debug!(logger, "Skipping archive member of type: {kind:?}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Creates the directory together with the missing parents, recording the
+// directories that did not exist before
+fn create_dirs(path: &Path, created: &mut Vec<PathBuf>) -> io::Result<()> {
+    let missing: Vec<_> = path.ancestors().take_while(|dir| !dir.exists()).collect();
+
+    for dir in missing.into_iter().rev() {
+        match fs::create_dir(dir) {
+            Ok(()) => created.push(dir.to_path_buf()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
 /// Check file ID for illegal characters so that the temp file is not created in
 /// parent directories
 fn validate_file_id_for_download(file_id: &FileId) -> crate::Result<()> {
@@ -1185,8 +1366,25 @@ pub async fn handle_finish_xfer_state(state: FinishTransferState<IncomingTransfe
 
 #[cfg(test)]
 mod tests {
+    use super::FileChunk;
     use crate::{file::FileSubPath, FileId};
 
+    #[test]
+    fn chunk_size_overflow() {
+        assert!(matches!(
+            super::received_after_chunk(1, &FileChunk::Zeros(u64::MAX), 1024),
+            Err(crate::Error::MismatchedSize)
+        ));
+        assert!(matches!(
+            super::received_after_chunk(1024, &FileChunk::Zeros(1), 1024),
+            Err(crate::Error::MismatchedSize)
+        ));
+        assert_eq!(
+            super::received_after_chunk(512, &FileChunk::Zeros(512), 1024).unwrap(),
+            1024
+        );
+    }
+
     #[test]
     fn validate_subpath() {
         let sp = FileSubPath::from_path("abc/dfg/hjk.txt").unwrap();
@@ -1237,4 +1435,90 @@ mod tests {
             Err(crate::Error::BadFileId)
         ));
     }
+
+    #[test]
+    fn unpack_archive() {
+        fn archive(name: &str, data: &[u8]) -> Vec<u8> {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(data.len() as _);
+            header.set_cksum();
+
+            let mut builder = tar::Builder::new(Vec::new());
+            builder.append(&header, data).unwrap();
+            builder.into_inner().unwrap()
+        }
+
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let limits = super::ArchiveLimits {
+            dir_depth_limit: 5,
+            max_members: 100,
+        };
+        let dir = tempfile::tempdir().expect("Failed to create tmp dir");
+        let subpath = FileSubPath::from_path("root").unwrap();
+        let dst = dir.path().join("root");
+
+        let data = archive("sub/a.txt", b"hello");
+        super::unpack_archive(&logger, &data[..], &subpath, &dst, limits).unwrap();
+        assert_eq!(
+            std::fs::read(dst.join("sub").join("a.txt")).unwrap(),
+            b"hello"
+        );
+
+        // Existing files are not overwritten
+        super::unpack_archive(&logger, &data[..], &subpath, &dst, limits).unwrap();
+        assert_eq!(
+            std::fs::read(dst.join("sub").join("a(1).txt")).unwrap(),
+            b"hello"
+        );
+
+        // Members cannot escape the destination
+        for name in ["../evil.txt", "/evil.txt", "sub/../../evil.txt"] {
+            let data = archive(name, b"evil");
+            assert!(matches!(
+                super::unpack_archive(&logger, &data[..], &subpath, &dst, limits),
+                Err(crate::Error::BadPath(..))
+            ));
+        }
+        assert!(!dir.path().join("evil.txt").exists());
+
+        // The limits apply and nothing is left behind on failure
+        let limits = super::ArchiveLimits {
+            dir_depth_limit: 2,
+            ..limits
+        };
+        let dst = dir.path().join("limited");
+
+        let data = archive("a/b/c.txt", b"deep");
+        assert!(matches!(
+            super::unpack_archive(&logger, &data[..], &subpath, &dst, limits),
+            Err(crate::Error::TransferLimitsExceeded)
+        ));
+        assert!(!dst.exists());
+
+        let limits = super::ArchiveLimits {
+            max_members: 1,
+            ..limits
+        };
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["a/1.txt", "a/2.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(name).unwrap();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(1);
+            header.set_cksum();
+            builder.append(&header, &b"x"[..]).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        assert!(matches!(
+            super::unpack_archive(&logger, &data[..], &subpath, &dst, limits),
+            Err(crate::Error::TransferLimitsExceeded)
+        ));
+        assert!(!dst.exists());
+    }
 }
@@ -1,10 +1,8 @@
 use std::{
     cmp::Ordering,
     collections::{hash_map::Entry, HashMap},
-    fs,
     future::Future,
     net::IpAddr,
-    path::PathBuf,
     sync::Arc,
 };
 
@@ -12,7 +10,7 @@ use anyhow::Context;
 use async_cell::sync::AsyncCell;
 use drop_config::DropConfig;
 use drop_core::Status;
-use slog::{debug, error, info, warn};
+use slog::{debug, error, info, o, warn};
 use tokio::{
     sync::mpsc::{self, Sender, UnboundedSender},
     task::{AbortHandle, JoinSet},
@@ -22,30 +20,33 @@ use warp::ws::Message;
 use super::{
     handler::{self, MsgToSend},
     socket::WebSocket,
-    TmpFileState,
+    FileChunk, TmpFileState,
 };
 use crate::{
-    file::{self, FileToRecv},
+    file::{ChecksumAlgorithm, FileToRecv},
     manager::FileTerminalState,
-    protocol::v6 as prot,
+    protocol::{v7 as prot, Version},
     service::State,
     tasks::AliveGuard,
     transfer::{IncomingTransfer, Transfer},
-    utils::{self, Hidden},
+    utils,
     ws::events::FileEventTx,
     File, FileId,
 };
 
 pub struct HandlerInit<'a> {
+    version: Version,
     peer: IpAddr,
     state: Arc<State>,
     logger: &'a slog::Logger,
     alive: &'a AliveGuard,
 }
 
-pub struct HandlerLoop<'a> {
+pub struct HandlerLoop {
+    version: Version,
+    algorithm: ChecksumAlgorithm,
     state: Arc<State>,
-    logger: &'a slog::Logger,
+    logger: slog::Logger,
     msg_tx: Sender<MsgToSend>,
     xfer: Arc<IncomingTransfer>,
     jobs: HashMap<FileId, FileTask>,
@@ -53,29 +54,32 @@ pub struct HandlerLoop<'a> {
 }
 
 struct Downloader {
+    version: Version,
+    algorithm: ChecksumAlgorithm,
     logger: slog::Logger,
     file_id: FileId,
     msg_tx: Sender<MsgToSend>,
     csum_rx: mpsc::Receiver<prot::ReportChsum>,
     full_csum: Arc<AsyncCell<[u8; 32]>>,
-    offset: u64,
 }
 
 struct FileTask {
     job: AbortHandle,
-    chunks_tx: UnboundedSender<Vec<u8>>,
+    chunks_tx: UnboundedSender<FileChunk>,
     events: Arc<FileEventTx<IncomingTransfer>>,
     csum_tx: mpsc::Sender<prot::ReportChsum>,
 }
 
 impl<'a> HandlerInit<'a> {
     pub(crate) fn new(
+        version: Version,
         peer: IpAddr,
         state: Arc<State>,
         logger: &'a slog::Logger,
         alive: &'a AliveGuard,
     ) -> Self {
         Self {
+            version,
             peer,
             state,
             logger,
@@ -87,7 +91,7 @@ impl<'a> HandlerInit<'a> {
 #[async_trait::async_trait]
 impl<'a> handler::HandlerInit for HandlerInit<'a> {
     type Request = (prot::TransferRequest, IpAddr, Arc<DropConfig>);
-    type Loop = HandlerLoop<'a>;
+    type Loop = HandlerLoop;
     type Pinger = tokio::time::Interval;
 
     async fn recv_req(&mut self, ws: &mut WebSocket) -> anyhow::Result<Self::Request> {
@@ -102,9 +106,10 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
         let msg = msg.to_str().ok().context("Expected JSON message")?;
         debug!(self.logger, "Request received:\n\t{msg}");
 
-        let req = serde_json::from_str(msg).context("Failed to deserialize transfer request")?;
+        let req = prot::TransferRequest::from_json(msg, self.version)
+            .context("Failed to deserialize transfer request")?;
 
-        Ok((req, self.peer, self.state.config.clone()))
+        Ok((req, self.peer, self.state.config()))
     }
 
     async fn on_error(&mut self, ws: &mut WebSocket, err: anyhow::Error) -> anyhow::Result<()> {
@@ -113,7 +118,7 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
             msg: err.to_string(),
         });
 
-        ws.send(Message::from(&msg))
+        ws.send(Message::text(msg.to_json(self.version)?))
             .await
             .context("Failed to send error message")?;
         Ok(())
@@ -126,6 +131,12 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
         msg_tx: Sender<MsgToSend>,
         xfer: Arc<IncomingTransfer>,
     ) -> Option<Self::Loop> {
+        // Only SHA-256 is supported by V6 peers
+        let algorithm = match self.version {
+            Version::V6 => ChecksumAlgorithm::Sha256,
+            Version::V7 => self.state.config().checksum_algorithm,
+        };
+
         let task = async {
             let checksums = self.state.storage.fetch_checksums(xfer.id()).await;
 
@@ -134,7 +145,10 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
 
             for (xfile, csum_bytes) in checksums.into_iter().filter_map(|csum| {
                 let xfile = xfer.files().get(&csum.file_id)?;
-                Some((xfile, csum.checksum))
+
+                // Checksums calculated with a different algorithm are useless
+                let csum_bytes = csum.checksum.filter(|_| csum.algorithm == Some(algorithm));
+                Some((xfile, csum_bytes))
             }) {
                 let acell = checksum_map
                     .entry(xfile.id().clone())
@@ -167,11 +181,13 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
         };
 
         let Self {
+            version,
             peer: _,
             state,
             logger,
             alive,
         } = self;
+        let logger = &logger.new(o!("transfer_id" => %xfer.id()));
 
         // task responsible for requesting the checksum
         let req_file_checksums = {
@@ -187,9 +203,18 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
                     let msg = prot::ReqChsum {
                         file: xfile.id().clone(),
                         limit: xfile.size(),
+                        algorithm,
                     };
                     let msg = prot::ServerMsg::ReqChsum(msg);
-                    if let Err(err) = msg_tx.send((&msg).into()).await {
+                    let json = match msg.to_json(version) {
+                        Ok(json) => json,
+                        Err(err) => {
+                            warn!(logger, "Failed to request checksum: {err}");
+                            continue;
+                        }
+                    };
+
+                    if let Err(err) = msg_tx.send(Message::text(json).into()).await {
                         warn!(logger, "Failed to request checksum: {err}");
                     }
                 }
@@ -199,11 +224,13 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
         jobs.spawn(req_file_checksums);
 
         Some(HandlerLoop {
+            version,
+            algorithm,
             state,
             msg_tx,
             xfer,
             jobs: HashMap::new(),
-            logger,
+            logger: logger.clone(),
             checksums,
         })
     }
@@ -213,12 +240,12 @@ impl<'a> handler::HandlerInit for HandlerInit<'a> {
     }
 }
 
-impl HandlerLoop<'_> {
+impl HandlerLoop {
     async fn on_chunk(
         &mut self,
         socket: &mut WebSocket,
         file_id: FileId,
-        chunk: Vec<u8>,
+        chunk: FileChunk,
     ) -> anyhow::Result<()> {
         if let Some(task) = self.jobs.get(&file_id) {
             if let Err(err) = task.chunks_tx.send(chunk) {
@@ -228,7 +255,9 @@ impl HandlerLoop<'_> {
                 };
 
                 socket
-                    .send(Message::from(&prot::ServerMsg::Error(msg)))
+                    .send(Message::text(
+                        prot::ServerMsg::Error(msg).to_json(self.version)?,
+                    ))
                     .await?;
             }
         }
@@ -276,7 +305,7 @@ impl HandlerLoop<'_> {
                     .await;
 
                 super::remove_temp_files(
-                    self.logger,
+                    &self.logger,
                     self.xfer.id(),
                     tmp_bases.into_iter().map(|base| (base, &file_id)),
                 );
@@ -347,6 +376,23 @@ impl HandlerLoop<'_> {
             None => return,
         };
 
+        if report.algorithm != self.algorithm {
+            warn!(
+                self.logger,
+                "Client reported checksum using {} while {} was requested, failing the file",
+                report.algorithm,
+                self.algorithm
+            );
+
+            // Nothing else would answer the checksum request
+            self.fail_file(
+                &report.file,
+                crate::Error::UnsupportedChecksumAlgorithm(report.algorithm),
+            )
+            .await;
+            return;
+        }
+
         // Full checksum requsted at the begining of the transfer
         if report.limit == xfile.size() {
             self.checksums
@@ -360,7 +406,12 @@ impl HandlerLoop<'_> {
 
             tokio::spawn(async move {
                 storage
-                    .save_checksum(transfer_id, file_id.as_ref(), &report.checksum)
+                    .save_checksum(
+                        transfer_id,
+                        file_id.as_ref(),
+                        report.algorithm,
+                        &report.checksum,
+                    )
                     .await;
             });
         // Requests made by the download task
@@ -374,6 +425,41 @@ impl HandlerLoop<'_> {
         }
     }
 
+    // Fails the file on our side and lets the sender know
+    async fn fail_file(&mut self, file_id: &FileId, err: crate::Error) {
+        let msg = prot::ServerMsg::Error(prot::Error {
+            file: Some(file_id.clone()),
+            msg: err.to_string(),
+        });
+        match msg.to_json(self.version) {
+            Ok(json) => {
+                if let Err(err) = self.msg_tx.send(Message::text(json).into()).await {
+                    warn!(self.logger, "Failed to report file failure: {err}");
+                }
+            }
+            Err(err) => warn!(self.logger, "Failed to report file failure: {err}"),
+        }
+
+        let status = Status::from(&err);
+        match self
+            .state
+            .transfer_manager
+            .incoming_terminal_recv(self.xfer.id(), file_id, FileTerminalState::Failed)
+            .await
+        {
+            Err(err) => {
+                warn!(self.logger, "Failed to accept failure: {err}");
+            }
+            Ok(Some(res)) => {
+                res.file_events.failed(err).await;
+                super::handle_finish_xfer_state(res.xfer_state, true).await;
+            }
+            Ok(None) => (),
+        }
+
+        self.stop_task(file_id, status).await;
+    }
+
     fn take_pause_futures(&mut self) -> impl Future<Output = ()> {
         let jobs = std::mem::take(&mut self.jobs);
 
@@ -388,7 +474,7 @@ impl HandlerLoop<'_> {
 }
 
 #[async_trait::async_trait]
-impl handler::HandlerLoop for HandlerLoop<'_> {
+impl handler::HandlerLoop for HandlerLoop {
     async fn start_download(&mut self, ctx: super::FileStreamCtx<'_>) -> anyhow::Result<()> {
         let is_running = self
             .jobs
@@ -409,12 +495,13 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         let (csum_tx, csum_rx) = mpsc::channel(4);
 
         let downloader = Downloader {
+            version: self.version,
+            algorithm: self.algorithm,
             file_id: ctx.task.file.id().clone(),
             msg_tx: self.msg_tx.clone(),
-            logger: self.logger.clone(),
+            logger: self.logger.new(o!("file_id" => %ctx.task.file.id())),
             csum_rx,
             full_csum: full_csum_cell,
-            offset: 0,
         };
 
         let file_id = ctx.task.file.id().clone();
@@ -441,7 +528,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         let msg = prot::ServerMsg::Reject(prot::Reject {
             file: file_id.clone(),
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::text(msg.to_json(self.version)?))
+            .await?;
 
         self.stop_task(&file_id, Status::FileRejected).await;
 
@@ -453,7 +542,7 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             .await;
 
         super::remove_temp_files(
-            self.logger,
+            &self.logger,
             self.xfer.id(),
             tmp_bases.into_iter().map(|base| (base, &file_id)),
         );
@@ -471,7 +560,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             file: Some(file_id),
             msg,
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::text(msg.to_json(self.version)?))
+            .await?;
 
         Ok(())
     }
@@ -483,7 +574,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             file: file_id,
             bytes_transfered: file.size(),
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::text(msg.to_json(self.version)?))
+            .await?;
         Ok(())
     }
 
@@ -497,7 +590,9 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             file: file_id.clone(),
             offset,
         });
-        socket.send(Message::from(&msg)).await?;
+        socket
+            .send(Message::text(msg.to_json(self.version)?))
+            .await?;
         Ok(())
     }
 
@@ -511,15 +606,18 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         futures::future::join_all(tasks).await;
     }
 
-    async fn on_text_msg(&mut self, _: &mut WebSocket, text: &str) -> anyhow::Result<()> {
-        let msg: prot::ClientMsg =
-            serde_json::from_str(text).context("Failed to deserialize json")?;
+    async fn on_text_msg(&mut self, ws: &mut WebSocket, text: &str) -> anyhow::Result<()> {
+        let msg =
+            prot::ClientMsg::from_json(text, self.version).context("Failed to deserialize json")?;
 
         match msg {
             prot::ClientMsg::Error(prot::Error { file, msg }) => self.on_error(file, msg).await,
             prot::ClientMsg::Cancel(prot::Cancel { file }) => self.on_cancel(file).await,
             prot::ClientMsg::ReportChsum(report) => self.on_checksum(report).await,
             prot::ClientMsg::Reject(prot::Reject { file }) => self.on_reject(file).await,
+            prot::ClientMsg::Zeros(prot::Zeros { file, len }) => {
+                self.on_chunk(ws, file, FileChunk::Zeros(len)).await?
+            }
         }
         Ok(())
     }
@@ -528,7 +626,7 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
         let prot::Chunk { file, data } =
             prot::Chunk::decode(bytes).context("Failed to decode file chunk")?;
 
-        self.on_chunk(ws, file, data).await?;
+        self.on_chunk(ws, file, FileChunk::Data(data)).await?;
 
         Ok(())
     }
@@ -543,7 +641,7 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
             .await;
 
         super::remove_temp_files(
-            self.logger,
+            &self.logger,
             self.xfer.id(),
             files
                 .into_iter()
@@ -558,7 +656,7 @@ impl handler::HandlerLoop for HandlerLoop<'_> {
     }
 }
 
-impl Drop for HandlerLoop<'_> {
+impl Drop for HandlerLoop {
     fn drop(&mut self) {
         debug!(self.logger, "Stopping server handler");
         tokio::spawn(self.take_pause_futures());
@@ -566,9 +664,9 @@ impl Drop for HandlerLoop<'_> {
 }
 
 impl Downloader {
-    async fn send(&mut self, msg: impl Into<Message>) -> crate::Result<()> {
+    async fn send(&mut self, msg: &prot::ServerMsg) -> crate::Result<()> {
         self.msg_tx
-            .send(msg.into().into())
+            .send(Message::text(msg.to_json(self.version)?).into())
             .await
             .map_err(|_| crate::Error::Canceled)
     }
@@ -577,8 +675,9 @@ impl Downloader {
         let msg = prot::ServerMsg::ReqChsum(prot::ReqChsum {
             file: self.file_id.clone(),
             limit,
+            algorithm: self.algorithm,
         });
-        self.send(Message::from(&msg)).await?;
+        self.send(&msg).await?;
 
         let report = self.csum_rx.recv().await.ok_or(crate::Error::Canceled)?;
 
@@ -594,14 +693,14 @@ impl handler::Downloader for Downloader {
         tmpstate: Option<TmpFileState>,
     ) -> crate::Result<handler::DownloadInit> {
         match tmpstate {
-            Some(TmpFileState { meta, csum }) => {
-                self.offset = match meta.len().cmp(&task.file.size()) {
+            Some(TmpFileState { len, csum }) => {
+                let offset = match len.cmp(&task.file.size()) {
                     Ordering::Less => {
-                        let report = self.request_csum(meta.len()).await?;
+                        let report = self.request_csum(len).await?;
 
-                        if report.limit == meta.len() && report.checksum == csum {
+                        if report.limit == len && report.checksum == csum {
                             // All matches, we can continue with temp file
-                            meta.len()
+                            len
                         } else {
                             info!(
                                 self.logger,
@@ -614,7 +713,7 @@ impl handler::Downloader for Downloader {
                     Ordering::Equal => {
                         if self.full_csum.get().await == csum {
                             // All matches the temp file is actually the full file
-                            meta.len()
+                            len
                         } else {
                             info!(
                                 self.logger,
@@ -636,22 +735,14 @@ impl handler::Downloader for Downloader {
                     }
                 };
 
-                Ok(handler::DownloadInit::Stream {
-                    offset: self.offset,
-                })
+                Ok(handler::DownloadInit::Stream { offset })
             }
             None => Ok(handler::DownloadInit::Stream { offset: 0 }),
         }
     }
 
-    async fn open(&mut self, path: &Hidden<PathBuf>) -> crate::Result<fs::File> {
-        let file = if self.offset == 0 {
-            fs::File::create(&path.0)?
-        } else {
-            fs::File::options().append(true).open(&path.0)?
-        };
-
-        Ok(file)
+    fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
     }
 
     async fn progress(&mut self, bytes: u64) -> crate::Result<()> {
@@ -662,20 +753,8 @@ impl handler::Downloader for Downloader {
         .await
     }
 
-    async fn validate<F, Fut>(
-        &mut self,
-        path: &Hidden<PathBuf>,
-        progress_cb: Option<F>,
-        event_granularity: Option<u64>,
-    ) -> crate::Result<()>
-    where
-        F: FnMut(u64) -> Fut + Send + Sync,
-        Fut: Future<Output = ()> + Send,
-    {
-        let file = std::fs::File::open(&path.0)?;
-        let csum = file::checksum(file, progress_cb, event_granularity).await?;
-
-        if self.full_csum.get().await != csum {
+    async fn validate(&mut self, checksum: &[u8; 32]) -> crate::Result<()> {
+        if self.full_csum.get().await != *checksum {
             return Err(crate::Error::ChecksumMismatch);
         }
 
@@ -697,7 +776,13 @@ fn map_files(files: Vec<prot::File>) -> anyhow::Result<Vec<FileToRecv>> {
 
     let mut used_mappings = HashMap::new();
 
-    for prot::File { mut path, id, size } in files {
+    for prot::File {
+        mut path,
+        id,
+        size,
+        archive,
+    } in files
+    {
         let uroot = path.root();
         let nroot = utils::normalize_filename(uroot);
 
@@ -726,7 +811,7 @@ fn map_files(files: Vec<prot::File>) -> anyhow::Result<Vec<FileToRecv>> {
             *piter.next().context("Subpath should always contain root")? = nroot;
             piter.for_each(|s| *s = utils::normalize_filename(&*s));
 
-            out.push(FileToRecv::new(id, path, size));
+            out.push(FileToRecv::new(id, path, size, archive));
             break;
         }
     }
@@ -747,16 +832,19 @@ mod tests {
                 path: FileSubPath::from("a/b"),
                 id: FileId::from("id1"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from("b"),
                 id: FileId::from("id2"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from("c"),
                 id: FileId::from("id3"),
                 size: 0,
+                archive: false,
             },
         ];
         let output = map_files(input).unwrap();
@@ -771,11 +859,13 @@ mod tests {
                 path: FileSubPath::from("a/b"),
                 id: FileId::from("id1"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from("a/c"),
                 id: FileId::from("id2"),
                 size: 0,
+                archive: false,
             },
         ];
         let output = map_files(input).unwrap();
@@ -789,21 +879,25 @@ mod tests {
                 path: FileSubPath::from("</a"),
                 id: FileId::from("id1"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from("</b"),
                 id: FileId::from("id2"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from(">/c"),
                 id: FileId::from("id3"),
                 size: 0,
+                archive: false,
             },
             prot::File {
                 path: FileSubPath::from(">/d"),
                 id: FileId::from("id4"),
                 size: 0,
+                archive: false,
             },
         ];
         let output = map_files(input).unwrap();
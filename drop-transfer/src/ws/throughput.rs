@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{event::Throughput, File, FileId, Transfer};
+
+// The time constant of the exponential smoothing. The older samples fade out
+// after about that long
+const SMOOTHING_WINDOW: Duration = Duration::from_secs(5);
+// Samples closer to each other are accumulated into a single one, otherwise
+// the bursts of the small chunks make the rate jumpy
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Exponentially weighted moving average of the bytes per second rate
+#[derive(Default)]
+pub(super) struct RateEstimator {
+    last: Option<(Instant, u64)>,
+    rate: Option<f64>,
+}
+
+impl RateEstimator {
+    /// Starts measuring from the given amount of bytes. Call on (re)start so
+    /// that the already transferred part does not count as the throughput
+    pub fn reset(&mut self, now: Instant, total: u64) {
+        self.last = Some((now, total));
+        self.rate = None;
+    }
+
+    /// Feeds the total amount of bytes transferred so far and returns the
+    /// smoothed rate in bytes per second
+    pub fn update(&mut self, now: Instant, total: u64) -> u64 {
+        let (last_time, last_total) = match self.last {
+            Some((time, bytes)) if bytes <= total => (time, bytes),
+            _ => {
+                self.reset(now, total);
+                return 0;
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(last_time);
+        if elapsed < MIN_SAMPLE_INTERVAL {
+            return self.bytes_per_sec();
+        }
+
+        let sample = (total - last_total) as f64 / elapsed.as_secs_f64();
+        let rate = match self.rate {
+            Some(rate) => {
+                let alpha = 1.0 - (-elapsed.as_secs_f64() / SMOOTHING_WINDOW.as_secs_f64()).exp();
+                rate + alpha * (sample - rate)
+            }
+            None => sample,
+        };
+
+        self.last = Some((now, total));
+        self.rate = Some(rate);
+
+        self.bytes_per_sec()
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.rate.map_or(0, |rate| rate.round() as u64)
+    }
+}
+
+impl Throughput {
+    pub(super) fn new(bytes_per_sec: u64, remaining: u64) -> Self {
+        let eta = if remaining == 0 {
+            Some(Duration::ZERO)
+        } else if bytes_per_sec == 0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                remaining as f64 / bytes_per_sec as f64,
+            ))
+        };
+
+        Self { bytes_per_sec, eta }
+    }
+}
+
+struct FileEntry {
+    size: u64,
+    transferred: u64,
+    // Failed or rejected files are not going to be transferred anymore
+    excluded: bool,
+}
+
+/// Aggregates the progress of all the files within a transfer
+pub(super) struct TransferMeter {
+    files: HashMap<FileId, FileEntry>,
+    // Sum of the progress deltas. Grows monotonically, unlike the transferred
+    // bytes which jump on resume
+    counted: u64,
+    rate: RateEstimator,
+}
+
+impl TransferMeter {
+    pub fn new(xfer: &impl Transfer) -> Self {
+        let files = xfer
+            .files()
+            .values()
+            .map(|file| {
+                (
+                    file.id().clone(),
+                    FileEntry {
+                        size: file.size(),
+                        transferred: 0,
+                        excluded: false,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            files,
+            counted: 0,
+            rate: RateEstimator::default(),
+        }
+    }
+
+    /// The file (re)starts from the given offset
+    pub fn start(&mut self, file_id: &FileId, offset: u64) {
+        if let Some(entry) = self.files.get_mut(file_id) {
+            entry.transferred = offset;
+        }
+    }
+
+    pub fn progress(&mut self, now: Instant, file_id: &FileId, transferred: u64) {
+        if let Some(entry) = self.files.get_mut(file_id) {
+            self.counted += transferred.saturating_sub(entry.transferred);
+            entry.transferred = transferred;
+        }
+
+        self.rate.update(now, self.counted);
+    }
+
+    pub fn finish(&mut self, file_id: &FileId, success: bool) {
+        if let Some(entry) = self.files.get_mut(file_id) {
+            if success {
+                entry.transferred = entry.size;
+            } else {
+                entry.excluded = true;
+            }
+        }
+    }
+
+    /// The bytes transferred across all the files
+    pub fn transferred(&self) -> u64 {
+        self.active().map(|entry| entry.transferred).sum()
+    }
+
+    /// The bytes left across all the files
+    pub fn remaining(&self) -> u64 {
+        self.active()
+            .map(|entry| entry.size.saturating_sub(entry.transferred))
+            .sum()
+    }
+
+    pub fn throughput(&self) -> Throughput {
+        Throughput::new(self.rate.bytes_per_sec(), self.remaining())
+    }
+
+    fn active(&self) -> impl Iterator<Item = &FileEntry> {
+        self.files.values().filter(|entry| !entry.excluded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_smoothing() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut rate = RateEstimator::default();
+        rate.reset(start, 1000);
+
+        // The first sample is taken as is
+        assert_eq!(rate.update(at(1000), 2000), 1000);
+
+        // The samples too close to each other are accumulated
+        assert_eq!(rate.update(at(1050), 10_000), 1000);
+
+        // A sudden burst is smoothed out
+        let burst = rate.update(at(2000), 12_000);
+        assert!(burst > 1000 && burst < 10_000, "{burst}");
+
+        // The steady rate is reached eventually
+        let mut total = 12_000;
+        for sec in 3..60 {
+            total += 1000;
+            rate.update(at(sec * 1000), total);
+        }
+        assert_eq!(rate.bytes_per_sec(), 1000);
+
+        // Going backwards restarts the measurement
+        assert_eq!(rate.update(at(61_000), 0), 0);
+    }
+
+    #[test]
+    fn eta() {
+        assert_eq!(Throughput::new(0, 100).eta, None);
+        assert_eq!(Throughput::new(0, 0).eta, Some(Duration::ZERO));
+        assert_eq!(Throughput::new(50, 100).eta, Some(Duration::from_secs(2)));
+    }
+}
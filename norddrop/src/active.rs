@@ -0,0 +1,89 @@
+use drop_transfer::snapshot;
+
+pub enum TransferDirection {
+    Incoming,
+    Outgoing,
+}
+
+pub enum FileActivity {
+    Idle,
+    Pending,
+    Throttled,
+    Preparing,
+    InFlight,
+    Completed,
+    Rejected,
+    Failed,
+}
+
+pub struct ActiveFile {
+    pub id: String,
+    pub path: String,
+    pub size: u64,
+    pub transferred: u64,
+    pub activity: FileActivity,
+}
+
+pub struct ActiveTransfer {
+    pub id: String,
+    pub peer: String,
+    pub direction: TransferDirection,
+    pub connected: bool,
+    pub protocol_version: Option<u32>,
+    pub reconnects: u32,
+    pub connection_retries: u32,
+    pub files: Vec<ActiveFile>,
+}
+
+impl From<snapshot::Direction> for TransferDirection {
+    fn from(value: snapshot::Direction) -> Self {
+        match value {
+            snapshot::Direction::Incoming => Self::Incoming,
+            snapshot::Direction::Outgoing => Self::Outgoing,
+        }
+    }
+}
+
+impl From<snapshot::FileActivity> for FileActivity {
+    fn from(value: snapshot::FileActivity) -> Self {
+        use snapshot::FileActivity::*;
+
+        match value {
+            Idle => Self::Idle,
+            Pending => Self::Pending,
+            Throttled => Self::Throttled,
+            Preparing => Self::Preparing,
+            InFlight => Self::InFlight,
+            Completed => Self::Completed,
+            Rejected => Self::Rejected,
+            Failed => Self::Failed,
+        }
+    }
+}
+
+impl From<snapshot::FileSnapshot> for ActiveFile {
+    fn from(value: snapshot::FileSnapshot) -> Self {
+        Self {
+            id: value.id.to_string(),
+            path: value.subpath,
+            size: value.size,
+            transferred: value.transferred,
+            activity: value.activity.into(),
+        }
+    }
+}
+
+impl From<snapshot::TransferSnapshot> for ActiveTransfer {
+    fn from(value: snapshot::TransferSnapshot) -> Self {
+        Self {
+            id: value.id.to_string(),
+            peer: value.peer.to_string(),
+            direction: value.direction.into(),
+            connected: value.connected,
+            protocol_version: value.protocol_version.map(|ver| ver as _),
+            reconnects: value.reconnects,
+            connection_retries: value.connection_retries,
+            files: value.files.into_iter().map(From::from).collect(),
+        }
+    }
+}
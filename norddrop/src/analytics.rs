@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use drop_analytics as da;
+
+use crate::{Analytics, TransferDirection};
+
+pub enum AnalyticsFilePhase {
+    Paused,
+    Finished,
+}
+
+pub struct InitAnalyticsEvent {
+    pub init_duration: i32,
+    pub result: i32,
+}
+
+pub struct TransferIntentAnalyticsEvent {
+    pub transfer_id: String,
+    pub file_count: i32,
+    pub transfer_size: i32,
+    pub path_ids: String,
+    pub file_sizes: String,
+    pub extensions: String,
+    pub mime_types: String,
+}
+
+pub struct TransferIntentReceivedAnalyticsEvent {
+    pub transfer_id: String,
+}
+
+pub struct TransferStateAnalyticsEvent {
+    pub protocol_version: i32,
+    pub transfer_id: String,
+    pub result: i32,
+    pub reconnects: i32,
+}
+
+pub struct TransferFileAnalyticsEvent {
+    pub phase: AnalyticsFilePhase,
+    pub transfer_id: String,
+    pub transfer_time: i32,
+    pub path_id: String,
+    pub direction: TransferDirection,
+    pub transferred: i32,
+    pub result: i32,
+    pub resume_offset: i32,
+    pub file_size: i32,
+    pub throttle_time: i32,
+    pub verify_checksum_time: i32,
+    pub finalize_checksum_time: i32,
+}
+
+pub struct DeveloperExceptionAnalyticsEvent {
+    pub code: i32,
+    pub note: String,
+    pub message: String,
+    pub name: String,
+}
+
+pub struct DeveloperExceptionWithValueAnalyticsEvent {
+    pub arbitrary_value: i32,
+    pub code: i32,
+    pub note: String,
+    pub message: String,
+    pub name: String,
+}
+
+/// Forwards the analytics events to the host app instead of the compiled-in
+/// backend
+pub(crate) struct HostAnalytics(Arc<dyn Analytics>);
+
+impl HostAnalytics {
+    pub(crate) fn new(analytics: Arc<dyn Analytics>) -> Self {
+        Self(analytics)
+    }
+}
+
+impl da::Moose for HostAnalytics {
+    fn event_init(&self, data: da::InitEventData) {
+        self.0.event_init(InitAnalyticsEvent {
+            init_duration: data.init_duration,
+            result: data.result,
+        });
+    }
+
+    fn event_transfer_intent(&self, data: da::TransferIntentEventData) {
+        self.0.event_transfer_intent(TransferIntentAnalyticsEvent {
+            transfer_id: data.transfer_id,
+            file_count: data.file_count,
+            transfer_size: data.transfer_size,
+            path_ids: data.path_ids,
+            file_sizes: data.file_sizes,
+            extensions: data.extensions,
+            mime_types: data.mime_types,
+        });
+    }
+
+    fn event_transfer_intent_received(&self, data: da::TransferIntentReceivedEventData) {
+        self.0
+            .event_transfer_intent_received(TransferIntentReceivedAnalyticsEvent {
+                transfer_id: data.transfer_id,
+            });
+    }
+
+    fn event_transfer_state(&self, data: da::TransferStateEventData) {
+        self.0.event_transfer_state(TransferStateAnalyticsEvent {
+            protocol_version: data.protocol_version,
+            transfer_id: data.transfer_id,
+            result: data.result,
+            reconnects: data.reconnects,
+        });
+    }
+
+    fn event_transfer_file(&self, data: da::TransferFileEventData) {
+        self.0.event_transfer_file(TransferFileAnalyticsEvent {
+            phase: match data.phase {
+                da::TransferFilePhase::Paused => AnalyticsFilePhase::Paused,
+                da::TransferFilePhase::Finished => AnalyticsFilePhase::Finished,
+            },
+            transfer_id: data.transfer_id,
+            transfer_time: data.transfer_time,
+            path_id: data.path_id,
+            direction: match data.direction {
+                da::TransferDirection::Upload => TransferDirection::Outgoing,
+                da::TransferDirection::Download => TransferDirection::Incoming,
+            },
+            transferred: data.transferred,
+            result: data.result,
+            resume_offset: data.resume_offset,
+            file_size: data.file_size,
+            throttle_time: data.throttle_time,
+            verify_checksum_time: data.verify_checksum_time,
+            finalize_checksum_time: data.finalize_checksum_time,
+        });
+    }
+
+    fn developer_exception(&self, data: da::DeveloperExceptionEventData) {
+        self.0
+            .developer_exception(DeveloperExceptionAnalyticsEvent {
+                code: data.code,
+                note: data.note,
+                message: data.message,
+                name: data.name,
+            });
+    }
+
+    fn developer_exception_with_value(&self, data: da::DeveloperExceptionWithValueEventData) {
+        self.0
+            .developer_exception_with_value(DeveloperExceptionWithValueAnalyticsEvent {
+                arbitrary_value: data.arbitrary_value,
+                code: data.code,
+                note: data.note,
+                message: data.message,
+                name: data.name,
+            });
+    }
+}
@@ -1,5 +1,7 @@
 use std::time::Duration;
 
+use drop_core::ChecksumAlgorithm;
+
 #[derive(Debug)]
 pub struct Config {
     pub dir_depth_limit: u64,
@@ -7,10 +9,38 @@ pub struct Config {
     pub moose_event_path: String,
     pub moose_prod: bool,
     pub storage_path: String,
+    pub history_enabled: Option<bool>,
     pub checksum_events_size_threshold: Option<u64>,
     pub checksum_events_granularity: Option<u64>,
     pub connection_retries: Option<u32>,
     pub auto_retry_interval_ms: Option<u32>,
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+    pub preallocate_downloads: Option<bool>,
+    pub archive_directories: Option<bool>,
+    pub progress_events_granularity: Option<u64>,
+    pub progress_events_interval_ms: Option<u32>,
+    pub retention_max_age_ms: Option<u64>,
+    pub retention_max_transfers: Option<u32>,
+    pub retention_terminal_only: Option<bool>,
+    pub analytics_enabled: Option<bool>,
+}
+
+/// A `Config` field breaking one of the validation rules
+#[derive(Debug)]
+pub struct ConfigFieldError {
+    pub field: String,
+    pub rule: String,
+    pub accepted: String,
+}
+
+impl ConfigFieldError {
+    pub(crate) fn new(field: &str, rule: &str, accepted: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            rule: rule.to_string(),
+            accepted: accepted.to_string(),
+        }
+    }
 }
 
 impl Config {
@@ -21,6 +51,10 @@ impl Config {
     const fn default_checksum_granularity() -> u32 {
         256 * 1024
     }
+
+    const fn default_progress_granularity() -> u64 {
+        64 * 1024
+    }
 }
 
 impl From<Config> for drop_config::Config {
@@ -31,10 +65,20 @@ impl From<Config> for drop_config::Config {
             moose_event_path,
             moose_prod,
             storage_path,
+            history_enabled,
             checksum_events_size_threshold,
             checksum_events_granularity,
             connection_retries,
             auto_retry_interval_ms,
+            checksum_algorithm,
+            preallocate_downloads,
+            archive_directories,
+            progress_events_granularity,
+            progress_events_interval_ms,
+            retention_max_age_ms,
+            retention_max_transfers,
+            retention_terminal_only,
+            analytics_enabled,
         } = val;
 
         drop_config::Config {
@@ -42,6 +86,7 @@ impl From<Config> for drop_config::Config {
                 dir_depth_limit: dir_depth_limit as _,
                 transfer_file_limit: transfer_file_limit as _,
                 storage_path,
+                history_enabled: history_enabled.unwrap_or(true),
                 checksum_events_size_threshold: checksum_events_size_threshold.map(|x| x as _),
                 checksum_events_granularity: checksum_events_granularity
                     .unwrap_or(Config::default_checksum_granularity() as _),
@@ -49,10 +94,23 @@ impl From<Config> for drop_config::Config {
                     .unwrap_or(Config::default_connection_retries()),
                 auto_retry_interval: auto_retry_interval_ms
                     .map(|ms| Duration::from_millis(ms as _)),
+                checksum_algorithm: checksum_algorithm.unwrap_or_default(),
+                preallocate_downloads: preallocate_downloads.unwrap_or(false),
+                archive_directories: archive_directories.unwrap_or(false),
+                progress_events_granularity: progress_events_granularity
+                    .unwrap_or(Config::default_progress_granularity()),
+                progress_events_interval: progress_events_interval_ms
+                    .map_or(Duration::ZERO, |ms| Duration::from_millis(ms as _)),
+                retention: drop_config::RetentionPolicy {
+                    max_age: retention_max_age_ms.map(Duration::from_millis),
+                    max_transfers: retention_max_transfers.map(|x| x as _),
+                    terminal_only: retention_terminal_only.unwrap_or(false),
+                },
             },
             moose: drop_config::MooseConfig {
                 event_path: moose_event_path,
                 prod: moose_prod,
+                disabled: !analytics_enabled.unwrap_or(true),
             },
         }
     }
@@ -1,21 +1,31 @@
 use std::{
     net::{IpAddr, ToSocketAddrs},
-    sync::Arc,
-    time::SystemTime,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use drop_analytics::DeveloperExceptionEventData;
 use drop_auth::{PublicKey, SecretKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
-use drop_config::{Config, DropConfig, MooseConfig};
-use drop_storage::types::Transfer as TransferInfo;
-use drop_transfer::{auth, utils::Hidden, Event, FileToSend, OutgoingTransfer, Service, Transfer};
+use drop_config::{Config, MooseConfig};
+use drop_storage::{
+    types::Transfer as TransferInfo, MemoryStorage, Storage, StorageBackend, StorageKey,
+};
+use drop_transfer::{
+    auth, snapshot::TransferSnapshot, utils::Hidden, Event, FileToSend, OutgoingTransfer, Service,
+    Transfer,
+};
 use slog::{debug, error, trace, warn, Logger};
 use tokio::{
     sync::{mpsc, Mutex},
     task::JoinHandle,
 };
 
-use crate::{event, KeyStore, TransferDescriptor};
+use crate::{
+    analytics::HostAnalytics, event, Analytics, KeyStore, StorageKeyStore, TransferDescriptor,
+};
 
 pub type Result<T = ()> = std::result::Result<T, crate::LibdropError>;
 
@@ -28,25 +38,41 @@ pub(super) struct NordDropFFI {
     instance: Arc<Mutex<Option<ServiceData>>>,
     event_dispatcher: EventDispatcher,
     keys: Arc<auth::Context>,
-    config: DropConfig,
+    config: Config,
     #[cfg(unix)]
     fdresolv: Option<Arc<drop_transfer::file::FdResolver>>,
+    storage_keys: Option<Arc<dyn StorageKeyStore>>,
+    analytics: Option<Arc<dyn drop_analytics::Moose>>,
 }
 
 struct ServiceData {
     service: drop_transfer::Service,
     event_task: JoinHandle<()>,
+    // `None` when the history is disabled
+    history: Option<Arc<Storage>>,
+}
+
+impl ServiceData {
+    fn history(&self) -> Result<&Storage> {
+        self.history.as_deref().ok_or(crate::LibdropError::DbError)
+    }
 }
 
 #[derive(Clone)]
 struct EventDispatcher {
     cb: Arc<dyn Fn(crate::Event) + Send + Sync>,
+    // Drop the outdated progress events piled up while the host app was busy
+    coalesce_progress: Arc<AtomicBool>,
 }
 
 impl EventDispatcher {
     fn dispatch(&self, e: impl Into<crate::Event>) {
         (self.cb)(e.into());
     }
+
+    fn coalesces_progress(&self) -> bool {
+        self.coalesce_progress.load(Ordering::Relaxed)
+    }
 }
 
 impl NordDropFFI {
@@ -63,11 +89,14 @@ impl NordDropFFI {
             rt: tokio::runtime::Runtime::new().map_err(|_| crate::LibdropError::Unknown)?,
             event_dispatcher: EventDispatcher {
                 cb: Arc::new(event_cb) as _,
+                coalesce_progress: Arc::default(),
             },
-            config: DropConfig::default(),
+            config: Config::default(),
             keys: Arc::new(create_key_context(logger, key_store)),
             #[cfg(unix)]
             fdresolv: None,
+            storage_keys: None,
+            analytics: None,
         })
     }
 
@@ -80,7 +109,7 @@ impl NordDropFFI {
         );
 
         // Check preconditions first
-        validate_config(&self.logger, &config)?;
+        ensure_valid_config(&self.logger, &config, self.analytics.is_some())?;
         let addr: IpAddr = match listen_addr.parse() {
             Ok(addr) => addr,
             Err(err) => {
@@ -96,14 +125,41 @@ impl NordDropFFI {
 
         // All good, let's proceed
 
-        let moose = initialize_moose(&self.logger, config.moose)?;
+        let moose = initialize_moose(&self.logger, config.moose.clone(), self.analytics.as_ref())?;
+
+        // The database events are dispatched by the event task, the host app may
+        // wait for them on this very thread
+        let mut db_events = Vec::new();
+        let history = if config.drop.history_enabled {
+            let storage_key = self.storage_key()?;
+            match open_database(
+                &config.drop.storage_path,
+                storage_key.as_ref(),
+                &mut db_events,
+                &self.logger,
+                &moose,
+            ) {
+                Ok(storage) => Some(Arc::new(storage)),
+                Err(err) => {
+                    let ed = self.event_dispatcher.clone();
+                    self.rt.spawn_blocking(move || {
+                        db_events.into_iter().for_each(|ev| ed.dispatch(ev))
+                    });
+                    return Err(err);
+                }
+            }
+        } else {
+            debug!(
+                self.logger,
+                "History is disabled, keeping the transfers in memory"
+            );
+            None
+        };
 
-        let storage = Arc::new(open_database(
-            &config.drop.storage_path,
-            &self.event_dispatcher,
-            &self.logger,
-            &moose,
-        )?);
+        let storage: Arc<dyn StorageBackend> = match &history {
+            Some(history) => history.clone(),
+            None => Arc::new(MemoryStorage::new()),
+        };
 
         // Spawn a task grabbing events from the inner service and dispatch them
         // to the host app
@@ -113,17 +169,38 @@ impl NordDropFFI {
         let (tx, mut rx) = mpsc::unbounded_channel::<(Event, SystemTime)>();
 
         let event_task = self.rt.spawn(async move {
-            let mut dispatch = drop_transfer::StorageDispatch::new(&event_storage);
+            let mut dispatch = drop_transfer::StorageDispatch::new(&*event_storage);
+
+            for ev in db_events {
+                tokio::task::block_in_place(|| ed.dispatch(ev));
+            }
 
             while let Some(e) = rx.recv().await {
-                debug!(event_logger, "emitting event: {:#?}", e);
+                let mut batch = vec![e];
+                // The events emitted while the host app handled the previous ones
+                if ed.coalesces_progress() {
+                    while let Ok(e) = rx.try_recv() {
+                        batch.push(e);
+                    }
+                }
+
+                let mut events = Vec::with_capacity(batch.len());
+                for e in batch {
+                    debug!(event_logger, "emitting event: {:#?}", e);
+
+                    dispatch.handle_event(&e.0).await;
+                    events.push(crate::Event::from(e));
+                }
+
+                if ed.coalesces_progress() {
+                    events = crate::poll::coalesce_progress(events);
+                }
 
-                dispatch.handle_event(&e.0).await;
                 // Android team reported problems with the event ordering.
                 // The events where dispatched in different order than where emitted.
                 // To fix that we need to process the events sequentially.
                 // Also the callback may block the executor - we need to be resistant to that.
-                tokio::task::block_in_place(|| ed.dispatch(e));
+                tokio::task::block_in_place(|| events.into_iter().for_each(|e| ed.dispatch(e)));
             }
         });
 
@@ -136,12 +213,14 @@ impl NordDropFFI {
             moose,
             self.keys.clone(),
             init_time,
+            None,
             #[cfg(unix)]
             self.fdresolv.clone(),
         )) {
             Ok(service) => instance.replace(ServiceData {
                 service,
                 event_task,
+                history,
             }),
             Err(err) => {
                 error!(self.logger, "Failed to start the service: {}", err);
@@ -155,7 +234,7 @@ impl NordDropFFI {
             }
         };
 
-        self.config = config.drop;
+        self.config = config;
 
         Ok(())
     }
@@ -188,8 +267,7 @@ impl NordDropFFI {
         let storage = instance
             .as_mut()
             .ok_or(crate::LibdropError::NotStarted)?
-            .service
-            .storage();
+            .history()?;
 
         self.rt.block_on(storage.purge_transfers(transfer_ids));
         Ok(())
@@ -215,8 +293,7 @@ impl NordDropFFI {
         let storage = instance
             .as_mut()
             .ok_or(crate::LibdropError::NotStarted)?
-            .service
-            .storage();
+            .history()?;
 
         self.rt
             .block_on(storage.purge_transfers_until(until_timestamp_s));
@@ -244,13 +321,101 @@ impl NordDropFFI {
         let storage = instance
             .as_mut()
             .ok_or(crate::LibdropError::NotStarted)?
-            .service
-            .storage();
+            .history()?;
 
         let result = self.rt.block_on(storage.transfers_since(since_timestamp_s));
         Ok(result)
     }
 
+    pub(super) fn query_transfers(
+        &mut self,
+        query: &drop_storage::types::TransferQuery,
+    ) -> Result<drop_storage::types::TransferPage> {
+        trace!(self.logger, "norddrop_query_transfers() query: {:?}", query);
+
+        let mut instance = self.instance.blocking_lock();
+        let storage = instance
+            .as_mut()
+            .ok_or(crate::LibdropError::NotStarted)?
+            .history()?;
+
+        let result = self.rt.block_on(storage.query_transfers(query));
+        Ok(result)
+    }
+
+    pub(super) fn peer_stats(&mut self) -> Result<Vec<drop_storage::types::PeerStats>> {
+        trace!(self.logger, "norddrop_peer_stats()");
+
+        let mut instance = self.instance.blocking_lock();
+        let storage = instance
+            .as_mut()
+            .ok_or(crate::LibdropError::NotStarted)?
+            .history()?;
+
+        let result = self.rt.block_on(storage.peer_stats());
+        Ok(result)
+    }
+
+    pub(super) fn export_history(&mut self, path: &str) -> Result<usize> {
+        trace!(self.logger, "norddrop_export_history() path: {:?}", path);
+
+        let mut instance = self.instance.blocking_lock();
+        let storage = instance
+            .as_mut()
+            .ok_or(crate::LibdropError::NotStarted)?
+            .history()?;
+
+        self.rt
+            .block_on(storage.export_history(path.as_ref()))
+            .map_err(|err| {
+                error!(self.logger, "Failed to export history: {err}");
+                crate::LibdropError::DbError
+            })
+    }
+
+    pub(super) fn import_history(&mut self, path: &str) -> Result<usize> {
+        trace!(self.logger, "norddrop_import_history() path: {:?}", path);
+
+        let mut instance = self.instance.blocking_lock();
+        let storage = instance
+            .as_mut()
+            .ok_or(crate::LibdropError::NotStarted)?
+            .history()?;
+
+        self.rt
+            .block_on(storage.import_history(path.as_ref()))
+            .map_err(|err| {
+                error!(self.logger, "Failed to import history: {err}");
+                crate::LibdropError::DbError
+            })
+    }
+
+    pub(super) fn transfer_snapshot(
+        &self,
+        transfer_id: uuid::Uuid,
+    ) -> Result<Option<TransferSnapshot>> {
+        trace!(
+            self.logger,
+            "norddrop_get_transfer() transfer_id: {transfer_id}"
+        );
+
+        let instance = self.instance.blocking_lock();
+        let instance = instance.as_ref().ok_or(crate::LibdropError::NotStarted)?;
+
+        Ok(self
+            .rt
+            .block_on(instance.service.transfer_snapshot(transfer_id)))
+    }
+
+    pub(super) fn active_transfers(&self) -> Result<Vec<TransferSnapshot>> {
+        trace!(self.logger, "norddrop_active_transfers()");
+
+        let instance = self.instance.blocking_lock();
+        let instance = instance.as_ref().ok_or(crate::LibdropError::NotStarted)?;
+
+        Ok(self.rt.block_on(instance.service.active_transfers()))
+    }
+
     pub(super) fn remove_transfer_file(
         &self,
         transfer_id: uuid::Uuid,
@@ -265,8 +430,7 @@ impl NordDropFFI {
         let storage = instance
             .as_mut()
             .ok_or(crate::LibdropError::NotStarted)?
-            .service
-            .storage();
+            .history()?;
 
         let res = self
             .rt
@@ -293,7 +457,7 @@ impl NordDropFFI {
 
         let xfer = {
             let files = self.prepare_transfer_files(descriptors)?;
-            OutgoingTransfer::new(peer.ip(), files, &self.config).map_err(|e| {
+            OutgoingTransfer::new(peer.ip(), files, &self.config.drop).map_err(|e| {
                 error!(self.logger, "Could not create transfer: {e}");
                 crate::LibdropError::TransferCreate
             })?
@@ -315,6 +479,40 @@ impl NordDropFFI {
         Ok(xfid)
     }
 
+    pub(super) fn validate_config(&self, config: &Config) -> Vec<crate::ConfigFieldError> {
+        trace!(self.logger, "norddrop_validate_config()");
+
+        validate_config(config, self.analytics.is_some())
+    }
+
+    pub(super) fn set_config(&mut self, config: Config) -> Result<Vec<String>> {
+        trace!(self.logger, "norddrop_set_config()");
+
+        ensure_valid_config(&self.logger, &config, self.analytics.is_some())?;
+
+        let mut instance = self.instance.blocking_lock();
+        let instance = instance.as_mut().ok_or(crate::LibdropError::NotStarted)?;
+
+        let restart_required = restart_required_fields(&self.config, &config);
+        if !restart_required.is_empty() {
+            warn!(
+                self.logger,
+                "Config changes applied after restart: {restart_required:?}"
+            );
+        }
+
+        let config = keep_restart_required_fields(&self.config, config);
+
+        let _rt = self.rt.enter();
+        instance
+            .service
+            .update_config(Arc::new(config.drop.clone()));
+
+        self.config = config;
+
+        Ok(restart_required)
+    }
+
     pub(super) fn network_refresh(&mut self) -> Result<()> {
         trace!(self.logger, "norddrop_network_refresh()");
 
@@ -461,11 +659,96 @@ impl NordDropFFI {
         Ok(())
     }
 
+    pub(super) fn set_storage_key_store(
+        &mut self,
+        key_store: Arc<dyn StorageKeyStore>,
+    ) -> Result<()> {
+        trace!(self.logger, "norddrop_set_storage_key_store()");
+
+        let inst = self.instance.blocking_lock();
+        if inst.is_some() {
+            error!(
+                self.logger,
+                "Failed to set storage key store. Instance is already started"
+            );
+            return Err(crate::LibdropError::Unknown);
+        }
+        drop(inst);
+
+        self.storage_keys = Some(key_store);
+        Ok(())
+    }
+
+    pub(super) fn set_analytics(&mut self, analytics: Arc<dyn Analytics>) -> Result<()> {
+        trace!(self.logger, "norddrop_set_analytics()");
+
+        let inst = self.instance.blocking_lock();
+        if inst.is_some() {
+            error!(
+                self.logger,
+                "Failed to set analytics. Instance is already started"
+            );
+            return Err(crate::LibdropError::Unknown);
+        }
+        drop(inst);
+
+        self.analytics = Some(Arc::new(HostAnalytics::new(analytics)));
+        Ok(())
+    }
+
+    pub(super) fn set_callback_coalescing(&self, coalesce_progress: bool) {
+        trace!(
+            self.logger,
+            "norddrop_set_callback_coalescing() coalesce_progress: {coalesce_progress}"
+        );
+
+        self.event_dispatcher
+            .coalesce_progress
+            .store(coalesce_progress, Ordering::Relaxed);
+    }
+
+    pub(super) fn rotate_storage_key(&mut self, new_key: &[u8]) -> Result<()> {
+        trace!(self.logger, "norddrop_rotate_storage_key()");
+
+        let new_key = StorageKey::try_from(new_key).map_err(|err| {
+            error!(self.logger, "Invalid storage key: {err}");
+            crate::LibdropError::BadInput
+        })?;
+
+        let mut instance = self.instance.blocking_lock();
+        let storage = instance
+            .as_mut()
+            .ok_or(crate::LibdropError::NotStarted)?
+            .history()?;
+
+        self.rt
+            .block_on(storage.rotate_key(&new_key))
+            .map_err(|err| {
+                error!(self.logger, "Failed to rotate storage key: {err}");
+                crate::LibdropError::DbError
+            })
+    }
+
+    fn storage_key(&self) -> Result<Option<StorageKey>> {
+        let Some(key_store) = &self.storage_keys else {
+            return Ok(None);
+        };
+
+        key_store
+            .storage_key()
+            .map(|key| StorageKey::try_from(key.as_slice()))
+            .transpose()
+            .map_err(|err| {
+                error!(self.logger, "Invalid storage key: {err}");
+                crate::LibdropError::BadInput
+            })
+    }
+
     fn prepare_transfer_files(
         &self,
         descriptors: &[TransferDescriptor],
     ) -> Result<Vec<FileToSend>> {
-        let mut gather = drop_transfer::file::GatherCtx::new(&self.config);
+        let mut gather = drop_transfer::file::GatherCtx::new(&self.config.drop);
 
         #[cfg(unix)]
         if let Some(fdresolv) = self.fdresolv.as_ref() {
@@ -549,12 +832,27 @@ fn create_key_context(logger: slog::Logger, key_store: Arc<dyn KeyStore>) -> aut
 
 fn open_database(
     dbpath: &str,
-    events: &EventDispatcher,
+    key: Option<&StorageKey>,
+    events: &mut Vec<crate::EventKind>,
     logger: &slog::Logger,
     moose: &Arc<dyn drop_analytics::Moose>,
 ) -> Result<drop_storage::Storage> {
-    match drop_storage::Storage::new(logger.clone(), dbpath) {
+    match drop_storage::Storage::open(logger.clone(), dbpath, key) {
         Ok(storage) => Ok(storage),
+        // The file is most likely fine, recovering would move it aside for
+        // nothing
+        Err(err @ drop_storage::error::Error::Encryption(_)) => {
+            let error = crate::LibdropError::DbError;
+            moose.developer_exception(DeveloperExceptionEventData {
+                code: error.code(),
+                note: err.to_string(),
+                message: "Failed to unlock DB file".to_string(),
+                name: "DB Error".to_string(),
+            });
+            error!(logger, "Failed to unlock DB at \"{dbpath}\": {err}");
+
+            Err(error)
+        }
         Err(err) => {
             error!(logger, "Failed to open DB at \"{dbpath}\": {err}",);
 
@@ -563,7 +861,7 @@ fn open_database(
             if dbpath == ":memory:" {
                 let error = crate::LibdropError::DbError;
                 moose.developer_exception(DeveloperExceptionEventData {
-                    code: error as i32,
+                    code: error.code(),
                     note: err.to_string(),
                     message: "Failed to open in-memory DB".to_string(),
                     name: "DB Error".to_string(),
@@ -572,40 +870,78 @@ fn open_database(
                 Err(error)
             } else {
                 moose.developer_exception(DeveloperExceptionEventData {
-                    code: crate::LibdropError::DbError as i32,
+                    code: crate::LibdropError::DbError.code(),
                     note: "Initial DB open failed, recreating".to_string(),
                     message: "Failed to open DB file".to_string(),
                     name: "DB Error".to_string(),
                 });
+
+                // Keep the broken file aside and salvage what's still readable
+                let backup_path = match drop_storage::Storage::recover(logger.clone(), dbpath, key)
+                {
+                    Ok((storage, report)) => {
+                        warn!(logger, "Recovered DB: {report:?}");
+                        events.push(crate::EventKind::from(report));
+                        return Ok(storage);
+                    }
+                    Err(err) => {
+                        moose.developer_exception(DeveloperExceptionEventData {
+                            code: crate::LibdropError::DbError.code(),
+                            note: err.to_string(),
+                            message: "Failed to recover DB file".to_string(),
+                            name: "DB Error".to_string(),
+                        });
+                        error!(logger, "Failed to recover DB: {err}");
+
+                        match err {
+                            drop_storage::error::Error::Recovery { backup_path, .. } => backup_path,
+                            _ => None,
+                        }
+                    }
+                };
+
                 // Still problems? Let's try to delete the file, provided it's not in memory
                 warn!(logger, "Removing old DB file");
-                if let Err(err) = std::fs::remove_file(dbpath) {
-                    moose.developer_exception(DeveloperExceptionEventData {
-                        code: crate::LibdropError::DbError as i32,
-                        note: err.to_string(),
-                        message: "Failed to remove old DB file".to_string(),
-                        name: "DB Error".to_string(),
-                    });
-                    error!(
-                        logger,
-                        "Failed to open DB and failed to remove it's file: {err}"
-                    );
-                    // Try to at least open db in memory if the path doesn't work
-                    return open_database(":memory:", events, logger, moose);
-                } else {
-                    // Inform app that we wiped the old DB file
-                    events.dispatch(crate::EventKind::RuntimeError {
-                        status: drop_core::Status::DbLost as _,
-                    });
+                let removed = match std::fs::remove_file(dbpath) {
+                    Ok(()) => true,
+                    // The recovery could have moved the file aside already
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => true,
+                    Err(err) => {
+                        moose.developer_exception(DeveloperExceptionEventData {
+                            code: crate::LibdropError::DbError.code(),
+                            note: err.to_string(),
+                            message: "Failed to remove old DB file".to_string(),
+                            name: "DB Error".to_string(),
+                        });
+                        error!(
+                            logger,
+                            "Failed to open DB and failed to remove it's file: {err}"
+                        );
+                        false
+                    }
                 };
 
+                // Inform app that the history is lost, the backup keeps the old file if
+                // the recovery got to move it aside
+                events.push(crate::EventKind::DbLost {
+                    backup_path: backup_path.map(|path| path.to_string_lossy().into_owned()),
+                    integrity_ok: false,
+                    recovered_transfers: 0,
+                    resumable_transfers: 0,
+                });
+
+                if !removed {
+                    // Try to at least open db in memory if the path doesn't work
+                    return open_database(":memory:", None, events, logger, moose);
+                }
+
                 // Final try after cleaning up old DB file
-                match drop_storage::Storage::new(logger.clone(), dbpath) {
+                match drop_storage::Storage::open(logger.clone(), dbpath, key) {
                     Ok(storage) => Ok(storage),
                     Err(err) => {
                         let error = crate::LibdropError::DbError;
                         moose.developer_exception(DeveloperExceptionEventData {
-                            code: error as i32,
+                            code: error.code(),
                             note: err.to_string(),
                             message: "Failed to open DB after cleanup".to_string(),
                             name: "DB Error".to_string(),
@@ -646,19 +982,158 @@ fn crate_fd_callback(
     Arc::new(func)
 }
 
-fn validate_config(logger: &slog::Logger, config: &Config) -> Result<()> {
-    if config.moose.event_path.is_empty() {
-        error!(logger, "Moose path cannot be empty");
-        return Err(crate::LibdropError::BadInput);
+fn validate_config(config: &Config, host_analytics: bool) -> Vec<crate::ConfigFieldError> {
+    use crate::ConfigFieldError as E;
+
+    let mut errors = Vec::new();
+
+    // The path is used only by the compiled-in analytics
+    if !config.moose.disabled && !host_analytics && config.moose.event_path.is_empty() {
+        errors.push(E::new(
+            "moose_event_path",
+            "must not be empty when the compiled-in analytics are enabled",
+            "non-empty path",
+        ));
+    }
+    if config.drop.history_enabled && config.drop.storage_path.is_empty() {
+        errors.push(E::new(
+            "storage_path",
+            "must not be empty when the history is enabled",
+            "non-empty path or \":memory:\"",
+        ));
+    }
+    if config.drop.dir_depth_limit == 0 {
+        errors.push(E::new("dir_depth_limit", "must be positive", ">= 1"));
+    }
+    if config.drop.transfer_file_limit == 0 {
+        errors.push(E::new("transfer_file_limit", "must be positive", ">= 1"));
+    }
+    // Zero would make the checksum progress reporting loop forever
+    if config.drop.checksum_events_granularity == 0 {
+        errors.push(E::new(
+            "checksum_events_granularity",
+            "must be positive",
+            ">= 1",
+        ));
+    }
+    if config.drop.auto_retry_interval == Some(Duration::ZERO) {
+        errors.push(E::new(
+            "auto_retry_interval_ms",
+            "must be positive when set",
+            ">= 1 or null",
+        ));
+    }
+    if config.drop.retention.max_age == Some(Duration::ZERO) {
+        errors.push(E::new(
+            "retention_max_age_ms",
+            "must be positive when set",
+            ">= 1 or null",
+        ));
+    }
+
+    errors
+}
+
+fn ensure_valid_config(logger: &slog::Logger, config: &Config, host_analytics: bool) -> Result<()> {
+    let errors = validate_config(config, host_analytics);
+
+    for err in &errors {
+        error!(
+            logger,
+            "Invalid config field {}: {}, accepted: {}", err.field, err.rule, err.accepted
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(crate::LibdropError::InvalidConfig { fields: errors })
     }
+}
+
+/// The names of the changed `Config` fields which the running instance reads
+/// only on start
+fn restart_required_fields(old: &Config, new: &Config) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut check = |name: &str, changed: bool| {
+        if changed {
+            fields.push(name.to_string());
+        }
+    };
+
+    check(
+        "storage_path",
+        old.drop.storage_path != new.drop.storage_path,
+    );
+    check(
+        "history_enabled",
+        old.drop.history_enabled != new.drop.history_enabled,
+    );
+    check(
+        "progress_events_granularity",
+        old.drop.progress_events_granularity != new.drop.progress_events_granularity,
+    );
+    check(
+        "progress_events_interval_ms",
+        old.drop.progress_events_interval != new.drop.progress_events_interval,
+    );
+    check(
+        "retention_max_age_ms",
+        old.drop.retention.max_age != new.drop.retention.max_age,
+    );
+    check(
+        "retention_max_transfers",
+        old.drop.retention.max_transfers != new.drop.retention.max_transfers,
+    );
+    check(
+        "retention_terminal_only",
+        old.drop.retention.terminal_only != new.drop.retention.terminal_only,
+    );
+    check(
+        "moose_event_path",
+        old.moose.event_path != new.moose.event_path,
+    );
+    check("moose_prod", old.moose.prod != new.moose.prod);
+    check(
+        "analytics_enabled",
+        old.moose.disabled != new.moose.disabled,
+    );
+
+    fields
+}
 
-    Ok(())
+/// Takes the fields listed by `restart_required_fields()` from the config the
+/// instance was started with, so only the live ones are applied
+fn keep_restart_required_fields(started: &Config, mut new: Config) -> Config {
+    new.drop.storage_path = started.drop.storage_path.clone();
+    new.drop.history_enabled = started.drop.history_enabled;
+    new.drop.progress_events_granularity = started.drop.progress_events_granularity;
+    new.drop.progress_events_interval = started.drop.progress_events_interval;
+    new.drop.retention = started.drop.retention.clone();
+    new.moose = started.moose.clone();
+
+    new
 }
 
 fn initialize_moose(
     logger: &slog::Logger,
-    MooseConfig { event_path, prod }: MooseConfig,
+    MooseConfig {
+        event_path,
+        prod,
+        disabled,
+    }: MooseConfig,
+    host: Option<&Arc<dyn drop_analytics::Moose>>,
 ) -> Result<Arc<dyn drop_analytics::Moose>> {
+    if disabled {
+        debug!(logger, "Analytics are disabled");
+        return Ok(drop_analytics::moose_mock());
+    }
+
+    if let Some(host) = host {
+        debug!(logger, "Using the host analytics");
+        return Ok(host.clone());
+    }
+
     let moose = match drop_analytics::init_moose(
         logger.clone(),
         event_path,
@@ -96,6 +96,44 @@ pub struct TransferInfo {
     pub kind: TransferKind,
 }
 
+pub enum TransferStateFilter {
+    Terminal,
+    NonTerminal,
+}
+
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+pub struct TransferQuery {
+    pub direction: Option<crate::TransferDirection>,
+    pub peer: Option<String>,
+    pub state: Option<TransferStateFilter>,
+    pub filename: Option<String>,
+    pub order: SortOrder,
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+    pub summary: bool,
+}
+
+pub struct TransferPage {
+    pub transfers: Vec<TransferInfo>,
+    pub next_cursor: Option<String>,
+}
+
+pub struct PeerStats {
+    pub peer: String,
+    pub transfers: u32,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub files_sent: u32,
+    pub files_received: u32,
+    pub files_failed: u32,
+    pub last_seen: i64,
+    pub throughput: Option<f64>,
+}
+
 impl From<db::TransferStateEventData> for TransferStateKind {
     fn from(value: db::TransferStateEventData) -> Self {
         match value {
@@ -270,3 +308,64 @@ impl From<db::DbTransferType> for TransferKind {
         }
     }
 }
+
+impl TryFrom<TransferQuery> for db::TransferQuery {
+    type Error = crate::LibdropError;
+
+    fn try_from(value: TransferQuery) -> Result<Self, Self::Error> {
+        let cursor = value
+            .cursor
+            .map(|cursor| cursor.parse())
+            .transpose()
+            .map_err(|_| crate::LibdropError::BadInput)?;
+
+        Ok(Self {
+            direction: value.direction.map(|direction| match direction {
+                crate::TransferDirection::Incoming => db::TransferType::Incoming,
+                crate::TransferDirection::Outgoing => db::TransferType::Outgoing,
+            }),
+            peer: value.peer,
+            state: value.state.map(|state| match state {
+                TransferStateFilter::Terminal => db::TransferStateFilter::Terminal,
+                TransferStateFilter::NonTerminal => db::TransferStateFilter::NonTerminal,
+            }),
+            filename: value.filename,
+            order: match value.order {
+                SortOrder::Ascending => db::SortOrder::Ascending,
+                SortOrder::Descending => db::SortOrder::Descending,
+            },
+            cursor,
+            limit: value.limit,
+            summary: value.summary,
+        })
+    }
+}
+
+impl From<db::TransferPage> for TransferPage {
+    fn from(value: db::TransferPage) -> Self {
+        Self {
+            transfers: value
+                .transfers
+                .into_iter()
+                .map(TransferInfo::from)
+                .collect(),
+            next_cursor: value.next_cursor.map(|cursor| cursor.to_string()),
+        }
+    }
+}
+
+impl From<db::PeerStats> for PeerStats {
+    fn from(value: db::PeerStats) -> Self {
+        Self {
+            peer: value.peer,
+            transfers: value.transfers,
+            bytes_sent: value.bytes_sent as _,
+            bytes_received: value.bytes_received as _,
+            files_sent: value.files_sent,
+            files_received: value.files_received,
+            files_failed: value.files_failed,
+            last_seen: value.last_seen.and_utc().timestamp_millis(),
+            throughput: value.throughput,
+        }
+    }
+}
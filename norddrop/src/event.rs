@@ -46,6 +46,14 @@ pub enum EventKind {
         transfer_id: String,
         file_id: String,
         transferred: u64,
+        bytes_per_second: u64,
+        eta_ms: Option<u64>,
+    },
+    TransferProgress {
+        transfer_id: String,
+        transferred: u64,
+        bytes_per_second: u64,
+        eta_ms: Option<u64>,
     },
     FileDownloaded {
         transfer_id: String,
@@ -128,6 +136,113 @@ pub enum EventKind {
     RuntimeError {
         status: crate::StatusCode,
     },
+    DbLost {
+        backup_path: Option<String>,
+        integrity_ok: bool,
+        recovered_transfers: u32,
+        resumable_transfers: u32,
+    },
+}
+
+/// The kind of the event without the data, used to filter the events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    RequestReceived,
+    RequestQueued,
+    FileStarted,
+    FileProgress,
+    TransferProgress,
+    FileDownloaded,
+    FileUploaded,
+    FileFailed,
+    FileRejected,
+    FilePaused,
+    FileThrottled,
+    FilePending,
+    TransferFinalized,
+    TransferFailed,
+    TransferDeferred,
+    FinalizeChecksumStarted,
+    FinalizeChecksumFinished,
+    FinalizeChecksumProgress,
+    VerifyChecksumStarted,
+    VerifyChecksumFinished,
+    VerifyChecksumProgress,
+    RuntimeError,
+    DbLost,
+}
+
+impl EventKind {
+    pub(crate) fn event_type(&self) -> EventType {
+        match self {
+            Self::RequestReceived { .. } => EventType::RequestReceived,
+            Self::RequestQueued { .. } => EventType::RequestQueued,
+            Self::FileStarted { .. } => EventType::FileStarted,
+            Self::FileProgress { .. } => EventType::FileProgress,
+            Self::TransferProgress { .. } => EventType::TransferProgress,
+            Self::FileDownloaded { .. } => EventType::FileDownloaded,
+            Self::FileUploaded { .. } => EventType::FileUploaded,
+            Self::FileFailed { .. } => EventType::FileFailed,
+            Self::FileRejected { .. } => EventType::FileRejected,
+            Self::FilePaused { .. } => EventType::FilePaused,
+            Self::FileThrottled { .. } => EventType::FileThrottled,
+            Self::FilePending { .. } => EventType::FilePending,
+            Self::TransferFinalized { .. } => EventType::TransferFinalized,
+            Self::TransferFailed { .. } => EventType::TransferFailed,
+            Self::TransferDeferred { .. } => EventType::TransferDeferred,
+            Self::FinalizeChecksumStarted { .. } => EventType::FinalizeChecksumStarted,
+            Self::FinalizeChecksumFinished { .. } => EventType::FinalizeChecksumFinished,
+            Self::FinalizeChecksumProgress { .. } => EventType::FinalizeChecksumProgress,
+            Self::VerifyChecksumStarted { .. } => EventType::VerifyChecksumStarted,
+            Self::VerifyChecksumFinished { .. } => EventType::VerifyChecksumFinished,
+            Self::VerifyChecksumProgress { .. } => EventType::VerifyChecksumProgress,
+            Self::RuntimeError { .. } => EventType::RuntimeError,
+            Self::DbLost { .. } => EventType::DbLost,
+        }
+    }
+
+    /// Identifies the progress events superseded by the newer ones of the same
+    /// kind, transfer and file
+    pub(crate) fn progress_key(&self) -> Option<(EventType, &str, Option<&str>)> {
+        match self {
+            Self::FileProgress {
+                transfer_id,
+                file_id,
+                ..
+            }
+            | Self::FinalizeChecksumProgress {
+                transfer_id,
+                file_id,
+                ..
+            }
+            | Self::VerifyChecksumProgress {
+                transfer_id,
+                file_id,
+                ..
+            } => Some((
+                self.event_type(),
+                transfer_id.as_str(),
+                Some(file_id.as_str()),
+            )),
+            Self::TransferProgress { transfer_id, .. } => {
+                Some((self.event_type(), transfer_id.as_str(), None))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<drop_storage::RecoveryReport> for EventKind {
+    fn from(report: drop_storage::RecoveryReport) -> Self {
+        Self::DbLost {
+            backup_path: report
+                .backup_path
+                .map(|path| path.to_string_lossy().into_owned()),
+            integrity_ok: report.integrity_errors.is_empty(),
+            recovered_transfers: report.recovered_transfers as _,
+            resumable_transfers: report.resumable_transfers as _,
+        }
+    }
 }
 
 impl From<&drop_transfer::Error> for Status {
@@ -188,15 +303,29 @@ impl From<drop_transfer::Event> for EventKind {
                 file_id: fid.to_string(),
                 transferred,
             },
-            FileUploadProgress(tx, fid, progress) => Self::FileProgress {
+            FileUploadProgress(tx, fid, progress, throughput) => Self::FileProgress {
                 transfer_id: tx.id().to_string(),
                 file_id: fid.to_string(),
                 transferred: progress,
+                bytes_per_second: throughput.bytes_per_sec,
+                eta_ms: eta_ms(&throughput),
             },
-            FileDownloadProgress(tx, fid, progress) => Self::FileProgress {
+            FileDownloadProgress(tx, fid, progress, throughput) => Self::FileProgress {
                 transfer_id: tx.id().to_string(),
                 file_id: fid.to_string(),
                 transferred: progress,
+                bytes_per_second: throughput.bytes_per_sec,
+                eta_ms: eta_ms(&throughput),
+            },
+            TransferProgress {
+                transfer_id,
+                transferred,
+                throughput,
+            } => Self::TransferProgress {
+                transfer_id: transfer_id.to_string(),
+                transferred,
+                bytes_per_second: throughput.bytes_per_sec,
+                eta_ms: eta_ms(&throughput),
             },
             FileUploadSuccess(tx, fid) => Self::FileUploaded {
                 transfer_id: tx.id().to_string(),
@@ -342,6 +471,10 @@ impl From<drop_transfer::Event> for EventKind {
     }
 }
 
+fn eta_ms(throughput: &drop_transfer::event::Throughput) -> Option<u64> {
+    throughput.eta.map(|eta| eta.as_millis() as u64)
+}
+
 fn current_timestamp() -> i64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{EventKind, EventType};
+
+/// The set of event types passed to the host app, one bit per type. Can be
+/// changed at any time
+pub(crate) struct EventFilter(AtomicU64);
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self(AtomicU64::new(u64::MAX))
+    }
+}
+
+impl EventFilter {
+    /// `None` lets through all the events
+    pub(crate) fn set(&self, types: Option<&[EventType]>) {
+        let mask = match types {
+            Some(types) => types.iter().fold(0, |mask, ty| mask | bit(*ty)),
+            None => u64::MAX,
+        };
+
+        self.0.store(mask, Ordering::Relaxed);
+    }
+
+    pub(crate) fn accepts(&self, kind: &EventKind) -> bool {
+        self.0.load(Ordering::Relaxed) & bit(kind.event_type()) != 0
+    }
+}
+
+fn bit(ty: EventType) -> u64 {
+    1 << ty as u32
+}
@@ -1,17 +1,23 @@
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod active;
+mod analytics;
 mod config;
 pub mod device;
 mod dump;
 mod event;
+mod filter;
 mod log;
+mod poll;
 mod types;
 mod uni;
 
 uniffi::include_scaffolding!("norddrop");
 
+pub use active::*;
+pub use analytics::*;
 pub use config::*;
-pub use drop_core::Status as StatusCode;
+pub use drop_core::{ChecksumAlgorithm, Status as StatusCode};
 pub use dump::*;
 pub use event::*;
 pub use types::*;
@@ -1,27 +1,61 @@
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     fmt,
     panic::{RefUnwindSafe, UnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 use slog::{o, Drain, KV};
 
-pub fn create(callback: Box<dyn crate::Logger>) -> slog::Logger {
+use crate::LogFormat;
+
+pub fn create(callback: Box<dyn crate::Logger>) -> (slog::Logger, FormatHandle) {
     let level = callback.level();
-    slog::Logger::root(
-        super::log::Log(callback).filter_level(level.into()).fuse(),
+    let format = FormatHandle::default();
+
+    let logger = slog::Logger::root(
+        super::log::Log {
+            callback,
+            format: format.clone(),
+        }
+        .filter_level(level.into())
+        .fuse(),
         o!(),
-    )
+    );
+
+    (logger, format)
 }
 
-struct Log(pub Box<dyn crate::Logger>);
+/// Switches the format of the messages passed to the logger callback. Can be
+/// changed at any time
+#[derive(Clone, Default)]
+pub struct FormatHandle(Arc<AtomicBool>);
+
+impl FormatHandle {
+    pub fn set(&self, format: LogFormat) {
+        self.0
+            .store(matches!(format, LogFormat::Json), Ordering::Relaxed);
+    }
+
+    fn is_json(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+struct Log {
+    callback: Box<dyn crate::Logger>,
+    format: FormatHandle,
+}
 
 impl UnwindSafe for Log {}
 impl RefUnwindSafe for Log {}
 
 struct KeyValueSerializer<'a> {
     rec: &'a slog::Record<'a>,
-    kv: HashMap<slog::Key, String>,
+    kv: BTreeMap<slog::Key, String>,
 }
 
 impl<'a> slog::Serializer for KeyValueSerializer<'a> {
@@ -35,7 +69,7 @@ impl<'a> KeyValueSerializer<'a> {
     fn new(rec: &'a slog::Record) -> Self {
         KeyValueSerializer {
             rec,
-            kv: HashMap::new(),
+            kv: BTreeMap::new(),
         }
     }
 
@@ -56,6 +90,23 @@ impl<'a> KeyValueSerializer<'a> {
 
         format!("{file}:{line} {msg} @ [{kv_str}]")
     }
+
+    fn json(self) -> String {
+        let fields: serde_json::Map<_, _> = self
+            .kv
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), serde_json::Value::String(v)))
+            .collect();
+
+        serde_json::json!({
+            "level": self.rec.level().as_str(),
+            "file": self.rec.file(),
+            "line": self.rec.line(),
+            "msg": self.rec.msg().to_string(),
+            "fields": fields,
+        })
+        .to_string()
+    }
 }
 
 impl Drain for Log {
@@ -65,18 +116,28 @@ impl Drain for Log {
     /// Log a record.
     /// record.kv() contains key:value pairs inside of macro calls for logging
     /// with a `a => b` syntax
-    /// the key:val pair inside of arguments is of the parent logger
-    fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<Self::Ok, Self::Err> {
+    /// the key:val pairs of the parent logger (transfer_id, file_id, peer...)
+    /// are in `values`. The record ones take precedence on a key clash
+    fn log(
+        &self,
+        record: &slog::Record,
+        values: &slog::OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
         if !self.is_enabled(record.level()) {
             return Ok(());
         }
 
-        let kv = record.kv();
-
         let mut serializer = KeyValueSerializer::new(record);
-        let _ = kv.serialize(record, &mut serializer);
+        let _ = values.serialize(record, &mut serializer);
+        let _ = record.kv().serialize(record, &mut serializer);
+
+        let msg = if self.format.is_json() {
+            serializer.json()
+        } else {
+            serializer.msg()
+        };
 
-        self.0.on_log(record.level().into(), serializer.msg());
+        self.callback.on_log(record.level().into(), msg);
         Ok(())
     }
 }
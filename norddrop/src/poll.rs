@@ -0,0 +1,293 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::Event;
+
+// How long the event dispatcher waits for the host app to make space in the
+// full queue
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Holds the events until the host app pulls them with `poll_events()`. The
+/// queue is bounded, the event dispatcher waits for the space when it's full
+/// so the events are never reordered. The wait is limited by `PUSH_TIMEOUT`
+/// and cut short by `close()`. Only the progress events are ever dropped: a
+/// progress event is dropped when the wait ends, the other events evict the
+/// oldest queued progress event instead of waiting, or grow the queue past its
+/// capacity when the wait ends. With `coalesce_progress` a queued progress
+/// event is dropped when a newer one of the same file or transfer comes in,
+/// and the progress events never wait: the oldest queued one makes space in
+/// the full queue or the new one is dropped
+pub(crate) struct EventQueue {
+    capacity: usize,
+    coalesce_progress: bool,
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+struct QueueState {
+    events: VecDeque<Event>,
+    // Set while the instance is stopping, nobody waits for the space then
+    closed: bool,
+}
+
+impl EventQueue {
+    pub(crate) fn new(capacity: usize, coalesce_progress: bool) -> Self {
+        Self {
+            capacity,
+            coalesce_progress,
+            state: Mutex::new(QueueState {
+                events: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("Poisoned lock")
+    }
+
+    /// Returns `false` if the progress event was dropped because the queue
+    /// stayed full, the other events are always queued
+    pub(crate) fn push(&self, event: Event) -> bool {
+        let mut state = self.lock();
+        let is_progress = event.kind.progress_key().is_some();
+
+        if self.coalesce_progress {
+            if let Some(key) = event.kind.progress_key() {
+                // The newer event goes to the end so it never precedes the events emitted
+                // in the meantime
+                if let Some(pos) = state
+                    .events
+                    .iter()
+                    .position(|queued| queued.kind.progress_key() == Some(key))
+                {
+                    state.events.remove(pos);
+                }
+            }
+        }
+
+        if state.events.len() >= self.capacity && (self.coalesce_progress || !is_progress) {
+            if let Some(pos) = state
+                .events
+                .iter()
+                .position(|queued| queued.kind.progress_key().is_some())
+            {
+                state.events.remove(pos);
+            } else if is_progress {
+                // Outdated by the next progress event anyway
+                return true;
+            }
+        }
+
+        let (mut state, _) = self
+            .not_full
+            .wait_timeout_while(state, PUSH_TIMEOUT, |state| {
+                state.events.len() >= self.capacity && !state.closed
+            })
+            .expect("Poisoned lock");
+
+        if state.events.len() >= self.capacity && is_progress {
+            return false;
+        }
+
+        state.events.push_back(event);
+        self.not_empty.notify_one();
+        true
+    }
+
+    /// Takes up to `max` events, waiting at most `timeout` for the first one
+    pub(crate) fn poll(&self, max: usize, timeout: Duration) -> Vec<Event> {
+        let state = self.lock();
+        let (mut state, _) = self
+            .not_empty
+            .wait_timeout_while(state, timeout, |state| state.events.is_empty())
+            .expect("Poisoned lock");
+
+        let count = max.min(state.events.len());
+        let polled: Vec<_> = state.events.drain(..count).collect();
+
+        if !polled.is_empty() {
+            self.not_full.notify_all();
+        }
+        polled
+    }
+
+    /// Wakes up the waiting pushes and makes the following ones stop waiting
+    /// for the space in the full queue, until `open()` is called
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.not_full.notify_all();
+    }
+
+    pub(crate) fn open(&self) {
+        self.lock().closed = false;
+    }
+}
+
+/// Keeps only the latest progress event of each file and transfer, the other
+/// events stay in order
+pub(crate) fn coalesce_progress(events: Vec<Event>) -> Vec<Event> {
+    let keep: Vec<_> = {
+        let mut seen = HashSet::new();
+        let mut keep: Vec<_> = events
+            .iter()
+            .rev()
+            .map(|ev| match ev.kind.progress_key() {
+                Some(key) => seen.insert(key),
+                None => true,
+            })
+            .collect();
+        keep.reverse();
+        keep
+    };
+
+    events
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(ev, keep)| keep.then_some(ev))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread, time::Instant};
+
+    use super::*;
+    use crate::EventKind;
+
+    fn progress(file_id: &str, transferred: u64) -> Event {
+        Event {
+            timestamp: 0,
+            kind: EventKind::FileProgress {
+                transfer_id: "xfer".to_string(),
+                file_id: file_id.to_string(),
+                transferred,
+                bytes_per_second: 0,
+                eta_ms: None,
+            },
+        }
+    }
+
+    fn finalized() -> Event {
+        Event {
+            timestamp: 0,
+            kind: EventKind::TransferFinalized {
+                transfer_id: "xfer".to_string(),
+                by_peer: false,
+            },
+        }
+    }
+
+    fn failed(file_id: &str) -> Event {
+        Event {
+            timestamp: 0,
+            kind: EventKind::FileFailed {
+                transfer_id: "xfer".to_string(),
+                file_id: file_id.to_string(),
+                status: crate::Status {
+                    status: crate::StatusCode::BadPath,
+                    os_error_code: None,
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn close_wakes_up_the_full_queue() {
+        let queue = Arc::new(EventQueue::new(2, false));
+        assert!(queue.push(progress("a", 0)));
+        assert!(queue.push(progress("b", 0)));
+
+        let pusher = thread::spawn({
+            let queue = queue.clone();
+            move || {
+                let start = Instant::now();
+                let pushed = queue.push(progress("c", 0));
+                (pushed, start.elapsed())
+            }
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        queue.close();
+
+        let (pushed, elapsed) = pusher.join().unwrap();
+        assert!(!pushed);
+        assert!(elapsed < PUSH_TIMEOUT);
+
+        // Doesn't wait once closed
+        assert!(!queue.push(progress("d", 0)));
+        assert_eq!(queue.poll(10, Duration::ZERO).len(), 2);
+
+        queue.open();
+        assert!(queue.push(progress("e", 0)));
+    }
+
+    #[test]
+    fn full_queue_keeps_terminal_events() {
+        let queue = EventQueue::new(2, false);
+        assert!(queue.push(failed("a")));
+        assert!(queue.push(progress("b", 0)));
+
+        // Evicts the queued progress event instead of waiting
+        assert!(queue.push(failed("b")));
+
+        // Nothing to evict, grows past the capacity once the wait ends
+        queue.close();
+        assert!(queue.push(finalized()));
+
+        let events = queue.poll(10, Duration::ZERO);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[2].kind,
+            EventKind::TransferFinalized { .. }
+        ));
+    }
+
+    #[test]
+    fn full_coalescing_queue_drops_progress() {
+        let queue = EventQueue::new(2, true);
+        assert!(queue.push(progress("a", 0)));
+        assert!(queue.push(failed("a")));
+
+        // Replaces the oldest progress event instead of waiting
+        assert!(queue.push(failed("b")));
+        // No progress event to replace, the new one is dropped
+        assert!(queue.push(progress("c", 0)));
+
+        let events = queue.poll(10, Duration::ZERO);
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|ev| matches!(ev.kind, EventKind::FileFailed { .. })));
+    }
+
+    #[test]
+    fn coalesce_keeps_latest_progress() {
+        let events = coalesce_progress(vec![
+            progress("a", 1),
+            progress("b", 1),
+            failed("b"),
+            progress("a", 2),
+            progress("b", 2),
+        ]);
+
+        let transferred: Vec<_> = events
+            .iter()
+            .map(|ev| match &ev.kind {
+                EventKind::FileProgress {
+                    file_id,
+                    transferred,
+                    ..
+                } => format!("{file_id}:{transferred}"),
+                _ => "failed".to_string(),
+            })
+            .collect();
+        assert_eq!(transferred, ["failed", "a:2", "b:2"]);
+    }
+}
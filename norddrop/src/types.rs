@@ -13,38 +13,62 @@ pub enum TransferDescriptor {
     },
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
 pub enum LibdropError {
     /// Operation resulted to unknown error.
-    Unknown = 1,
+    Unknown,
 
     /// Failed to parse C string, meaning the string provided is not valid UTF8
     /// or is a null pointer
-    InvalidString = 2,
+    InvalidString,
 
     /// One of the arguments provided is invalid
-    BadInput = 3,
+    BadInput,
 
     /// Failed to create transfer based on arguments provided
-    TransferCreate = 5,
+    TransferCreate,
 
     /// The libdrop instance is not started yet
-    NotStarted = 6,
+    NotStarted,
 
     /// Address already in use
-    AddrInUse = 7,
+    AddrInUse,
 
     /// Failed to start the libdrop instance
-    InstanceStart = 8,
+    InstanceStart,
 
     /// Failed to stop the libdrop instance
-    InstanceStop = 9,
+    InstanceStop,
 
     /// Invalid private key provided
-    InvalidPrivkey = 10,
+    InvalidPrivkey,
 
     /// Database error
-    DbError = 11,
+    DbError,
+
+    /// The configuration is invalid
+    InvalidConfig {
+        fields: Vec<crate::ConfigFieldError>,
+    },
+}
+
+impl LibdropError {
+    /// The numeric code of the error, stable across the releases
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Unknown => 1,
+            Self::InvalidString => 2,
+            Self::BadInput => 3,
+            Self::TransferCreate => 5,
+            Self::NotStarted => 6,
+            Self::AddrInUse => 7,
+            Self::InstanceStart => 8,
+            Self::InstanceStop => 9,
+            Self::InvalidPrivkey => 10,
+            Self::DbError => 11,
+            Self::InvalidConfig { .. } => 12,
+        }
+    }
 }
 
 impl fmt::Display for LibdropError {
@@ -66,6 +90,13 @@ pub enum LogLevel {
     Trace = 6,
 }
 
+#[derive(Copy, Clone)]
+/// The format of the messages passed to the logger callback.
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 macro_rules! map_enum {
     ($from:tt <=> $to:tt, $($f:tt = $t:tt),+ $(,)?) => {
         impl From<$from> for $to {
@@ -1,6 +1,14 @@
-use std::sync::Mutex;
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
 
-use crate::{device::NordDropFFI, Event, TransferDescriptor, TransferInfo};
+use slog::warn;
+
+use crate::{
+    device::NordDropFFI, ActiveTransfer, Event, PeerStats, TransferDescriptor, TransferInfo,
+    TransferPage, TransferQuery,
+};
 
 pub type Result<T> = std::result::Result<T, crate::LibdropError>;
 
@@ -22,8 +30,28 @@ pub trait FdResolver: Send + Sync {
     fn on_fd(&self, content_uri: String) -> Option<i32>;
 }
 
+pub trait StorageKeyStore: Send + Sync {
+    fn storage_key(&self) -> Option<Vec<u8>>;
+}
+
+pub trait Analytics: Send + Sync {
+    fn event_init(&self, data: crate::InitAnalyticsEvent);
+    fn event_transfer_intent(&self, data: crate::TransferIntentAnalyticsEvent);
+    fn event_transfer_intent_received(&self, data: crate::TransferIntentReceivedAnalyticsEvent);
+    fn event_transfer_state(&self, data: crate::TransferStateAnalyticsEvent);
+    fn event_transfer_file(&self, data: crate::TransferFileAnalyticsEvent);
+    fn developer_exception(&self, data: crate::DeveloperExceptionAnalyticsEvent);
+    fn developer_exception_with_value(
+        &self,
+        data: crate::DeveloperExceptionWithValueAnalyticsEvent,
+    );
+}
+
 pub struct NordDrop {
     dev: Mutex<NordDropFFI>,
+    log_format: super::log::FormatHandle,
+    event_queue: Arc<OnceLock<super::poll::EventQueue>>,
+    event_filter: Arc<super::filter::EventFilter>,
 }
 
 impl NordDrop {
@@ -32,19 +60,79 @@ impl NordDrop {
         key_store: Box<dyn KeyStore>,
         logger: Box<dyn Logger>,
     ) -> Result<Self> {
-        let logger = super::log::create(logger);
+        let (logger, log_format) = super::log::create(logger);
 
+        let event_queue = Arc::new(OnceLock::new());
+
+        let event_filter = Arc::new(super::filter::EventFilter::default());
+
+        let queue = event_queue.clone();
+        let filter = event_filter.clone();
+        let event_logger = logger.clone();
         let dev = NordDropFFI::new(
-            move |ev| event_callback.on_event(ev),
+            move |ev| {
+                if !filter.accepts(&ev.kind) {
+                    return;
+                }
+
+                match queue.get() {
+                    Some(queue) => {
+                        let ty = ev.kind.event_type();
+                        if !queue.push(ev) {
+                            warn!(event_logger, "Event queue is full, dropping {ty:?} event");
+                        }
+                    }
+                    None => event_callback.on_event(ev),
+                }
+            },
             key_store.into(),
             logger,
         )?;
 
         Ok(Self {
             dev: Mutex::new(dev),
+            log_format,
+            event_queue,
+            event_filter,
         })
     }
 
+    pub fn set_event_filter(&self, types: Option<Vec<crate::EventType>>) {
+        self.event_filter.set(types.as_deref());
+    }
+
+    pub fn set_callback_coalescing(&self, coalesce_progress: bool) {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .set_callback_coalescing(coalesce_progress);
+    }
+
+    pub fn enable_event_polling(&self, capacity: u32, coalesce_progress: bool) -> Result<()> {
+        if capacity == 0 {
+            return Err(crate::LibdropError::BadInput);
+        }
+
+        self.event_queue
+            .set(super::poll::EventQueue::new(
+                capacity as _,
+                coalesce_progress,
+            ))
+            .map_err(|_| crate::LibdropError::BadInput)
+    }
+
+    pub fn poll_events(&self, max: u32, timeout_ms: u32) -> Result<Vec<Event>> {
+        let queue = self
+            .event_queue
+            .get()
+            .ok_or(crate::LibdropError::BadInput)?;
+        Ok(queue.poll(max as _, Duration::from_millis(timeout_ms.into())))
+    }
+
+    pub fn set_log_format(&self, format: crate::LogFormat) {
+        self.log_format.set(format);
+    }
+
     #[cfg(not(unix))]
     pub fn set_fd_resolver(&self, resolver: Box<dyn FdResolver>) -> Result<()> {
         Err(crate::LibdropError::Unknown)
@@ -60,6 +148,27 @@ impl NordDrop {
         Ok(())
     }
 
+    pub fn set_storage_key_store(&self, key_store: Box<dyn StorageKeyStore>) -> Result<()> {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .set_storage_key_store(key_store.into())
+    }
+
+    pub fn set_analytics(&self, analytics: Box<dyn Analytics>) -> Result<()> {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .set_analytics(analytics.into())
+    }
+
+    pub fn rotate_storage_key(&self, new_key: Vec<u8>) -> Result<()> {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .rotate_storage_key(&new_key)
+    }
+
     pub fn start(&self, addr: &str, config: crate::Config) -> Result<()> {
         self.dev
             .lock()
@@ -67,8 +176,34 @@ impl NordDrop {
             .start(addr, config.into())
     }
 
+    pub fn validate_config(&self, config: crate::Config) -> Vec<crate::ConfigFieldError> {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .validate_config(&config.into())
+    }
+
+    pub fn set_config(&self, config: crate::Config) -> Result<Vec<String>> {
+        self.dev
+            .lock()
+            .expect("Poisoned lock")
+            .set_config(config.into())
+    }
+
     pub fn stop(&self) -> Result<()> {
-        self.dev.lock().expect("Poisoned lock").stop()
+        // The stop waits for the event dispatcher, which must not wait for the
+        // host app to poll the events in turn
+        let queue = self.event_queue.get();
+        if let Some(queue) = queue {
+            queue.close();
+        }
+
+        let res = self.dev.lock().expect("Poisoned lock").stop();
+
+        if let Some(queue) = queue {
+            queue.open();
+        }
+        res
     }
 
     pub fn purge_transfers(&self, transfer_ids: &[String]) -> Result<()> {
@@ -100,6 +235,57 @@ impl NordDrop {
         Ok(xfers)
     }
 
+    pub fn query_transfers(&self, query: TransferQuery) -> Result<TransferPage> {
+        let page = self
+            .dev
+            .lock()
+            .expect("Poisoned lock")
+            .query_transfers(&query.try_into()?)?;
+
+        Ok(page.into())
+    }
+
+    pub fn peer_stats(&self) -> Result<Vec<PeerStats>> {
+        let stats = self.dev.lock().expect("Poisoned lock").peer_stats()?;
+        Ok(stats.into_iter().map(PeerStats::from).collect())
+    }
+
+    pub fn export_history(&self, path: &str) -> Result<u32> {
+        let count = self
+            .dev
+            .lock()
+            .expect("Poisoned lock")
+            .export_history(path)?;
+
+        Ok(count as _)
+    }
+
+    pub fn import_history(&self, path: &str) -> Result<u32> {
+        let count = self
+            .dev
+            .lock()
+            .expect("Poisoned lock")
+            .import_history(path)?;
+
+        Ok(count as _)
+    }
+
+    pub fn get_transfer(&self, transfer_id: &str) -> Result<Option<ActiveTransfer>> {
+        let snapshot = self.dev.lock().expect("Poisoned lock").transfer_snapshot(
+            transfer_id
+                .parse()
+                .map_err(|_| crate::LibdropError::InvalidString)?,
+        )?;
+
+        Ok(snapshot.map(ActiveTransfer::from))
+    }
+
+    pub fn active_transfers(&self) -> Result<Vec<ActiveTransfer>> {
+        let snapshots = self.dev.lock().expect("Poisoned lock").active_transfers()?;
+
+        Ok(snapshots.into_iter().map(ActiveTransfer::from).collect())
+    }
+
     pub fn new_transfer(&self, peer: &str, descriptors: &[TransferDescriptor]) -> Result<String> {
         let transfer_id = self
             .dev
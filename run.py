@@ -67,7 +67,14 @@ def server_run(client: DockerClient, args) -> None:
     print('Starting container…')
 
     container.start()
-    cmd = ['target/debug/examples/udrop', '-l', '0.0.0.0', '--output', '/root']
+    cmd = [
+        'target/debug/udrop',
+        'listen',
+        '--addr', '0.0.0.0',
+        '--key', 'drop-transfer/src/bin/udrop/demo.key',
+        '--peers', 'drop-transfer/src/bin/udrop/demo.peers',
+        '--output', '/root',
+    ]
     res = container.exec_run(cmd, tty=True, stream=True)
 
     try:
@@ -607,6 +607,7 @@ class Wait(Action):
             not isinstance(self._event, event.Progress),
             not isinstance(self._event, event.FinalizeChecksumProgress),
             not isinstance(self._event, event.VerifyChecksumProgress),
+            not isinstance(self._event, event.TransferProgress),
         )
 
     def __str__(self):
@@ -804,6 +805,52 @@ class Stop(Action):
         return "Stop"
 
 
+class SetEventFilter(Action):
+    def __init__(self, types: typing.Optional[typing.List[norddrop.EventType]]):
+        self._types = types
+
+    async def run(self, drop: ffi.Drop):
+        drop.set_event_filter(self._types)
+
+    def __str__(self):
+        return f"SetEventFilter({self._types})"
+
+
+class SetCallbackCoalescing(Action):
+    def __init__(self, coalesce_progress: bool):
+        self._coalesce_progress = coalesce_progress
+
+    async def run(self, drop: ffi.Drop):
+        drop.set_callback_coalescing(self._coalesce_progress)
+
+    def __str__(self):
+        return f"SetCallbackCoalescing({self._coalesce_progress})"
+
+
+class EnableEventPolling(Action):
+    def __init__(self, capacity: int = 64, coalesce_progress: bool = False):
+        self._capacity = capacity
+        self._coalesce_progress = coalesce_progress
+
+    async def run(self, drop: ffi.Drop):
+        drop.enable_event_polling(self._capacity, self._coalesce_progress)
+
+    def __str__(self):
+        return f"EnableEventPolling({self._capacity}, {self._coalesce_progress})"
+
+
+class PollEvents(Action):
+    def __init__(self, max: int = 64, timeout_ms: int = 0):
+        self._max = max
+        self._timeout_ms = timeout_ms
+
+    async def run(self, drop: ffi.Drop):
+        drop.poll_events(self._max, self._timeout_ms)
+
+    def __str__(self):
+        return f"PollEvents({self._max}, {self._timeout_ms})"
+
+
 class ModifyFile(Action):
     def __init__(self, file_glob: str):
         self._file = file_glob
@@ -975,12 +1022,20 @@ class Start(Action):
         checksum_events_size_threshold=2**32,  # don't emit events for existing tests
         checksum_events_granularity=None,
         auto_retry_interval_ms=None,
+        checksum_algorithm=None,
+        archive_directories=None,
+        progress_events_granularity=None,
+        progress_events_interval_ms=None,
     ):
         self._addr = addr
         self._dbpath = dbpath
         self._checksum_events_size_threshold = checksum_events_size_threshold
         self._checksum_events_granularity = checksum_events_granularity
         self._auto_retry_interval_ms = auto_retry_interval_ms
+        self._checksum_algorithm = checksum_algorithm
+        self._archive_directories = archive_directories
+        self._progress_events_granularity = progress_events_granularity
+        self._progress_events_interval_ms = progress_events_interval_ms
 
     async def run(self, drop: ffi.Drop):
         drop.start(
@@ -989,12 +1044,60 @@ class Start(Action):
             self._checksum_events_size_threshold,
             self._checksum_events_granularity,
             self._auto_retry_interval_ms,
+            self._checksum_algorithm,
+            self._archive_directories,
+            self._progress_events_granularity,
+            self._progress_events_interval_ms,
         )
 
     def __str__(self):
         return f"Start(addr={peer_resolver.resolve(self._addr)}, dbpath={self._dbpath}, checksum_events_size_threshold={self._checksum_events_size_threshold})"
 
 
+class SetConfig(Action):
+    def __init__(
+        self,
+        dbpath: str = ":memory:",
+        checksum_events_size_threshold=2**32,
+        checksum_events_granularity=None,
+        auto_retry_interval_ms=None,
+        checksum_algorithm=None,
+        archive_directories=None,
+        progress_events_granularity=None,
+        progress_events_interval_ms=None,
+        restart_required: typing.List[str] = [],
+    ):
+        self._dbpath = dbpath
+        self._checksum_events_size_threshold = checksum_events_size_threshold
+        self._checksum_events_granularity = checksum_events_granularity
+        self._auto_retry_interval_ms = auto_retry_interval_ms
+        self._checksum_algorithm = checksum_algorithm
+        self._archive_directories = archive_directories
+        self._progress_events_granularity = progress_events_granularity
+        self._progress_events_interval_ms = progress_events_interval_ms
+        self._restart_required = restart_required
+
+    async def run(self, drop: ffi.Drop):
+        restart_required = drop.set_config(
+            self._dbpath,
+            self._checksum_events_size_threshold,
+            self._checksum_events_granularity,
+            self._auto_retry_interval_ms,
+            self._checksum_algorithm,
+            self._archive_directories,
+            self._progress_events_granularity,
+            self._progress_events_interval_ms,
+        )
+
+        if sorted(restart_required) != sorted(self._restart_required):
+            raise Exception(
+                f"Expected {self._restart_required} to require a restart, got {restart_required}"
+            )
+
+    def __str__(self):
+        return f"SetConfig(dbpath={self._dbpath}, auto_retry_interval_ms={self._auto_retry_interval_ms}, restart_required={self._restart_required})"
+
+
 class RemoveTransferFile(Action):
     def __init__(self, uuid_slot: int, fid):
         self._uuid_slot = uuid_slot
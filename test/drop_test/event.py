@@ -163,6 +163,27 @@ class Progress(Event):
         return f"Progress(transfer={print_uuid(self._uuid_slot)}, file={self._file}, transfered={self._transferred})"
 
 
+class TransferProgress(Event):
+    def __init__(self, uuid_slot: int, transferred: typing.Optional[int] = None):
+        self._uuid_slot = uuid_slot
+        self._transferred = transferred
+
+    def __eq__(self, rhs) -> bool:
+        if not isinstance(rhs, TransferProgress):
+            return False
+        if self._uuid_slot != rhs._uuid_slot:
+            return False
+
+        if self._transferred is not None and rhs._transferred is not None:
+            if self._transferred != rhs._transferred:
+                return False
+
+        return True
+
+    def __str__(self):
+        return f"TransferProgress(transfer={print_uuid(self._uuid_slot)}, transferred={self._transferred})"
+
+
 class Throttled(Event):
     def __init__(
         self, uuid_slot: int, file: str, transferred: typing.Optional[int] = 0
@@ -502,6 +523,36 @@ class RuntimeError(Event):
         return f"RuntimeError(status={self._status})"
 
 
+class DbLost(Event):
+    def __init__(
+        self,
+        backed_up: bool,
+        integrity_ok: bool,
+        recovered_transfers: int,
+        resumable_transfers: int,
+    ):
+        self._backed_up = backed_up
+        self._integrity_ok = integrity_ok
+        self._recovered_transfers = recovered_transfers
+        self._resumable_transfers = resumable_transfers
+
+    def __eq__(self, rhs):
+        if not isinstance(rhs, DbLost):
+            return False
+        if self._backed_up != rhs._backed_up:
+            return False
+        if self._integrity_ok != rhs._integrity_ok:
+            return False
+        if self._recovered_transfers != rhs._recovered_transfers:
+            return False
+        if self._resumable_transfers != rhs._resumable_transfers:
+            return False
+        return True
+
+    def __str__(self):
+        return f"DbLost(backed_up={self._backed_up}, integrity_ok={self._integrity_ok}, recovered_transfers={self._recovered_transfers}, resumable_transfers={self._resumable_transfers})"
+
+
 class TransferDeferred(Event):
     def __init__(
         self,
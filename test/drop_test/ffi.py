@@ -4,7 +4,7 @@ import logging
 from enum import Enum
 import bindings.norddrop as norddrop  # type: ignore
 
-from threading import Lock
+from threading import Lock, Thread
 
 from . import event
 from .logger import logger
@@ -82,7 +82,9 @@ class EventQueue(norddrop.EventCallback):
 
                 if ignore_progress:
                     self._events = [
-                        ev for ev in self._events if not isinstance(ev, event.Progress)
+                        ev
+                        for ev in self._events
+                        if not isinstance(ev, (event.Progress, event.TransferProgress))
                     ]
 
                 if len(self._events) > 0:
@@ -108,6 +110,7 @@ class EventQueue(norddrop.EventCallback):
         ignore_progress: bool = True,
         ignore_finalize_checksum_progress: bool = True,
         ignore_verify_checksum_progress: bool = True,
+        ignore_transfer_progress: bool = True,
     ) -> None:
         # TODO: a better solution would be to have infinite loop with a timeout check for all wait commands
         for _ in range(100):
@@ -129,6 +132,11 @@ class EventQueue(norddrop.EventCallback):
                     ):
                         continue
 
+                    if ignore_transfer_progress and isinstance(
+                        e, event.TransferProgress
+                    ):
+                        continue
+
                     if e == target_event:
                         return
 
@@ -149,6 +157,7 @@ class EventQueue(norddrop.EventCallback):
         ignore_throttled: bool = True,
         ignore_finalize_checksum_progress: bool = True,
         ignore_verify_checksum_progress: bool = True,
+        ignore_transfer_progress: bool = True,
     ) -> None:
         success = []
 
@@ -179,6 +188,11 @@ class EventQueue(norddrop.EventCallback):
                     ):
                         continue
 
+                    if ignore_transfer_progress and isinstance(
+                        e, event.TransferProgress
+                    ):
+                        continue
+
                     found = False
                     for te in target_events:
                         if te == e:
@@ -323,29 +337,110 @@ class Drop:
         checksum_events_size_threshold=None,
         checksum_events_granularity=None,
         auto_retry_interval_ms=None,
+        checksum_algorithm=None,
+        archive_directories=None,
+        progress_events_granularity=None,
+        progress_events_interval_ms=None,
     ):
-        cfg = norddrop.Config(
-            dir_depth_limit=5,
-            transfer_file_limit=1000,
-            moose_event_path="/tmp/moose-events.json",
-            moose_prod=False,
-            storage_path=dbpath,
-            checksum_events_size_threshold=checksum_events_size_threshold,
-            checksum_events_granularity=checksum_events_granularity,
-            connection_retries=1,
-            auto_retry_interval_ms=auto_retry_interval_ms,
+        cfg = new_config(
+            dbpath,
+            checksum_events_size_threshold,
+            checksum_events_granularity,
+            auto_retry_interval_ms,
+            checksum_algorithm,
+            archive_directories,
+            progress_events_granularity,
+            progress_events_interval_ms,
         )
 
         self._instance.start(addr, cfg)
 
+    def set_config(
+        self,
+        dbpath: str,
+        checksum_events_size_threshold=None,
+        checksum_events_granularity=None,
+        auto_retry_interval_ms=None,
+        checksum_algorithm=None,
+        archive_directories=None,
+        progress_events_granularity=None,
+        progress_events_interval_ms=None,
+    ) -> typing.List[str]:
+        cfg = new_config(
+            dbpath,
+            checksum_events_size_threshold,
+            checksum_events_granularity,
+            auto_retry_interval_ms,
+            checksum_algorithm,
+            archive_directories,
+            progress_events_granularity,
+            progress_events_interval_ms,
+        )
+
+        return self._instance.set_config(cfg)
+
     def stop(self):
         self._instance.stop()
 
+    def set_event_filter(self, types: typing.Optional[typing.List[norddrop.EventType]]):
+        self._instance.set_event_filter(types)
+
+    def set_callback_coalescing(self, coalesce_progress: bool):
+        self._instance.set_callback_coalescing(coalesce_progress)
+
+    # Pulls the events on a separate thread and feeds them to the same queue
+    # the callback does, so the waits work the same way in both modes
+    def enable_event_polling(self, capacity: int, coalesce_progress: bool):
+        self._instance.enable_event_polling(capacity, coalesce_progress)
+
+        def poll():
+            while True:
+                for ev in self._instance.poll_events(64, 100):
+                    self._events.on_event(ev)
+
+        Thread(target=poll, daemon=True).start()
+
+    def poll_events(self, max: int, timeout_ms: int):
+        return self._instance.poll_events(max, timeout_ms)
+
     @property
     def version(self) -> str:
         return norddrop.version()
 
 
+def new_config(
+    dbpath: str,
+    checksum_events_size_threshold,
+    checksum_events_granularity,
+    auto_retry_interval_ms,
+    checksum_algorithm,
+    archive_directories,
+    progress_events_granularity,
+    progress_events_interval_ms,
+) -> norddrop.Config:
+    return norddrop.Config(
+        dir_depth_limit=5,
+        transfer_file_limit=1000,
+        moose_event_path="/tmp/moose-events.json",
+        moose_prod=False,
+        storage_path=dbpath,
+        history_enabled=None,
+        checksum_events_size_threshold=checksum_events_size_threshold,
+        checksum_events_granularity=checksum_events_granularity,
+        connection_retries=1,
+        auto_retry_interval_ms=auto_retry_interval_ms,
+        checksum_algorithm=checksum_algorithm,
+        preallocate_downloads=None,
+        archive_directories=archive_directories,
+        progress_events_granularity=progress_events_granularity,
+        progress_events_interval_ms=progress_events_interval_ms,
+        retention_max_age_ms=None,
+        retention_max_transfers=None,
+        retention_terminal_only=None,
+        analytics_enabled=None,
+    )
+
+
 class IncomingRequestEntry:
     def __init__(self, id, path):
         self._id = id
@@ -394,6 +489,8 @@ def new_event(ev: norddrop.Event) -> event.Event:
         return event.Start(transfer_slot, ev.file_id, ev.transferred)
     elif ev.is_file_progress():
         return event.Progress(transfer_slot, ev.file_id, ev.transferred)
+    elif ev.is_transfer_progress():
+        return event.TransferProgress(transfer_slot, ev.transferred)
     elif ev.is_file_downloaded():
         return event.FinishFileDownloaded(transfer_slot, ev.file_id, ev.final_path)
     elif ev.is_file_uploaded():
@@ -445,6 +542,13 @@ def new_event(ev: norddrop.Event) -> event.Event:
 
     elif ev.is_runtime_error():
         return event.RuntimeError(ev.status)
+    elif ev.is_db_lost():
+        return event.DbLost(
+            ev.backup_path is not None,
+            ev.integrity_ok,
+            ev.recovered_transfers,
+            ev.resumable_transfers,
+        )
 
     else:
         raise Exception("Unknown event type")
@@ -11725,8 +11725,9 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
-                            "result": 0
+                            "protocol_version": 7,
+                            "result": 0,
+                            "reconnects": 0
                         }""",
                             """{
                             "type": "file",
@@ -11738,7 +11739,9 @@ scenarios = [
                             "path_id": \""""
                             + FILES["testfile-big"].id
                             + """\",
-                            "direction": "upload"
+                            "direction": "upload",
+                            "resume_offset": 0,
+                            "file_size": 10240
                         }""",
                         ]
                     ),
@@ -11805,7 +11808,10 @@ scenarios = [
                             "path_id": \""""
                             + FILES["testfile-big"].id
                             + """\",
-                            "direction": "download"
+                            "direction": "download",
+                            "resume_offset": 0,
+                            "file_size": 10240,
+                            "verify_checksum_time": 0
                         }""",
                         ]
                     ),
@@ -11884,7 +11890,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -12067,7 +12073,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -12215,7 +12221,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -12232,7 +12238,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -12415,7 +12421,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -12510,7 +12516,7 @@ scenarios = [
                         "DROP_PEER_REN",
                         dbpath="/tmp/db/26-1-corrupted.sqlite",
                     ),
-                    action.Wait(event.RuntimeError(norddrop.StatusCode.DB_LOST)),
+                    action.Wait(event.DbLost(True, False, 0, 0)),
                     action.NoEvent(),
                     action.Stop(),
                     action.AssertMooseEvents(
@@ -12644,7 +12650,7 @@ scenarios = [
                         }""",
                             """{
                             "type": "transfer_state",
-                            "protocol_version": 6,
+                            "protocol_version": 7,
                             "result": 0
                         }""",
                             """{
@@ -14149,4 +14155,396 @@ scenarios = [
             ),
         },
     ),
+    Scenario(
+        "scenario54",
+        "Send one file to a peer configured to use the BLAKE3 checksum, expect it to be transferred",
+        {
+            "DROP_PEER_REN": ActionList(
+                [
+                    action.Start("DROP_PEER_REN"),
+                    action.WaitForAnotherPeer("DROP_PEER_STIMPY"),
+                    action.NewTransfer("DROP_PEER_STIMPY", ["/tmp/testfile-big"]),
+                    action.Wait(
+                        event.Queued(
+                            0,
+                            "DROP_PEER_STIMPY",
+                            [
+                                norddrop.QueuedFile(
+                                    FILES["testfile-big"].id,
+                                    "testfile-big",
+                                    10485760,
+                                    "/tmp",
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Wait(event.Start(0, FILES["testfile-big"].id)),
+                    action.Wait(
+                        event.FinishFileUploaded(
+                            0,
+                            FILES["testfile-big"].id,
+                        )
+                    ),
+                    action.ExpectCancel([0], True),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+            "DROP_PEER_STIMPY": ActionList(
+                [
+                    action.Start(
+                        "DROP_PEER_STIMPY",
+                        checksum_algorithm=norddrop.ChecksumAlgorithm.BLAKE3,
+                    ),
+                    action.Wait(
+                        event.Receive(
+                            0,
+                            "DROP_PEER_REN",
+                            [
+                                norddrop.ReceivedFile(
+                                    FILES["testfile-big"].id, "testfile-big", 10485760
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Download(
+                        0,
+                        FILES["testfile-big"].id,
+                        "/tmp/received/54",
+                    ),
+                    action.Wait(
+                        event.Pending(0, FILES["testfile-big"].id, "/tmp/received/54")
+                    ),
+                    action.Wait(event.Start(0, FILES["testfile-big"].id)),
+                    action.Wait(
+                        event.FinishFileDownloaded(
+                            0,
+                            FILES["testfile-big"].id,
+                            "/tmp/received/54/testfile-big",
+                        )
+                    ),
+                    action.CheckDownloadedFiles(
+                        [
+                            action.File("/tmp/received/54/testfile-big", 10485760),
+                        ],
+                    ),
+                    action.ExpectCancel([0], False),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+        },
+    ),
+    Scenario(
+        "scenario55",
+        "Start with an invalid config, expect the instance not to start, then start with a valid one",
+        {
+            "DROP_PEER_REN": ActionList(
+                [
+                    action.ExpectError(
+                        action.Start(
+                            "DROP_PEER_REN",
+                            checksum_events_granularity=0,
+                            auto_retry_interval_ms=0,
+                        ),
+                        norddrop.LibdropError.InvalidConfig,
+                    ),
+                    action.ExpectError(
+                        action.NetworkRefresh(),
+                        norddrop.LibdropError.NotStarted,
+                    ),
+                    action.Start("DROP_PEER_REN"),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+        },
+    ),
+    Scenario(
+        "scenario56",
+        "Send one file to a peer while pulling the events with poll_events() on both sides, expect it to be transferred",
+        {
+            "DROP_PEER_REN": ActionList(
+                [
+                    action.ExpectError(
+                        action.PollEvents(),
+                        norddrop.LibdropError.BadInput,
+                    ),
+                    action.ExpectError(
+                        action.EnableEventPolling(capacity=0),
+                        norddrop.LibdropError.BadInput,
+                    ),
+                    action.EnableEventPolling(),
+                    action.ExpectError(
+                        action.EnableEventPolling(),
+                        norddrop.LibdropError.BadInput,
+                    ),
+                    action.Start("DROP_PEER_REN"),
+                    action.WaitForAnotherPeer("DROP_PEER_STIMPY"),
+                    action.NewTransfer("DROP_PEER_STIMPY", ["/tmp/testfile-big"]),
+                    action.Wait(
+                        event.Queued(
+                            0,
+                            "DROP_PEER_STIMPY",
+                            [
+                                norddrop.QueuedFile(
+                                    FILES["testfile-big"].id,
+                                    "testfile-big",
+                                    10485760,
+                                    "/tmp",
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Wait(event.Start(0, FILES["testfile-big"].id)),
+                    action.Wait(
+                        event.FinishFileUploaded(
+                            0,
+                            FILES["testfile-big"].id,
+                        )
+                    ),
+                    action.ExpectCancel([0], True),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+            "DROP_PEER_STIMPY": ActionList(
+                [
+                    # A tiny queue makes libdrop wait for the poller
+                    action.EnableEventPolling(capacity=1),
+                    action.Start("DROP_PEER_STIMPY"),
+                    action.Wait(
+                        event.Receive(
+                            0,
+                            "DROP_PEER_REN",
+                            [
+                                norddrop.ReceivedFile(
+                                    FILES["testfile-big"].id, "testfile-big", 10485760
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Download(
+                        0,
+                        FILES["testfile-big"].id,
+                        "/tmp/received/56",
+                    ),
+                    action.Wait(
+                        event.Pending(0, FILES["testfile-big"].id, "/tmp/received/56")
+                    ),
+                    action.Wait(event.Start(0, FILES["testfile-big"].id)),
+                    action.Wait(
+                        event.FinishFileDownloaded(
+                            0,
+                            FILES["testfile-big"].id,
+                            "/tmp/received/56/testfile-big",
+                        )
+                    ),
+                    action.CheckDownloadedFiles(
+                        [
+                            action.File("/tmp/received/56/testfile-big", 10485760),
+                        ],
+                    ),
+                    action.ExpectCancel([0], False),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+        },
+    ),
+    Scenario(
+        "scenario57",
+        "Send one file to a peer with the event filters and the progress coalescing enabled, expect only the selected events",
+        {
+            "DROP_PEER_REN": ActionList(
+                [
+                    action.EnableEventPolling(capacity=4, coalesce_progress=True),
+                    action.SetEventFilter(
+                        [
+                            norddrop.EventType.REQUEST_QUEUED,
+                            norddrop.EventType.FILE_PROGRESS,
+                            norddrop.EventType.FILE_UPLOADED,
+                            norddrop.EventType.TRANSFER_FINALIZED,
+                        ]
+                    ),
+                    action.Start("DROP_PEER_REN"),
+                    action.WaitForAnotherPeer("DROP_PEER_STIMPY"),
+                    action.NewTransfer("DROP_PEER_STIMPY", ["/tmp/testfile-big"]),
+                    action.Wait(
+                        event.Queued(
+                            0,
+                            "DROP_PEER_STIMPY",
+                            [
+                                norddrop.QueuedFile(
+                                    FILES["testfile-big"].id,
+                                    "testfile-big",
+                                    10485760,
+                                    "/tmp",
+                                ),
+                            ],
+                        )
+                    ),
+                    # No FileStarted, any event other than the progress fails the wait
+                    action.Wait(
+                        event.FinishFileUploaded(
+                            0,
+                            FILES["testfile-big"].id,
+                        )
+                    ),
+                    action.ExpectCancel([0], True),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+            "DROP_PEER_STIMPY": ActionList(
+                [
+                    action.SetCallbackCoalescing(True),
+                    action.SetEventFilter(
+                        [
+                            norddrop.EventType.REQUEST_RECEIVED,
+                            norddrop.EventType.FILE_DOWNLOADED,
+                            norddrop.EventType.TRANSFER_FINALIZED,
+                        ]
+                    ),
+                    action.Start("DROP_PEER_STIMPY"),
+                    action.Wait(
+                        event.Receive(
+                            0,
+                            "DROP_PEER_REN",
+                            [
+                                norddrop.ReceivedFile(
+                                    FILES["testfile-big"].id, "testfile-big", 10485760
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Download(
+                        0,
+                        FILES["testfile-big"].id,
+                        "/tmp/received/57",
+                    ),
+                    # No FilePending nor FileStarted
+                    action.Wait(
+                        event.FinishFileDownloaded(
+                            0,
+                            FILES["testfile-big"].id,
+                            "/tmp/received/57/testfile-big",
+                        )
+                    ),
+                    action.CheckDownloadedFiles(
+                        [
+                            action.File("/tmp/received/57/testfile-big", 10485760),
+                        ],
+                    ),
+                    action.ExpectCancel([0], False),
+                    action.NoEvent(),
+                    action.Stop(),
+                ]
+            ),
+        },
+    ),
+    Scenario(
+        "scenario58",
+        "Try to send file to an offline peer, turn the auto retry interval down with set_config() while the peer comes online, expect the transfer to be retried without a restart",
+        {
+            "DROP_PEER_REN": ActionList(
+                [
+                    action.Start("DROP_PEER_REN", auto_retry_interval_ms=600000),
+                    action.NewTransfer("DROP_PEER_STIMPY", ["/tmp/testfile-big"]),
+                    action.Wait(
+                        event.Queued(
+                            0,
+                            "DROP_PEER_STIMPY",
+                            [
+                                norddrop.QueuedFile(
+                                    FILES["testfile-big"].id,
+                                    "testfile-big",
+                                    10485760,
+                                    "/tmp",
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Wait(
+                        event.TransferDeferred(
+                            0,
+                            "DROP_PEER_STIMPY",
+                            norddrop.StatusCode.IO_ERROR,
+                            ignore_os=True,
+                        )
+                    ),
+                    action.ExpectError(
+                        action.SetConfig(auto_retry_interval_ms=0),
+                        norddrop.LibdropError.InvalidConfig,
+                    ),
+                    action.SetConfig(auto_retry_interval_ms=1000),
+                    action.Sleep(8),
+                    action.WaitAndIgnoreExcept(
+                        [event.Start(0, FILES["testfile-big"].id)]
+                    ),
+                    action.Wait(
+                        event.FinishFileUploaded(
+                            0,
+                            FILES["testfile-big"].id,
+                        )
+                    ),
+                    action.ExpectCancel([0], True),
+                    action.SetConfig(
+                        auto_retry_interval_ms=1000,
+                        progress_events_granularity=1024,
+                        restart_required=["progress_events_granularity"],
+                    ),
+                    action.Stop(),
+                    action.ExpectError(
+                        action.SetConfig(auto_retry_interval_ms=1000),
+                        norddrop.LibdropError.NotStarted,
+                    ),
+                    action.NoEvent(),
+                ]
+            ),
+            "DROP_PEER_STIMPY": ActionList(
+                [
+                    action.Sleep(5),
+                    action.Start("DROP_PEER_STIMPY"),
+                    action.Wait(
+                        event.Receive(
+                            0,
+                            "DROP_PEER_REN",
+                            [
+                                norddrop.ReceivedFile(
+                                    FILES["testfile-big"].id,
+                                    "testfile-big",
+                                    10485760,
+                                ),
+                            ],
+                        )
+                    ),
+                    action.Download(
+                        0,
+                        FILES["testfile-big"].id,
+                        "/tmp/received/58",
+                    ),
+                    action.Wait(
+                        event.Pending(0, FILES["testfile-big"].id, "/tmp/received/58")
+                    ),
+                    action.Wait(event.Start(0, FILES["testfile-big"].id)),
+                    action.Wait(
+                        event.FinishFileDownloaded(
+                            0,
+                            FILES["testfile-big"].id,
+                            "/tmp/received/58/testfile-big",
+                        )
+                    ),
+                    action.CheckDownloadedFiles(
+                        [
+                            action.File("/tmp/received/58/testfile-big", 10485760),
+                        ],
+                    ),
+                    action.ExpectCancel([0], False),
+                    action.NoEvent(),
+                ]
+            ),
+        },
+        tags=["offline"],
+    ),
 ]